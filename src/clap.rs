@@ -0,0 +1,424 @@
+use std::ffi::{c_char, c_void};
+use std::path::Path;
+use crate::{AudioEffect, EffectKind, ParamUpdate, SCRATCH_FRAMES};
+
+// minimal CLAP ABI (clap 1.x headers), only what it takes to run an audio effect and set its parameters
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct ClapVersion {
+    major: u32,
+    minor: u32,
+    revision: u32,
+}
+
+#[repr(C)]
+struct ClapPluginEntry {
+    clap_version: ClapVersion,
+    init: unsafe extern "C" fn(plugin_path: *const c_char) -> bool,
+    deinit: unsafe extern "C" fn(),
+    get_factory: unsafe extern "C" fn(factory_id: *const c_char) -> *const c_void,
+}
+
+#[repr(C)]
+struct ClapPluginFactory {
+    get_plugin_count: unsafe extern "C" fn(*const ClapPluginFactory) -> u32,
+    get_plugin_descriptor: unsafe extern "C" fn(*const ClapPluginFactory, u32) -> *const ClapDescriptor,
+    create_plugin: unsafe extern "C" fn(*const ClapPluginFactory, *const ClapHost, *const c_char) -> *const ClapPluginApi,
+}
+
+#[repr(C)]
+struct ClapDescriptor {
+    clap_version: ClapVersion,
+    id: *const c_char,
+    name: *const c_char,
+    vendor: *const c_char,
+    url: *const c_char,
+    manual_url: *const c_char,
+    support_url: *const c_char,
+    version: *const c_char,
+    description: *const c_char,
+    features: *const *const c_char,
+}
+
+#[repr(C)]
+struct ClapHost {
+    clap_version: ClapVersion,
+    host_data: *mut c_void,
+    name: *const c_char,
+    vendor: *const c_char,
+    url: *const c_char,
+    version: *const c_char,
+    get_extension: unsafe extern "C" fn(*const ClapHost, *const c_char) -> *const c_void,
+    request_restart: unsafe extern "C" fn(*const ClapHost),
+    request_process: unsafe extern "C" fn(*const ClapHost),
+    request_callback: unsafe extern "C" fn(*const ClapHost),
+}
+
+#[repr(C)]
+struct ClapPluginApi {
+    desc: *const ClapDescriptor,
+    plugin_data: *mut c_void,
+    init: unsafe extern "C" fn(*const ClapPluginApi) -> bool,
+    destroy: unsafe extern "C" fn(*const ClapPluginApi),
+    activate: unsafe extern "C" fn(*const ClapPluginApi, f64, u32, u32) -> bool,
+    deactivate: unsafe extern "C" fn(*const ClapPluginApi),
+    start_processing: unsafe extern "C" fn(*const ClapPluginApi) -> bool,
+    stop_processing: unsafe extern "C" fn(*const ClapPluginApi),
+    reset: unsafe extern "C" fn(*const ClapPluginApi),
+    process: unsafe extern "C" fn(*const ClapPluginApi, *const ClapProcess) -> i32,
+    get_extension: unsafe extern "C" fn(*const ClapPluginApi, *const c_char) -> *const c_void,
+    on_main_thread: unsafe extern "C" fn(*const ClapPluginApi),
+}
+
+#[repr(C)]
+struct ClapAudioBuffer {
+    data32: *mut *mut f32,
+    data64: *mut *mut f64,
+    channel_count: u32,
+    latency: u32,
+    constant_mask: u64,
+}
+
+#[repr(C)]
+struct ClapInputEvents {
+    ctx: *mut c_void,
+    size: unsafe extern "C" fn(*const ClapInputEvents) -> u32,
+    get: unsafe extern "C" fn(*const ClapInputEvents, u32) -> *const c_void,
+}
+
+#[repr(C)]
+struct ClapOutputEvents {
+    ctx: *mut c_void,
+    try_push: unsafe extern "C" fn(*const ClapOutputEvents, *const c_void) -> bool,
+}
+
+#[repr(C)]
+struct ClapProcess {
+    steady_time: i64,
+    frames_count: u32,
+    transport: *const c_void,
+    audio_inputs: *const ClapAudioBuffer,
+    audio_outputs: *mut ClapAudioBuffer,
+    audio_inputs_count: u32,
+    audio_outputs_count: u32,
+    in_events: *const ClapInputEvents,
+    out_events: *const ClapOutputEvents,
+}
+
+#[repr(C)]
+struct ClapAudioPortInfo {
+    id: u32,
+    name: [c_char; 256],
+    flags: u32,
+    channel_count: u32,
+    port_type: *const c_char,
+    in_place_pair: u32,
+}
+
+#[repr(C)]
+struct ClapPluginAudioPorts {
+    count: unsafe extern "C" fn(*const ClapPluginApi, bool) -> u32,
+    get: unsafe extern "C" fn(*const ClapPluginApi, u32, bool, *mut ClapAudioPortInfo) -> bool,
+}
+
+#[repr(C)]
+struct ClapParamInfo {
+    id: u32,
+    flags: u32,
+    cookie: *mut c_void,
+    name: [c_char; 256],
+    module: [c_char; 1024],
+    min_value: f64,
+    max_value: f64,
+    default_value: f64,
+}
+
+#[repr(C)]
+struct ClapPluginParams {
+    count: unsafe extern "C" fn(*const ClapPluginApi) -> u32,
+    get_info: unsafe extern "C" fn(*const ClapPluginApi, u32, *mut ClapParamInfo) -> bool,
+    get_value: unsafe extern "C" fn(*const ClapPluginApi, u32, *mut f64) -> bool,
+    value_to_text: unsafe extern "C" fn(*const ClapPluginApi, u32, f64, *mut c_char, u32) -> bool,
+    text_to_value: unsafe extern "C" fn(*const ClapPluginApi, u32, *const c_char, *mut f64) -> bool,
+    flush: unsafe extern "C" fn(*const ClapPluginApi, *const ClapInputEvents, *const ClapOutputEvents),
+}
+
+#[repr(C)]
+struct ClapEventHeader {
+    size: u32,
+    time: u32,
+    space_id: u16,
+    type_: u16,
+    flags: u32,
+}
+
+#[repr(C)]
+struct ClapParamValueEvent {
+    header: ClapEventHeader,
+    param_id: u32,
+    cookie: *mut c_void,
+    note_id: i32,
+    port_index: i16,
+    channel: i16,
+    key: i16,
+    value: f64,
+}
+
+const CLAP_PROCESS_ERROR: i32 = 0;
+const CLAP_EVENT_PARAM_VALUE: u16 = 5;
+const CLAP_PARAM_IS_STEPPED: u32 = 1 << 0;
+const CLAP_PARAM_IS_HIDDEN: u32 = 1 << 2;
+const CLAP_PARAM_IS_READONLY: u32 = 1 << 3;
+const CLAP_MAX_PENDING_PARAMS: usize = 64;
+
+// reh offers no host extensions and ignores requests; plugins have to cope with a bare host
+unsafe extern "C" fn clap_host_get_extension(_host: *const ClapHost, _id: *const c_char) -> *const c_void { std::ptr::null() }
+unsafe extern "C" fn clap_host_request(_host: *const ClapHost) {}
+unsafe extern "C" fn clap_drop_event(_list: *const ClapOutputEvents, _event: *const c_void) -> bool { true }
+// input events come from ClapPlugin::pending, which ctx points at
+unsafe extern "C" fn clap_pending_size(list: *const ClapInputEvents) -> u32 {
+    unsafe { (*((*list).ctx as *const Vec<ClapParamValueEvent>)).len() as u32 }
+}
+unsafe extern "C" fn clap_pending_get(list: *const ClapInputEvents, index: u32) -> *const c_void {
+    let pending = unsafe { &*((*list).ctx as *const Vec<ClapParamValueEvent>) };
+    pending.get(index as usize).map_or(std::ptr::null(), |e| e as *const ClapParamValueEvent as *const c_void)
+}
+
+// a plugin parameter as the effects panel shows it
+#[derive(Clone)]
+pub(crate) struct ClapParam {
+    pub(crate) id: u32,
+    pub(crate) name: String,
+    pub(crate) min: f64,
+    pub(crate) max: f64,
+    pub(crate) default: f64,
+    pub(crate) value: f64,
+    pub(crate) stepped: bool,
+}
+
+// a CLAP effect running in the output chain; parameters are set from the effects panel, there's no editor
+pub(crate) struct ClapPlugin {
+    id: u32,
+    plugin: *const ClapPluginApi,
+    activated: bool,
+    processing: bool,
+    rate: u32, // activated at this rate; a rebuilt output reloads the plugin at its own
+    steady_time: i64,
+    inputs: Vec<Vec<f32>>, // deinterleaved scratch, SCRATCH_FRAMES per plugin channel, allocated at load
+    outputs: Vec<Vec<f32>>,
+    input_ptrs: Vec<*mut f32>,
+    output_ptrs: Vec<*mut f32>,
+    pending: Vec<ClapParamValueEvent>, // parameter changes for the next process call
+    _host: Box<ClapHost>, // the plugin keeps a pointer to this
+    entry: *const ClapPluginEntry,
+    _library: libloading::Library, // dropped last, after deinit
+}
+
+// the raw pointers all belong to the plugin instance, which is only ever used from one thread at a time
+unsafe impl Send for ClapPlugin {}
+
+impl ClapPlugin {
+    // loads the first audio effect in a .clap file (or macOS bundle), returning it with its display name
+    pub(crate) fn load(path: &Path, id: u32, sample_rate: u32) -> Result<(Self, String), String> {
+        let binary = if path.is_dir() {
+            std::fs::read_dir(path.join("Contents").join("MacOS")).map_err(|e| e.to_string())?
+                .flatten().map(|e| e.path()).next().ok_or("empty plugin bundle")?
+        } else {
+            path.to_path_buf()
+        };
+        let c_path = std::ffi::CString::new(path.to_string_lossy().as_bytes()).map_err(|e| e.to_string())?;
+        unsafe {
+            let library = libloading::Library::new(&binary).map_err(|e| e.to_string())?;
+            let entry = *library.get::<*const ClapPluginEntry>(b"clap_entry\0").map_err(|_| "not a CLAP plugin")?;
+            if entry.is_null() || (*entry).clap_version.major < 1 { return Err("unsupported CLAP version".into()); }
+            if !((*entry).init)(c_path.as_ptr()) { return Err("plugin failed to initialize".into()); }
+            let mut fx = ClapPlugin {
+                id,
+                plugin: std::ptr::null(),
+                activated: false,
+                processing: false,
+                rate: sample_rate,
+                steady_time: 0,
+                inputs: Vec::new(),
+                outputs: Vec::new(),
+                input_ptrs: Vec::new(),
+                output_ptrs: Vec::new(),
+                pending: Vec::with_capacity(CLAP_MAX_PENDING_PARAMS),
+                _host: Box::new(ClapHost {
+                    clap_version: ClapVersion { major: 1, minor: 2, revision: 0 },
+                    host_data: std::ptr::null_mut(),
+                    name: c"Reh".as_ptr(),
+                    vendor: c"reh".as_ptr(),
+                    url: c"https://github.com/kipm808/reh".as_ptr(),
+                    version: c"0.1".as_ptr(),
+                    get_extension: clap_host_get_extension,
+                    request_restart: clap_host_request,
+                    request_process: clap_host_request,
+                    request_callback: clap_host_request,
+                }),
+                entry,
+                _library: library,
+            };
+            let factory = ((*entry).get_factory)(c"clap.plugin-factory".as_ptr()) as *const ClapPluginFactory;
+            if factory.is_null() { return Err("no plugins in this file".into()); }
+            // instruments, analyzers and note effects share the factory; only audio effects fit the chain
+            let desc = (0..((*factory).get_plugin_count)(factory))
+                .map(|i| ((*factory).get_plugin_descriptor)(factory, i))
+                .find(|&d| !d.is_null() && Self::has_feature(d, c"audio-effect"))
+                .ok_or("no audio effect in this file")?;
+            let name = std::ffi::CStr::from_ptr((*desc).name).to_string_lossy().into_owned();
+            fx.plugin = ((*factory).create_plugin)(factory, &*fx._host, (*desc).id);
+            if fx.plugin.is_null() || !((*fx.plugin).init)(fx.plugin) { return Err(format!("{name} failed to start")); }
+            let (ins, outs) = fx.main_ports();
+            if ins == 0 || outs == 0 { return Err(format!("{name} has no audio input or output")); }
+            fx.inputs = vec![vec![0.0; SCRATCH_FRAMES]; ins];
+            fx.outputs = vec![vec![0.0; SCRATCH_FRAMES]; outs];
+            fx.input_ptrs = fx.inputs.iter_mut().map(|c| c.as_mut_ptr()).collect();
+            fx.output_ptrs = fx.outputs.iter_mut().map(|c| c.as_mut_ptr()).collect();
+            fx.activated = ((*fx.plugin).activate)(fx.plugin, sample_rate as f64, 1, SCRATCH_FRAMES as u32);
+            if !fx.activated { return Err(format!("{name} refused {sample_rate} Hz")); }
+            Ok((fx, name))
+        }
+    }
+
+    unsafe fn has_feature(desc: *const ClapDescriptor, feature: &std::ffi::CStr) -> bool {
+        unsafe {
+            let mut f = (*desc).features;
+            while !f.is_null() && !(*f).is_null() {
+                if std::ffi::CStr::from_ptr(*f) == feature { return true; }
+                f = f.add(1);
+            }
+        }
+        false
+    }
+
+    // channel counts of the main input and output ports; plugins without the extension are taken as stereo
+    fn main_ports(&self) -> (usize, usize) {
+        unsafe {
+            let ports = ((*self.plugin).get_extension)(self.plugin, c"clap.audio-ports".as_ptr()) as *const ClapPluginAudioPorts;
+            if ports.is_null() { return (2, 2); }
+            let channels = |is_input: bool| {
+                if ((*ports).count)(self.plugin, is_input) == 0 { return 0; }
+                let mut info: ClapAudioPortInfo = std::mem::zeroed();
+                if ((*ports).get)(self.plugin, 0, is_input, &mut info) { info.channel_count as usize } else { 2 }
+            };
+            (channels(true), channels(false))
+        }
+    }
+
+    // the visible, writable parameters with their current values; main thread only, so call before inserting
+    pub(crate) fn params(&self) -> Vec<ClapParam> {
+        unsafe {
+            let params = ((*self.plugin).get_extension)(self.plugin, c"clap.params".as_ptr()) as *const ClapPluginParams;
+            if params.is_null() { return Vec::new(); }
+            (0..((*params).count)(self.plugin)).filter_map(|i| {
+                let mut info: ClapParamInfo = std::mem::zeroed();
+                if !((*params).get_info)(self.plugin, i, &mut info) { return None; }
+                if info.flags & (CLAP_PARAM_IS_HIDDEN | CLAP_PARAM_IS_READONLY) != 0 { return None; }
+                let mut value = info.default_value;
+                ((*params).get_value)(self.plugin, info.id, &mut value);
+                Some(ClapParam {
+                    id: info.id,
+                    name: std::ffi::CStr::from_ptr(info.name.as_ptr()).to_string_lossy().into_owned(),
+                    min: info.min_value,
+                    max: info.max_value,
+                    default: info.default_value,
+                    value,
+                    stepped: info.flags & CLAP_PARAM_IS_STEPPED != 0,
+                })
+            }).collect()
+        }
+    }
+}
+
+impl Drop for ClapPlugin {
+    fn drop(&mut self) {
+        unsafe {
+            if !self.plugin.is_null() {
+                // normally already stopped by retire(); only reached here once the audio thread is gone
+                if self.processing { ((*self.plugin).stop_processing)(self.plugin); }
+                if self.activated { ((*self.plugin).deactivate)(self.plugin); }
+                ((*self.plugin).destroy)(self.plugin);
+            }
+            ((*self.entry).deinit)();
+        }
+    }
+}
+
+impl AudioEffect for ClapPlugin {
+    fn kind(&self) -> EffectKind { EffectKind::Plugin(self.id) }
+
+    fn update(&mut self, update: &ParamUpdate) {
+        if let ParamUpdate::PluginParam { id, param, value } = *update && id == self.id {
+            // a newer value for the same parameter replaces the queued one, which keeps the queue within its capacity
+            if let Some(e) = self.pending.iter_mut().find(|e| e.param_id == param) {
+                e.value = value;
+            } else if self.pending.len() < CLAP_MAX_PENDING_PARAMS {
+                self.pending.push(ClapParamValueEvent {
+                    header: ClapEventHeader { size: std::mem::size_of::<ClapParamValueEvent>() as u32, time: 0, space_id: 0, type_: CLAP_EVENT_PARAM_VALUE, flags: 0 },
+                    param_id: param,
+                    cookie: std::ptr::null_mut(),
+                    note_id: -1,
+                    port_index: -1,
+                    channel: -1,
+                    key: -1,
+                    value,
+                });
+            }
+        }
+    }
+
+    fn retire(&mut self) {
+        if self.processing { unsafe { ((*self.plugin).stop_processing)(self.plugin); } }
+        self.processing = false;
+    }
+
+    fn process(&mut self, data: &mut [f32], channels: usize, sample_rate: u32) {
+        // activated for another rate: pass through until rebuild_output swaps in the reloaded plugin
+        if sample_rate != self.rate { return; }
+        let (ins, outs) = (self.inputs.len(), self.outputs.len());
+        unsafe {
+            if !self.processing {
+                self.processing = ((*self.plugin).start_processing)(self.plugin);
+                if !self.processing { return; }
+            }
+            let in_events = ClapInputEvents { ctx: &mut self.pending as *mut Vec<ClapParamValueEvent> as *mut c_void, size: clap_pending_size, get: clap_pending_get };
+            let out_events = ClapOutputEvents { ctx: std::ptr::null_mut(), try_push: clap_drop_event };
+            for chunk in data.chunks_mut(SCRATCH_FRAMES * channels) {
+                let frames = chunk.len() / channels;
+                // a mono plugin gets the downmix; otherwise plugin channel k reads device channel k, the last one repeating
+                for (f, frame) in chunk.chunks(channels).enumerate() {
+                    if ins == 1 {
+                        self.inputs[0][f] = frame.iter().sum::<f32>() / channels as f32;
+                    } else {
+                        for (k, input) in self.inputs.iter_mut().enumerate() { input[f] = frame[k.min(channels - 1)]; }
+                    }
+                }
+                let input = ClapAudioBuffer { data32: self.input_ptrs.as_mut_ptr(), data64: std::ptr::null_mut(), channel_count: ins as u32, latency: 0, constant_mask: 0 };
+                let mut output = ClapAudioBuffer { data32: self.output_ptrs.as_mut_ptr(), data64: std::ptr::null_mut(), channel_count: outs as u32, latency: 0, constant_mask: 0 };
+                let process = ClapProcess {
+                    steady_time: self.steady_time,
+                    frames_count: frames as u32,
+                    transport: std::ptr::null(),
+                    audio_inputs: &input,
+                    audio_outputs: &mut output,
+                    audio_inputs_count: 1,
+                    audio_outputs_count: 1,
+                    in_events: &in_events,
+                    out_events: &out_events,
+                };
+                let status = ((*self.plugin).process)(self.plugin, &process);
+                self.pending.clear();
+                self.steady_time += frames as i64;
+                // on error the chunk passes through dry
+                if status == CLAP_PROCESS_ERROR { continue; }
+                // a mono plugin feeds every device channel; device channels past the plugin's stay dry
+                for (f, frame) in chunk.chunks_mut(channels).enumerate() {
+                    for (d, s) in frame.iter_mut().enumerate() {
+                        if outs == 1 { *s = self.outputs[0][f]; } else if d < outs { *s = self.outputs[d][f]; }
+                    }
+                }
+            }
+        }
+    }
+}
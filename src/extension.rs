@@ -0,0 +1,169 @@
+use eframe::egui;
+use std::ffi::{c_char, c_void};
+use std::path::{Path, PathBuf};
+use crate::{config_dir, Action};
+
+// C ABI for extension libraries in <config>/extensions: each exports `reh_extension`, returning an
+// ExtDescriptor. Extensions get engine events and can draw a panel through the ExtUi calls, which
+// keeps them independent of the egui version Reh is built with.
+const EXT_API_VERSION: u32 = 1;
+pub(crate) const EXT_EVENT_POSITION: u32 = 0; // value: cursor in seconds, sent when it moves
+pub(crate) const EXT_EVENT_LOOP_WRAP: u32 = 1; // value: loop start in seconds
+pub(crate) const EXT_EVENT_FILE_LOADED: u32 = 2; // value: duration in seconds, text: path
+pub(crate) const EXT_EVENT_TEMPO: u32 = 3; // value: BPM, 0 when cleared
+pub(crate) const EXT_EVENT_KEY: u32 = 4; // text: key name like "F#m", null when cleared
+pub(crate) const EXT_EVENT_PHRASES: u32 = 5; // value: number of phrases found
+
+#[repr(C)]
+struct ExtEvent {
+    kind: u32,
+    value: f64,
+    text: *const c_char,
+}
+
+#[repr(C)]
+struct ExtDescriptor {
+    api_version: u32,
+    name: *const c_char,
+    state: *mut c_void, // handed back on every call
+    on_event: Option<unsafe extern "C" fn(state: *mut c_void, event: *const ExtEvent)>,
+    draw_panel: Option<unsafe extern "C" fn(state: *mut c_void, ui: *const ExtUi)>,
+    destroy: Option<unsafe extern "C" fn(state: *mut c_void)>,
+}
+
+// widgets an extension can add while drawing; `perform` queues an action by its macro name, e.g. "play" or "speed 70%"
+#[repr(C)]
+struct ExtUi {
+    ui: *mut c_void,
+    label: unsafe extern "C" fn(ui: *mut c_void, text: *const c_char),
+    button: unsafe extern "C" fn(ui: *mut c_void, text: *const c_char) -> bool,
+    checkbox: unsafe extern "C" fn(ui: *mut c_void, text: *const c_char, value: *mut bool) -> bool,
+    slider: unsafe extern "C" fn(ui: *mut c_void, text: *const c_char, value: *mut f32, min: f32, max: f32) -> bool,
+    separator: unsafe extern "C" fn(ui: *mut c_void),
+    horizontal: unsafe extern "C" fn(ui: *mut c_void, draw: unsafe extern "C" fn(user: *mut c_void, ui: *const ExtUi), user: *mut c_void),
+    perform: unsafe extern "C" fn(ui: *mut c_void, action: *const c_char) -> bool,
+}
+
+// what ExtUi::ui points at while an extension draws
+struct ExtUiBridge<'a> {
+    ui: &'a mut egui::Ui,
+    actions: &'a mut Vec<Action>,
+}
+
+unsafe fn ext_str<'a>(text: *const c_char) -> std::borrow::Cow<'a, str> {
+    if text.is_null() { "".into() } else { unsafe { std::ffi::CStr::from_ptr(text) }.to_string_lossy() }
+}
+
+unsafe fn ext_bridge<'a>(ui: *mut c_void) -> &'a mut ExtUiBridge<'a> {
+    unsafe { &mut *(ui as *mut ExtUiBridge<'a>) }
+}
+
+unsafe extern "C" fn ext_label(ui: *mut c_void, text: *const c_char) {
+    unsafe { ext_bridge(ui).ui.label(ext_str(text)); }
+}
+
+unsafe extern "C" fn ext_button(ui: *mut c_void, text: *const c_char) -> bool {
+    unsafe { ext_bridge(ui).ui.button(ext_str(text)).clicked() }
+}
+
+unsafe extern "C" fn ext_checkbox(ui: *mut c_void, text: *const c_char, value: *mut bool) -> bool {
+    unsafe { ext_bridge(ui).ui.checkbox(&mut *value, ext_str(text)).changed() }
+}
+
+unsafe extern "C" fn ext_slider(ui: *mut c_void, text: *const c_char, value: *mut f32, min: f32, max: f32) -> bool {
+    unsafe { ext_bridge(ui).ui.add(egui::Slider::new(&mut *value, min..=max).text(ext_str(text))).changed() }
+}
+
+unsafe extern "C" fn ext_separator(ui: *mut c_void) {
+    unsafe { ext_bridge(ui).ui.separator(); }
+}
+
+unsafe extern "C" fn ext_horizontal(ui: *mut c_void, draw: unsafe extern "C" fn(*mut c_void, *const ExtUi), user: *mut c_void) {
+    let bridge = unsafe { ext_bridge(ui) };
+    let actions = &mut *bridge.actions;
+    bridge.ui.horizontal(|ui| ext_draw(ui, actions, |ext_ui| unsafe { draw(user, ext_ui) }));
+}
+
+unsafe extern "C" fn ext_perform(ui: *mut c_void, action: *const c_char) -> bool {
+    let bridge = unsafe { ext_bridge(ui) };
+    let Some(action) = Action::parse(&unsafe { ext_str(action) }) else { return false };
+    bridge.actions.push(action);
+    true
+}
+
+// runs `draw` with an ExtUi wired to this egui Ui
+fn ext_draw(ui: &mut egui::Ui, actions: &mut Vec<Action>, draw: impl FnOnce(*const ExtUi)) {
+    let mut bridge = ExtUiBridge { ui, actions };
+    let ext_ui = ExtUi {
+        ui: &mut bridge as *mut ExtUiBridge as *mut c_void,
+        label: ext_label,
+        button: ext_button,
+        checkbox: ext_checkbox,
+        slider: ext_slider,
+        separator: ext_separator,
+        horizontal: ext_horizontal,
+        perform: ext_perform,
+    };
+    draw(&ext_ui);
+}
+
+pub(crate) struct Extension {
+    pub(crate) name: String,
+    desc: *const ExtDescriptor,
+    _library: libloading::Library, // declared last so it unloads after destroy
+}
+
+impl Extension {
+    fn load(path: &Path) -> Result<Self, String> {
+        unsafe {
+            let library = libloading::Library::new(path).map_err(|e| e.to_string())?;
+            let entry = library.get::<unsafe extern "C" fn() -> *const ExtDescriptor>(b"reh_extension\0").map_err(|_| "no reh_extension entry point")?;
+            let desc = entry();
+            if desc.is_null() { return Err("extension declined to load".into()); }
+            if (*desc).api_version != EXT_API_VERSION { return Err(format!("built for extension API {}, this Reh has {}", (*desc).api_version, EXT_API_VERSION)); }
+            let name = ext_str((*desc).name).into_owned();
+            Ok(Extension { name, desc, _library: library })
+        }
+    }
+
+    pub(crate) fn send(&self, kind: u32, value: f64, text: Option<&str>) {
+        let text = text.and_then(|t| std::ffi::CString::new(t).ok());
+        let event = ExtEvent { kind, value, text: text.as_ref().map_or(std::ptr::null(), |t| t.as_ptr()) };
+        unsafe { if let Some(f) = (*self.desc).on_event { f((*self.desc).state, &event); } }
+    }
+
+    pub(crate) fn has_panel(&self) -> bool {
+        unsafe { (*self.desc).draw_panel.is_some() }
+    }
+
+    pub(crate) fn draw(&self, ui: &mut egui::Ui, actions: &mut Vec<Action>) {
+        let Some(f) = (unsafe { (*self.desc).draw_panel }) else { return };
+        let state = unsafe { (*self.desc).state };
+        ext_draw(ui, actions, |ext_ui| unsafe { f(state, ext_ui) });
+    }
+}
+
+impl Drop for Extension {
+    fn drop(&mut self) {
+        unsafe { if let Some(f) = (*self.desc).destroy { f((*self.desc).state); } }
+    }
+}
+
+pub(crate) fn load_extensions() -> Vec<Extension> {
+    let Ok(entries) = std::fs::read_dir(config_dir().join("extensions")) else { return Vec::new() };
+    let mut paths: Vec<PathBuf> = entries.flatten().map(|e| e.path())
+        .filter(|p| p.extension().is_some_and(|e| e == std::env::consts::DLL_EXTENSION)).collect();
+    paths.sort();
+    paths.iter().filter_map(|p| Extension::load(p).map_err(|e| eprintln!("extension {}: {}", p.display(), e)).ok()).collect()
+}
+
+// what the extensions were last told, so events only go out on change
+#[derive(Default)]
+pub(crate) struct ExtSeen {
+    pub(crate) file: String,
+    pub(crate) cursor: usize,
+    pub(crate) tempo: Option<f32>,
+    pub(crate) key: Option<String>,
+    pub(crate) phrases: usize,
+    pub(crate) wraps: usize,
+}
@@ -3,318 +3,7831 @@ use rfd::FileDialog;
 use cpal::traits::{DeviceTrait, HostTrait, StreamTrait};
 use signalsmith_stretch::Stretch;
 use std::sync::{Arc, Mutex};
-use std::sync::atomic::{AtomicBool, AtomicUsize, AtomicU32, Ordering};
+use std::sync::atomic::{AtomicBool, AtomicUsize, AtomicU32, AtomicU64, Ordering};
 use symphonia::core::io::MediaSourceStream;
 use symphonia::core::audio::SampleBuffer;
 use symphonia::core::probe::Hint;
+use symphonia::core::codecs::{CodecParameters, Decoder, CODEC_TYPE_NULL};
+use symphonia::core::formats::{FormatReader, SeekMode, SeekTo, Track};
 use std::thread;
-use std::path::PathBuf;
-use crossbeam_channel::{unbounded, Receiver, Sender};
+use std::path::{Path, PathBuf};
+use crossbeam_channel::{bounded, unbounded, Receiver, Sender};
+use rustfft::{Fft, FftPlanner, num_complex::Complex};
+use ringbuf::{HeapConsumer, HeapProducer, HeapRb};
+
+mod clap;
+mod extension;
+use clap::{ClapParam, ClapPlugin};
+use extension::{load_extensions, ExtSeen, Extension, EXT_EVENT_FILE_LOADED, EXT_EVENT_KEY, EXT_EVENT_LOOP_WRAP, EXT_EVENT_PHRASES, EXT_EVENT_POSITION, EXT_EVENT_TEMPO};
 
 struct AppState {
     file_path: String,
     total_samples: usize,
     sample_rate: u32,
     channels: usize,
-    waveform: Vec<f32>,
+    waveform: Vec<WaveBucket>, // ~1000-bucket overview of the whole file
+    waveform_colors: Vec<egui::Color32>, // per-bucket tint from low/mid/high band energy
+    waveform_gen: u64, // bumped whenever the waveform changes, so the drawn texture knows to rebuild
+    wave_mipmaps: Arc<Vec<WaveMipmap>>, // min/max/power pyramids for the drawn waveform: the mix, then each channel
+    phrases: Vec<(usize, usize)>, // sample ranges of speech/music separated by pauses
+    onsets: Vec<usize>, // sample indices of detected note/drum attacks
+    clipped: Vec<(usize, usize)>, // sample ranges where the source sits at full scale
+    file_lufs: Option<f32>, // integrated loudness of the whole file
+    loop_suggestions: Vec<LoopSuggestion>,
+    subtitles: Vec<SubtitleCue>,
+    key: Option<MusicalKey>, // detected or picked by hand
+    detected_key: Option<MusicalKey>,
+    audio_tracks: Vec<(u32, String)>, // (track id, label) of every audio track in the container
+    audio_track: Option<u32>, // the one playing
+    tempo: Option<TempoGrid>, // the grid beat jumps and snapping follow: detected, tapped or typed in
+    detected_tempo: Option<TempoGrid>,
+    stems: Vec<(String, Arc<Vec<f32>>)>,
+    stem_status: String,
+    transcript_status: String,
+    location_status: String, // a download in progress, the loaded file plays on meanwhile
+}
+
+impl Default for AppState {
+    fn default() -> Self {
+        AppState {
+            file_path: "No file selected".to_string(),
+            total_samples: 0,
+            sample_rate: 44100,
+            channels: 2,
+            waveform: Vec::new(),
+            waveform_colors: Vec::new(),
+            waveform_gen: 0,
+            wave_mipmaps: Arc::new(Vec::new()),
+            phrases: Vec::new(),
+            onsets: Vec::new(),
+            clipped: Vec::new(),
+            file_lufs: None,
+            loop_suggestions: Vec::new(),
+            subtitles: Vec::new(),
+            key: None,
+            detected_key: None,
+            audio_tracks: Vec::new(),
+            audio_track: None,
+            tempo: None,
+            detected_tempo: None,
+            stems: Vec::new(),
+            stem_status: String::new(),
+            transcript_status: String::new(),
+            location_status: String::new(),
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+struct TempoGrid {
+    bpm: f32,
+    downbeat: usize, // sample index of the first beat
+}
+
+impl TempoGrid {
+    fn beat_samples(&self, sample_rate: u32, channels: usize) -> f64 {
+        60.0 / self.bpm as f64 * sample_rate as f64 * channels as f64
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+struct MusicalKey {
+    tonic: i32, // pitch class, 0 = C
+    minor: bool,
+}
+
+impl MusicalKey {
+    fn name(self) -> String {
+        format!("{}{}", NOTE_NAMES[self.tonic.rem_euclid(12) as usize], if self.minor { "m" } else { "" })
+    }
+
+    // spelled the way the key signature would, "E♭ minor" rather than "D#m"
+    fn full_name(self) -> String {
+        const MAJOR: [&str; 12] = ["C", "D♭", "D", "E♭", "E", "F", "F♯", "G", "A♭", "A", "B♭", "B"];
+        const MINOR: [&str; 12] = ["C", "C♯", "D", "E♭", "E", "F", "F♯", "G", "G♯", "A", "B♭", "B"];
+        let tonic = self.tonic.rem_euclid(12) as usize;
+        if self.minor { format!("{} minor", MINOR[tonic]) } else { format!("{} major", MAJOR[tonic]) }
+    }
+
+    fn transposed(self, semitones: i32) -> Self {
+        MusicalKey { tonic: (self.tonic + semitones).rem_euclid(12), minor: self.minor }
+    }
+}
+
+#[derive(Clone)]
+struct LoopSuggestion {
+    start: usize, // sample indices
+    end: usize,
+    repeats: usize,
+}
+
+struct SubtitleCue {
+    start: f64, // seconds
+    end: f64,
+    text: String,
 }
 
 struct AudioControls {
     speed: AtomicU32, 
     pitch: AtomicU32, 
     volume: AtomicU32, 
+    deck_gain: AtomicU32, // per-deck gain offset for A/B comparison, linear
     cursor: AtomicUsize,
     loop_start: AtomicUsize,
     loop_end: AtomicUsize,
     is_playing: AtomicBool,
     is_loading: AtomicBool,
-    is_seeking: AtomicBool, // Restored to prevent chirping
+    load_progress: AtomicU32, // f32, fraction of the file decoded so far, negative when unknown
+    retired: Mutex<Vec<(std::time::Instant, Box<dyn std::any::Any + Send>)>>, // swapped-out sources and noise profiles, see set_source
+    pause_at: AtomicUsize, // dictation stop point, usize::MAX when unarmed
+    stop_at: AtomicUsize, // end of the last loop pass before stopping, usize::MAX when unarmed
+    advance_at_end: AtomicBool, // a queued track follows: stop at the end of the file and flag it instead of sitting there
+    track_ended: AtomicBool,
     pcm_data: Mutex<Arc<Vec<f32>>>, 
+    stream: Mutex<Option<Arc<StreamBuffer>>>, // set instead of pcm_data for long files
+    stem_mix: Mutex<Arc<StemMix>>,
+    take: Mutex<Arc<Take>>,
+    speed_curve: Mutex<Arc<Automation>>, // speed factor along the timeline, on top of the speed slider
+    pitch_regions: Mutex<Arc<Automation>>, // pitch offset in semitones from each point on
+    volume_envelope: Mutex<Arc<Automation>>, // gain in dB along the timeline
+    take_blend: AtomicU32, // 0 = original only, 1 = recorded take only
+    lufs_short: AtomicU32, // output loudness published by the audio thread
+    lufs_integrated: AtomicU32,
+    correlation: AtomicU32, // smoothed L/R phase correlation of the output, -1..1
+    levels: LevelMeter, // the output after the volume
+    limiter_reduction: AtomicU32, // deepest gain reduction in dB since the UI last took it
+    second_volume: AtomicU32,
+    audition_pos: AtomicUsize, // loop-edge snippet being played, done once it reaches audition_end
+    audition_end: AtomicUsize,
+    cue_cursor: AtomicUsize, // the cue bus plays the same file from its own position
+    cue_start: AtomicUsize,
+    cue_end: AtomicUsize, // equal to cue_start when the cue has no loop region
+    cue_playing: AtomicBool,
+    cue_volume: AtomicU32,
+    monitor_gain: AtomicU32, // input passthrough level, linear
+    loop_xfade_ms: AtomicU32, // f32, length of the crossfade where the loop wraps
+    source_rate: AtomicU32, // sample rate of the playing file, resampled to the device rate in the callback
+    source_channels: AtomicUsize, // interleaved channels of the playing file, mapped to the device layout
+    device_lost: AtomicBool, // the output device went away, the UI rebuilds on the default one
+    loop_wraps: AtomicUsize, // bumped each time the engine jumps from the loop end back to its start
+    count_in: AtomicUsize, // device frames of count-in clicks left before the music starts
+    count_in_beat: AtomicUsize, // device frames per count-in beat
+}
+
+// when populated, the engine plays the weighted sum of the stems instead of pcm_data
+#[derive(Default)]
+struct StemMix {
+    stems: Vec<Arc<Vec<f32>>>,
+    gains: Vec<f32>,
+}
+
+impl StemMix {
+    fn sample(&self, i: usize) -> f32 {
+        self.stems.iter().zip(&self.gains).map(|(s, g)| s[i] * g).sum()
+    }
+}
+
+// breakpoints along the timeline, linearly interpolated and held flat past either end;
+// stepped automation instead holds each value until the next point, and is unset before the first
+#[derive(Default, Clone)]
+struct Automation {
+    points: Vec<(usize, f32)>, // (interleaved sample index, value), sorted by position
+    stepped: bool,
+}
+
+impl Automation {
+    // linear gain from a curve drawn in dB; unity where there are no points
+    fn gain_at(&self, pos: usize) -> f32 {
+        self.value_at(pos).map_or(1.0, |db| 10f32.powf(db / 20.0))
+    }
+
+    fn value_at(&self, pos: usize) -> Option<f32> {
+        let i = self.points.partition_point(|p| p.0 <= pos);
+        match (i.checked_sub(1).map(|k| self.points[k]), self.points.get(i)) {
+            (None, None) => None,
+            (Some(a), None) => Some(a.1),
+            (None, Some(_)) if self.stepped => None,
+            (None, Some(b)) => Some(b.1),
+            (Some(a), Some(_)) if self.stepped => Some(a.1),
+            (Some(a), Some(b)) => Some(a.1 + (b.1 - a.1) * (pos - a.0) as f32 / (b.0 - a.0).max(1) as f32),
+        }
+    }
+}
+
+// a recorded take, mono, already mapped onto the file's timeline so it stretches along with it
+#[derive(Default)]
+struct Take {
+    start: usize, // interleaved sample index in the file where the take begins
+    channels: usize,
+    pcm: Vec<f32>, // one sample per file frame
+}
+
+impl Take {
+    fn sample(&self, i: usize) -> Option<f32> {
+        self.pcm.get(i.checked_sub(self.start)? / self.channels).copied()
+    }
 }
 
 enum ParamUpdate {
     Speed(f32),
     Pitch(f32),
+    Tuning(f32), // cents on top of the pitch, to bring a recording to concert pitch
+    Mode(PlaybackMode),
+    Channels(ChannelMatrix),
+    Nudge(f32), // momentary speed factor while a nudge key is held
+    Tone { enabled: bool, freq: f32, level: f32 },
+    Drone { enabled: bool, root: f32, octave: bool, fifth: bool, level: f32 },
+    Denoise { enabled: bool, strength: f32 },
+    NoiseProfile(Arc<Vec<f32>>), // built on the UI thread, which parks the one it replaces in controls.retired
+    ResetLoudness,
+    Stretcher { tuning: Option<StretchTuning>, tonality_limit: Option<f32> }, // tuning saved for the output's format, limit in Hz
+    Stretchers { preset: StretchPreset, rate: u32, stretchers: Vec<Stretch> }, // built off the audio thread on its request
+    ChainLayout(Vec<(EffectKind, bool)>), // effect order and bypass flags
+    InsertEffect(Box<dyn AudioEffect>), // appended to the end of the chain
+    RemoveEffect(EffectKind), // handed back to the UI thread to be dropped there
+    PluginParam { id: u32, param: u32, value: f64 }, // CLAP parameter change for plugin `id`
+    LoudnessCompensation(bool),
+    Limiter(bool),
+    Leveler { enabled: bool, target_db: f32, attack_ms: f32, release_ms: f32, max_gain_db: f32 },
+    BandSolo { enabled: bool, low: f32, high: f32 }, // Hz
+    Karaoke { enabled: bool, amount: f32, keep_bass: bool },
+    Eq(EqSettings),
+    Metronome { enabled: bool, level: f32, accent: bool, beat: f64, downbeat: f64 }, // grid in file frames, beat 0 without one
+    SecondOutput(Option<HeapProducer<(f32, f32)>>), // feed for the extra output device, None to stop
+    Monitor(Option<(HeapConsumer<f32>, u32, Arc<LatencyProbe>)>), // mono input at its own rate, None to stop
 }
 
-struct PlayerApp {
-    state: Arc<Mutex<AppState>>,
-    controls: Arc<AudioControls>,
-    dragging_marker: Option<bool>, 
-    _stream: Option<cpal::Stream>,
-    tx: Sender<ParamUpdate>,
+#[derive(Clone, Copy, PartialEq)]
+enum PlaybackMode {
+    Stretch,
+    Varispeed, // tape-style: bypasses the stretcher, pitch follows speed
 }
 
-impl PlayerApp {
-    fn new(_cc: &eframe::CreationContext<'_>, initial_path: Option<PathBuf>) -> Self {
-        let (tx, rx) = unbounded();
-        let controls = Arc::new(AudioControls {
-            speed: AtomicU32::new(1.0f32.to_bits()),
-            pitch: AtomicU32::new(1.0f32.to_bits()),
-            volume: AtomicU32::new(1.0f32.to_bits()),
-            cursor: AtomicUsize::new(0),
-            loop_start: AtomicUsize::new(0),
-            loop_end: AtomicUsize::new(0),
-            is_playing: AtomicBool::new(true),
-            is_loading: AtomicBool::new(false),
-            is_seeking: AtomicBool::new(false),
-            pcm_data: Mutex::new(Arc::new(Vec::new())),
-        });
+// normalizing won't push a whisper-quiet file up (or a brickwalled one down) further than this
+const MAX_MAKEUP_DB: f32 = 12.0;
 
-        let state = Arc::new(Mutex::new(AppState {
-            file_path: "No file selected".to_string(),
-            total_samples: 0,
-            sample_rate: 44100,
-            channels: 2,
-            waveform: Vec::new(),
-        }));
+// the bottom of the volume fader, which means silence
+const VOLUME_FLOOR_DB: f32 = -60.0;
 
-        let mut app = Self {
-            state,
-            controls,
-            dragging_marker: None,
-            _stream: None,
-            tx,
-        };
+// one click (or Ctrl+1..4) away under the speed slider
+const SPEED_PRESETS: [f32; 4] = [0.5, 0.66, 0.75, 1.0];
 
-        if let Some(path) = initial_path {
-            app.load_audio_file(path);
+// the stretcher scratch buffers are fixed size, so large speed-ups are processed in chunks
+const SCRATCH_FRAMES: usize = 8192;
+
+// hand-tuned stretcher timing, in ms so it holds across sample rates
+#[derive(Clone, Copy, PartialEq)]
+struct StretchTuning {
+    block_ms: f32,
+    interval_ms: f32,
+}
+
+impl StretchTuning {
+    // what a new custom tuning starts from, close to the stretcher's own default
+    const NORMAL: StretchTuning = StretchTuning { block_ms: 120.0, interval_ms: 30.0 };
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum StretchPreset {
+    Slow,
+    Default,
+    Fast,
+    Custom(StretchTuning),
+}
+
+impl StretchPreset {
+    fn for_speed(speed: f32) -> Self {
+        if speed < 0.5 { StretchPreset::Slow }
+        else if speed > 2.0 { StretchPreset::Fast }
+        else { StretchPreset::Default }
+    }
+
+    // the user's tuning for this output format if there is one, else the preset for this speed
+    fn resolve(speed: f32, tuning: Option<StretchTuning>) -> Self {
+        tuning.map(StretchPreset::Custom).unwrap_or(Self::for_speed(speed))
+    }
+
+    fn build(self, channels: usize, sample_rate: u32) -> Vec<Stretch> {
+        (0..channels).map(|_| match self {
+            // longer blocks with more overlap keep extreme slow-downs smooth
+            StretchPreset::Slow => {
+                let block = (sample_rate as f32 * 0.16) as usize;
+                Stretch::new(1, block, block / 6)
+            }
+            StretchPreset::Default => Stretch::preset_default(1, sample_rate),
+            StretchPreset::Fast => Stretch::preset_cheaper(1, sample_rate),
+            StretchPreset::Custom(t) => {
+                let ms = |v: f32| ((sample_rate as f32 * v / 1000.0) as usize).max(16);
+                Stretch::new(1, ms(t.block_ms), ms(t.interval_ms).min(ms(t.block_ms)))
+            }
+        }).collect()
+    }
+}
+
+const NOTE_NAMES: [&str; 12] = ["C", "C#", "D", "Eb", "E", "F", "F#", "G", "Ab", "A", "Bb", "B"];
+
+// default input device, downmixed to mono; returns the running stream and its sample rate
+fn open_input(mut on_data: impl FnMut(&[f32]) + Send + 'static) -> Result<(cpal::Stream, u32), String> {
+    let device = cpal::default_host().default_input_device().ok_or("no input device")?;
+    let config = device.default_input_config().map_err(|e| e.to_string())?.config();
+    let channels = config.channels as usize;
+    let mut mono = Vec::new();
+    let stream = device.build_input_stream(&config, move |data: &[f32], _| {
+        mono.clear();
+        mono.extend(data.chunks(channels).map(|f| f.iter().sum::<f32>() / channels as f32));
+        on_data(&mono);
+    }, |err| eprintln!("input stream error: {}", err), None).map_err(|e| e.to_string())?;
+    stream.play().map_err(|e| e.to_string())?;
+    Ok((stream, config.sample_rate.0))
+}
+
+// one output channel in the device layout for a file frame read through `frame(channel)`: mono goes to
+// both sides, wider files fold down to stereo (L R C / L R Ls Rs / L R C Ls Rs / L R C LFE Ls Rs ...),
+// a mono device gets the average and speakers past the front pair stay silent
+fn map_channel(frame: impl Fn(usize) -> f32, file_channels: usize, device_channels: usize, out: usize) -> f32 {
+    let side = |s: usize| match file_channels {
+        1 => frame(0),
+        2 => frame(s),
+        n => {
+            let center = if n == 3 || n >= 5 { 0.707 * frame(2) } else { 0.0 };
+            let surround = match n { 3 => n, 4 => 2, 5 => 3, _ => 4 };
+            frame(s) + center + (surround + s..n).step_by(2).map(|k| 0.707 * frame(k)).sum::<f32>()
         }
-        app.start_playback(rx);
-        app
+    };
+    match (device_channels, out) {
+        (1, _) => 0.5 * (side(0) + side(1)),
+        (_, 0 | 1) => side(out),
+        _ => 0.0,
     }
+}
 
-    fn load_audio_file(&mut self, path: PathBuf) {
-        if !path.exists() { return; }
-        let c = self.controls.clone();
-        let s_ptr = self.state.clone();
-        
-        c.is_loading.store(true, Ordering::SeqCst);
-        {
-            let mut s = s_ptr.lock().unwrap();
-            s.file_path = path.to_string_lossy().into_owned();
+// which side of a stereo file is heard, ahead of the stretcher: a part panned hard to one side
+// is easier to pick out soloed, in the middle of the head
+#[derive(Clone, Copy, PartialEq)]
+enum ChannelMatrix {
+    Stereo,
+    Left,
+    Right,
+    Mono,
+}
+
+impl ChannelMatrix {
+    // map_channel with the matrix applied to the front pair
+    fn channel(self, frame: impl Fn(usize) -> f32, file_channels: usize, device_channels: usize, out: usize) -> f32 {
+        if self == ChannelMatrix::Stereo { return map_channel(frame, file_channels, device_channels, out); }
+        if out >= 2 { return 0.0; }
+        let side = |s: usize| map_channel(&frame, file_channels, 2, s);
+        match self {
+            ChannelMatrix::Left => side(0),
+            ChannelMatrix::Right => side(1),
+            _ => 0.5 * (side(0) + side(1)),
         }
+    }
+}
 
-        thread::spawn(move || {
-            let file = match std::fs::File::open(&path) {
-                Ok(f) => f,
-                Err(_) => { c.is_loading.store(false, Ordering::SeqCst); return; }
-            };
-            let mss = MediaSourceStream::new(Box::new(file), Default::default());
-            let mut hint = Hint::new();
-            if let Some(ext) = path.extension() { hint.with_extension(&ext.to_string_lossy()); }
-            
-            let probed = symphonia::default::get_probe().format(&hint, mss, &Default::default(), &Default::default());
-            let mut format = match probed {
-                Ok(p) => p.format,
-                Err(_) => { c.is_loading.store(false, Ordering::SeqCst); return; }
-            };
-            let track = match format.default_track() {
-                Some(t) => t,
-                None => { c.is_loading.store(false, Ordering::SeqCst); return; }
-            };
+// catmull-rom through y[1]..y[2], t in 0..1
+fn cubic(y: [f32; 4], t: f32) -> f32 {
+    let [a, b, c, d] = y;
+    b + 0.5 * t * (c - a + t * (2.0 * a - 5.0 * b + 4.0 * c - d + t * (3.0 * (b - c) + d - a)))
+}
+
+// zero crossings each side of the downsampling kernel, in output frames
+const RESAMPLE_ZEROS: f64 = 4.0;
+
+// the source at fractional frame `pos`, read through `frame`, for a file played back `ratio` source
+// frames per output frame: catmull-rom when upsampling, and a Hann-windowed sinc cut off below the
+// output's Nyquist when downsampling, so what the output can't carry is filtered out instead of folding back
+fn resample_at(frame: impl Fn(usize) -> f32, pos: f64, ratio: f64) -> f32 {
+    let idx = pos as usize;
+    let t = pos - idx as f64;
+    if ratio <= 1.0 {
+        return cubic([frame(idx.saturating_sub(1)), frame(idx), frame(idx + 1), frame(idx + 2)], t as f32);
+    }
+    let cutoff = 0.95 / ratio; // of the source's Nyquist
+    let half = (RESAMPLE_ZEROS * ratio).ceil();
+    let (mut sum, mut norm) = (0.0f64, 0.0f64);
+    for k in (1 - half as isize)..=half as isize {
+        let x = k as f64 - t;
+        let arg = std::f64::consts::PI * cutoff * x;
+        let sinc = if arg.abs() < 1e-9 { 1.0 } else { arg.sin() / arg };
+        let h = sinc * (0.5 + 0.5 * (std::f64::consts::PI * x / half).cos());
+        sum += h * frame((idx as isize + k).max(0) as usize) as f64;
+        norm += h;
+    }
+    (sum / norm) as f32
+}
+
+// YIN fundamental estimate, None for unvoiced/silent frames
+fn yin_pitch(x: &[f32], sample_rate: u32, min_hz: f32, max_hz: f32) -> Option<f32> {
+    let tau_min = (sample_rate as f32 / max_hz) as usize;
+    let tau_max = ((sample_rate as f32 / min_hz) as usize).min(x.len() / 2);
+    if tau_max <= tau_min + 2 { return None; }
+    if x.iter().map(|v| v * v).sum::<f32>() / (x.len() as f32) < 1e-5 { return None; }
+    let w = x.len() - tau_max;
+    let diff: Vec<f32> = (0..=tau_max).map(|tau| (0..w).map(|i| { let d = x[i] - x[i + tau]; d * d }).sum()).collect();
+    // cumulative mean normalised difference
+    let mut cmnd = vec![1.0f32; tau_max + 1];
+    let mut running = 0.0;
+    for tau in 1..=tau_max {
+        running += diff[tau];
+        cmnd[tau] = if running > 0.0 { diff[tau] * tau as f32 / running } else { 1.0 };
+    }
+    let mut tau = (tau_min.max(2)..tau_max).find(|&t| cmnd[t] < 0.15)?;
+    while tau + 1 < tau_max && cmnd[tau + 1] < cmnd[tau] { tau += 1; }
+    // parabolic interpolation around the dip
+    let (a, b, c) = (cmnd[tau - 1], cmnd[tau], cmnd[tau + 1]);
+    let shift = if a + c - 2.0 * b != 0.0 { 0.5 * (a - c) / (a + c - 2.0 * b) } else { 0.0 };
+    Some(sample_rate as f32 / (tau as f32 + shift.clamp(-1.0, 1.0)))
+}
+
+fn freq_to_midi(freq: f32) -> f32 {
+    69.0 + 12.0 * (freq / 440.0).log2()
+}
 
-            let params = track.codec_params.clone();
-            let mut decoder = symphonia::default::get_codecs().make(&params, &Default::default()).unwrap();
-            let mut pcm = Vec::new();
+fn midi_to_freq(note: i32) -> f32 {
+    440.0 * 2f32.powf((note - 69) as f32 / 12.0)
+}
+
+fn note_name(note: i32) -> String {
+    format!("{}{}", NOTE_NAMES[note.rem_euclid(12) as usize], note.div_euclid(12) - 1)
+}
+
+// sine generator mixed on top of the track, runs even while playback is paused
+struct ToneGenerator {
+    enabled: bool,
+    freq: f32,
+    level: f32,
+    gain: f32, // smoothed towards level to avoid clicks when toggling
+    phase: f32,
+}
+
+impl ToneGenerator {
+    fn new() -> Self {
+        Self { enabled: false, freq: 440.0, level: 0.2, gain: 0.0, phase: 0.0 }
+    }
+
+    fn mix(&mut self, data: &mut [f32], channels: usize, sample_rate: u32) {
+        let target = if self.enabled { self.level } else { 0.0 };
+        if target == 0.0 && self.gain < 1e-4 { self.gain = 0.0; return; }
+        let step = self.freq / sample_rate as f32 * std::f32::consts::TAU;
+        for frame in data.chunks_mut(channels.max(1)) {
+            self.gain += (target - self.gain) * 0.002;
+            let v = self.phase.sin() * self.gain;
+            for s in frame { *s += v; }
+            self.phase = (self.phase + step) % std::f32::consts::TAU;
+        }
+    }
+}
+
+const COUNT_IN_BEATS: usize = 4;
+
+// how long a seek takes to fade the old position out, and the new one in
+const SEEK_FADE_MS: f32 = 8.0;
+
+// a quarter-sine ramp over the first `len` frames, from silence up or down to silence, which then holds
+fn seek_ramp(data: &mut [f32], channels: usize, len: usize, fade_in: bool) {
+    for (k, frame) in data.chunks_mut(channels).enumerate() {
+        let t = (k as f32 / len.max(1) as f32).min(1.0);
+        let g = (t * std::f32::consts::FRAC_PI_2).sin();
+        let g = if fade_in { g } else { 1.0 - g };
+        for x in frame { *x *= g; }
+    }
+}
+
+// a short decaying blip `frame` frames after the beat, higher on the first beat of the bar
+fn click(frame: usize, accent: bool, sample_rate: u32) -> f32 {
+    let t = frame as f32 / sample_rate as f32;
+    if t > 0.05 { return 0.0; }
+    let freq = if accent { 1500.0 } else { 1000.0 };
+    (t * freq * std::f32::consts::TAU).sin() * (-t * 90.0).exp() * 0.5
+}
+
+// clicks on the file's beat grid, following the playback position through speed changes and loop wraps
+struct Metronome {
+    enabled: bool,
+    level: f32,
+    accent: bool,
+    beat: f64, // file frames per beat
+    downbeat: f64,
+    last_beat: Option<i64>, // beat the previous output frame fell in, None while stopped
+    since_click: usize,
+    accented: bool,
+}
+
+impl Metronome {
+    fn new() -> Self {
+        Self { enabled: false, level: 0.5, accent: true, beat: 0.0, downbeat: 0.0, last_beat: None, since_click: usize::MAX, accented: false }
+    }
+
+    // pos gives the file frame heard at each output frame, None when the music isn't moving
+    fn mix(&mut self, data: &mut [f32], channels: usize, sample_rate: u32, pos: impl Fn(usize) -> Option<f64>) {
+        let on = self.enabled && self.beat > 0.0;
+        if !on && self.since_click == usize::MAX { return; }
+        for (k, frame) in data.chunks_mut(channels.max(1)).enumerate() {
+            match pos(k).filter(|_| on) {
+                Some(f) => {
+                    let rel = (f - self.downbeat) / self.beat;
+                    let b = rel.floor() as i64;
+                    // a new beat just begun; a loop wrap only clicks when it lands on one
+                    if self.last_beat.is_some_and(|l| l != b) && rel - (b as f64) < 0.25 {
+                        self.since_click = 0;
+                        self.accented = self.accent && b.rem_euclid(4) == 0;
+                    }
+                    self.last_beat = Some(b);
+                }
+                None => self.last_beat = None,
+            }
+            if self.since_click < sample_rate as usize / 20 {
+                let v = click(self.since_click, self.accented, sample_rate) * self.level;
+                for s in frame.iter_mut() { *s += v; }
+                self.since_click += 1;
+            } else {
+                self.since_click = usize::MAX;
+            }
+        }
+    }
+}
+
+// sustained root (+ optional octave and fifth) with a few harmonics so it reads as a drone, not a test tone
+struct DroneGenerator {
+    enabled: bool,
+    root: f32,
+    octave: bool,
+    fifth: bool,
+    level: f32,
+    gain: f32,
+    phases: [f32; 3],
+}
+
+impl DroneGenerator {
+    fn new() -> Self {
+        Self { enabled: false, root: midi_to_freq(48), octave: false, fifth: false, level: 0.2, gain: 0.0, phases: [0.0; 3] }
+    }
+
+    fn mix(&mut self, data: &mut [f32], channels: usize, sample_rate: u32) {
+        let target = if self.enabled { self.level } else { 0.0 };
+        if target == 0.0 && self.gain < 1e-4 { self.gain = 0.0; return; }
+        let tau = std::f32::consts::TAU;
+        let voices = [
+            (self.root, 1.0),
+            (self.root * 2.0, if self.octave { 0.6 } else { 0.0 }),
+            (self.root * 1.5, if self.fifth { 0.6 } else { 0.0 }),
+        ];
+        let norm = 1.0 / voices.iter().map(|v| v.1).sum::<f32>();
+        for frame in data.chunks_mut(channels.max(1)) {
+            self.gain += (target - self.gain) * 0.0005;
+            let mut v = 0.0;
+            for (k, &(freq, amp)) in voices.iter().enumerate() {
+                let p = self.phases[k];
+                v += amp * (p.sin() + 0.3 * (2.0 * p).sin() + 0.15 * (3.0 * p).sin());
+                self.phases[k] = (p + freq / sample_rate as f32 * tau) % tau;
+            }
+            let v = v * norm * self.gain * 0.7;
+            for s in frame { *s += v; }
+        }
+    }
+}
+
+#[derive(Clone, Copy, Default)]
+struct Biquad {
+    b0: f32, b1: f32, b2: f32,
+    a1: f32, a2: f32,
+    z1: f32, z2: f32,
+}
+
+impl Biquad {
+    fn new(b: [f64; 3], a: [f64; 3]) -> Self {
+        Biquad {
+            b0: (b[0] / a[0]) as f32, b1: (b[1] / a[0]) as f32, b2: (b[2] / a[0]) as f32,
+            a1: (a[1] / a[0]) as f32, a2: (a[2] / a[0]) as f32,
+            z1: 0.0, z2: 0.0,
+        }
+    }
+
+    // RBJ cookbook designs
+    fn lowpass(sample_rate: f32, freq: f32, q: f32) -> Self {
+        let w = std::f64::consts::TAU * freq.min(sample_rate * 0.49) as f64 / sample_rate as f64;
+        let alpha = w.sin() / (2.0 * q as f64);
+        let cos = w.cos();
+        Self::new([(1.0 - cos) / 2.0, 1.0 - cos, (1.0 - cos) / 2.0], [1.0 + alpha, -2.0 * cos, 1.0 - alpha])
+    }
+
+    fn highpass(sample_rate: f32, freq: f32, q: f32) -> Self {
+        let w = std::f64::consts::TAU * freq.min(sample_rate * 0.49) as f64 / sample_rate as f64;
+        let alpha = w.sin() / (2.0 * q as f64);
+        let cos = w.cos();
+        Self::new([(1.0 + cos) / 2.0, -(1.0 + cos), (1.0 + cos) / 2.0], [1.0 + alpha, -2.0 * cos, 1.0 - alpha])
+    }
+
+    fn low_shelf(sample_rate: f32, freq: f32, gain_db: f32) -> Self {
+        Self::shelf(sample_rate, freq, gain_db, false)
+    }
+
+    fn high_shelf(sample_rate: f32, freq: f32, gain_db: f32) -> Self {
+        Self::shelf(sample_rate, freq, gain_db, true)
+    }
+
+    fn peaking(sample_rate: f32, freq: f32, q: f32, gain_db: f32) -> Self {
+        let a = 10f64.powf(gain_db as f64 / 40.0);
+        let w = std::f64::consts::TAU * freq.min(sample_rate * 0.49) as f64 / sample_rate as f64;
+        let alpha = w.sin() / (2.0 * q as f64);
+        let cos = w.cos();
+        Self::new([1.0 + alpha * a, -2.0 * cos, 1.0 - alpha * a], [1.0 + alpha / a, -2.0 * cos, 1.0 - alpha / a])
+    }
+
+    // shelf slope S = 1
+    fn shelf(sample_rate: f32, freq: f32, gain_db: f32, high: bool) -> Self {
+        let a = 10f64.powf(gain_db as f64 / 40.0);
+        let w = std::f64::consts::TAU * freq.min(sample_rate * 0.49) as f64 / sample_rate as f64;
+        let (sin, cos) = w.sin_cos();
+        let alpha = sin / 2.0 * 2f64.sqrt();
+        let k = 2.0 * a.sqrt() * alpha;
+        let s = if high { -1.0 } else { 1.0 }; // the high shelf is the low one with the cosine terms flipped
+        Self::new(
+            [a * ((a + 1.0) - s * (a - 1.0) * cos + k), s * 2.0 * a * ((a - 1.0) - s * (a + 1.0) * cos), a * ((a + 1.0) - s * (a - 1.0) * cos - k)],
+            [(a + 1.0) + s * (a - 1.0) * cos + k, -s * 2.0 * ((a - 1.0) + s * (a + 1.0) * cos), (a + 1.0) + s * (a - 1.0) * cos - k],
+        )
+    }
+
+    // swap in new coefficients without resetting the filter state, for clickless parameter changes
+    fn retune(&mut self, to: Biquad) {
+        (self.b0, self.b1, self.b2, self.a1, self.a2) = (to.b0, to.b1, to.b2, to.a1, to.a2);
+    }
+
+    fn process(&mut self, x: f32) -> f32 {
+        let y = self.b0 * x + self.z1;
+        self.z1 = self.b1 * x - self.a1 * y + self.z2;
+        self.z2 = self.b2 * x - self.a2 * y;
+        y
+    }
+}
+
+// sample peak and 300 ms rms of the first two output channels, published by the audio thread;
+// the UI swaps the peaks back to zero as it reads them, so none are missed between frames
+struct LevelMeter {
+    peak: [AtomicU32; 2],
+    rms: [AtomicU32; 2],
+}
 
-            while let Ok(packet) = format.next_packet() {
-                if let Ok(decoded) = decoder.decode(&packet) {
-                    let mut sb = SampleBuffer::<f32>::new(decoded.capacity() as u64, *decoded.spec());
-                    sb.copy_interleaved_ref(decoded);
-                    pcm.extend_from_slice(sb.samples());
+impl LevelMeter {
+    fn new() -> Self {
+        LevelMeter { peak: [AtomicU32::new(0), AtomicU32::new(0)], rms: [AtomicU32::new(0), AtomicU32::new(0)] }
+    }
+
+    // `power` is the audio thread's running mean square per channel
+    fn process(&self, data: &[f32], channels: usize, sample_rate: u32, power: &mut [f32; 2]) {
+        let coeff = 1.0 - (-1.0 / (0.3 * sample_rate as f32)).exp();
+        for (ch, power) in power.iter_mut().enumerate() {
+            let at = ch.min(channels - 1);
+            let mut peak = 0.0f32;
+            for frame in data.chunks_exact(channels) {
+                let x = frame[at];
+                peak = peak.max(x.abs());
+                *power += (x * x - *power) * coeff;
+            }
+            self.peak[ch].fetch_max(peak.to_bits(), Ordering::Relaxed); // positive floats order like their bits
+            self.rms[ch].store(power.sqrt().to_bits(), Ordering::Relaxed);
+        }
+    }
+
+    fn take(&self, ch: usize) -> (f32, f32) {
+        (f32::from_bits(self.peak[ch].swap(0, Ordering::Relaxed)), f32::from_bits(self.rms[ch].load(Ordering::Relaxed)))
+    }
+}
+
+fn gain_to_db(g: f32) -> f32 { 20.0 * g.max(1e-6).log10() }
+
+// the integrated gates work from a histogram of the gating blocks, 0.1 LU per bin from the absolute
+// gate up, so the meter can run for hours on the audio thread without growing
+const LOUDNESS_GATE_LUFS: f32 = -70.0;
+const LOUDNESS_BINS: usize = 800;
+
+// EBU R128 / BS.1770 loudness: K-weighting, 100 ms sub-blocks, gated integration
+struct LoudnessMeter {
+    filters: Vec<[Biquad; 2]>,
+    block_len: usize, // frames per 100 ms
+    block_fill: usize,
+    block_energy: f64,
+    recent: std::collections::VecDeque<f64>, // last 30 sub-blocks (3 s)
+    gating_hist: Vec<(u64, f64)>, // count and summed mean square of the 400 ms blocks (75% overlap) in each bin
+}
+
+impl LoudnessMeter {
+    fn new(channels: usize, sample_rate: u32) -> Self {
+        let fs = sample_rate as f64;
+        // pre-filter (high shelf) and RLB high-pass, coefficients per libebur128 so any rate works
+        let k = (std::f64::consts::PI * 1681.974450955533 / fs).tan();
+        let (vh, q) = (10f64.powf(3.999843853973347 / 20.0), 0.7071752369554196);
+        let vb = vh.powf(0.4996667741545416);
+        let shelf = Biquad::new([vh + vb * k / q + k * k, 2.0 * (k * k - vh), vh - vb * k / q + k * k], [1.0 + k / q + k * k, 2.0 * (k * k - 1.0), 1.0 - k / q + k * k]);
+        let k = (std::f64::consts::PI * 38.13547087602444 / fs).tan();
+        let q = 0.5003270373238773;
+        let highpass = Biquad::new([1.0, -2.0, 1.0], [1.0 + k / q + k * k, 2.0 * (k * k - 1.0), 1.0 - k / q + k * k]);
+        Self {
+            filters: vec![[shelf, highpass]; channels.max(1)],
+            block_len: (sample_rate as usize / 10).max(1),
+            block_fill: 0,
+            block_energy: 0.0,
+            recent: std::collections::VecDeque::with_capacity(31),
+            gating_hist: vec![(0, 0.0); LOUDNESS_BINS],
+        }
+    }
+
+    fn reset(&mut self) {
+        for f in &mut self.filters { for b in f.iter_mut() { b.z1 = 0.0; b.z2 = 0.0; } }
+        self.block_fill = 0;
+        self.block_energy = 0.0;
+        self.recent.clear();
+        self.gating_hist.fill((0, 0.0));
+    }
+
+    fn process(&mut self, data: &[f32]) {
+        let n_ch = self.filters.len();
+        for frame in data.chunks_exact(n_ch) {
+            for (x, f) in frame.iter().zip(self.filters.iter_mut()) {
+                let shelved = f[0].process(*x);
+                let y = f[1].process(shelved);
+                self.block_energy += (y * y) as f64;
+            }
+            self.block_fill += 1;
+            if self.block_fill == self.block_len {
+                self.recent.push_back(self.block_energy / self.block_len as f64);
+                if self.recent.len() > 30 { self.recent.pop_front(); }
+                if self.recent.len() >= 4 {
+                    let n = self.recent.len();
+                    let ms = self.recent.range(n - 4..).sum::<f64>() / 4.0;
+                    let lufs = Self::to_lufs(ms);
+                    if lufs > LOUDNESS_GATE_LUFS {
+                        let bin = (((lufs - LOUDNESS_GATE_LUFS) * 10.0) as usize).min(LOUDNESS_BINS - 1);
+                        self.gating_hist[bin].0 += 1;
+                        self.gating_hist[bin].1 += ms;
+                    }
                 }
+                self.block_fill = 0;
+                self.block_energy = 0.0;
+            }
+        }
+    }
+
+    fn to_lufs(mean_square: f64) -> f32 {
+        (-0.691 + 10.0 * mean_square.log10()) as f32
+    }
+
+    fn short_term(&self) -> f32 {
+        if self.recent.len() < 30 { return f32::NEG_INFINITY; }
+        Self::to_lufs(self.recent.iter().sum::<f64>() / 30.0)
+    }
+
+    // mean loudness of the blocks in the bins from `first` up
+    fn mean_from(&self, first: usize) -> Option<f32> {
+        let (n, sum) = self.gating_hist[first.min(LOUDNESS_BINS)..].iter().fold((0, 0.0), |(n, sum), b| (n + b.0, sum + b.1));
+        (n > 0).then(|| Self::to_lufs(sum / n as f64))
+    }
+
+    fn integrated(&self) -> f32 {
+        let Some(abs_gated) = self.mean_from(0) else { return f32::NEG_INFINITY };
+        // the relative gate to the nearest bin: whole bins above it count
+        let relative_gate = abs_gated - 10.0;
+        let first = ((relative_gate - LOUDNESS_GATE_LUFS) * 10.0).max(0.0).ceil() as usize;
+        self.mean_from(first).unwrap_or(f32::NEG_INFINITY)
+    }
+}
+
+const NR_FFT: usize = 1024;
+const NR_HOP: usize = NR_FFT / 4;
+
+fn hann(n: usize) -> Vec<f32> {
+    (0..n).map(|i| 0.5 - 0.5 * (std::f32::consts::TAU * i as f32 / n as f32).cos()).collect()
+}
+
+// average magnitude spectrum of a (noise-only) region, used as the subtraction profile
+fn noise_profile(pcm: &[f32], channels: usize, start: usize, end: usize) -> Vec<f32> {
+    let channels = channels.max(1);
+    let fft = FftPlanner::new().plan_fft_forward(NR_FFT);
+    let window = hann(NR_FFT);
+    let mut profile = vec![0.0f32; NR_FFT / 2 + 1];
+    let mut frames = 0;
+    let mut buf = vec![Complex::new(0.0f32, 0.0); NR_FFT];
+    let mut frame_start = start - start % channels;
+    while frame_start + NR_FFT * channels <= end.min(pcm.len()) {
+        for ch in 0..channels {
+            for i in 0..NR_FFT {
+                buf[i] = Complex::new(pcm[frame_start + i * channels + ch] * window[i], 0.0);
+            }
+            fft.process(&mut buf);
+            for (p, b) in profile.iter_mut().zip(&buf) { *p += b.norm(); }
+            frames += 1;
+        }
+        frame_start += NR_HOP * channels;
+    }
+    if frames > 0 { profile.iter_mut().for_each(|p| *p /= frames as f32); }
+    profile
+}
+
+struct DenoiseChannel {
+    input: Vec<f32>,
+    output: Vec<f32>,
+    fill: usize,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum EffectKind {
+    Denoise,
+    Leveler,
+    BandSolo,
+    Karaoke,
+    Eq,
+    Plugin(u32), // a loaded CLAP plugin, numbered in load order
+}
+
+impl EffectKind {
+    fn label(self) -> &'static str {
+        match self {
+            EffectKind::Denoise => "Noise reduction",
+            EffectKind::Leveler => "Leveler",
+            EffectKind::BandSolo => "Band solo",
+            EffectKind::Karaoke => "Vocal removal",
+            EffectKind::Eq => "EQ",
+            EffectKind::Plugin(_) => "Plugin",
+        }
+    }
+}
+
+// a stage of the post-stretch chain; each picks the ParamUpdates meant for it out of the stream
+trait AudioEffect: Send {
+    fn kind(&self) -> EffectKind;
+    fn update(&mut self, _update: &ParamUpdate) {}
+    // called on the audio thread as the effect leaves the chain, before it's handed back for dropping
+    fn retire(&mut self) {}
+    fn process(&mut self, data: &mut [f32], channels: usize, sample_rate: u32);
+}
+
+// ordered, individually bypassable effects between the stretcher and the output
+struct EffectsChain {
+    effects: Vec<(Box<dyn AudioEffect>, bool)>, // (effect, bypassed)
+}
+
+impl EffectsChain {
+    fn new(effects: Vec<Box<dyn AudioEffect>>) -> Self {
+        EffectsChain { effects: effects.into_iter().map(|fx| (fx, false)).collect() }
+    }
+
+    fn update(&mut self, update: &ParamUpdate) {
+        if let ParamUpdate::ChainLayout(layout) = update {
+            // reorder to match the UI, effects it doesn't mention keep their place at the end
+            self.effects.sort_by_key(|(fx, _)| layout.iter().position(|l| l.0 == fx.kind()).unwrap_or(usize::MAX));
+            for (fx, bypassed) in &mut self.effects {
+                if let Some(l) = layout.iter().find(|l| l.0 == fx.kind()) { *bypassed = l.1; }
             }
+            return;
+        }
+        for (fx, _) in &mut self.effects { fx.update(update); }
+    }
+
+    fn remove(&mut self, kind: EffectKind) -> Option<Box<dyn AudioEffect>> {
+        let i = self.effects.iter().position(|(fx, _)| fx.kind() == kind)?;
+        Some(self.effects.remove(i).0)
+    }
+
+    fn process(&mut self, data: &mut [f32], channels: usize, sample_rate: u32) {
+        for (fx, bypassed) in &mut self.effects {
+            if !*bypassed { fx.process(data, channels, sample_rate); }
+        }
+    }
+}
+
+// equal-loudness compensation: the ear loses bass (and some treble) faster than mids as the
+// level drops, so boost the ends in proportion to how far the volume is below unity (ISO 226 slopes, roughly)
+struct LoudnessCompensation {
+    enabled: bool,
+    filters: Vec<[Biquad; 2]>,
+    applied_db: f32, // volume attenuation the filters are currently tuned for
+}
+
+impl LoudnessCompensation {
+    fn new(channels: usize) -> Self {
+        LoudnessCompensation { enabled: false, filters: (0..channels).map(|_| [Biquad::new([1.0, 0.0, 0.0], [1.0, 0.0, 0.0]), Biquad::new([1.0, 0.0, 0.0], [1.0, 0.0, 0.0])]).collect(), applied_db: 0.0 }
+    }
+
+    fn process(&mut self, data: &mut [f32], sample_rate: u32, volume: f32) {
+        if !self.enabled { return; }
+        let atten = (-20.0 * volume.max(1e-3).log10()).clamp(0.0, 40.0);
+        if (atten - self.applied_db).abs() > 0.25 {
+            self.applied_db = atten;
+            let fs = sample_rate as f32;
+            for f in &mut self.filters {
+                f[0].retune(Biquad::low_shelf(fs, 150.0, atten * 0.35));
+                f[1].retune(Biquad::high_shelf(fs, 8000.0, atten * 0.1));
+            }
+        }
+        if self.applied_db == 0.0 { return; }
+        let channels = self.filters.len();
+        for frame in data.chunks_mut(channels) {
+            for (x, f) in frame.iter_mut().zip(self.filters.iter_mut()) {
+                let low = f[0].process(*x);
+                *x = f[1].process(low);
+            }
+        }
+    }
+}
+
+// the last thing before the device, off unless asked for: anything under -1 dBFS passes untouched,
+// louder peaks bend into the last dB below full scale, so a boosted volume or a pitch shift's extra
+// peaks don't hard-clip. Instant attack on the linked peak of all channels, 100 ms release
+struct SoftLimiter {
+    enabled: bool,
+    env: f32,
+}
+
+impl SoftLimiter {
+    const CEILING_DB: f32 = -1.0; // where the knee starts; output approaches 0 dBFS above it
+
+    fn new() -> Self {
+        SoftLimiter { enabled: false, env: 0.0 }
+    }
+
+    // returns the deepest gain reduction of the block in dB (0 or negative)
+    fn process(&mut self, data: &mut [f32], channels: usize, sample_rate: u32) -> f32 {
+        if !self.enabled { return 0.0; }
+        let release = (-1.0 / (0.1 * sample_rate as f32)).exp();
+        let head = -Self::CEILING_DB;
+        let mut deepest = 0.0f32;
+        for frame in data.chunks_mut(channels) {
+            let peak = frame.iter().fold(0.0f32, |m, x| m.max(x.abs()));
+            self.env = peak.max(self.env * release);
+            let level = gain_to_db(self.env);
+            if level <= Self::CEILING_DB { continue; }
+            let out = Self::CEILING_DB + head * (1.0 - (-(level - Self::CEILING_DB) / head).exp());
+            let reduction = out - level;
+            deepest = deepest.min(reduction);
+            let g = 10f32.powf(reduction / 20.0);
+            for x in frame { *x *= g; }
+        }
+        deepest
+    }
+}
+
+// slow automatic gain control: follows the programme level and pulls it toward a target,
+// so a quiet interview and a loud band in the same file come out at similar levels
+struct Leveler {
+    enabled: bool,
+    target_db: f32,
+    attack_ms: f32, // how fast gain comes down when it gets loud
+    release_ms: f32, // how fast gain comes back up when it gets quiet
+    max_gain_db: f32,
+    power: f32, // smoothed mean square
+    gain_db: f32,
+}
+
+impl Leveler {
+    fn new() -> Self {
+        Leveler { enabled: false, target_db: -18.0, attack_ms: 300.0, release_ms: 3000.0, max_gain_db: 18.0, power: 0.0, gain_db: 0.0 }
+    }
+}
+
+impl AudioEffect for Leveler {
+    fn kind(&self) -> EffectKind { EffectKind::Leveler }
+
+    fn update(&mut self, update: &ParamUpdate) {
+        if let ParamUpdate::Leveler { enabled, target_db, attack_ms, release_ms, max_gain_db } = *update {
+            self.enabled = enabled;
+            self.target_db = target_db;
+            self.attack_ms = attack_ms;
+            self.release_ms = release_ms;
+            self.max_gain_db = max_gain_db;
+        }
+    }
+
+    fn process(&mut self, data: &mut [f32], channels: usize, sample_rate: u32) {
+        if !self.enabled { return; }
+        let coef = |ms: f32| 1.0 - (-1000.0 / (ms * sample_rate as f32)).exp();
+        let (window, attack, release) = (coef(400.0), coef(self.attack_ms), coef(self.release_ms));
+        for frame in data.chunks_mut(channels.max(1)) {
+            let ms = frame.iter().map(|x| x * x).sum::<f32>() / frame.len() as f32;
+            self.power += (ms - self.power) * window;
+            let level_db = 10.0 * self.power.max(1e-12).log10();
+            // below the gate it's a pause or noise floor: hold the gain rather than pumping it up
+            if level_db > -50.0 {
+                let wanted = (self.target_db - level_db).clamp(-24.0, self.max_gain_db);
+                self.gain_db += (wanted - self.gain_db) * if wanted < self.gain_db { attack } else { release };
+            }
+            let g = 10f32.powf(self.gain_db / 20.0);
+            for x in frame.iter_mut() { *x *= g; }
+        }
+    }
+}
+
+// Q of each biquad in an 8th-order Butterworth cascade
+const BUTTERWORTH_Q8: [f32; 4] = [0.5098, 0.6013, 0.9000, 2.5629];
+
+// plays only the frequencies between two edges, 48 dB/octave on each side, to pick out
+// a hi-hat pattern or a buried line
+struct BandSolo {
+    enabled: bool,
+    low: f32,
+    high: f32,
+    tuned: (f32, f32, u32), // edges and rate the filters are set for
+    filters: Vec<[Biquad; 8]>, // per channel: four high-pass then four low-pass sections
+}
+
+impl BandSolo {
+    fn new(channels: usize) -> Self {
+        BandSolo { enabled: false, low: 200.0, high: 2000.0, tuned: (0.0, 0.0, 0), filters: vec![[Biquad::default(); 8]; channels] }
+    }
+}
+
+impl AudioEffect for BandSolo {
+    fn kind(&self) -> EffectKind { EffectKind::BandSolo }
+
+    fn update(&mut self, update: &ParamUpdate) {
+        if let ParamUpdate::BandSolo { enabled, low, high } = *update {
+            // start from silence rather than whatever the filters last held
+            if enabled && !self.enabled { self.filters.iter_mut().for_each(|f| *f = [Biquad::default(); 8]); self.tuned.2 = 0; }
+            self.enabled = enabled;
+            self.low = low.min(high);
+            self.high = high.max(low);
+        }
+    }
+
+    fn process(&mut self, data: &mut [f32], channels: usize, sample_rate: u32) {
+        if !self.enabled { return; }
+        if self.tuned != (self.low, self.high, sample_rate) {
+            self.tuned = (self.low, self.high, sample_rate);
+            let fs = sample_rate as f32;
+            for f in &mut self.filters {
+                for (k, q) in BUTTERWORTH_Q8.into_iter().enumerate() {
+                    f[k].retune(Biquad::highpass(fs, self.low, q));
+                    f[k + 4].retune(Biquad::lowpass(fs, self.high, q));
+                }
+            }
+        }
+        for frame in data.chunks_mut(channels.max(1)) {
+            for (x, f) in frame.iter_mut().zip(self.filters.iter_mut()) {
+                *x = f.iter_mut().fold(*x, |v, b| b.process(v));
+            }
+        }
+    }
+}
+
+// the EQ's knobs; the cut filters are off at the ends of their ranges
+#[derive(Clone, Copy, PartialEq)]
+struct EqSettings {
+    enabled: bool,
+    low_db: f32, // shelf at 120 Hz
+    mid_db: f32,
+    mid_hz: f32,
+    high_db: f32, // shelf at 6 kHz
+    low_cut: f32, // Hz, EQ_CUT_OFF.0 is off
+    high_cut: f32, // Hz, EQ_CUT_OFF.1 is off
+}
+
+const EQ_CUT_OFF: (f32, f32) = (20.0, 20000.0);
+
+impl EqSettings {
+    const FLAT: EqSettings = EqSettings { enabled: false, low_db: 0.0, mid_db: 0.0, mid_hz: 1000.0, high_db: 0.0, low_cut: EQ_CUT_OFF.0, high_cut: EQ_CUT_OFF.1 };
+}
+
+// low shelf, mid peak and high shelf, then 12 dB/octave high-pass and low-pass cuts
+struct Equalizer {
+    settings: EqSettings,
+    tuned: Option<(EqSettings, u32)>, // what the filters are set for
+    filters: Vec<[Biquad; 5]>, // per channel
+}
+
+impl Equalizer {
+    fn new(channels: usize) -> Self {
+        Equalizer { settings: EqSettings::FLAT, tuned: None, filters: vec![[Biquad::default(); 5]; channels] }
+    }
+}
+
+impl AudioEffect for Equalizer {
+    fn kind(&self) -> EffectKind { EffectKind::Eq }
+
+    fn update(&mut self, update: &ParamUpdate) {
+        if let ParamUpdate::Eq(settings) = *update {
+            if settings.enabled && !self.settings.enabled { self.filters.iter_mut().for_each(|f| *f = [Biquad::default(); 5]); self.tuned = None; }
+            self.settings = settings;
+        }
+    }
+
+    fn process(&mut self, data: &mut [f32], channels: usize, sample_rate: u32) {
+        let e = self.settings;
+        if !e.enabled { return; }
+        if self.tuned != Some((e, sample_rate)) {
+            self.tuned = Some((e, sample_rate));
+            let fs = sample_rate as f32;
+            for f in &mut self.filters {
+                f[0].retune(Biquad::low_shelf(fs, 120.0, e.low_db));
+                f[1].retune(Biquad::peaking(fs, e.mid_hz, 0.9, e.mid_db));
+                f[2].retune(Biquad::high_shelf(fs, 6000.0, e.high_db));
+                f[3].retune(Biquad::highpass(fs, e.low_cut, 0.707));
+                f[4].retune(Biquad::lowpass(fs, e.high_cut, 0.707));
+            }
+        }
+        // flat bands and cuts at their stops are skipped, so the EQ is transparent until touched
+        let active = [e.low_db != 0.0, e.mid_db != 0.0, e.high_db != 0.0, e.low_cut > EQ_CUT_OFF.0, e.high_cut < EQ_CUT_OFF.1];
+        for frame in data.chunks_mut(channels.max(1)) {
+            for (x, f) in frame.iter_mut().zip(self.filters.iter_mut()) {
+                for (b, on) in f.iter_mut().zip(active) {
+                    if on { *x = b.process(*x); }
+                }
+            }
+        }
+    }
+}
+
+// centre-channel removal: whatever is identical in both channels (usually the lead vocal) is taken
+// out of the mid, leaving the side. Keeping the bass only removes the mid above ~150 Hz, since bass
+// and kick usually sit in the centre too
+struct Karaoke {
+    enabled: bool,
+    amount: f32, // 0..1
+    keep_bass: bool,
+    tuned: u32, // rate the low-pass is set for
+    lowpass: [Biquad; 2], // the bass that Keep bass puts back
+}
+
+impl Karaoke {
+    fn new() -> Self {
+        Karaoke { enabled: false, amount: 1.0, keep_bass: true, tuned: 0, lowpass: [Biquad::default(); 2] }
+    }
+}
+
+impl AudioEffect for Karaoke {
+    fn kind(&self) -> EffectKind { EffectKind::Karaoke }
+
+    fn update(&mut self, update: &ParamUpdate) {
+        if let ParamUpdate::Karaoke { enabled, amount, keep_bass } = *update {
+            if enabled && !self.enabled { self.lowpass = [Biquad::default(); 2]; self.tuned = 0; }
+            self.enabled = enabled;
+            self.amount = amount.clamp(0.0, 1.0);
+            self.keep_bass = keep_bass;
+        }
+    }
+
+    fn process(&mut self, data: &mut [f32], channels: usize, sample_rate: u32) {
+        if !self.enabled || channels < 2 { return; }
+        if self.tuned != sample_rate {
+            self.tuned = sample_rate;
+            for (k, q) in [0.5412f32, 1.3066].into_iter().enumerate() { self.lowpass[k].retune(Biquad::lowpass(sample_rate as f32, 150.0, q)); }
+        }
+        for frame in data.chunks_mut(channels) {
+            let mid = 0.5 * (frame[0] + frame[1]);
+            let side = 0.5 * (frame[0] - frame[1]);
+            // the centre fades out and only its low-passed bass comes back in: taking a high-passed copy
+            // away instead isn't complementary and would boost the centre around the corner
+            let kept = if self.keep_bass { self.lowpass.iter_mut().fold(mid, |v, b| b.process(v)) } else { 0.0 };
+            let mid = mid * (1.0 - self.amount) + kept * self.amount;
+            frame[0] = mid + side;
+            frame[1] = mid - side;
+        }
+    }
+}
+
+// streaming spectral subtraction (STFT, 75% overlap, Hann analysis and synthesis windows)
+struct SpectralDenoiser {
+    enabled: bool,
+    strength: f32,
+    profile: Arc<Vec<f32>>, // swapped, never copied, on the audio thread
+    fft: Arc<dyn Fft<f32>>,
+    ifft: Arc<dyn Fft<f32>>,
+    window: Vec<f32>,
+    spectrum: Vec<Complex<f32>>,
+    channels: Vec<DenoiseChannel>,
+}
+
+impl SpectralDenoiser {
+    fn new(channels: usize) -> Self {
+        let mut planner = FftPlanner::new();
+        Self {
+            enabled: false,
+            strength: 1.5,
+            profile: Arc::new(Vec::new()),
+            fft: planner.plan_fft_forward(NR_FFT),
+            ifft: planner.plan_fft_inverse(NR_FFT),
+            window: hann(NR_FFT),
+            spectrum: vec![Complex::new(0.0, 0.0); NR_FFT],
+            channels: (0..channels).map(|_| DenoiseChannel { input: vec![0.0; NR_FFT], output: vec![0.0; NR_FFT], fill: 0 }).collect(),
+        }
+    }
+
+    fn reset(&mut self) {
+        for ch in &mut self.channels {
+            ch.input.fill(0.0);
+            ch.output.fill(0.0);
+            ch.fill = 0;
+        }
+    }
+}
+
+impl AudioEffect for SpectralDenoiser {
+    fn kind(&self) -> EffectKind { EffectKind::Denoise }
+
+    fn update(&mut self, update: &ParamUpdate) {
+        match update {
+            ParamUpdate::Denoise { enabled, strength } => {
+                if *enabled && !self.enabled { self.reset(); }
+                self.enabled = *enabled;
+                self.strength = *strength;
+            }
+            ParamUpdate::NoiseProfile(profile) => self.profile = Arc::clone(profile),
+            _ => {}
+        }
+    }
+
+    fn process(&mut self, data: &mut [f32], _channels: usize, _sample_rate: u32) {
+        if !self.enabled || self.profile.len() != NR_FFT / 2 + 1 { return; }
+        let n_ch = self.channels.len();
+        // hann^2 at 75% overlap sums to 1.5, and rustfft doesn't normalize
+        let scale = 1.0 / (NR_FFT as f32 * 1.5);
+        for (i, sample) in data.iter_mut().enumerate() {
+            let ch = &mut self.channels[i % n_ch];
+            let x = *sample;
+            *sample = ch.output[ch.fill];
+            ch.input[NR_FFT - NR_HOP + ch.fill] = x;
+            ch.fill += 1;
+            if ch.fill < NR_HOP { continue; }
+            ch.fill = 0;
+
+            for (k, s) in self.spectrum.iter_mut().enumerate() {
+                *s = Complex::new(ch.input[k] * self.window[k], 0.0);
+            }
+            self.fft.process(&mut self.spectrum);
+            for k in 0..=NR_FFT / 2 {
+                let mag = self.spectrum[k].norm().max(1e-9);
+                let gain = ((mag - self.strength * self.profile[k]) / mag).max(0.05);
+                self.spectrum[k] *= gain;
+                if k > 0 && k < NR_FFT / 2 { self.spectrum[NR_FFT - k] *= gain; }
+            }
+            self.ifft.process(&mut self.spectrum);
+
+            ch.output.copy_within(NR_HOP.., 0);
+            ch.output[NR_FFT - NR_HOP..].fill(0.0);
+            for k in 0..NR_FFT {
+                ch.output[k] += self.spectrum[k].re * self.window[k] * scale;
+            }
+            ch.input.copy_within(NR_HOP.., 0);
+        }
+    }
+}
+
+struct DecodedAudio {
+    pcm: Vec<f32>, // interleaved
+    sample_rate: u32,
+    channels: usize,
+}
+
+// everything a key binding, button or macro can trigger; carried out by PlayerApp::perform
+#[derive(Clone, Copy, PartialEq)]
+enum Action {
+    TogglePlay,
+    Play,
+    Pause,
+    Quit,
+    ResetSpeedPitch,
+    ClearLoop,
+    SwapDecks,
+    NextPhrase,
+    PrevPhrase,
+    LoopPhrase,
+    RestartLoop,
+    BeatJumpBack,
+    BeatJumpForward,
+    LoopStartAtCursor,
+    LoopEndAtCursor,
+    ShiftLoopBack,
+    ShiftLoopForward,
+    UndoEdit,
+    SeekBack,
+    SeekForward,
+    NextTrack,
+    PrevTrack,
+    ToggleSpectrogram,
+    AddMarker,
+    NextMarker,
+    PrevMarker,
+    NudgeBack,
+    NudgeForward,
+    ToggleMetronome,
+    Nudge(f32), // seconds, negative for back
+    SetSpeed(f32),
+    SetPitch(f32),
+}
+
+impl Action {
+    // the actions without a value, by the name they're saved under
+    const SIMPLE: [(&'static str, Action); 29] = [
+        ("play-pause", Action::TogglePlay), ("play", Action::Play), ("pause", Action::Pause), ("quit", Action::Quit),
+        ("reset-speed-pitch", Action::ResetSpeedPitch), ("clear-loop", Action::ClearLoop), ("swap-decks", Action::SwapDecks),
+        ("next-phrase", Action::NextPhrase), ("previous-phrase", Action::PrevPhrase), ("loop-phrase", Action::LoopPhrase),
+        ("restart-loop", Action::RestartLoop), ("beat-jump-back", Action::BeatJumpBack), ("beat-jump-forward", Action::BeatJumpForward),
+        ("loop-start-here", Action::LoopStartAtCursor), ("loop-end-here", Action::LoopEndAtCursor),
+        ("shift-loop-back", Action::ShiftLoopBack), ("shift-loop-forward", Action::ShiftLoopForward),
+        ("undo-edit", Action::UndoEdit), ("seek-back", Action::SeekBack), ("seek-forward", Action::SeekForward),
+        ("next-track", Action::NextTrack), ("previous-track", Action::PrevTrack), ("toggle-spectrogram", Action::ToggleSpectrogram),
+        ("add-marker", Action::AddMarker), ("next-marker", Action::NextMarker), ("previous-marker", Action::PrevMarker),
+        ("nudge-back", Action::NudgeBack), ("nudge-forward", Action::NudgeForward), ("toggle-metronome", Action::ToggleMetronome),
+    ];
+
+    fn name(self) -> String {
+        match self {
+            Action::SetSpeed(v) => format!("speed {:.0}%", v * 100.0),
+            Action::SetPitch(v) => format!("pitch {:+.2} st", 12.0 * v.log2()),
+            Action::Nudge(v) => format!("nudge {:+} s", v),
+            _ => Self::SIMPLE.iter().find(|a| a.1 == self).map_or("?", |a| a.0).to_string(),
+        }
+    }
+
+    fn parse(text: &str) -> Option<Self> {
+        let text = text.trim();
+        if let Some(v) = text.strip_prefix("speed ").and_then(|v| v.trim_end_matches('%').trim().parse::<f32>().ok()) {
+            return Some(Action::SetSpeed((v / 100.0).clamp(0.1, 8.0)));
+        }
+        if let Some(v) = text.strip_prefix("pitch ").and_then(|v| v.trim_end_matches("st").trim().parse::<f32>().ok()) {
+            return Some(Action::SetPitch(2f32.powf(v / 12.0).clamp(0.5, 2.0)));
+        }
+        if let Some(v) = text.strip_prefix("nudge ").and_then(|v| v.trim_end_matches('s').trim().parse::<f32>().ok()) {
+            return Some(Action::Nudge(v));
+        }
+        Self::SIMPLE.iter().find(|a| a.0 == text).map(|a| a.1)
+    }
+}
+
+fn default_keymap() -> Vec<(egui::Modifiers, egui::Key, Action)> {
+    use egui::{Key, Modifiers};
+    vec![
+        (Modifiers::NONE, Key::Space, Action::TogglePlay),
+        (Modifiers::NONE, Key::Q, Action::Quit),
+        (Modifiers::NONE, Key::Escape, Action::Quit),
+        (Modifiers::NONE, Key::R, Action::ResetSpeedPitch),
+        (Modifiers::NONE, Key::C, Action::ClearLoop),
+        (Modifiers::NONE, Key::B, Action::SwapDecks),
+        // before plain N and P, which would otherwise match with shift held too
+        (Modifiers::SHIFT, Key::N, Action::NextTrack),
+        (Modifiers::SHIFT, Key::P, Action::PrevTrack),
+        (Modifiers::NONE, Key::N, Action::NextPhrase),
+        (Modifiers::NONE, Key::P, Action::PrevPhrase),
+        (Modifiers::NONE, Key::L, Action::LoopPhrase),
+        (Modifiers::NONE, Key::S, Action::ToggleSpectrogram),
+        (Modifiers::NONE, Key::T, Action::ToggleMetronome),
+        (Modifiers::NONE, Key::M, Action::AddMarker),
+        (Modifiers::NONE, Key::J, Action::PrevMarker),
+        (Modifiers::NONE, Key::K, Action::NextMarker),
+        (Modifiers::NONE, Key::Comma, Action::BeatJumpBack),
+        (Modifiers::NONE, Key::Period, Action::BeatJumpForward),
+        (Modifiers::NONE, Key::OpenBracket, Action::LoopStartAtCursor),
+        (Modifiers::NONE, Key::CloseBracket, Action::LoopEndAtCursor),
+        (Modifiers::COMMAND, Key::Z, Action::UndoEdit),
+        (Modifiers::COMMAND, Key::Num1, Action::SetSpeed(0.5)),
+        (Modifiers::COMMAND, Key::Num2, Action::SetSpeed(0.66)),
+        (Modifiers::COMMAND, Key::Num3, Action::SetSpeed(0.75)),
+        (Modifiers::COMMAND, Key::Num4, Action::SetSpeed(1.0)),
+        (Modifiers::COMMAND, Key::ArrowLeft, Action::ShiftLoopBack),
+        (Modifiers::COMMAND, Key::ArrowRight, Action::ShiftLoopForward),
+        // jump history before the plain alt nudges, which would otherwise match with shift held too
+        (Modifiers::ALT | Modifiers::SHIFT, Key::ArrowLeft, Action::SeekBack),
+        (Modifiers::ALT | Modifiers::SHIFT, Key::ArrowRight, Action::SeekForward),
+        (Modifiers::ALT, Key::ArrowLeft, Action::Nudge(-0.05)),
+        (Modifiers::ALT, Key::ArrowRight, Action::Nudge(0.05)),
+        (Modifiers::SHIFT, Key::ArrowLeft, Action::Nudge(-1.0)),
+        (Modifiers::SHIFT, Key::ArrowRight, Action::Nudge(1.0)),
+        (Modifiers::NONE, Key::ArrowLeft, Action::NudgeBack),
+        (Modifiers::NONE, Key::ArrowRight, Action::NudgeForward),
+    ]
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Panel {
+    Phrases,
+    Dictation,
+    Stretcher,
+    Automation,
+    Ab,
+    Macros,
+    Edit,
+    Take,
+    Monitor,
+    SingAlong,
+    Suggestions,
+    Tempo,
+    Meter,
+    SecondOutput,
+    Cue,
+    Stems,
+    Denoise,
+    Leveler,
+    BandSolo,
+    Karaoke,
+    Eq,
+    Effects,
+    Tone,
+    Drone,
+    Setlist,
+    Playlist,
+    Podcasts,
+    Subtitles,
+    System,
+    Sync,
+    Trainer,
+    Extensions,
+}
+
+// a tuned setup for one kind of work: which panels show and in what order, the speed slider's
+// range, and keys bound on top of the default keymap
+struct Profile {
+    name: &'static str,
+    speed_range: (f32, f32),
+    panels: &'static [Panel],
+    keys: &'static [(egui::Key, Action)],
+}
+
+const PROFILES: [Profile; 4] = [
+    Profile {
+        name: "Everything",
+        speed_range: (0.1, 8.0),
+        panels: &[Panel::Phrases, Panel::Dictation, Panel::Trainer, Panel::Stretcher, Panel::Automation, Panel::Ab, Panel::Macros, Panel::Edit,
+                  Panel::Take, Panel::Monitor, Panel::SingAlong, Panel::Suggestions, Panel::Tempo, Panel::Meter, Panel::SecondOutput,
+                  Panel::Cue, Panel::Stems, Panel::Denoise, Panel::Leveler, Panel::BandSolo, Panel::Eq, Panel::Karaoke, Panel::Effects, Panel::Tone,
+                  Panel::Drone,
+                  Panel::Setlist, Panel::Playlist, Panel::Podcasts, Panel::Subtitles, Panel::Sync, Panel::System, Panel::Extensions],
+        keys: &[],
+    },
+    Profile {
+        name: "Transcription",
+        speed_range: (0.25, 1.0),
+        panels: &[Panel::Trainer, Panel::Suggestions, Panel::Tempo, Panel::Stems, Panel::BandSolo, Panel::Eq, Panel::Karaoke, Panel::Tone, Panel::Drone, Panel::SingAlong, Panel::Automation,
+                  Panel::Stretcher, Panel::Denoise, Panel::Effects, Panel::Edit, Panel::Macros, Panel::Sync, Panel::Extensions],
+        keys: &[(egui::Key::ArrowDown, Action::RestartLoop), (egui::Key::ArrowUp, Action::LoopPhrase)],
+    },
+    Profile {
+        name: "Language study",
+        speed_range: (0.5, 1.25),
+        panels: &[Panel::Phrases, Panel::Dictation, Panel::Subtitles, Panel::Take, Panel::Suggestions, Panel::Playlist, Panel::Podcasts,
+                  Panel::Macros, Panel::Sync, Panel::Extensions],
+        keys: &[(egui::Key::ArrowLeft, Action::PrevPhrase), (egui::Key::ArrowRight, Action::NextPhrase),
+                (egui::Key::ArrowDown, Action::RestartLoop), (egui::Key::Enter, Action::LoopPhrase)],
+    },
+    Profile {
+        name: "DJ prep",
+        speed_range: (0.8, 1.2),
+        panels: &[Panel::Tempo, Panel::Ab, Panel::Cue, Panel::SecondOutput, Panel::Meter, Panel::Suggestions, Panel::Leveler, Panel::Eq,
+                  Panel::Effects, Panel::Setlist, Panel::Playlist, Panel::Macros, Panel::Extensions],
+        keys: &[(egui::Key::ArrowLeft, Action::BeatJumpBack), (egui::Key::ArrowRight, Action::BeatJumpForward)],
+    },
+];
+
+impl Profile {
+    fn keymap(&self) -> Vec<(egui::Modifiers, egui::Key, Action)> {
+        // the profile's keys come first so they win over the defaults
+        self.keys.iter().map(|&(k, a)| (egui::Modifiers::NONE, k, a)).chain(default_keymap()).collect()
+    }
+}
+
+// a named, recorded sequence of actions, optionally on a function key
+struct Macro {
+    name: String,
+    key: Option<egui::Key>,
+    actions: Vec<Action>,
+}
+
+struct Macros {
+    list: Vec<Macro>,
+    recording: Option<Vec<Action>>,
+    new_name: String,
+    new_key: Option<egui::Key>,
+}
+
+// one macro per line: name, key (or -), then the actions separated by ';', tab separated
+fn parse_macros(text: &str) -> Vec<Macro> {
+    text.lines().filter_map(|line| {
+        let mut f = line.splitn(3, '\t');
+        let name = f.next()?.to_string();
+        let key = egui::Key::from_name(f.next()?.trim());
+        let actions = f.next()?.split(';').filter_map(Action::parse).collect();
+        Some(Macro { name, key, actions })
+    }).collect()
+}
+
+fn format_macros(list: &[Macro]) -> String {
+    list.iter().map(|m| {
+        let actions: Vec<String> = m.actions.iter().map(|a| a.name()).collect();
+        format!("{}\t{}\t{}\n", m.name.replace('\t', " "), m.key.map_or("-", |k| k.name()), actions.join("; "))
+    }).collect()
+}
+
+// cursor positions jumped away from, stepped through like browser back/forward
+#[derive(Default)]
+struct SeekHistory {
+    back: Vec<usize>,
+    forward: Vec<usize>,
+}
+
+impl SeekHistory {
+    // moves the cursor and remembers where it was
+    fn jump(&mut self, c: &AudioControls, pos: usize) {
+        let from = c.cursor.load(Ordering::Relaxed);
+        if from != pos && self.back.last() != Some(&from) {
+            self.back.push(from);
+            if self.back.len() > 100 { self.back.remove(0); }
+        }
+        self.forward.clear();
+        c.cursor.store(pos, Ordering::Relaxed);
+    }
+
+    fn step(&mut self, c: &AudioControls, forward: bool) {
+        let (from, to) = if forward { (&mut self.forward, &mut self.back) } else { (&mut self.back, &mut self.forward) };
+        let Some(pos) = from.pop() else { return };
+        to.push(c.cursor.load(Ordering::Relaxed));
+        c.cursor.store(pos, Ordering::Relaxed);
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum RegionEdit {
+    Delete,
+    Silence,
+    FadeIn,
+    FadeOut,
+}
+
+// undoing puts `removed` back where `inserted` samples now sit at `at`
+struct EditUndo {
+    at: usize,
+    removed: Vec<f32>,
+    inserted: usize,
+}
+
+static WAVEFORM_GEN: AtomicU64 = AtomicU64::new(0);
+
+// the waveform as an image, one column per bucket, uploaded once and stretched over the
+// waveform area instead of painting a line per bucket every frame: the min/max envelope in
+// a darker shade with the rms level brighter inside it
+fn waveform_image(buckets: &[WaveBucket], colors: &[egui::Color32]) -> egui::ColorImage {
+    const HEIGHT: usize = 128;
+    let wave_color = egui::Color32::from_rgb(0, 180, 100);
+    let mut image = egui::ColorImage::new([buckets.len(), HEIGHT], egui::Color32::TRANSPARENT);
+    let (mid, scale) = (HEIGHT as f32 / 2.0, HEIGHT as f32 * 0.45);
+    // rows covering hi..lo, at least one
+    let rows = |hi: f32, lo: f32| {
+        let top = ((mid - hi * scale).floor().max(0.0) as usize).min(HEIGHT - 1);
+        top..((mid - lo * scale).ceil() as usize).clamp(top + 1, HEIGHT)
+    };
+    let shade = |c: egui::Color32, to: f32, t: f32| {
+        let [r, g, b, _] = c.to_array().map(|v| (v as f32 + (to - v as f32) * t) as u8);
+        egui::Color32::from_rgb(r, g, b)
+    };
+    for (x, b) in buckets.iter().enumerate() {
+        let color = colors.get(x).copied().unwrap_or(wave_color);
+        let outer = shade(color, 0.0, 0.45);
+        for y in rows(b.max, b.min) { image.pixels[y * buckets.len() + x] = outer; }
+        let rms = b.power.sqrt();
+        let inner = shade(color, 255.0, 0.3);
+        for y in rows(rms.min(b.max.max(0.0)), (-rms).max(b.min.min(0.0))) { image.pixels[y * buckets.len() + x] = inner; }
+    }
+    image
+}
+
+// generation, spectral colors, sample range and width of the drawn waveform image
+type ZoomKey = (u64, bool, usize, usize, usize);
+
+#[derive(Clone, Copy)]
+struct WaveBucket {
+    min: f32,
+    max: f32,
+    power: f32, // mean square
+}
+
+impl WaveBucket {
+    const SILENT: WaveBucket = WaveBucket { min: 0.0, max: 0.0, power: 0.0 };
+
+    fn of<'a>(samples: impl IntoIterator<Item = &'a f32>) -> WaveBucket {
+        let (min, max, sum, n) = samples.into_iter().fold((f32::MAX, f32::MIN, 0.0f32, 0usize), |(lo, hi, sq, n), &x| (lo.min(x), hi.max(x), sq + x * x, n + 1));
+        if n == 0 { return WaveBucket::SILENT; }
+        WaveBucket { min, max, power: sum / n as f32 }
+    }
+
+    fn merge(buckets: &[WaveBucket]) -> WaveBucket {
+        if buckets.is_empty() { return WaveBucket::SILENT; }
+        let n = buckets.len() as f32;
+        buckets.iter().fold(WaveBucket { min: f32::MAX, max: f32::MIN, power: 0.0 }, |acc, b| {
+            WaveBucket { min: acc.min.min(b.min), max: acc.max.max(b.max), power: acc.power + b.power / n }
+        })
+    }
+}
+
+const MIPMAP_BASE: usize = 256; // frames per bucket at the finest level
+
+// the pcm summarised at 1:256, 1:1024, 1:4096 ... frames per bucket, built once at load, so any
+// zoom level draws from about one bucket per pixel instead of walking every sample in view
+#[derive(Default)]
+struct WaveMipmap {
+    channels: usize,
+    lane: Option<usize>, // the one channel summarised, or all of them mixed
+    levels: Vec<(usize, Vec<WaveBucket>)>, // (frames per bucket, buckets), finest first
+}
+
+impl WaveMipmap {
+    fn build(pcm: &[f32], channels: usize, lane: Option<usize>) -> Self {
+        let channels = channels.max(1);
+        let mut levels = Vec::new();
+        let mut frames = MIPMAP_BASE;
+        let of = |frames: &[f32]| WaveMipmap::lane_bucket(frames, channels, lane);
+        let mut level: Vec<WaveBucket> = pcm.chunks(MIPMAP_BASE * channels).map(of).collect();
+        // stop once a level fits on screen a couple of times over
+        while level.len() > 2048 {
+            let next = level.chunks(4).map(WaveBucket::merge).collect();
+            levels.push((frames, std::mem::replace(&mut level, next)));
+            frames *= 4;
+        }
+        levels.push((frames, level));
+        WaveMipmap { channels, lane, levels }
+    }
+
+    fn lane_bucket(frames: &[f32], channels: usize, lane: Option<usize>) -> WaveBucket {
+        match lane {
+            Some(l) => WaveBucket::of(frames.iter().skip(l).step_by(channels)),
+            None => WaveBucket::of(frames),
+        }
+    }
+
+    // one bucket per column for frames from..to, read from the coarsest level that is still finer
+    // than a column, or straight from the pcm when zoomed in past the finest level
+    fn columns(&self, pcm: &[f32], from: usize, to: usize, columns: usize) -> Vec<WaveBucket> {
+        let ch = self.channels.max(1);
+        let per_column = to.saturating_sub(from) as f64 / columns.max(1) as f64;
+        let level = self.levels.iter().rev().find(|(f, _)| *f as f64 <= per_column);
+        (0..columns).map(|c| {
+            let a = from + (c as f64 * per_column) as usize;
+            let b = (from + ((c + 1) as f64 * per_column) as usize).max(a + 1);
+            match level {
+                Some((f, buckets)) => buckets.get(a / f..b.div_ceil(*f).min(buckets.len())).map_or(WaveBucket::SILENT, WaveBucket::merge),
+                None => pcm.get(a * ch..(b * ch).min(pcm.len())).map_or(WaveBucket::SILENT, |f| WaveMipmap::lane_bucket(f, ch, self.lane)),
+            }
+        }).collect()
+    }
+}
+
+// everything derived from the samples, recomputed on load and after each edit
+struct PcmAnalysis {
+    waveform: Vec<WaveBucket>,
+    waveform_colors: Vec<egui::Color32>,
+    mipmaps: Vec<WaveMipmap>,
+    phrases: Vec<(usize, usize)>,
+    onsets: Vec<usize>,
+    clipped: Vec<(usize, usize)>,
+    file_lufs: Option<f32>,
+    loop_suggestions: Vec<LoopSuggestion>,
+    tempo: Option<TempoGrid>,
+    key: Option<MusicalKey>,
+}
+
+impl PcmAnalysis {
+    fn new(pcm: &[f32], channels: usize, sample_rate: u32) -> Self {
+        let chunk_size = (pcm.len() / 1000).max(1);
+        let waveform = pcm.chunks(chunk_size).map(WaveBucket::of).collect();
+        let mut meter = LoudnessMeter::new(channels, sample_rate);
+        meter.process(pcm);
+        PcmAnalysis {
+            waveform,
+            waveform_colors: band_colors(pcm, channels, sample_rate, chunk_size),
+            mipmaps: std::iter::once(None).chain((0..channels).filter(|_| channels > 1).map(Some))
+                .map(|lane| WaveMipmap::build(pcm, channels, lane)).collect(),
+            phrases: segment_phrases(pcm, channels, sample_rate),
+            onsets: detect_onsets(pcm, channels, sample_rate),
+            clipped: detect_clipping(pcm, channels, sample_rate),
+            file_lufs: Some(meter.integrated()).filter(|l| l.is_finite()),
+            loop_suggestions: suggest_loops(pcm, channels, sample_rate),
+            tempo: detect_tempo(pcm, channels, sample_rate),
+            key: detect_key(pcm, channels, sample_rate),
+        }
+    }
+
+    // for a file that isn't all in memory (yet)
+    fn empty() -> Self {
+        PcmAnalysis { waveform: Vec::new(), waveform_colors: Vec::new(), mipmaps: Vec::new(), phrases: Vec::new(), onsets: Vec::new(),
+                      clipped: Vec::new(), file_lufs: None, loop_suggestions: Vec::new(), tempo: None, key: None }
+    }
+
+    fn store(self, s: &mut AppState) {
+        s.waveform = self.waveform;
+        s.waveform_colors = self.waveform_colors;
+        s.wave_mipmaps = Arc::new(self.mipmaps);
+        s.waveform_gen = WAVEFORM_GEN.fetch_add(1, Ordering::Relaxed) + 1;
+        s.phrases = self.phrases;
+        s.onsets = self.onsets;
+        s.clipped = self.clipped;
+        s.file_lufs = self.file_lufs;
+        s.loop_suggestions = self.loop_suggestions;
+        // a grid already set by hand survives re-analysis after an edit
+        s.detected_tempo = self.tempo;
+        if s.tempo.is_none() { s.tempo = self.tempo; }
+        // likewise a key picked by hand
+        s.detected_key = self.key;
+        if s.key.is_none() { s.key = self.key; }
+    }
+}
+
+type OpenedAudio = (Box<dyn FormatReader>, Box<dyn Decoder>, CodecParameters, u32);
+
+// errors are worded for the user: they end up in the load failure banner
+fn probe_format(path: &Path) -> Result<Box<dyn FormatReader>, String> {
+    let file = std::fs::File::open(path).map_err(|e| format!("could not open {}: {e}", path.display()))?;
+    let mss = MediaSourceStream::new(Box::new(file), Default::default());
+    let mut hint = Hint::new();
+    if let Some(ext) = path.extension() { hint.with_extension(&ext.to_string_lossy()); }
+
+    let probed = symphonia::default::get_probe().format(&hint, mss, &Default::default(), &Default::default())
+        .map_err(|e| format!("unrecognised format: {e}"))?;
+    Ok(probed.format)
+}
+
+// video, subtitle and cover art tracks come through with no codec or sample rate
+fn is_audio_track(track: &Track) -> bool {
+    track.codec_params.codec != CODEC_TYPE_NULL && track.codec_params.sample_rate.is_some()
+}
+
+// the wanted track if the file has it, else the container's default when that's audio, else its
+// first audio track (a video's default track is usually the picture)
+fn pick_track(format: &dyn FormatReader, wanted: Option<u32>) -> Option<&Track> {
+    let tracks = format.tracks();
+    wanted.and_then(|id| tracks.iter().find(|t| t.id == id && is_audio_track(t)))
+        .or_else(|| format.default_track().filter(|t| is_audio_track(t)))
+        .or_else(|| tracks.iter().find(|t| is_audio_track(t)))
+}
+
+// id and label of each audio track, to choose from when a container has several, and the one that plays
+fn list_audio_tracks(path: &Path, wanted: Option<u32>) -> (Vec<(u32, String)>, Option<u32>) {
+    let Ok(format) = probe_format(path) else { return (Vec::new(), None) };
+    let tracks = format.tracks().iter().filter(|t| is_audio_track(t)).enumerate().map(|(n, t)| {
+        let params = &t.codec_params;
+        let mut label = format!("{}: {}", n + 1, symphonia::default::get_codecs().get_codec(params.codec).map_or("?", |d| d.short_name));
+        if let Some(layout) = params.channels { label.push_str(&format!(", {} ch", layout.count())); }
+        if let Some(language) = &t.language { label.push_str(&format!(", {language}")); }
+        (t.id, label)
+    }).collect();
+    (tracks, pick_track(format.as_ref(), wanted).map(|t| t.id))
+}
+
+// the container, a decoder for the chosen (or default) audio track, the track's parameters and id
+fn open_audio(path: &Path, track: Option<u32>) -> Result<OpenedAudio, String> {
+    let format = probe_format(path)?;
+    let track = pick_track(format.as_ref(), track).ok_or("the file has no audio track")?;
+    let (params, id) = (track.codec_params.clone(), track.id);
+    let decoder = symphonia::default::get_codecs().make(&params, &Default::default())
+        .map_err(|e| format!("unsupported codec: {e}"))?;
+    Ok((format, decoder, params, id))
+}
+
+fn decode_audio(path: &Path) -> Result<DecodedAudio, String> {
+    decode_audio_with(path, None, |_, _, _| true)
+}
+
+// `progress` hears the samples decoded so far and what fraction of the file that is after each
+// packet (-1 when the file doesn't state its length), and stops the decode by returning false
+fn decode_audio_with(path: &Path, track: Option<u32>, mut progress: impl FnMut(&CodecParameters, &[f32], f32) -> bool) -> Result<DecodedAudio, String> {
+    let (mut format, mut decoder, params, track_id) = open_audio(path, track)?;
+    let mut pcm = Vec::new();
+    let mut last_error = None;
+
+    while let Ok(packet) = format.next_packet() {
+        if packet.track_id() != track_id { continue; }
+        let done = params.n_frames.map_or(-1.0, |n| ((packet.ts() + packet.dur()) as f64 / n.max(1) as f64).min(1.0) as f32);
+        if !progress(&params, &pcm, done) { return Err("cancelled".into()); }
+        match decoder.decode(&packet) {
+            Ok(decoded) => {
+                let mut sb = SampleBuffer::<f32>::new(decoded.capacity() as u64, *decoded.spec());
+                sb.copy_interleaved_ref(decoded);
+                pcm.extend_from_slice(sb.samples());
+            }
+            Err(e) => last_error = Some(e),
+        }
+    }
+    // a few bad packets are skipped, but a file where nothing decoded is an error
+    if pcm.is_empty() && let Some(e) = last_error { return Err(format!("decode error: {e}")); }
+
+    Ok(DecodedAudio {
+        pcm,
+        sample_rate: params.sample_rate.unwrap_or(44100),
+        channels: params.channels.map(|c| c.count()).unwrap_or(2),
+    })
+}
+
+// how much more of the file is decoded before the waveform is handed the next part
+const PROGRESSIVE_STEP_SECS: usize = 5;
+
+// the stated length in frames; mkv states it in its own timebase (ms) rather than in frames
+fn stated_frames(params: &CodecParameters) -> Option<u64> {
+    let (n, rate) = (params.n_frames?, params.sample_rate? as u64);
+    match params.time_base {
+        Some(tb) if tb.numer as u64 * rate != tb.denom as u64 => {
+            let t = tb.calc_time(n);
+            Some(t.seconds * rate + (t.frac * rate as f64) as u64)
+        }
+        _ => Some(n),
+    }
+}
+
+// a whole-file load that can be played and drawn while the rest decodes: a window of what's decoded
+// so far is kept on the cursor, which the engine reads like a streamed file's
+struct ProgressiveLoad {
+    buffer: Arc<StreamBuffer>,
+    channels: usize,
+    waveform: Vec<WaveBucket>, // the overview, flat past what's decoded
+    chunk: usize, // samples per waveform bucket, as PcmAnalysis will have them
+    drawn: usize, // samples the waveform has taken in
+}
+
+impl ProgressiveLoad {
+    fn new(total: usize, channels: usize, rate: u32) -> Self {
+        let chunk = (total / 1000).max(1);
+        let capacity = (2 * STREAM_AHEAD_SECS * rate as usize * channels).min(total).max(1);
+        ProgressiveLoad {
+            buffer: Arc::new(StreamBuffer {
+                total,
+                data: (0..capacity).map(|_| AtomicU32::new(0)).collect(),
+                start: AtomicUsize::new(0),
+                end: AtomicUsize::new(0),
+            }),
+            channels,
+            waveform: vec![WaveBucket::SILENT; total.div_ceil(chunk)],
+            chunk,
+            drawn: 0,
+        }
+    }
+
+    // keeps the window on the cursor and topped up from the samples decoded so far
+    fn follow(&self, pcm: &[f32], cursor: usize) {
+        let b = &self.buffer;
+        let available = pcm.len().min(b.total);
+        let ahead = b.data.len() / 2;
+        let mut end = b.end.load(Ordering::Acquire);
+        if cursor < b.start.load(Ordering::Acquire) || cursor > end + ahead / 4 {
+            // moved outside the window: start it again at the cursor, or as far as the decode has got
+            let at = (cursor - cursor % self.channels).min(available);
+            b.start.store(usize::MAX, Ordering::Release);
+            b.end.store(at, Ordering::Release);
+            b.start.store(at, Ordering::Release);
+            end = at;
+        }
+        let new_end = available.min(cursor + ahead);
+        if new_end <= end { return; }
+        // give up the oldest samples before overwriting their slots
+        b.start.fetch_max(new_end.saturating_sub(b.data.len()), Ordering::AcqRel);
+        for (k, &x) in pcm[end..new_end].iter().enumerate() { b.data[(end + k) % b.data.len()].store(x.to_bits(), Ordering::Relaxed); }
+        b.end.store(new_end, Ordering::Release);
+    }
+
+    // takes the waveform up to everything decoded so far
+    fn draw(&mut self, pcm: &[f32]) {
+        let (from, to) = (self.drawn, pcm.len().min(self.buffer.total));
+        if from >= to { return; }
+        for (b, bucket) in self.waveform.iter_mut().enumerate().take(to.div_ceil(self.chunk)).skip(from / self.chunk) {
+            *bucket = WaveBucket::of(&pcm[b * self.chunk..((b + 1) * self.chunk).min(to)]);
+        }
+        self.drawn = to;
+    }
+}
+
+// how long a swapped-out source is kept before being dropped, well past any audio block
+const RETIRE_AFTER_MS: u64 = 250;
+
+// the callback clones pcm_data and stream for each block, so whatever replaces them could leave it
+// holding the last reference and freeing the samples on the audio thread: the old ones are parked
+// in controls.retired instead, and dropped by the UI once the callback is surely done with them
+fn set_source(c: &AudioControls, pcm: Arc<Vec<f32>>, stream: Option<Arc<StreamBuffer>>) {
+    let old_pcm = std::mem::replace(&mut *c.pcm_data.lock().unwrap(), pcm);
+    let old_stream = std::mem::replace(&mut *c.stream.lock().unwrap(), stream);
+    let now = std::time::Instant::now();
+    let mut retired = c.retired.lock().unwrap();
+    retired.push((now, Box::new(old_pcm)));
+    if let Some(old) = old_stream { retired.push((now, Box::new(old))); }
+}
+
+// the stem buffers and the take are held the same way, so the old ones get parked too
+fn swap_stem_mix(c: &AudioControls, mix: Arc<StemMix>) {
+    let old = std::mem::replace(&mut *c.stem_mix.lock().unwrap(), mix);
+    c.retired.lock().unwrap().push((std::time::Instant::now(), Box::new(old)));
+}
+
+fn swap_take(c: &AudioControls, take: Arc<Take>) {
+    let old = std::mem::replace(&mut *c.take.lock().unwrap(), take);
+    c.retired.lock().unwrap().push((std::time::Instant::now(), Box::new(old)));
+}
+
+// back to the empty player, for a load that failed or was cancelled once the old file's session was saved and cleared
+fn unload_audio(c: &AudioControls, s: &mut AppState) {
+    c.is_playing.store(false, Ordering::SeqCst);
+    c.cursor.store(0, Ordering::SeqCst);
+    c.loop_start.store(0, Ordering::SeqCst);
+    c.loop_end.store(0, Ordering::SeqCst);
+    set_source(c, Arc::new(Vec::new()), None);
+    swap_stem_mix(c, Arc::new(StemMix::default()));
+    *s = AppState { waveform_gen: WAVEFORM_GEN.fetch_add(1, Ordering::Relaxed) + 1, ..AppState::default() };
+}
+
+// files longer than this play from a window decoded just ahead of the cursor instead of from memory
+const STREAM_THRESHOLD_SECS: u64 = 20 * 60;
+const STREAM_AHEAD_SECS: usize = 20;
+
+// the decoded window of a streamed file: absolute interleaved samples start..end, held at
+// index % capacity; the audio thread reads it lock-free while the decoder worker writes ahead
+struct StreamBuffer {
+    total: usize, // interleaved samples in the whole file
+    data: Box<[AtomicU32]>,
+    start: AtomicUsize,
+    end: AtomicUsize,
+}
+
+impl StreamBuffer {
+    fn get(&self, i: usize) -> f32 {
+        if i < self.start.load(Ordering::Acquire) || i >= self.end.load(Ordering::Acquire) { return 0.0; }
+        f32::from_bits(self.data[i % self.data.len()].load(Ordering::Relaxed))
+    }
+
+    fn covers(&self, from: usize, len: usize) -> bool {
+        from >= self.start.load(Ordering::Acquire) && (from + len).min(self.total) <= self.end.load(Ordering::Acquire)
+    }
+}
+
+// owns the open file for a streamed track and keeps the window filled around the cursor,
+// seeking the container when the cursor lands outside it
+struct DecoderWorker {
+    format: Box<dyn FormatReader>,
+    decoder: Box<dyn Decoder>,
+    track_id: u32,
+    channels: usize,
+    buffer: Arc<StreamBuffer>,
+    skip: usize, // samples to drop after a seek landed before the wanted frame
+}
+
+impl DecoderWorker {
+    // None for short files and ones that don't state their length; those are decoded whole
+    fn open(path: &Path, track: Option<u32>) -> Option<(Self, u32)> {
+        let (format, decoder, params, track_id) = open_audio(path, track).ok()?;
+        let (rate, channels) = (params.sample_rate?, params.channels?.count());
+        let frames = params.n_frames?;
+        if frames < STREAM_THRESHOLD_SECS * rate as u64 { return None; }
+        let capacity = 2 * STREAM_AHEAD_SECS * rate as usize * channels;
+        let buffer = Arc::new(StreamBuffer {
+            total: frames as usize * channels,
+            data: (0..capacity).map(|_| AtomicU32::new(0)).collect(),
+            start: AtomicUsize::new(0),
+            end: AtomicUsize::new(0),
+        });
+        Some((DecoderWorker { format, decoder, track_id, channels, buffer, skip: 0 }, rate))
+    }
+
+    fn seek(&mut self, to: usize) {
+        let frame = to / self.channels;
+        let b = &self.buffer;
+        b.start.store(usize::MAX, Ordering::Release);
+        b.end.store(to, Ordering::Release);
+        let seeked = self.format.seek(SeekMode::Accurate, SeekTo::TimeStamp { ts: frame as u64, track_id: self.track_id });
+        self.decoder.reset();
+        let landed = seeked.map_or(frame, |s| (s.actual_ts as usize).min(frame));
+        self.skip = (frame - landed) * self.channels;
+        b.end.store(frame * self.channels, Ordering::Release);
+        b.start.store(frame * self.channels, Ordering::Release);
+    }
+
+    fn push(&mut self, samples: &[f32]) {
+        let n = self.skip.min(samples.len());
+        self.skip -= n;
+        let b = &self.buffer;
+        let end = b.end.load(Ordering::Acquire);
+        let samples = &samples[n..][..(b.total - end).min(samples.len() - n)];
+        let new_end = end + samples.len();
+        // give up the oldest samples before overwriting their slots
+        b.start.fetch_max(new_end.saturating_sub(b.data.len()), Ordering::AcqRel);
+        for (k, &x) in samples.iter().enumerate() { b.data[(end + k) % b.data.len()].store(x.to_bits(), Ordering::Relaxed); }
+        b.end.store(new_end, Ordering::Release);
+    }
+
+    // runs until nothing but the worker holds the buffer, i.e. another file was opened
+    fn run(mut self, c: Arc<AudioControls>) {
+        let ahead = self.buffer.data.len() / 2;
+        while Arc::strong_count(&self.buffer) > 1 {
+            // a parked A/B deck keeps its window but doesn't follow the other deck's cursor
+            let active = c.stream.lock().unwrap().as_ref().is_some_and(|s| Arc::ptr_eq(s, &self.buffer));
+            let cursor = c.cursor.load(Ordering::Relaxed);
+            let (start, end) = (self.buffer.start.load(Ordering::Acquire), self.buffer.end.load(Ordering::Acquire));
+            if active && cursor < self.buffer.total && (cursor < start || cursor > end + ahead / 4) {
+                self.seek(cursor - cursor % self.channels);
+                continue;
+            }
+            if !active || end >= self.buffer.total || end >= cursor + ahead {
+                thread::sleep(std::time::Duration::from_millis(10));
+                continue;
+            }
+            match self.format.next_packet() {
+                Ok(packet) if packet.track_id() == self.track_id => {
+                    if let Ok(decoded) = self.decoder.decode(&packet) {
+                        let mut sb = SampleBuffer::<f32>::new(decoded.capacity() as u64, *decoded.spec());
+                        sb.copy_interleaved_ref(decoded);
+                        self.push(sb.samples());
+                    }
+                }
+                Ok(_) => {}
+                // the stated length can overshoot the real end: pad with silence so playback reaches it
+                Err(_) => self.push(&[0.0; 4096]),
+            }
+        }
+    }
+}
+
+// waveform peaks for a streamed file from a second pass over it, without holding the samples
+fn stream_overview(path: &Path, track: Option<u32>, total: usize) -> Option<Vec<WaveBucket>> {
+    let (mut format, mut decoder, _, track_id) = open_audio(path, track).ok()?;
+    let chunk = (total / 1000).max(1);
+    let mut buckets = vec![WaveBucket { min: f32::MAX, max: f32::MIN, power: 0.0 }; total.div_ceil(chunk)];
+    let mut pos = 0;
+    while let Ok(packet) = format.next_packet() {
+        if packet.track_id() != track_id { continue; }
+        let Ok(decoded) = decoder.decode(&packet) else { continue };
+        let mut sb = SampleBuffer::<f32>::new(decoded.capacity() as u64, *decoded.spec());
+        sb.copy_interleaved_ref(decoded);
+        for &x in sb.samples() {
+            if let Some(b) = buckets.get_mut(pos / chunk) {
+                b.min = b.min.min(x);
+                b.max = b.max.max(x);
+                b.power += x * x / chunk as f32;
+            }
+            pos += 1;
+        }
+    }
+    // anything the decoder never reached stays flat
+    for b in buckets.iter_mut().filter(|b| b.min > b.max) { *b = WaveBucket::SILENT; }
+    Some(buckets)
+}
+
+// set once at startup: the folder beside the executable when running portable (--portable or a portable.toml there)
+static PORTABLE_ROOT: std::sync::OnceLock<Option<PathBuf>> = std::sync::OnceLock::new();
+
+fn portable_root() -> Option<&'static PathBuf> {
+    PORTABLE_ROOT.get().and_then(|r| r.as_ref())
+}
+
+fn cache_dir() -> PathBuf {
+    if let Some(root) = portable_root() { return root.join("reh-data").join("cache"); }
+    dirs::cache_dir().unwrap_or_else(std::env::temp_dir).join("reh")
+}
+
+// user data that should survive a cache wipe: setlists, settings
+fn config_dir() -> PathBuf {
+    if let Some(root) = portable_root() { return root.join("reh-data").join("config"); }
+    dirs::config_dir().unwrap_or_else(std::env::temp_dir).join("reh")
+}
+
+// FNV-1a, for names on disk: unlike DefaultHasher it gives the same hash on every Rust release
+const FNV_OFFSET: u64 = 0xcbf2_9ce4_8422_2325;
+fn fnv1a(bytes: &[u8], seed: u64) -> u64 {
+    bytes.iter().fold(seed, |h, &b| (h ^ b as u64).wrapping_mul(0x0100_0000_01b3))
+}
+
+// cache key that changes when the file is replaced or edited
+fn file_cache_key(path: &Path) -> String {
+    let mut h = fnv1a(path.as_os_str().as_encoded_bytes(), FNV_OFFSET);
+    if let Ok(meta) = std::fs::metadata(path) {
+        h = fnv1a(&meta.len().to_le_bytes(), h);
+        if let Some(t) = meta.modified().ok().and_then(|t| t.duration_since(std::time::UNIX_EPOCH).ok()) {
+            h = fnv1a(&t.as_nanos().to_le_bytes(), h);
+        }
+    }
+    format!("{:016x}", h)
+}
+
+// a labelled point on the timeline ("verse 2 lick"), kept with the file's session
+#[derive(Clone)]
+struct Marker {
+    pos: usize, // interleaved sample index
+    label: String,
+}
+
+// per-file session sidecar, kept under the config dir so read-only music folders work too;
+// tab separated "key value..." lines, and unknown keys are skipped
+#[derive(Default)]
+struct Session {
+    volume_envelope: Vec<(usize, f32)>, // (interleaved sample index, dB)
+    loop_range: Option<(usize, usize)>, // interleaved sample indices
+    cursor: Option<usize>,
+    speed: Option<f32>,
+    pitch: Option<f32>, // ratio
+    tuning: Option<f32>, // cents
+    level: Option<f32>, // main volume, linear
+    track: Option<u32>, // audio track id, for containers with several
+    markers: Vec<Marker>,
+}
+
+impl Session {
+    // keyed on the canonical path so a relative CLI path, the dialog and batch runs all find the same
+    // session; FNV-1a because DefaultHasher's output may change between Rust releases
+    fn path_for(file: &Path) -> PathBuf {
+        let file = std::fs::canonicalize(file).unwrap_or_else(|_| file.to_path_buf());
+        let hash = fnv1a(file.as_os_str().as_encoded_bytes(), FNV_OFFSET);
+        config_dir().join("sessions").join(format!("{:016x}.reh", hash))
+    }
+
+    // where sessions went before path_for was stable, read until the file's session is next saved
+    fn legacy_path_for(file: &Path) -> PathBuf {
+        use std::hash::{Hash, Hasher};
+        let mut h = std::collections::hash_map::DefaultHasher::new();
+        file.hash(&mut h);
+        config_dir().join("sessions").join(format!("{:016x}.reh", h.finish()))
+    }
+
+    fn load(file: &Path) -> Self {
+        let mut session = Session::default();
+        let Ok(text) = std::fs::read_to_string(Self::path_for(file))
+            .or_else(|_| std::fs::read_to_string(Self::legacy_path_for(file))) else { return session };
+        for line in text.lines() {
+            let f: Vec<&str> = line.split('\t').collect();
+            match f[..] {
+                ["volume", pos, db] => if let (Ok(pos), Ok(db)) = (pos.parse(), db.parse()) { session.volume_envelope.push((pos, db)); },
+                ["loop", a, b] => if let (Ok(a), Ok(b)) = (a.parse(), b.parse()) { session.loop_range = Some((a, b)); },
+                ["cursor", v] => session.cursor = v.parse().ok(),
+                ["speed", v] => session.speed = v.parse().ok(),
+                ["pitch", v] => session.pitch = v.parse().ok(),
+                ["tuning", v] => session.tuning = v.parse().ok(),
+                ["level", v] => session.level = v.parse().ok(),
+                ["track", v] => session.track = v.parse().ok(),
+                ["marker", pos, label] => if let Ok(pos) = pos.parse() { session.markers.push(Marker { pos, label: label.to_string() }); },
+                _ => {}
+            }
+        }
+        session.volume_envelope.sort_by_key(|p| p.0);
+        session.markers.sort_by_key(|m| m.pos);
+        session
+    }
+
+    fn save(&self, file: &Path) -> std::io::Result<()> {
+        let path = Self::path_for(file);
+        let mut text = format!("file\t{}\n", file.display());
+        if let Some((a, b)) = self.loop_range { text.push_str(&format!("loop\t{}\t{}\n", a, b)); }
+        if let Some(v) = self.cursor { text.push_str(&format!("cursor\t{}\n", v)); }
+        if let Some(v) = self.speed { text.push_str(&format!("speed\t{}\n", v)); }
+        if let Some(v) = self.pitch { text.push_str(&format!("pitch\t{}\n", v)); }
+        if let Some(v) = self.tuning { text.push_str(&format!("tuning\t{}\n", v)); }
+        if let Some(v) = self.level { text.push_str(&format!("level\t{}\n", v)); }
+        if let Some(v) = self.track { text.push_str(&format!("track\t{}\n", v)); }
+        for (pos, db) in &self.volume_envelope { text.push_str(&format!("volume\t{}\t{:.2}\n", pos, db)); }
+        for m in &self.markers { text.push_str(&format!("marker\t{}\t{}\n", m.pos, m.label.replace(['\t', '\n'], " "))); }
+        std::fs::create_dir_all(config_dir().join("sessions"))?;
+        std::fs::write(path, text)
+    }
+}
+
+// a loop export running in the background
+struct ExportJob {
+    progress: Arc<AtomicU32>, // f32, 0..1
+    done: Receiver<Result<PathBuf, String>>,
+}
+
+// saved positions for a file that's still loading, applied once the load thread is done with it
+struct PendingRestore {
+    file: String,
+    loop_range: Option<(usize, usize)>,
+    cursor: Option<usize>,
+}
+
+// one line per item: name, start and end seconds, path, tab separated
+fn parse_setlist(text: &str) -> Vec<SetlistItem> {
+    text.lines().filter_map(|line| {
+        let mut f = line.splitn(4, '\t');
+        let name = f.next()?.to_string();
+        let start = f.next()?.trim().parse().ok()?;
+        let end = f.next()?.trim().parse().ok()?;
+        let path = PathBuf::from(f.next()?.trim());
+        Some(SetlistItem { name, path, start, end })
+    }).collect()
+}
+
+fn format_setlist(items: &[SetlistItem]) -> String {
+    items.iter().map(|i| format!("{}\t{:.3}\t{:.3}\t{}\n", i.name.replace('\t', " "), i.start, i.end, i.path.display())).collect()
+}
+
+// http(s), WebDAV (webdav:// or dav://, with an s for TLS) and SMB shares are fetched into the cache
+const REMOTE_SCHEMES: [&str; 7] = ["http://", "https://", "webdav://", "webdavs://", "dav://", "davs://", "smb://"];
+
+fn xml_unescape(text: &str) -> String {
+    let text = text.trim();
+    let text = text.strip_prefix("<![CDATA[").and_then(|t| t.strip_suffix("]]>")).unwrap_or(text);
+    text.replace("&lt;", "<").replace("&gt;", ">").replace("&quot;", "\"").replace("&apos;", "'").replace("&#39;", "'").replace("&amp;", "&")
+}
+
+fn xml_element<'a>(xml: &'a str, tag: &str) -> Option<&'a str> {
+    let open = xml.find(&format!("<{tag}"))?;
+    let body = open + xml[open..].find('>')? + 1;
+    let len = xml[body..].find(&format!("</{tag}>"))?;
+    Some(&xml[body..body + len])
+}
+
+fn xml_attr(tag: &str, name: &str) -> Option<String> {
+    let at = tag.find(&format!(" {name}="))? + name.len() + 2;
+    let quote = tag[at..].chars().next()?;
+    let len = tag[at + 1..].find(quote)?;
+    Some(xml_unescape(&tag[at + 1..at + 1 + len]))
+}
+
+// just enough RSS for podcasts: the channel title and each item's title, date and enclosure
+fn parse_rss(xml: &str) -> (String, Vec<Episode>) {
+    let mut items = xml.split("<item");
+    let head = items.next().unwrap_or("");
+    let title = xml_element(head, "title").map(xml_unescape).unwrap_or_default();
+    let mut episodes = Vec::new();
+    for item in items {
+        let item = item.split("</item>").next().unwrap_or(item);
+        let Some(at) = item.find("<enclosure") else { continue };
+        let tag = &item[at..at + item[at..].find('>').unwrap_or(item.len() - at)];
+        let Some(url) = xml_attr(tag, "url") else { continue };
+        episodes.push(Episode {
+            title: xml_element(item, "title").map(xml_unescape).unwrap_or_else(|| url.clone()),
+            published: xml_element(item, "pubDate").map(|d| xml_unescape(d).chars().take(16).collect()).unwrap_or_default(),
+            url,
+        });
+    }
+    (title, episodes)
+}
+
+fn fetch_feed(url: &str) -> Result<(String, Vec<Episode>), String> {
+    let text = ureq::get(url).call().map_err(|e| e.to_string())?.into_body().read_to_string().map_err(|e| e.to_string())?;
+    let (title, episodes) = parse_rss(&text);
+    if episodes.is_empty() { return Err("no audio episodes in the feed".into()); }
+    Ok((title, episodes))
+}
+
+// subscriptions, one per line: feed URL, title, tab separated
+fn load_subscriptions() -> Vec<Feed> {
+    let text = std::fs::read_to_string(config_dir().join("podcasts.txt")).unwrap_or_default();
+    text.lines().filter_map(|line| {
+        let (url, title) = line.split_once('\t').unwrap_or((line, ""));
+        (!url.trim().is_empty()).then(|| Feed { url: url.trim().to_string(), title: title.to_string(), episodes: Vec::new() })
+    }).collect()
+}
+
+fn save_subscriptions(feeds: &[Feed]) -> std::io::Result<()> {
+    std::fs::create_dir_all(config_dir())?;
+    let text: String = feeds.iter().map(|f| format!("{}\t{}\n", f.url, f.title.replace('\t', " "))).collect();
+    std::fs::write(config_dir().join("podcasts.txt"), text)
+}
+
+fn is_url(text: &str) -> bool {
+    REMOTE_SCHEMES.iter().any(|s| text.starts_with(s))
+}
+
+// user name and password for a server, kept in memory for the session only
+#[derive(Clone)]
+struct Login {
+    user: String,
+    password: String,
+}
+
+enum FetchError {
+    NeedsLogin,
+    Failed(String),
+}
+
+fn url_host(url: &str) -> &str {
+    url.split_once("://").map_or(url, |(_, rest)| rest.split('/').next().unwrap_or(rest))
+}
+
+fn base64(bytes: &[u8]) -> String {
+    const ABC: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut out = String::new();
+    for chunk in bytes.chunks(3) {
+        let n = chunk.iter().enumerate().fold(0u32, |n, (i, &b)| n | (b as u32) << (16 - 8 * i));
+        for i in 0..4 {
+            out.push(if i <= chunk.len() { ABC[(n >> (18 - 6 * i)) as usize & 63] as char } else { '=' });
+        }
+    }
+    out
+}
+
+fn percent_decode(text: &str) -> String {
+    let bytes = text.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'%' && let Some(b) = text.get(i + 1..i + 3).and_then(|h| u8::from_str_radix(h, 16).ok()) {
+            out.push(b);
+            i += 3;
+        } else {
+            out.push(bytes[i]);
+            i += 1;
+        }
+    }
+    String::from_utf8_lossy(&out).into_owned()
+}
+
+// turns pasted text (a path, a quoted path, ~/..., file:// or http(s) URL) into something openable
+fn parse_location(text: &str) -> Option<String> {
+    let text = text.lines().next()?.trim().trim_matches(|c| c == '"' || c == '\'');
+    if is_url(text) { return Some(text.to_string()); }
+    let path = if let Some(rest) = text.strip_prefix("file://") {
+        PathBuf::from(percent_decode(rest))
+    } else if let Some(rest) = text.strip_prefix("~/") {
+        dirs::home_dir()?.join(rest)
+    } else {
+        PathBuf::from(text)
+    };
+    path.is_file().then(|| path.to_string_lossy().into_owned())
+}
+
+fn download_url(url: &str, login: Option<&Login>) -> Result<PathBuf, FetchError> {
+    use std::hash::{Hash, Hasher};
+    let failed = |e: &dyn std::fmt::Display| FetchError::Failed(e.to_string());
+    let dir = cache_dir().join("downloads");
+    std::fs::create_dir_all(&dir).map_err(|e| failed(&e))?;
+    let mut h = std::collections::hash_map::DefaultHasher::new();
+    url.hash(&mut h);
+    // keep the remote name so the extension still hints the format to symphonia
+    let name = url.split(['?', '#']).next().and_then(|u| u.rsplit('/').next()).filter(|n| !n.is_empty()).unwrap_or("download");
+    let name: String = percent_decode(name).chars().map(|c| if c.is_alphanumeric() || ".-_ ".contains(c) { c } else { '_' }).collect();
+    let out = dir.join(format!("{:016x}-{name}", h.finish()));
+    if out.exists() { return Ok(out); }
+    let partial = out.with_extension("part");
+    if let Some(rest) = url.strip_prefix("smb://") {
+        fetch_smb(rest, login, &partial)?;
+    } else {
+        // WebDAV reads are plain GETs
+        let url = match url.split_once("://") {
+            Some(("webdavs" | "davs", rest)) => format!("https://{rest}"),
+            Some(("webdav" | "dav", rest)) => format!("http://{rest}"),
+            _ => url.to_string(),
+        };
+        let mut request = ureq::get(&url);
+        if let Some(l) = login { request = request.header("Authorization", format!("Basic {}", base64(format!("{}:{}", l.user, l.password).as_bytes()))); }
+        let response = match request.call() {
+            Err(ureq::Error::StatusCode(401 | 403)) => return Err(FetchError::NeedsLogin),
+            r => r.map_err(|e| failed(&e))?,
+        };
+        let mut file = std::fs::File::create(&partial).map_err(|e| failed(&e))?;
+        std::io::copy(&mut response.into_body().into_reader(), &mut file).map_err(|e| { let _ = std::fs::remove_file(&partial); failed(&e) })?;
+    }
+    std::fs::rename(&partial, &out).map_err(|e| failed(&e))?;
+    Ok(out)
+}
+
+// smb://host/share/dir/file.mp3 through smbclient, as a guest unless a login is given;
+// the password goes in through the environment so it doesn't show up in the process list
+fn fetch_smb(rest: &str, login: Option<&Login>, out: &Path) -> Result<(), FetchError> {
+    let mut parts = rest.splitn(3, '/');
+    let (Some(host), Some(share), Some(file)) = (parts.next(), parts.next(), parts.next()) else {
+        return Err(FetchError::Failed("expected smb://host/share/path".into()));
+    };
+    let mut cmd = std::process::Command::new("smbclient");
+    cmd.arg(format!("//{host}/{}", percent_decode(share)));
+    match login {
+        Some(l) => { cmd.arg("-U").arg(&l.user).env("PASSWD", &l.password); }
+        None => { cmd.arg("-N"); }
+    }
+    let file = percent_decode(file).replace('/', "\\");
+    let local = out.display().to_string();
+    // smbclient splits -c on ';' and has no escape for '"', so names with either can't be quoted safely
+    if [&file, &local].iter().any(|p| p.contains(['"', ';']) || p.chars().any(char::is_control)) {
+        return Err(FetchError::Failed("smbclient can't fetch a path containing \" or ;".into()));
+    }
+    cmd.arg("-c").arg(format!("get \"{file}\" \"{local}\""));
+    let result = cmd.output().map_err(|e| FetchError::Failed(format!("smbclient: {e} (is it installed?)")))?;
+    if result.status.success() && out.exists() { return Ok(()); }
+    let _ = std::fs::remove_file(out);
+    let text = String::from_utf8_lossy(&result.stdout).into_owned() + &String::from_utf8_lossy(&result.stderr);
+    if ["NT_STATUS_LOGON_FAILURE", "NT_STATUS_ACCESS_DENIED"].iter().any(|e| text.contains(e)) { return Err(FetchError::NeedsLogin); }
+    Err(FetchError::Failed(text.lines().rev().find(|l| !l.trim().is_empty()).unwrap_or("smbclient failed").trim().to_string()))
+}
+
+// the "Open Location" window: a URL, and a login when the server asks for one
+struct LocationPrompt {
+    location: String,
+    user: String,
+    password: String,
+    message: String,
+}
+
+const AUDIO_EXTENSIONS: [&str; 18] = ["mp3", "wav", "ogg", "oga", "opus", "flac", "m4a", "mp4", "m4v", "mov", "aac", "aiff", "aif", "caf", "mkv", "webm", "mid", "midi"];
+
+fn is_audio_file(path: &Path) -> bool {
+    path.extension().is_some_and(|e| AUDIO_EXTENSIONS.iter().any(|a| e.eq_ignore_ascii_case(a)))
+}
+
+// polls a folder and reports audio files that appear after the watch started,
+// once their size has stopped changing (so half-written exports aren't picked up)
+fn watch_folder(dir: PathBuf, tx: Sender<PathBuf>, stop: Arc<AtomicBool>) {
+    let list = |dir: &Path| -> Vec<(PathBuf, u64)> {
+        std::fs::read_dir(dir).map(|rd| rd.flatten()
+            .map(|e| e.path())
+            .filter(|p| p.is_file() && is_audio_file(p))
+            .map(|p| { let len = std::fs::metadata(&p).map(|m| m.len()).unwrap_or(0); (p, len) })
+            .collect()).unwrap_or_default()
+    };
+    let mut known: std::collections::HashSet<PathBuf> = list(&dir).into_iter().map(|(p, _)| p).collect();
+    let mut pending: std::collections::HashMap<PathBuf, u64> = std::collections::HashMap::new();
+    while !stop.load(Ordering::Relaxed) {
+        thread::sleep(std::time::Duration::from_secs(2));
+        for (path, len) in list(&dir) {
+            if known.contains(&path) { continue; }
+            if pending.get(&path) == Some(&len) && len > 0 {
+                pending.remove(&path);
+                known.insert(path.clone());
+                if tx.send(path).is_err() { return; }
+            } else {
+                pending.insert(path, len);
+            }
+        }
+    }
+}
+
+const AUDIO_MIME_TYPES: [&str; 17] = [
+    "audio/mpeg", "audio/wav", "audio/x-wav", "audio/ogg", "audio/opus", "audio/flac", "audio/x-flac", "audio/mp4",
+    "audio/x-m4a", "audio/aac", "audio/x-aiff", "audio/webm", "audio/midi", "video/mp4", "video/x-matroska", "video/webm",
+    "video/quicktime",
+];
+
+fn run_quiet(cmd: &mut std::process::Command) -> Result<(), String> {
+    let out = cmd.output().map_err(|e| e.to_string())?;
+    if out.status.success() { Ok(()) } else { Err(String::from_utf8_lossy(&out.stderr).trim().to_string()) }
+}
+
+// registers (or removes) reh as an "Open with" handler for the audio extensions; the OS then
+// launches `reh <file>`, which the existing path argument already handles
+fn register_file_types(register: bool) -> Result<String, String> {
+    let exe = std::env::current_exe().map_err(|e| e.to_string())?;
+    if cfg!(windows) {
+        let classes = r"HKCU\Software\Classes";
+        let command = format!(r"{classes}\Reh.AudioFile\shell\open\command");
+        if register {
+            run_quiet(std::process::Command::new("reg").args(["add", &format!(r"{classes}\Reh.AudioFile"), "/ve", "/d", "Reh audio file", "/f"]))?;
+            run_quiet(std::process::Command::new("reg").args(["add", &command, "/ve", "/d", &format!("\"{}\" \"%1\"", exe.display()), "/f"]))?;
+        } else {
+            run_quiet(std::process::Command::new("reg").args(["delete", &format!(r"{classes}\Reh.AudioFile"), "/f"]))?;
+        }
+        for ext in AUDIO_EXTENSIONS {
+            let key = format!(r"{classes}\.{ext}\OpenWithProgids");
+            let args: Vec<&str> = if register { vec!["add", &key, "/v", "Reh.AudioFile", "/t", "REG_NONE", "/f"] } else { vec!["delete", &key, "/v", "Reh.AudioFile", "/f"] };
+            let _ = run_quiet(std::process::Command::new("reg").args(args));
+        }
+        Ok(if register { "Registered, pick Reh under Open with".into() } else { "Unregistered".into() })
+    } else if cfg!(target_os = "macos") {
+        // document types live in the bundle's Info.plist, all we can do is ask Launch Services to re-read it
+        let bundle = exe.ancestors().find(|p| p.extension().is_some_and(|e| e == "app")).ok_or("run Reh from its .app bundle to register file types")?;
+        let lsregister = "/System/Library/Frameworks/CoreServices.framework/Frameworks/LaunchServices.framework/Support/lsregister";
+        run_quiet(std::process::Command::new(lsregister).arg(if register { "-f" } else { "-u" }).arg(bundle))?;
+        Ok(if register { "Registered".into() } else { "Unregistered".into() })
+    } else {
+        let apps = dirs::data_dir().ok_or("no data directory")?.join("applications");
+        let desktop = apps.join("reh.desktop");
+        if register {
+            std::fs::create_dir_all(&apps).map_err(|e| e.to_string())?;
+            let entry = format!(
+                "[Desktop Entry]\nType=Application\nName=Reh\nComment=Audio player for learning and transcribing music\nExec=\"{}\" %f\nTerminal=false\nCategories=AudioVideo;Audio;Player;Music;\nMimeType={};\n",
+                exe.display(), AUDIO_MIME_TYPES.join(";"));
+            // MimeType= alone puts Reh under Open with; the user's default players stay as they were
+            std::fs::write(&desktop, entry).map_err(|e| e.to_string())?;
+        } else if desktop.exists() {
+            std::fs::remove_file(&desktop).map_err(|e| e.to_string())?;
+        }
+        let _ = run_quiet(std::process::Command::new("update-desktop-database").arg(&apps));
+        Ok(if register { format!("Registered {}", desktop.display()) } else { "Unregistered".into() })
+    }
+}
+
+fn is_midi(path: &Path) -> bool {
+    path.extension().is_some_and(|e| e.eq_ignore_ascii_case("mid") || e.eq_ignore_ascii_case("midi"))
+}
+
+// first SoundFont found in the usual system locations, or $REH_SOUNDFONT
+fn default_soundfont() -> Option<PathBuf> {
+    let env = std::env::var_os("REH_SOUNDFONT").map(PathBuf::from);
+    let known = [
+        "/usr/share/sounds/sf2/FluidR3_GM.sf2",
+        "/usr/share/soundfonts/FluidR3_GM.sf2",
+        "/usr/share/soundfonts/default.sf2",
+        "/usr/share/sounds/sf2/default-GM.sf2",
+        "/opt/homebrew/share/soundfonts/default.sf2",
+    ];
+    env.into_iter().chain(known.iter().map(PathBuf::from)).find(|p| p.exists())
+}
+
+fn render_midi(path: &Path, soundfont: &Path) -> Result<PathBuf, String> {
+    let dir = cache_dir().join("midi");
+    std::fs::create_dir_all(&dir).map_err(|e| e.to_string())?;
+    // the font is part of the key, switching fonts re-renders
+    let out = dir.join(format!("{}-{}.wav", file_cache_key(path), file_cache_key(soundfont)));
+    if out.exists() { return Ok(out); }
+    let output = std::process::Command::new("fluidsynth")
+        .args(["-ni", "-r", "44100", "-F"]).arg(&out).arg(soundfont).arg(path)
+        .output().map_err(|e| format!("could not run fluidsynth: {e}"))?;
+    if !output.status.success() || !out.exists() {
+        let _ = std::fs::remove_file(&out);
+        return Err(String::from_utf8_lossy(&output.stderr).lines().last().unwrap_or("fluidsynth failed").to_string());
+    }
+    Ok(out)
+}
+
+// lesson videos, live streams etc: the best audio-only format yt-dlp offers, so no ffmpeg is needed
+fn fetch_with_ytdlp(url: &str) -> Result<PathBuf, String> {
+    use std::hash::{Hash, Hasher};
+    let dir = cache_dir().join("yt-dlp");
+    std::fs::create_dir_all(&dir).map_err(|e| e.to_string())?;
+    let mut h = std::collections::hash_map::DefaultHasher::new();
+    url.hash(&mut h);
+    let key = format!("{:016x}", h.finish());
+    let cached = std::fs::read_dir(&dir).ok().and_then(|rd| rd.flatten().map(|e| e.path())
+        .find(|p| p.file_stem().is_some_and(|s| s.to_string_lossy() == key) && is_audio_file(p)));
+    if let Some(path) = cached { return Ok(path); }
+    let output = std::process::Command::new("yt-dlp")
+        .args(["-f", "bestaudio/best", "--no-playlist", "--print", "after_move:filepath", "-o"])
+        .arg(dir.join(format!("{key}.%(ext)s"))).arg(url)
+        .output().map_err(|e| format!("could not run yt-dlp: {e}"))?;
+    let path = String::from_utf8_lossy(&output.stdout).lines().last().map(|l| PathBuf::from(l.trim())).filter(|p| p.is_file());
+    match path {
+        Some(path) if output.status.success() => Ok(path),
+        _ => Err(String::from_utf8_lossy(&output.stderr).lines().rfind(|l| l.contains("ERROR")).unwrap_or("yt-dlp failed").to_string()),
+    }
+}
+
+const STEM_NAMES: [&str; 4] = ["vocals", "drums", "bass", "other"];
+
+#[derive(Clone, Copy, PartialEq)]
+enum StemBackend {
+    Demucs,
+    Spleeter,
+    Hpss, // built-in harmonic/percussive split, no external tool needed
+}
+
+impl StemBackend {
+    fn label(self) -> &'static str {
+        match self {
+            StemBackend::Demucs => "demucs",
+            StemBackend::Spleeter => "spleeter",
+            StemBackend::Hpss => "harmonic/percussive",
+        }
+    }
+}
+
+fn find_file(dir: &Path, name: &str) -> Option<PathBuf> {
+    for entry in std::fs::read_dir(dir).ok()?.flatten() {
+        let p = entry.path();
+        if p.is_dir() {
+            if let Some(found) = find_file(&p, name) { return Some(found); }
+        } else if p.file_name().is_some_and(|n| n == name) {
+            return Some(p);
+        }
+    }
+    None
+}
+
+// runs the external separator once per file; later calls reuse the cached stems
+fn separate_stems(path: &Path, backend: StemBackend) -> Result<Vec<(String, PathBuf)>, String> {
+    let out_dir = cache_dir().join("stems").join(file_cache_key(path));
+    let cached = |dir: &Path| -> Option<Vec<(String, PathBuf)>> {
+        STEM_NAMES.iter().map(|n| find_file(dir, &format!("{n}.wav")).map(|p| (n.to_string(), p))).collect()
+    };
+    if let Some(stems) = cached(&out_dir) { return Ok(stems); }
+
+    std::fs::create_dir_all(&out_dir).map_err(|e| e.to_string())?;
+    let mut cmd = match backend {
+        StemBackend::Demucs => {
+            let mut c = std::process::Command::new("demucs");
+            c.arg("-o").arg(&out_dir).arg(path);
+            c
+        }
+        StemBackend::Spleeter => {
+            let mut c = std::process::Command::new("spleeter");
+            c.args(["separate", "-p", "spleeter:4stems", "-o"]).arg(&out_dir).arg(path);
+            c
+        }
+        StemBackend::Hpss => return Err("HPSS has no external command".into()),
+    };
+    let output = cmd.output().map_err(|e| format!("could not run separator: {e}"))?;
+    if !output.status.success() {
+        let err = String::from_utf8_lossy(&output.stderr);
+        return Err(err.lines().last().unwrap_or("separator failed").to_string());
+    }
+    cached(&out_dir).ok_or_else(|| "separator finished but stems were not found".to_string())
+}
+
+// a whisper.cpp ggml model: REH_WHISPER_MODEL, or the first .bin in the config dir's whisper folder
+fn find_whisper_model() -> Option<PathBuf> {
+    let env = std::env::var_os("REH_WHISPER_MODEL").map(PathBuf::from).filter(|p| p.exists());
+    env.or_else(|| std::fs::read_dir(config_dir().join("whisper")).ok()?.flatten().map(|e| e.path())
+        .find(|p| p.extension().is_some_and(|e| e == "bin")))
+}
+
+// speech to text through the whisper.cpp command line tool, as time-aligned cues; the result is
+// cached per file and model since a pass over a long recording takes minutes
+fn transcribe(path: &Path, pcm: &[f32], channels: usize, sample_rate: u32, model: &Path) -> Result<Vec<SubtitleCue>, String> {
+    const WHISPER_RATE: u32 = 16000;
+    let dir = cache_dir().join("transcripts");
+    std::fs::create_dir_all(&dir).map_err(|e| e.to_string())?;
+    // the length tells an edited buffer (cut or inserted regions) from the file on disk
+    let out = dir.join(format!("{}-{}-{}", file_cache_key(path), pcm.len(), file_cache_key(model)));
+    let srt = out.with_extension("srt");
+    if !srt.exists() {
+        // whisper wants 16 kHz mono
+        let channels = channels.max(1);
+        let mono: Vec<f32> = pcm.chunks(channels).map(|f| f.iter().sum::<f32>() / channels as f32).collect();
+        let step = sample_rate as f64 / WHISPER_RATE as f64;
+        let len = (mono.len() as f64 / step) as usize;
+        let frame = |k: usize| mono.get(k).copied().unwrap_or(0.0);
+        let resampled: Vec<f32> = (0..len).map(|i| resample_at(frame, i as f64 * step, step)).collect();
+        let wav = out.with_extension("wav");
+        write_wav(&wav, &resampled, WHISPER_RATE, 1).map_err(|e| e.to_string())?;
+        // the binary was renamed from main to whisper-cli; Homebrew calls it whisper-cpp.
+        // without -l it assumes English, which garbles lessons in any other language
+        let result = ["whisper-cli", "whisper-cpp", "whisper"].iter().find_map(|bin| {
+            std::process::Command::new(bin).arg("-m").arg(model).args(["-l", "auto"]).arg("-f").arg(&wav).arg("-osrt").arg("-of").arg(&out).output().ok()
+        });
+        let _ = std::fs::remove_file(&wav);
+        let output = result.ok_or("could not run whisper-cli (is whisper.cpp installed?)")?;
+        if !output.status.success() || !srt.exists() {
+            let _ = std::fs::remove_file(&srt);
+            return Err(String::from_utf8_lossy(&output.stderr).lines().last().unwrap_or("whisper failed").to_string());
+        }
+    }
+    let text = std::fs::read_to_string(&srt).map_err(|e| e.to_string())?;
+    Ok(parse_subtitles(&text))
+}
+
+// median-filtering HPSS (Fitzgerald): harmonic energy is smooth over time, percussive energy over frequency
+fn hpss(pcm: &[f32], channels: usize) -> (Vec<f32>, Vec<f32>) {
+    const N: usize = 2048;
+    const HOP: usize = N / 4;
+    const K: usize = 17; // median length in frames and in bins
+    let channels = channels.max(1);
+    let bins = N / 2 + 1;
+    let mut planner = FftPlanner::new();
+    let fft = planner.plan_fft_forward(N);
+    let ifft = planner.plan_fft_inverse(N);
+    let window = hann(N);
+    let scale = 1.0 / (N as f32 * 1.5);
+    let zero = Complex::new(0.0f32, 0.0);
+
+    let frames_len = pcm.len() / channels;
+    let total_frames = (frames_len + N) / HOP + 1;
+    let mut harm = vec![0.0f32; pcm.len()];
+    let mut perc = vec![0.0f32; pcm.len()];
+    let mut h_buf = vec![zero; N];
+    let mut p_buf = vec![zero; N];
+    let mut column = [0.0f32; K];
+
+    for ch in 0..channels {
+        // the oldest K/2 frames are virtual silence before the start
+        let mut spectra: std::collections::VecDeque<Vec<Complex<f32>>> = (0..K / 2).map(|_| vec![zero; N]).collect();
+        let mut mags: std::collections::VecDeque<Vec<f32>> = (0..K / 2).map(|_| vec![0.0; bins]).collect();
+
+        for j in 0..total_frames + K / 2 {
+            let mut spec = if spectra.len() == K { spectra.pop_front().unwrap() } else { vec![zero; N] };
+            let mut mag = if mags.len() == K { mags.pop_front().unwrap() } else { vec![0.0; bins] };
+            let start = (j * HOP) as isize - N as isize;
+            for (i, s) in spec.iter_mut().enumerate() {
+                let pos = start + i as isize;
+                let x = if j < total_frames && pos >= 0 && (pos as usize) < frames_len { pcm[pos as usize * channels + ch] } else { 0.0 };
+                *s = Complex::new(x * window[i], 0.0);
+            }
+            fft.process(&mut spec);
+            for (m, s) in mag.iter_mut().zip(&spec) { *m = s.norm(); }
+            spectra.push_back(spec);
+            mags.push_back(mag);
+            if spectra.len() < K { continue; }
+
+            let center = &spectra[K / 2];
+            let cmag = &mags[K / 2];
+            for b in 0..bins {
+                for (k, m) in mags.iter().enumerate() { column[k] = m[b]; }
+                let h = *column.select_nth_unstable_by(K / 2, |a, b| a.total_cmp(b)).1;
+                let lo = b.saturating_sub(K / 2);
+                let hi = (b + K / 2 + 1).min(bins);
+                let mut row = [0.0f32; K];
+                row[..hi - lo].copy_from_slice(&cmag[lo..hi]);
+                let p = *row[..hi - lo].select_nth_unstable_by((hi - lo) / 2, |a, b| a.total_cmp(b)).1;
+                let mask = h * h / (h * h + p * p + 1e-12);
+                h_buf[b] = center[b] * mask;
+                p_buf[b] = center[b] * (1.0 - mask);
+                if b > 0 && b < N / 2 {
+                    h_buf[N - b] = center[N - b] * mask;
+                    p_buf[N - b] = center[N - b] * (1.0 - mask);
+                }
+            }
+            ifft.process(&mut h_buf);
+            ifft.process(&mut p_buf);
+
+            let frame_start = ((j - K / 2) * HOP) as isize - N as isize;
+            for i in 0..N {
+                let pos = frame_start + i as isize;
+                if pos < 0 || pos as usize >= frames_len { continue; }
+                let idx = pos as usize * channels + ch;
+                harm[idx] += h_buf[i].re * window[i] * scale;
+                perc[idx] += p_buf[i].re * window[i] * scale;
+            }
+        }
+    }
+    (harm, perc)
+}
+
+// colors each waveform bucket by where its energy sits: red for bass, green for mids, blue for highs
+fn band_colors(pcm: &[f32], channels: usize, sample_rate: u32, chunk_size: usize) -> Vec<egui::Color32> {
+    let channels = channels.max(1);
+    let fs = sample_rate as f32;
+    let mut low = Biquad::lowpass(fs, 250.0, 0.707);
+    let mut high = Biquad::highpass(fs, 4000.0, 0.707);
+    // buckets are measured in interleaved samples, so walk frames and attribute each to its bucket
+    let buckets = pcm.len().div_ceil(chunk_size.max(1));
+    let mut energy = vec![[0.0f32; 3]; buckets];
+    for (f, frame) in pcm.chunks_exact(channels).enumerate() {
+        let x = frame.iter().sum::<f32>() / channels as f32;
+        let l = low.process(x);
+        let h = high.process(x);
+        let m = x - l - h;
+        let e = &mut energy[(f * channels / chunk_size.max(1)).min(buckets - 1)];
+        e[0] += l * l;
+        e[1] += m * m;
+        e[2] += h * h;
+    }
+    energy.iter().map(|e| {
+        // highs carry far less energy, so lift them before normalizing
+        let (l, m, h) = (e[0].sqrt(), e[1].sqrt() * 1.5, e[2].sqrt() * 3.0);
+        let max = l.max(m).max(h).max(1e-9);
+        let c = |v: f32| (40.0 + 215.0 * (v / max)) as u8;
+        egui::Color32::from_rgb(c(l), c(m), c(h))
+    }).collect()
+}
+
+const SPECTRO_FFT: usize = 2048;
+const SPECTRO_ROWS: usize = 256;
+
+// most columns a spectrogram texture gets, to stay within texture limits
+const SPECTRO_MAX_COLUMNS: usize = 4096;
+
+// frames `range` of the file as a log-frequency spectrogram image `columns` wide, 40 Hz at the bottom
+// to the top of the spectrum; quiet is dark, loud is hot
+fn spectrogram_image(pcm: &[f32], channels: usize, sample_rate: u32, range: std::ops::Range<usize>, columns: usize) -> egui::ColorImage {
+    let channels = channels.max(1);
+    let frames = pcm.len() / channels;
+    let columns = columns.clamp(1, SPECTRO_MAX_COLUMNS);
+    let hop = range.len() as f64 / columns as f64; // under a frame when zoomed in to single samples
+    let fft = FftPlanner::new().plan_fft_forward(SPECTRO_FFT);
+    let window = hann(SPECTRO_FFT);
+    let half = SPECTRO_FFT / 2;
+    let nyquist = sample_rate as f32 / 2.0;
+    let bin = |row: usize| ((40.0 * (nyquist / 40.0).powf(row as f32 / SPECTRO_ROWS as f32)) / nyquist * half as f32) as usize;
+    let rows: Vec<(usize, usize)> = (0..SPECTRO_ROWS).map(|r| (bin(r).min(half - 1), bin(r + 1).clamp(bin(r) + 1, half))).collect();
+    let heat = |v: f32| egui::Color32::from_rgb((255.0 * v.powf(0.6)) as u8, (255.0 * v * v) as u8, (200.0 * (v * (1.0 - v) * 4.0).max(v.powi(4))) as u8);
+
+    let mut image = egui::ColorImage::new([columns, SPECTRO_ROWS], egui::Color32::BLACK);
+    let mut buf = vec![Complex::new(0.0f32, 0.0); SPECTRO_FFT];
+    for col in 0..columns {
+        // centre the window on the column
+        let centre = range.start + ((col as f64 + 0.5) * hop) as usize;
+        for (i, b) in buf.iter_mut().enumerate() {
+            let f = (centre + i).checked_sub(half).filter(|&f| f < frames);
+            let x = f.map_or(0.0, |f| pcm[f * channels..(f + 1) * channels].iter().sum::<f32>() / channels as f32);
+            *b = Complex::new(x * window[i], 0.0);
+        }
+        fft.process(&mut buf);
+        for (r, &(a, b)) in rows.iter().enumerate() {
+            let mag = buf[a..b].iter().map(|c| c.norm()).fold(0.0f32, f32::max) / (SPECTRO_FFT as f32 / 4.0);
+            let v = ((20.0 * (mag + 1e-9).log10() + 90.0) / 90.0).clamp(0.0, 1.0);
+            image.pixels[(SPECTRO_ROWS - 1 - r) * columns + col] = heat(v);
+        }
+    }
+    image
+}
+
+// what a zoomed-in spectrogram covers: waveform_gen, first and end frame, columns
+type SpectroKey = (u64, usize, usize, usize);
+
+// what a pitch track was made from: waveform_gen, loop start, loop end
+type PitchKey = (u64, usize, usize);
+// (interleaved sample index, fractional midi note) for each voiced frame
+type NoteCurve = Vec<(usize, f32)>;
+
+// the longest stretch the pitch tracker takes on: YIN is quadratic in the window, so a whole song would crawl
+const PITCH_TRACK_MAX_SECS: f32 = 30.0;
+
+// a monophonic note curve over pcm[from..to]: YIN every 10 ms on the channel mix. Single-frame octave
+// slips are put back in line with their neighbours
+fn track_pitch(pcm: &[f32], channels: usize, sample_rate: u32, from: usize, to: usize) -> NoteCurve {
+    let channels = channels.max(1);
+    let window = (sample_rate as usize / 25).max(1024);
+    let hop = (sample_rate as usize / 100).max(1);
+    let (first, last) = (from / channels, (to.min(pcm.len()) / channels).saturating_sub(window));
+    let mut mono = vec![0.0f32; window];
+    let mut points: Vec<(usize, f32)> = Vec::new();
+    let mut frame = first;
+    while frame < last {
+        for (i, m) in mono.iter_mut().enumerate() {
+            let f = frame + i;
+            *m = pcm[f * channels..(f + 1) * channels].iter().sum::<f32>() / channels as f32;
+        }
+        // the estimate belongs to the middle of the window
+        if let Some(hz) = yin_pitch(&mono, sample_rate, 60.0, 1500.0) { points.push(((frame + window / 2) * channels, freq_to_midi(hz))); }
+        frame += hop;
+    }
+    for i in 1..points.len().saturating_sub(1) {
+        let (a, b, c) = (points[i - 1].1, points[i].1, points[i + 1].1);
+        for octave in [-12.0, 12.0] {
+            if (b + octave - a).abs() < 1.0 && (b + octave - c).abs() < 1.0 { points[i].1 = b + octave; }
+        }
+    }
+    points
+}
+
+// 12-bin pitch-class profiles every `hop` frames, L2-normalized
+fn chroma_frames(pcm: &[f32], channels: usize, sample_rate: u32, hop: usize) -> Vec<[f32; 12]> {
+    const N: usize = 4096;
+    let channels = channels.max(1);
+    let frames_len = pcm.len() / channels;
+    let fft = FftPlanner::new().plan_fft_forward(N);
+    let window = hann(N);
+    let bin_class: Vec<Option<usize>> = (0..N / 2).map(|k| {
+        let f = k as f32 * sample_rate as f32 / N as f32;
+        if !(55.0..5000.0).contains(&f) { return None; }
+        Some(((12.0 * (f / 440.0).log2()).round() as i32 + 69).rem_euclid(12) as usize)
+    }).collect();
+
+    let mut buf = vec![Complex::new(0.0f32, 0.0); N];
+    let mut out = Vec::new();
+    let mut start = 0;
+    while start + N <= frames_len {
+        for (i, b) in buf.iter_mut().enumerate() {
+            let frame = &pcm[(start + i) * channels..(start + i + 1) * channels];
+            *b = Complex::new(frame.iter().sum::<f32>() / channels as f32 * window[i], 0.0);
+        }
+        fft.process(&mut buf);
+        let mut chroma = [0.0f32; 12];
+        for (k, class) in bin_class.iter().enumerate() {
+            if let Some(c) = class { chroma[*c] += buf[k].norm_sqr(); }
+        }
+        let norm = chroma.iter().map(|x| x * x).sum::<f32>().sqrt().max(1e-9);
+        chroma.iter_mut().for_each(|x| *x /= norm);
+        out.push(chroma);
+        start += hop;
+    }
+    out
+}
+
+// finds sections that recur (chorus, riff) from diagonal stripes in a chroma self-similarity matrix
+fn suggest_loops(pcm: &[f32], channels: usize, sample_rate: u32) -> Vec<LoopSuggestion> {
+    let hop = sample_rate as usize / 4; // 250 ms
+    let smooth = 8; // 2 s context per feature
+    let min_len = 32; // 8 s
+    // quadratic in length, and long recordings (lectures, podcasts) rarely have a chorus anyway
+    if pcm.len() / channels.max(1) > sample_rate as usize * 20 * 60 { return Vec::new(); }
+    let chroma = chroma_frames(pcm, channels, sample_rate, hop);
+    if chroma.len() < smooth + 2 * min_len { return Vec::new(); }
+
+    let feats: Vec<[f32; 12]> = chroma.windows(smooth).map(|w| {
+        let mut f = [0.0f32; 12];
+        for c in w { for k in 0..12 { f[k] += c[k]; } }
+        let norm = f.iter().map(|x| x * x).sum::<f32>().sqrt().max(1e-9);
+        f.iter_mut().for_each(|x| *x /= norm);
+        f
+    }).collect();
+    let n = feats.len();
+    let sim = |a: usize, b: usize| (0..12).map(|k| feats[a][k] * feats[b][k]).sum::<f32>();
+
+    // chroma of a song in one key is similar almost everywhere, so threshold relative to the whole matrix
+    let (mut sum, mut sum_sq, mut count) = (0.0f64, 0.0f64, 0usize);
+    for lag in (min_len..n).step_by(4) {
+        for i in (0..n - lag).step_by(4) {
+            let v = sim(i, i + lag) as f64;
+            sum += v;
+            sum_sq += v * v;
+            count += 1;
+        }
+    }
+    if count == 0 { return Vec::new(); }
+    let mean = sum / count as f64;
+    let threshold = (mean + (sum_sq / count as f64 - mean * mean).max(0.0).sqrt()) as f32;
+
+    let mut segments: Vec<(usize, usize)> = Vec::new();
+    for lag in min_len..n {
+        let mut run_start: Option<usize> = None;
+        let mut misses = 0;
+        for i in 0..=n - lag {
+            let hit = i < n - lag && sim(i, i + lag) > threshold;
+            if hit {
+                if run_start.is_none() { run_start = Some(i); }
+                misses = 0;
+            } else if let Some(rs) = run_start {
+                misses += 1;
+                if misses > 2 || i == n - lag {
+                    let end = i - misses + 1;
+                    if end - rs >= min_len {
+                        segments.push((rs, end));
+                        segments.push((rs + lag, end + lag));
+                    }
+                    run_start = None;
+                    misses = 0;
+                }
+            }
+        }
+    }
+
+    // group occurrences of the same section by overlap, then keep the best non-overlapping groups
+    let overlap = |a: (usize, usize), b: (usize, usize)| {
+        let inter = a.1.min(b.1).saturating_sub(a.0.max(b.0));
+        inter as f32 / (a.1.max(b.1) - a.0.min(b.0)) as f32
+    };
+    let mut groups: Vec<((usize, usize), usize)> = Vec::new();
+    for seg in segments {
+        match groups.iter_mut().find(|g| overlap(g.0, seg) > 0.5) {
+            Some(g) => g.1 += 1,
+            None => groups.push((seg, 1)),
+        }
+    }
+    groups.sort_by_key(|g| std::cmp::Reverse(g.1 * (g.0.1 - g.0.0)));
+
+    let mut picked: Vec<((usize, usize), usize)> = Vec::new();
+    for g in groups {
+        if picked.len() == 6 { break; }
+        if picked.iter().any(|p| overlap(p.0, g.0) > 0.0) { continue; }
+        picked.push(g);
+    }
+    picked.sort_by_key(|g| g.0.0);
+    let channels = channels.max(1);
+    picked.into_iter().map(|((a, b), repeats)| LoopSuggestion {
+        start: a * hop * channels,
+        end: ((b + smooth) * hop * channels).min(pcm.len()),
+        // each stripe contributes both occurrences, so halve and count the original
+        repeats: repeats / 2 + 1,
+    }).collect()
+}
+
+// three or more consecutive full-scale samples on a channel count as clipping; nearby runs are merged
+fn detect_clipping(pcm: &[f32], channels: usize, sample_rate: u32) -> Vec<(usize, usize)> {
+    let channels = channels.max(1);
+    let merge_gap = sample_rate as usize / 100 * channels; // 10 ms
+    let frames = pcm.len() / channels;
+    let mut found: Vec<(usize, usize)> = Vec::new();
+    let mut runs = vec![0usize; channels];
+    for (frame, samples) in pcm.chunks_exact(channels).enumerate() {
+        for (ch, &x) in samples.iter().enumerate() {
+            if x.abs() >= 0.999 {
+                runs[ch] += 1;
+                continue;
+            }
+            if runs[ch] >= 3 { found.push(((frame - runs[ch]) * channels, frame * channels)); }
+            runs[ch] = 0;
+        }
+    }
+    // runs still open when the file ends
+    for &run in runs.iter().filter(|&&run| run >= 3) { found.push(((frames - run) * channels, frames * channels)); }
+    // runs close in end order, and a long one on one channel can start before a short one on another
+    found.sort_unstable();
+    let mut regions: Vec<(usize, usize)> = Vec::new();
+    for (start, end) in found {
+        match regions.last_mut() {
+            Some(last) if start <= last.1 + merge_gap => last.1 = last.1.max(end),
+            _ => regions.push((start, end)),
+        }
+    }
+    regions
+}
+
+// spectral-flux onset detection, refined to the point where the envelope actually rises
+const ONSET_N: usize = 1024;
+const ONSET_HOP: usize = 256;
+
+// spectral flux every ONSET_HOP frames: how much new energy each frame brings
+fn onset_flux(pcm: &[f32], channels: usize) -> Vec<f32> {
+    const N: usize = ONSET_N;
+    const HOP: usize = ONSET_HOP;
+    let channels = channels.max(1);
+    let frames_len = pcm.len() / channels;
+    let mono = |i: usize| pcm[i * channels..(i + 1) * channels].iter().sum::<f32>() / channels as f32;
+
+    let fft = FftPlanner::new().plan_fft_forward(N);
+    let window = hann(N);
+    let mut buf = vec![Complex::new(0.0f32, 0.0); N];
+    let mut prev = vec![0.0f32; N / 2 + 1];
+    let mut flux = Vec::new();
+    let mut start = 0;
+    while start + N <= frames_len {
+        for (i, b) in buf.iter_mut().enumerate() { *b = Complex::new(mono(start + i) * window[i], 0.0); }
+        fft.process(&mut buf);
+        let mut f = 0.0;
+        for (p, b) in prev.iter_mut().zip(&buf) {
+            let m = (1.0 + 10.0 * b.norm()).ln();
+            f += (m - *p).max(0.0);
+            *p = m;
+        }
+        flux.push(f);
+        start += HOP;
+    }
+    flux
+}
+
+fn detect_onsets(pcm: &[f32], channels: usize, sample_rate: u32) -> Vec<usize> {
+    const N: usize = ONSET_N;
+    const HOP: usize = ONSET_HOP;
+    let channels = channels.max(1);
+    let frames_len = pcm.len() / channels;
+    if frames_len < N { return Vec::new(); }
+    let mono = |i: usize| pcm[i * channels..(i + 1) * channels].iter().sum::<f32>() / channels as f32;
+    let flux = onset_flux(pcm, channels);
+
+    let mean = flux.iter().sum::<f32>() / flux.len() as f32;
+    let min_gap = (sample_rate as usize / 20 / HOP).max(1); // 50 ms
+    let mut onsets = Vec::new();
+    let mut last: Option<usize> = None;
+    for j in 1..flux.len().saturating_sub(1) {
+        let lo = j.saturating_sub(8);
+        let hi = (j + 9).min(flux.len());
+        let mut local = flux[lo..hi].to_vec();
+        let mid = local.len() / 2;
+        let median = *local.select_nth_unstable_by(mid, |a, b| a.total_cmp(b)).1;
+        let is_peak = flux[j] >= flux[j - 1] && flux[j] > flux[j + 1];
+        if !is_peak || flux[j] < median * 1.5 + mean * 0.2 { continue; }
+        if last.is_some_and(|l| j - l < min_gap) { continue; }
+        last = Some(j);
+
+        // the flux peak only says "somewhere in this frame"; find the first short block that reaches half the local peak energy
+        let from = (j * HOP + N / 2).saturating_sub(2 * HOP);
+        let to = (j * HOP + N / 2 + HOP).min(frames_len);
+        let energy: Vec<f32> = (from..to).step_by(32).map(|b| (b..(b + 32).min(to)).map(|i| mono(i).powi(2)).sum()).collect();
+        let peak = energy.iter().cloned().fold(0.0f32, f32::max);
+        let first = energy.iter().position(|&e| e >= peak * 0.5).unwrap_or(0);
+        onsets.push((from + first * 32) * channels);
+    }
+    onsets
+}
+
+// the frame nearest `at` (a sample index) where the mix rises through zero, within radius frames;
+// with both loop markers on rising crossings the wrap joins without a step
+fn nearest_zero_crossing(pcm: &[f32], channels: usize, at: usize, radius: usize) -> usize {
+    let channels = channels.max(1);
+    let frames = pcm.len() / channels;
+    let mono = |f: usize| pcm[f * channels..(f + 1) * channels].iter().sum::<f32>();
+    let centre = at / channels;
+    if frames < 2 || centre >= frames { return at; }
+    let rising = |f: usize| f > 0 && f < frames && mono(f - 1) < 0.0 && mono(f) >= 0.0;
+    (0..=radius).flat_map(|d| [centre.checked_sub(d), Some(centre + d)]).flatten()
+        .find(|&f| rising(f)).map_or(at, |f| f * channels)
+}
+
+// Krumhansl-Kessler key profiles, C major and C minor
+const KEY_PROFILES: [[f32; 12]; 2] = [
+    [6.35, 2.23, 3.48, 2.33, 4.38, 4.09, 2.52, 5.19, 2.39, 3.66, 2.29, 2.88],
+    [6.33, 2.68, 3.52, 5.38, 2.60, 3.53, 2.54, 4.75, 3.98, 2.69, 3.34, 3.17],
+];
+
+// the key whose profile best correlates with the file's summed chroma (Krumhansl-Schmuckler),
+// None when the file is too short or too flat (speech, drums) to say
+fn detect_key(pcm: &[f32], channels: usize, sample_rate: u32) -> Option<MusicalKey> {
+    let chroma = chroma_frames(pcm, channels, sample_rate, sample_rate as usize / 2);
+    if chroma.len() < 8 { return None; }
+    let mut total = [0.0f32; 12];
+    for c in &chroma { for k in 0..12 { total[k] += c[k]; } }
+    let correlate = |a: &[f32; 12], b: &dyn Fn(usize) -> f32| {
+        let (ma, mb) = (a.iter().sum::<f32>() / 12.0, (0..12).map(b).sum::<f32>() / 12.0);
+        let (mut num, mut da, mut db) = (0.0, 0.0, 0.0);
+        for (k, v) in a.iter().enumerate() {
+            let (x, y) = (v - ma, b(k) - mb);
+            num += x * y;
+            da += x * x;
+            db += y * y;
+        }
+        num / (da * db).sqrt().max(1e-9)
+    };
+    let (best, r) = (0..24).map(|i| {
+        let (tonic, minor) = (i % 12, i >= 12);
+        let profile = &KEY_PROFILES[minor as usize];
+        (MusicalKey { tonic: tonic as i32, minor }, correlate(&total, &|k| profile[(k + 12 - tonic) % 12]))
+    }).max_by(|a, b| a.1.total_cmp(&b.1))?;
+    (r > 0.5).then_some(best)
+}
+
+// tempo from the autocorrelation of the onset envelope, leaning towards 120 bpm so half or double
+// time only wins when it's clearly stronger; the downbeat is the phase where the beats hit most onsets
+fn detect_tempo(pcm: &[f32], channels: usize, sample_rate: u32) -> Option<TempoGrid> {
+    let flux = onset_flux(pcm, channels);
+    let fps = sample_rate as f64 / ONSET_HOP as f64;
+    let lag_of = |bpm: f64| 60.0 * fps / bpm;
+    let (lo, hi) = (lag_of(200.0).floor() as usize, lag_of(60.0).ceil() as usize);
+    if flux.len() < hi * 4 { return None; }
+    let mean = flux.iter().map(|&f| f as f64).sum::<f64>() / flux.len() as f64;
+    let env: Vec<f64> = flux.iter().map(|&f| f as f64 - mean).collect();
+    let ac = |lag: usize| env.iter().zip(&env[lag..]).map(|(a, b)| a * b).sum::<f64>() / (env.len() - lag) as f64;
+    let acs: Vec<f64> = (lo - 1..=hi + 1).map(ac).collect();
+    let weight = |lag: f64| (-0.5 * (60.0 * fps / lag / 120.0).log2().powi(2)).exp();
+    let best = (1..acs.len() - 1).max_by(|&a, &b| (acs[a] * weight((a + lo - 1) as f64)).total_cmp(&(acs[b] * weight((b + lo - 1) as f64))))?;
+    if acs[best] <= 0.0 { return None; }
+    // parabolic peak between the neighbouring lags
+    let (y0, y1, y2) = (acs[best - 1], acs[best], acs[best + 1]);
+    let denom = y0 - 2.0 * y1 + y2;
+    let shift = if denom.abs() > 1e-12 { (0.5 * (y0 - y2) / denom).clamp(-0.5, 0.5) } else { 0.0 };
+    let lag = (best + lo - 1) as f64 + shift;
+
+    let phase = (0..lag.round() as usize).max_by(|&a, &b| {
+        let score = |o: usize| (0..).map(|k| o + (k as f64 * lag).round() as usize).take_while(|&i| i < env.len()).map(|i| env[i]).sum::<f64>();
+        score(a).total_cmp(&score(b))
+    })?;
+    let bpm = (60.0 * fps / lag * 10.0).round() as f32 / 10.0;
+    Some(TempoGrid { bpm, downbeat: (phase * ONSET_HOP + ONSET_N / 2) * channels.max(1) })
+}
+
+// energy-based segmentation: phrases are louder runs separated by at least 250 ms of near-silence
+fn segment_phrases(pcm: &[f32], channels: usize, sample_rate: u32) -> Vec<(usize, usize)> {
+    let channels = channels.max(1);
+    let window = (sample_rate as usize / 50).max(1) * channels; // 20 ms
+    let rms: Vec<f32> = pcm.chunks(window)
+        .map(|w| (w.iter().map(|x| x * x).sum::<f32>() / w.len() as f32).sqrt())
+        .collect();
+    if rms.is_empty() { return Vec::new(); }
+
+    let mut sorted = rms.clone();
+    sorted.sort_by(|a, b| a.total_cmp(b));
+    let noise_floor = sorted[sorted.len() / 10];
+    let peak = sorted[sorted.len() - 1];
+    let threshold = (noise_floor * 3.0).max(peak * 0.03);
+
+    let min_gap = 13; // windows of silence that end a phrase
+    let min_len = 10; // shorter blips are ignored
+    let pad = 3;
+
+    let mut phrases = Vec::new();
+    let mut start: Option<usize> = None;
+    let mut silent_run = 0;
+    for (i, &level) in rms.iter().enumerate() {
+        if level >= threshold {
+            if start.is_none() { start = Some(i); }
+            silent_run = 0;
+        } else if let Some(s) = start {
+            silent_run += 1;
+            if silent_run >= min_gap {
+                let end = i + 1 - silent_run;
+                if end - s >= min_len { phrases.push((s, end)); }
+                start = None;
+            }
+        }
+    }
+    if let Some(s) = start && rms.len() - s >= min_len {
+        phrases.push((s, rms.len()));
+    }
+
+    phrases.into_iter()
+        .map(|(s, e)| (s.saturating_sub(pad) * window, ((e + pad) * window).min(pcm.len())))
+        .collect()
+}
+
+// accepts "hh:mm:ss,mmm" (SRT) as well as "mm:ss.mmm" / "hh:mm:ss.mmm" (VTT)
+fn parse_timestamp(t: &str) -> Option<f64> {
+    let t = t.trim().replace(',', ".");
+    let mut secs = 0.0;
+    for part in t.split(':') {
+        secs = secs * 60.0 + part.parse::<f64>().ok()?;
+    }
+    Some(secs)
+}
+
+fn parse_subtitles(text: &str) -> Vec<SubtitleCue> {
+    let text = text.replace("\r\n", "\n");
+    let mut cues = Vec::new();
+    for block in text.split("\n\n") {
+        let mut lines = block.lines().skip_while(|l| !l.contains("-->"));
+        let Some(timing) = lines.next() else { continue };
+        let mut parts = timing.split("-->");
+        let start = parts.next().and_then(parse_timestamp);
+        // VTT allows cue settings after the end time
+        let end = parts.next().and_then(|p| p.split_whitespace().next()).and_then(parse_timestamp);
+        let (Some(start), Some(end)) = (start, end) else { continue };
+
+        let mut cue_text = String::new();
+        for line in lines {
+            if !cue_text.is_empty() { cue_text.push('\n'); }
+            // drop formatting tags like <i> or <c.yellow>
+            let mut in_tag = false;
+            for ch in line.chars() {
+                match ch {
+                    '<' => in_tag = true,
+                    '>' => in_tag = false,
+                    _ if !in_tag => cue_text.push(ch),
+                    _ => {}
+                }
+            }
+        }
+        cues.push(SubtitleCue { start, end, text: cue_text });
+    }
+    cues
+}
+
+#[derive(Clone)]
+struct SetlistItem {
+    name: String,
+    path: PathBuf,
+    start: f64, // seconds
+    end: f64,
+}
+
+enum SetlistPhase {
+    Loading,
+    Playing { last_cursor: usize, end: usize },
+    Gap(std::time::Instant), // silent until then, then the next item
+}
+
+struct Setlist {
+    items: Vec<SetlistItem>,
+    gap_secs: f32,
+    new_name: String,
+    running: Option<(usize, SetlistPhase)>,
+}
+
+struct Playlist {
+    tracks: Vec<PathBuf>,
+    current: Option<usize>,
+    auto_advance: bool, // go on to the next track when one ends instead of stopping there
+}
+
+// raises the speed a step every few passes through the loop until it reaches the target
+struct SpeedTrainer {
+    enabled: bool,
+    step: f32, // percentage points added per step
+    every: u32, // loop passes per step
+    target: f32, // speed factor to stop at
+    passes: u32, // passes since the last step
+}
+
+struct Episode {
+    title: String,
+    url: String,
+    published: String,
+}
+
+struct Feed {
+    url: String,
+    title: String,
+    episodes: Vec<Episode>,
+}
+
+// a feed URL and its fetched title and episodes, sent back from the fetch thread
+type FeedUpdate = (String, Result<(String, Vec<Episode>), String>);
+
+struct Podcasts {
+    feeds: Vec<Feed>,
+    new_url: String,
+    status: String,
+    tx: Sender<FeedUpdate>,
+    rx: Receiver<FeedUpdate>,
+}
+
+struct FolderWatch {
+    dir: PathBuf,
+    rx: Receiver<PathBuf>,
+    stop: Arc<AtomicBool>,
+    auto_load: bool,
+}
+
+impl Drop for FolderWatch {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+    }
+}
+
+// holds off idle sleep and screen locking while it is active
+#[derive(Default)]
+struct SleepInhibitor {
+    #[cfg(not(windows))]
+    child: Option<std::process::Child>, // systemd-inhibit / caffeinate, released by killing it
+    #[cfg(not(windows))]
+    unavailable: bool, // the helper is missing, don't retry every frame
+    #[cfg(windows)]
+    active: bool,
+}
+
+impl SleepInhibitor {
+    fn set(&mut self, active: bool) {
+        #[cfg(not(windows))]
+        {
+            if active == self.child.is_some() || (active && self.unavailable) { return; }
+            if let Some(mut child) = self.child.take() {
+                let _ = child.kill();
+                let _ = child.wait();
+                return;
+            }
+            let mut cmd = if cfg!(target_os = "macos") {
+                let mut c = std::process::Command::new("caffeinate");
+                c.arg("-dis");
+                c
+            } else {
+                let mut c = std::process::Command::new("systemd-inhibit");
+                c.args(["--what=idle:sleep", "--who=reh", "--why=Playing audio", "--mode=block", "sleep", "infinity"]);
+                c
+            };
+            self.child = cmd.stdout(std::process::Stdio::null()).stderr(std::process::Stdio::null()).spawn().ok();
+            self.unavailable = self.child.is_none();
+        }
+        #[cfg(windows)]
+        {
+            use windows_sys::Win32::System::Power::{SetThreadExecutionState, ES_CONTINUOUS, ES_DISPLAY_REQUIRED, ES_SYSTEM_REQUIRED};
+            if active == self.active { return; }
+            self.active = active;
+            let flags = if active { ES_CONTINUOUS | ES_SYSTEM_REQUIRED | ES_DISPLAY_REQUIRED } else { ES_CONTINUOUS };
+            unsafe { SetThreadExecutionState(flags); }
+        }
+    }
+}
+
+impl Drop for SleepInhibitor {
+    fn drop(&mut self) {
+        self.set(false);
+    }
+}
+
+#[derive(Clone, Copy)]
+enum TrayAction {
+    Show,
+    PlayPause,
+    Back,
+    NextLoop,
+    Quit,
+}
+
+// slides the loop region one loop-length along, clamped to the file
+fn shift_loop(c: &AudioControls, total_samples: usize, forward: bool) {
+    let l_start = c.loop_start.load(Ordering::Relaxed);
+    let l_end = c.loop_end.load(Ordering::Relaxed);
+    let width = l_end.saturating_sub(l_start);
+    let (start, end) = if forward {
+        let shift = total_samples.saturating_sub(l_end).min(width);
+        (l_start + shift, l_end + shift)
+    } else {
+        let shift = l_start.min(width);
+        (l_start - shift, l_end - shift)
+    };
+    c.loop_start.store(start, Ordering::Relaxed);
+    c.loop_end.store(end, Ordering::Relaxed);
+}
+
+fn tray_image(playing: bool) -> Option<tray_icon::Icon> {
+    const SIZE: usize = 32;
+    let mut rgba = vec![0u8; SIZE * SIZE * 4];
+    for y in 0..SIZE {
+        for x in 0..SIZE {
+            let dy = (y as i32 - 16).abs();
+            // a play triangle while playing, pause bars otherwise
+            let on = if playing { (8..26).contains(&x) && dy * 2 <= 26 - x as i32 } else { ((9..14).contains(&x) || (18..23).contains(&x)) && dy < 9 };
+            if on {
+                let px = &mut rgba[(y * SIZE + x) * 4..][..4];
+                px.copy_from_slice(&if playing { [0, 180, 100, 255] } else { [170, 170, 170, 255] });
+            }
+        }
+    }
+    tray_icon::Icon::from_rgba(rgba, SIZE as u32, SIZE as u32).ok()
+}
+
+// menu actions run straight on the shared state, so they still work while the window is hidden
+fn build_tray(ctx: egui::Context, controls: Arc<AudioControls>, state: Arc<Mutex<AppState>>) -> Result<tray_icon::TrayIcon, String> {
+    use tray_icon::menu::{Menu, MenuEvent, MenuItem, PredefinedMenuItem};
+    let items = [
+        (MenuItem::new("Show Reh", true, None), TrayAction::Show),
+        (MenuItem::new("Play / Pause", true, None), TrayAction::PlayPause),
+        (MenuItem::new("Back 5 s", true, None), TrayAction::Back),
+        (MenuItem::new("Next loop", true, None), TrayAction::NextLoop),
+        (MenuItem::new("Quit", true, None), TrayAction::Quit),
+    ];
+    let menu = Menu::new();
+    for (i, (item, _)) in items.iter().enumerate() {
+        if i == items.len() - 1 { menu.append(&PredefinedMenuItem::separator()).map_err(|e| e.to_string())?; }
+        menu.append(item).map_err(|e| e.to_string())?;
+    }
+    let ids: Vec<_> = items.iter().map(|(item, action)| (item.id().clone(), *action)).collect();
+    let show_ctx = ctx.clone();
+    MenuEvent::set_event_handler(Some(move |event: MenuEvent| {
+        let Some(&(_, action)) = ids.iter().find(|(id, _)| *id == event.id) else { return };
+        let c = &controls;
+        match action {
+            TrayAction::Show => {
+                ctx.send_viewport_cmd(egui::ViewportCommand::Visible(true));
+                ctx.send_viewport_cmd(egui::ViewportCommand::Minimized(false));
+                ctx.send_viewport_cmd(egui::ViewportCommand::Focus);
+            }
+            TrayAction::PlayPause => { c.is_playing.fetch_xor(true, Ordering::Relaxed); }
+            TrayAction::Back => {
+                let step = { let s = state.lock().unwrap(); 5 * s.sample_rate as usize * s.channels };
+                c.cursor.store(c.cursor.load(Ordering::Relaxed).saturating_sub(step), Ordering::Relaxed);
+            }
+            TrayAction::NextLoop => {
+                shift_loop(c, state.lock().unwrap().total_samples, true);
+                c.cursor.store(c.loop_start.load(Ordering::Relaxed), Ordering::Relaxed);
+            }
+            TrayAction::Quit => ctx.send_viewport_cmd(egui::ViewportCommand::Close),
+        }
+        ctx.request_repaint();
+    }));
+    tray_icon::TrayIconEvent::set_event_handler(Some(move |event: tray_icon::TrayIconEvent| {
+        if let tray_icon::TrayIconEvent::DoubleClick { .. } = event {
+            show_ctx.send_viewport_cmd(egui::ViewportCommand::Visible(true));
+            show_ctx.send_viewport_cmd(egui::ViewportCommand::Focus);
+        }
+    }));
+    tray_icon::TrayIconBuilder::new()
+        .with_menu(Box::new(menu))
+        .with_tooltip("Reh")
+        .with_icon(tray_image(false).ok_or("bad tray icon")?)
+        .build()
+        .map_err(|e| e.to_string())
+}
+
+struct PlayerApp {
+    state: Arc<Mutex<AppState>>,
+    controls: Arc<AudioControls>,
+    dragging_marker: Option<bool>, 
+    audition_edges: bool,
+    last_audition: (std::time::Instant, usize), // when and where the last edge snippet started
+    _stream: Option<cpal::Stream>,
+    param_rx: Receiver<ParamUpdate>, // kept so the output stream can be rebuilt on another device
+    output_device: Option<String>, // None for the system default
+    output_status: String,
+    tx: Sender<ParamUpdate>,
+    playback_mode: PlaybackMode,
+    dictation: Dictation,
+    tone: ToneSettings,
+    metronome: MetronomeSettings,
+    drone: DroneSettings,
+    beat_jump: u32,
+    nudge_percent: f32,
+    nudge_active: f32,
+    denoise: DenoiseSettings,
+    stem_backend: StemBackend,
+    stem_channels: Vec<StemChannel>,
+    use_stems: bool,
+    snap_transients: bool,
+    snap_grid: u32, // beats per snap step on the tempo grid: 0 off, 1 beats, 4 bars
+    snap_zero: bool, // move released loop markers onto the nearest rising zero crossing
+    spectral_colors: bool,
+    wave_texture: Option<((u64, bool), egui::TextureHandle)>,
+    wave_view: (f64, f64), // visible part of the file as fractions of its length
+    zoom_textures: Vec<Option<(ZoomKey, egui::TextureHandle)>>, // per mipmap lane
+    split_channels: bool, // one waveform lane per channel instead of the mix
+    show_spectrogram: bool,
+    show_pitch: bool,
+    pitch_curve: Option<(PitchKey, NoteCurve)>,
+    pitch_job: Option<(PitchKey, Receiver<NoteCurve>)>,
+    midi_quantize: u32, // grid steps per beat for the melody export, 0 for none
+    taps: Vec<std::time::Instant>, // tap tempo, cleared after a pause
+    spectrogram: Option<(u64, egui::TextureHandle)>, // for the waveform generation it was computed from
+    spectrogram_view: Option<(SpectroKey, egui::TextureHandle)>, // sharper one around the zoomed-in view
+    spectrogram_view_job: Option<(SpectroKey, Receiver<egui::ColorImage>)>,
+    spectrogram_job: Option<(u64, Receiver<egui::ColorImage>)>,
+    whisper_model: Option<PathBuf>,
+    pitch_semitones: bool, // pitch control in semitones and cents rather than as a ratio
+    soundfont: Option<PathBuf>,
+    scope_rx: Option<HeapConsumer<(f32, f32)>>, // L/R pairs from the audio thread for the goniometer
+    scope_points: std::collections::VecDeque<(f32, f32)>,
+    output_rate: u32,
+    output_channels: usize,
+    output_devices: Vec<String>,
+    second_output: Option<(String, cpal::Stream)>,
+    cue_output: Option<(String, cpal::Stream)>,
+    keep_awake: bool,
+    system_status: String,
+    sleep_inhibitor: SleepInhibitor,
+    minimize_to_tray: bool,
+    tray_started: bool,
+    #[cfg(not(target_os = "linux"))]
+    tray: Option<(tray_icon::TrayIcon, bool)>, // icon and the play state it currently shows
+    playlist: Playlist,
+    setlist: Setlist,
+    watch: Option<FolderWatch>,
+    ab: AbCompare,
+    stretch_advanced: StretchAdvanced,
+    leveler: LevelerSettings,
+    band_solo: BandSoloSettings,
+    karaoke: KaraokeSettings,
+    eq: EqSettings,
+    effect_layout: Vec<(EffectKind, bool)>, // chain order as shown in the UI, with bypass flags
+    target_key: Option<i32>, // tonic to transpose to, keeps the file's mode
+    plugin_names: Vec<(u32, String, PathBuf, Vec<ClapParam>)>, // id, name, the file to reload it from when the output is rebuilt, and its parameters
+    next_plugin_id: u32,
+    plugin_status: String,
+    retired_effects: Option<Receiver<Box<dyn AudioEffect>>>, // removed effects, dropped on this thread
+    retired_stretchers: Option<Receiver<Vec<Stretch>>>, // replaced by another preset, likewise
+    loudness_comp: bool,
+    limiter: bool,
+    normalize: (bool, f32), // on, target LUFS
+    channel_matrix: ChannelMatrix,
+    limiter_shown: f32, // gain reduction in dB, held a moment so it can be read
+    speed_lane: AutomationLane,
+    volume_lane: AutomationLane,
+    volume_dirty: bool, // envelope edited since the sidecar was last written
+    pitch_lane: AutomationLane,
+    recorder: Option<Recorder>,
+    sing_along: Option<SingAlong>,
+    sing_along_status: String,
+    monitor: Option<InputMonitor>,
+    edit_undo: Vec<EditUndo>,
+    seek_history: SeekHistory,
+    keymap: Vec<(egui::Modifiers, egui::Key, Action)>,
+    profile: usize, // index into PROFILES
+    extensions: Vec<Extension>,
+    ext_seen: ExtSeen,
+    macros: Macros,
+    monitor_status: String,
+    take_overlay: Option<TakeOverlay>,
+    take_status: String,
+    take_latency_ms: f32, // skipped from the start of a take's capture
+    take_latency_measured: bool,
+    open_tx: Sender<PathBuf>, // background work (downloads) hands finished files back to the UI
+    open_rx: Receiver<PathBuf>,
+    login_tx: Sender<String>, // a download that was refused, to retry once the user logs in
+    login_rx: Receiver<String>,
+    load_error_tx: Sender<String>, // why the load thread gave up on a file
+    load_error_rx: Receiver<String>,
+    load_error: Option<String>, // shown in a banner until dismissed or the next load
+    load_cancel: Arc<AtomicBool>, // set to abandon the running load, replaced for each new one
+    track_choice: Option<u32>, // audio track picked from a container with several, None for its default
+    logins: std::collections::HashMap<String, Login>, // by host
+    location_prompt: Option<LocationPrompt>,
+    pending_restore: Option<PendingRestore>,
+    export: Option<ExportJob>,
+    export_status: String,
+    podcasts: Podcasts,
+    remote_rx: Receiver<RemoteCommand>,
+    lan_sync: LanSync,
+    remote_queue: std::collections::VecDeque<RemoteCommand>,
+    seen_wraps: usize, // controls.loop_wraps as of the last poll
+    trainer: SpeedTrainer,
+    loop_count: u32, // passes through the current loop region
+    loop_counted: (usize, usize), // the region loop_count belongs to, moving it starts again
+    stop_after_enabled: bool,
+    stop_after: u32, // passes to play before pausing at the loop start
+    count_in: bool, // click a bar in before playing from the loop start
+    markers: Vec<Marker>, // sorted by position
+    show_markers: bool,
+    focus_marker: Option<usize>, // a just-added marker whose label gets the keyboard
+    goto_text: String,
+    goto_bad: bool,
+    nudge_secs: f32, // plain arrow keys
+    tuning_cents: f32,
+    reference_hz: f32, // the A4 a recording was tuned to, for working out tuning_cents
+    meter_peaks: [f32; 2], // falling peak bars
+    meter_hold: [(f32, std::time::Instant); 2],
+    clipped: bool,
+}
+
+struct StemChannel {
+    volume: f32,
+    mute: bool,
+    solo: bool,
+}
+
+// the inactive side of an A/B comparison, parked with everything needed to swap it back in
+struct Deck {
+    state: AppState,
+    pcm: Arc<Vec<f32>>,
+    stream: Option<Arc<StreamBuffer>>,
+    stem_mix: Arc<StemMix>,
+    stem_channels: Vec<StemChannel>,
+}
+
+struct AbCompare {
+    other: Option<Deck>,
+    on_b: bool,
+    gain_db: [f32; 2], // A, B
+    offset_secs: f32, // where B's timeline sits relative to A's
+}
+
+struct StretchAdvanced {
+    formats: Vec<(usize, u32, StretchTuning)>, // custom tuning per output channel count and sample rate
+    tonality: (bool, f32), // Hz
+}
+
+impl StretchAdvanced {
+    fn tuning_for(&self, channels: usize, rate: u32) -> Option<StretchTuning> {
+        self.formats.iter().find(|f| f.0 == channels && f.1 == rate).map(|f| f.2)
+    }
+
+    // stretch_presets.txt: one "channels<TAB>rate<TAB>block ms<TAB>interval ms" line per format
+    fn load_formats() -> Vec<(usize, u32, StretchTuning)> {
+        let text = std::fs::read_to_string(config_dir().join("stretch_presets.txt")).unwrap_or_default();
+        text.lines().filter_map(|line| {
+            let f: Vec<&str> = line.split('\t').collect();
+            let [channels, rate, block, interval] = f[..] else { return None };
+            Some((channels.parse().ok()?, rate.parse().ok()?, StretchTuning { block_ms: block.parse().ok()?, interval_ms: interval.parse().ok()? }))
+        }).collect()
+    }
+
+    fn save_formats(&self) {
+        let text: String = self.formats.iter().map(|(c, r, t)| format!("{c}\t{r}\t{}\t{}\n", t.block_ms, t.interval_ms)).collect();
+        let _ = std::fs::create_dir_all(config_dir());
+        if let Err(e) = std::fs::write(config_dir().join("stretch_presets.txt"), text) { eprintln!("stretch presets: {}", e); }
+    }
+}
+
+struct AutomationLane {
+    curve: Automation,
+    enabled: bool,
+    dragging: Option<usize>, // index of the point being dragged
+}
+
+impl AutomationLane {
+    fn new(stepped: bool) -> Self {
+        AutomationLane { curve: Automation { points: Vec::new(), stepped }, enabled: true, dragging: None }
+    }
+
+    // click to add a point, drag to move it, right-click to delete; returns true when the curve changed
+    fn ui(&mut self, ui: &mut egui::Ui, total: usize, cursor: usize, range: std::ops::RangeInclusive<f32>, neutral: f32, fmt: impl Fn(f32) -> String) -> bool {
+        let (rect, response) = ui.allocate_exact_size(egui::vec2(ui.available_width(), 60.0), egui::Sense::click_and_drag());
+        if total == 0 { return false; }
+        let (lo, hi) = (*range.start(), *range.end());
+        let to_x = |pos: usize| rect.left() + pos as f32 / total as f32 * rect.width();
+        let to_y = |v: f32| rect.bottom() - (v - lo) / (hi - lo) * rect.height();
+        let from_pointer = |p: egui::Pos2| {
+            let pos = (((p.x - rect.left()) / rect.width()).clamp(0.0, 1.0) * total as f32) as usize;
+            let v = lo + (rect.bottom() - p.y) / rect.height() * (hi - lo);
+            (pos, v.clamp(lo, hi))
+        };
+        let near = |p: egui::Pos2, points: &[(usize, f32)]| points.iter().position(|&(x, v)| egui::pos2(to_x(x), to_y(v)).distance(p) < 8.0);
+
+        let mut changed = false;
+        let points = &mut self.curve.points;
+        if let Some(p) = response.interact_pointer_pos() {
+            if response.drag_started() || response.clicked() {
+                self.dragging = near(p, points).or_else(|| {
+                    let (pos, v) = from_pointer(p);
+                    let i = points.partition_point(|q| q.0 < pos);
+                    points.insert(i, (pos, v));
+                    changed = true;
+                    Some(i)
+                });
+            }
+            if response.dragged() && let Some(i) = self.dragging {
+                let (pos, v) = from_pointer(p);
+                // a point can't pass its neighbours
+                let min = if i > 0 { points[i - 1].0 + 1 } else { 0 };
+                let max = points.get(i + 1).map(|q| q.0.saturating_sub(1)).unwrap_or(total);
+                points[i] = (pos.clamp(min, max.max(min)), v);
+                changed = true;
+            }
+            if response.secondary_clicked() && let Some(i) = near(p, points) {
+                points.remove(i);
+                changed = true;
+            }
+        }
+        if response.drag_stopped() || response.clicked() { self.dragging = None; }
+
+        let painter = ui.painter_at(rect);
+        painter.rect_filled(rect, 2.0, egui::Color32::from_rgb(10, 10, 10));
+        painter.line_segment([egui::pos2(rect.left(), to_y(neutral)), egui::pos2(rect.right(), to_y(neutral))], (1.0, egui::Color32::from_gray(50)));
+        let color = if self.enabled { egui::Color32::from_rgb(255, 200, 60) } else { egui::Color32::from_gray(90) };
+        if !points.is_empty() {
+            let mut line = Vec::new();
+            if self.curve.stepped {
+                line.push(egui::pos2(rect.left(), to_y(neutral)));
+                let mut level = neutral;
+                for &(x, v) in points.iter() {
+                    line.push(egui::pos2(to_x(x), to_y(level)));
+                    line.push(egui::pos2(to_x(x), to_y(v)));
+                    level = v;
+                }
+            } else {
+                line.push(egui::pos2(rect.left(), to_y(points[0].1)));
+                line.extend(points.iter().map(|&(x, v)| egui::pos2(to_x(x), to_y(v))));
+            }
+            line.push(egui::pos2(rect.right(), to_y(points[points.len() - 1].1)));
+            painter.add(egui::Shape::line(line, egui::Stroke::new(1.5, color)));
+            for &(x, v) in points.iter() { painter.circle_filled(egui::pos2(to_x(x), to_y(v)), 3.0, color); }
+        }
+        painter.line_segment([egui::pos2(to_x(cursor), rect.top()), egui::pos2(to_x(cursor), rect.bottom())], (1.0, egui::Color32::WHITE));
+        if let Some(p) = response.hover_pos() {
+            response.on_hover_text_at_pointer(fmt(from_pointer(p).1));
+        }
+        changed
+    }
+}
+
+// mic pitch against the track's pitch, sampled a few dozen times a second for the scrolling trace
+struct SingAlong {
+    _stream: cpal::Stream,
+    rx: HeapConsumer<f32>,
+    rate: u32,
+    window: std::collections::VecDeque<f32>,
+    trace: std::collections::VecDeque<(Option<f32>, Option<f32>)>, // (mic, track) as fractional MIDI notes
+    last: std::time::Instant,
+}
+
+// times a click from the output back to the input to get the round-trip latency
+struct LatencyProbe {
+    epoch: std::time::Instant,
+    armed: AtomicBool, // the output mutes the deck and writes a click when it sees this
+    sent_us: AtomicU64, // since epoch, 0 while no click is in flight; the deck stays muted meanwhile
+    result_us: AtomicU64, // last measured round trip, 0 for none
+    input_rate: AtomicU32,
+    listening: AtomicBool, // the input fills `capture` while set
+    capture: Mutex<(u64, Vec<f32>)>, // when the first captured sample came in (µs since epoch), and the input since arming
+}
+
+// silence before the click, so the music's tail and any bleed die away first
+const PROBE_QUIET_MS: usize = 100;
+// how long after the click the input is searched for it
+const PROBE_LISTEN_MS: u64 = 500;
+
+// 2 ms of 2 kHz, the same at any rate so the input can be matched against it
+fn probe_click(rate: u32) -> impl Iterator<Item = f32> {
+    (0..rate as usize / 500).map(move |i| 0.8 * (std::f32::consts::TAU * 2000.0 * i as f32 / rate as f32).sin())
+}
+
+impl LatencyProbe {
+    fn new() -> Self {
+        LatencyProbe {
+            epoch: std::time::Instant::now(),
+            armed: AtomicBool::new(false),
+            sent_us: AtomicU64::new(0),
+            result_us: AtomicU64::new(0),
+            input_rate: AtomicU32::new(48000),
+            listening: AtomicBool::new(false),
+            capture: Mutex::new((0, Vec::new())),
+        }
+    }
+
+    fn arm(&self) {
+        let rate = self.input_rate.load(Ordering::Relaxed) as usize;
+        *self.capture.lock().unwrap() = (0, Vec::with_capacity(rate * (PROBE_QUIET_MS + PROBE_LISTEN_MS as usize + 500) / 1000));
+        self.result_us.store(0, Ordering::Relaxed);
+        self.listening.store(true, Ordering::Relaxed);
+        self.armed.store(true, Ordering::Relaxed);
+    }
+
+    // the round trip in µs: where the captured input best matches the click that went out (cross-correlation),
+    // so music, bleed or a knock on the mic that happens to be loud doesn't count as the click
+    fn find_click(&self, sent: u64) -> Option<u64> {
+        let rate = self.input_rate.load(Ordering::Relaxed);
+        let click: Vec<f32> = probe_click(rate).collect();
+        let capture = self.capture.lock().unwrap();
+        let (start_us, input) = (capture.0, &capture.1);
+        if input.len() < click.len() * 2 { return None; }
+        let from = (sent.saturating_sub(start_us) as f64 * 1e-6 * rate as f64) as usize;
+        let corr: Vec<f32> = (from.min(input.len() - click.len())..=input.len() - click.len())
+            .map(|lag| input[lag..lag + click.len()].iter().zip(&click).map(|(a, b)| a * b).sum::<f32>().abs())
+            .collect();
+        let (best, &peak) = corr.iter().enumerate().max_by(|a, b| a.1.total_cmp(b.1))?;
+        // the match has to stand well clear of what the rest of the input correlates to
+        let mut sorted = corr.clone();
+        let mid = sorted.len() / 2;
+        let median = *sorted.select_nth_unstable_by(mid, f32::total_cmp).1;
+        if peak < median * 8.0 + 1e-3 { return None; }
+        let heard = start_us as f64 + (from + best) as f64 / rate as f64 * 1e6;
+        Some((heard as u64).saturating_sub(sent).max(1))
+    }
+}
+
+struct InputMonitor {
+    _stream: cpal::Stream,
+    probe: Arc<LatencyProbe>,
+}
+
+struct Recorder {
+    _stream: cpal::Stream,
+    buffer: Arc<Mutex<Vec<f32>>>, // mono capture at the input device rate
+    rate: u32,
+    speed: f32, // playback speed while recording, to map wall-clock time back onto the file
+    start: usize,
+    end: usize,
+    last_cursor: usize,
+}
+
+// peaks of the looped original and of the take, plus their attacks, for the stacked view
+struct TakeOverlay {
+    original: Vec<f32>,
+    take: Vec<f32>,
+    original_onsets: Vec<f32>, // 0..1 across the loop
+    take_onsets: Vec<f32>,
+}
+
+struct LevelerSettings {
+    enabled: bool,
+    target_db: f32,
+    attack_ms: f32,
+    release_ms: f32,
+    max_gain_db: f32,
+}
+
+struct KaraokeSettings {
+    enabled: bool,
+    amount: f32,
+    keep_bass: bool,
+}
+
+struct BandSoloSettings {
+    enabled: bool,
+    low: f32, // Hz
+    high: f32,
+}
+
+struct DenoiseSettings {
+    enabled: bool,
+    strength: f32,
+    has_profile: bool,
+    profile: Arc<Vec<f32>>, // kept to hand to a rebuilt output stream
+}
+
+struct DroneSettings {
+    enabled: bool,
+    root: i32, // midi note
+    octave: bool,
+    fifth: bool,
+    level: f32,
+}
+
+struct ToneSettings {
+    enabled: bool,
+    note: i32, // midi note the frequency was picked from
+    freq: f32,
+    level: f32,
+}
+
+struct MetronomeSettings {
+    enabled: bool,
+    level: f32,
+    accent: bool,
+    sent: Option<(bool, f32, bool, f32, usize)>, // what the engine was last told, with the grid's bpm (0 for none) and downbeat
+}
+
+struct Dictation {
+    enabled: bool,
+    at_phrases: bool,
+    interval_secs: f32,
+}
+
+impl PlayerApp {
+    fn new(_cc: &eframe::CreationContext<'_>, initial_paths: Vec<PathBuf>) -> Self {
+        let (tx, rx) = unbounded();
+        let (open_tx, open_rx) = unbounded();
+        let (login_tx, login_rx) = unbounded();
+        let (load_error_tx, load_error_rx) = unbounded();
+        let (remote_tx, remote_rx) = unbounded();
+        #[cfg(unix)]
+        start_control_server(remote_tx);
+        #[cfg(not(unix))]
+        drop(remote_tx);
+        let controls = Arc::new(AudioControls {
+            speed: AtomicU32::new(1.0f32.to_bits()),
+            pitch: AtomicU32::new(1.0f32.to_bits()),
+            volume: AtomicU32::new(1.0f32.to_bits()),
+            deck_gain: AtomicU32::new(1.0f32.to_bits()),
+            cursor: AtomicUsize::new(0),
+            loop_start: AtomicUsize::new(0),
+            loop_end: AtomicUsize::new(0),
+            is_playing: AtomicBool::new(true),
+            is_loading: AtomicBool::new(false),
+            load_progress: AtomicU32::new((-1f32).to_bits()),
+            retired: Mutex::new(Vec::new()),
+            pause_at: AtomicUsize::new(usize::MAX),
+            stop_at: AtomicUsize::new(usize::MAX),
+            advance_at_end: AtomicBool::new(false),
+            track_ended: AtomicBool::new(false),
+            pcm_data: Mutex::new(Arc::new(Vec::new())),
+            stream: Mutex::new(None),
+            stem_mix: Mutex::new(Arc::new(StemMix::default())),
+            take: Mutex::new(Arc::new(Take::default())),
+            speed_curve: Mutex::new(Arc::new(Automation::default())),
+            pitch_regions: Mutex::new(Arc::new(Automation::default())),
+            volume_envelope: Mutex::new(Arc::new(Automation::default())),
+            take_blend: AtomicU32::new(0.0f32.to_bits()),
+            lufs_short: AtomicU32::new(f32::NEG_INFINITY.to_bits()),
+            lufs_integrated: AtomicU32::new(f32::NEG_INFINITY.to_bits()),
+            correlation: AtomicU32::new(0.0f32.to_bits()),
+            levels: LevelMeter::new(),
+            limiter_reduction: AtomicU32::new(0),
+            second_volume: AtomicU32::new(1.0f32.to_bits()),
+            audition_pos: AtomicUsize::new(0),
+            audition_end: AtomicUsize::new(0),
+            cue_cursor: AtomicUsize::new(0),
+            cue_start: AtomicUsize::new(0),
+            cue_end: AtomicUsize::new(0),
+            cue_playing: AtomicBool::new(false),
+            cue_volume: AtomicU32::new(1.0f32.to_bits()),
+            monitor_gain: AtomicU32::new(1.0f32.to_bits()),
+            loop_xfade_ms: AtomicU32::new(10.0f32.to_bits()),
+            source_rate: AtomicU32::new(0),
+            source_channels: AtomicUsize::new(2),
+            device_lost: AtomicBool::new(false),
+            loop_wraps: AtomicUsize::new(0),
+            count_in: AtomicUsize::new(0),
+            count_in_beat: AtomicUsize::new(1),
+        });
+
+        let state = Arc::new(Mutex::new(AppState::default()));
+
+        let mut app = Self {
+            state,
+            controls,
+            dragging_marker: None,
+            audition_edges: true,
+            last_audition: (std::time::Instant::now(), usize::MAX),
+            _stream: None,
+            tx,
+            param_rx: rx,
+            output_device: Some(std::fs::read_to_string(config_dir().join("output_device.txt")).unwrap_or_default().trim().to_string()).filter(|n| !n.is_empty()),
+            output_status: String::new(),
+            playback_mode: PlaybackMode::Stretch,
+            dictation: Dictation { enabled: false, at_phrases: true, interval_secs: 5.0 },
+            tone: ToneSettings { enabled: false, note: 69, freq: 440.0, level: 0.2 },
+            metronome: MetronomeSettings { enabled: false, level: 0.5, accent: true, sent: None },
+            drone: DroneSettings { enabled: false, root: 48, octave: false, fifth: false, level: 0.2 },
+            beat_jump: 4,
+            nudge_percent: 4.0,
+            nudge_active: 1.0,
+            denoise: DenoiseSettings { enabled: false, strength: 1.5, has_profile: false, profile: Arc::new(Vec::new()) },
+            stem_backend: StemBackend::Demucs,
+            stem_channels: Vec::new(),
+            use_stems: true,
+            snap_transients: false,
+            snap_grid: 0,
+            snap_zero: false,
+            spectral_colors: false,
+            wave_texture: None,
+            wave_view: (0.0, 1.0),
+            zoom_textures: Vec::new(),
+            split_channels: false,
+            show_spectrogram: false,
+            show_pitch: false,
+            pitch_curve: None,
+            pitch_job: None,
+            midi_quantize: 4,
+            taps: Vec::new(),
+            spectrogram: None,
+            spectrogram_view: None,
+            spectrogram_view_job: None,
+            spectrogram_job: None,
+            whisper_model: find_whisper_model(),
+            pitch_semitones: true,
+            soundfont: default_soundfont(),
+            output_rate: 44100,
+            output_channels: 2,
+            output_devices: Vec::new(),
+            second_output: None,
+            cue_output: None,
+            keep_awake: true,
+            system_status: String::new(),
+            sleep_inhibitor: SleepInhibitor::default(),
+            minimize_to_tray: false,
+            tray_started: false,
+            #[cfg(not(target_os = "linux"))]
+            tray: None,
+            playlist: Playlist { tracks: Vec::new(), current: None, auto_advance: true },
+            setlist: Setlist { items: Vec::new(), gap_secs: 3.0, new_name: String::new(), running: None },
+            watch: None,
+            loudness_comp: false,
+            limiter: false,
+            channel_matrix: ChannelMatrix::Stereo,
+            normalize: std::fs::read_to_string(config_dir().join("normalize.txt")).ok()
+                .and_then(|t| { let (on, target) = t.trim().split_once('\t')?; Some((on.parse().ok()?, target.parse().ok()?)) })
+                .unwrap_or((false, -18.0)),
+            limiter_shown: 0.0,
+            effect_layout: vec![(EffectKind::Denoise, false), (EffectKind::Leveler, false), (EffectKind::BandSolo, false), (EffectKind::Karaoke, false), (EffectKind::Eq, false)],
+            target_key: None,
+            plugin_names: Vec::new(),
+            next_plugin_id: 0,
+            plugin_status: String::new(),
+            retired_effects: None,
+            retired_stretchers: None,
+            leveler: LevelerSettings { enabled: false, target_db: -18.0, attack_ms: 300.0, release_ms: 3000.0, max_gain_db: 18.0 },
+            band_solo: BandSoloSettings { enabled: false, low: 200.0, high: 2000.0 },
+            karaoke: KaraokeSettings { enabled: false, amount: 1.0, keep_bass: true },
+            eq: EqSettings::FLAT,
+            stretch_advanced: StretchAdvanced { formats: StretchAdvanced::load_formats(), tonality: (false, 8000.0) },
+            speed_lane: AutomationLane::new(false),
+            volume_lane: AutomationLane::new(false),
+            volume_dirty: false,
+            pitch_lane: AutomationLane::new(true),
+            recorder: None,
+            sing_along: None,
+            sing_along_status: String::new(),
+            monitor: None,
+            edit_undo: Vec::new(),
+            seek_history: SeekHistory::default(),
+            keymap: default_keymap(),
+            profile: 0,
+            extensions: load_extensions(),
+            ext_seen: ExtSeen::default(),
+            macros: Macros {
+                list: std::fs::read_to_string(config_dir().join("macros.txt")).map(|t| parse_macros(&t)).unwrap_or_default(),
+                recording: None,
+                new_name: String::new(),
+                new_key: None,
+            },
+            monitor_status: String::new(),
+            take_overlay: None,
+            take_status: String::new(),
+            take_latency_ms: 0.0,
+            take_latency_measured: false,
+            ab: AbCompare { other: None, on_b: false, gain_db: [0.0, 0.0], offset_secs: 0.0 },
+            open_tx,
+            open_rx,
+            login_tx,
+            login_rx,
+            load_error_tx,
+            load_error_rx,
+            load_error: None,
+            load_cancel: Arc::new(AtomicBool::new(false)),
+            track_choice: None,
+            logins: std::collections::HashMap::new(),
+            location_prompt: None,
+            pending_restore: None,
+            export: None,
+            export_status: String::new(),
+            podcasts: { let (tx, rx) = unbounded(); Podcasts { feeds: load_subscriptions(), new_url: String::new(), status: String::new(), tx, rx } },
+            remote_rx,
+            remote_queue: std::collections::VecDeque::new(),
+            lan_sync: LanSync { mode: SyncMode::Off, socket: None, target: String::new(), last_sent: std::time::Instant::now(), last_heard: None, status: String::new() },
+            seen_wraps: 0,
+            trainer: SpeedTrainer { enabled: false, step: 2.0, every: 2, target: 1.0, passes: 0 },
+            loop_count: 0,
+            loop_counted: (0, 0),
+            stop_after_enabled: false,
+            stop_after: 4,
+            count_in: false,
+            markers: Vec::new(),
+            show_markers: false,
+            focus_marker: None,
+            goto_text: String::new(),
+            goto_bad: false,
+            tuning_cents: 0.0,
+            reference_hz: 440.0,
+            meter_peaks: [0.0; 2],
+            meter_hold: [(0.0, std::time::Instant::now()); 2],
+            clipped: false,
+            nudge_secs: std::fs::read_to_string(config_dir().join("nudge.txt")).ok().and_then(|t| t.trim().parse().ok()).unwrap_or(5.0),
+            scope_rx: None,
+            scope_points: std::collections::VecDeque::new(),
+        };
+
+        let saved_profile = std::fs::read_to_string(config_dir().join("profile.txt")).unwrap_or_default();
+        if let Some(i) = PROFILES.iter().position(|p| p.name == saved_profile.trim()) {
+            app.profile = i;
+            app.keymap = app.profile().keymap();
+        }
+
+        // several files on the command line become the queue
+        if initial_paths.len() > 1 {
+            app.playlist.tracks = initial_paths;
+            app.play_track(0);
+        } else if let Some(path) = initial_paths.into_iter().next() {
+            app.load_audio_file(path);
+        }
+        app.start_playback();
+        // a custom tuning saved for this output's format applies from the start
+        let tuning = app.stretch_advanced.tuning_for(app.output_channels, app.output_rate);
+        if tuning.is_some() { let _ = app.tx.send(ParamUpdate::Stretcher { tuning, tonality_limit: None }); }
+        app
+    }
+
+    fn load_audio_file(&mut self, path: PathBuf) {
+        if !path.exists() {
+            self.load_error = Some(format!("{}: file not found", path.display()));
+            return;
+        }
+        self.load_error = None;
+        // the track picked for this file holds while it's reloaded, another file starts on its default
+        if path.to_string_lossy() != self.state.lock().unwrap().file_path { self.track_choice = None; }
+        self.save_session();
+        let c = self.controls.clone();
+        let s_ptr = self.state.clone();
+        // a load still running for the previous file gives up without touching anything
+        self.load_cancel.store(true, Ordering::SeqCst);
+        self.load_cancel = Arc::new(AtomicBool::new(false));
+        let cancel = self.load_cancel.clone();
+        
+        c.load_progress.store((-1f32).to_bits(), Ordering::Relaxed);
+        c.is_loading.store(true, Ordering::SeqCst);
+        {
+            let mut s = s_ptr.lock().unwrap();
+            s.file_path = path.to_string_lossy().into_owned();
+            s.subtitles.clear();
+            s.key = None;
+            s.detected_key = None;
+            s.audio_tracks.clear();
+            s.audio_track = None;
+            s.tempo = None;
+            s.detected_tempo = None;
+            s.stems.clear();
+            s.stem_status.clear();
+            s.transcript_status.clear();
+        }
+        self.stem_channels.clear();
+        self.clear_take();
+        self.clear_automation();
+        self.edit_undo.clear();
+        self.seek_history = SeekHistory::default();
+        self.wave_view = (0.0, 1.0);
+        self.load_session(true);
+        let wanted_track = self.track_choice;
+        if let Some(sub) = ["srt", "vtt"].iter().map(|e| path.with_extension(e)).find(|p| p.exists()) {
+            self.load_subtitles(sub);
+        }
+
+        let soundfont = self.soundfont.clone();
+        let error_tx = self.load_error_tx.clone();
+        thread::spawn(move || {
+            // the previous file's audio is still loaded under the new name, so a failed load leaves the player empty
+            let fail = |e: String| {
+                let mut s = s_ptr.lock().unwrap();
+                if cancel.load(Ordering::SeqCst) { return; }
+                unload_audio(&c, &mut s);
+                let _ = error_tx.send(format!("{}: {e}", path.display()));
+                c.is_loading.store(false, Ordering::SeqCst);
+            };
+            // MIDI has no audio of its own: render it once through a SoundFont and play the cached WAV
+            let source = if is_midi(&path) {
+                match soundfont.ok_or_else(|| "no SoundFont selected".to_string()).and_then(|sf| render_midi(&path, &sf)) {
+                    Ok(wav) => wav,
+                    Err(e) => { fail(e); return; }
+                }
+            } else {
+                path.clone()
+            };
+            if cancel.load(Ordering::SeqCst) { return; }
+            let (tracks, track) = list_audio_tracks(&source, wanted_track);
+            {
+                let mut s = s_ptr.lock().unwrap();
+                if cancel.load(Ordering::SeqCst) { return; }
+                // several tracks and none picked before: stop here and ask, the pick reloads the file on it
+                let ask = wanted_track.is_none() && tracks.len() > 1;
+                s.audio_tracks = tracks;
+                s.audio_track = if ask { None } else { track };
+                if ask { return; }
+            }
+            if let Some((worker, sample_rate)) = DecoderWorker::open(&source, track) {
+                let (channels, total_samples) = (worker.channels, worker.buffer.total);
+                c.cursor.store(0, Ordering::SeqCst);
+                c.loop_start.store(0, Ordering::SeqCst);
+                c.loop_end.store(total_samples, Ordering::SeqCst);
+                c.source_rate.store(sample_rate, Ordering::SeqCst);
+                c.source_channels.store(channels, Ordering::SeqCst);
+                set_source(&c, Arc::new(Vec::new()), Some(worker.buffer.clone()));
+                swap_stem_mix(&c, Arc::new(StemMix::default()));
+                {
+                    let mut s = s_ptr.lock().unwrap();
+                    s.total_samples = total_samples;
+                    s.sample_rate = sample_rate;
+                    s.channels = channels;
+                    PcmAnalysis::empty().store(&mut s);
+                }
+                let decode_c = c.clone();
+                thread::spawn(move || worker.run(decode_c));
+                c.is_loading.store(false, Ordering::SeqCst);
+
+                // phrases, onsets etc. need the whole file in memory; a long file only gets its waveform
+                let Some(peaks) = stream_overview(&source, track, total_samples) else { return };
+                let mut s = s_ptr.lock().unwrap();
+                if s.file_path != path.to_string_lossy() { return; }
+                s.waveform = peaks;
+                s.waveform_gen = WAVEFORM_GEN.fetch_add(1, Ordering::Relaxed) + 1;
+                return;
+            }
+            let mut progressive: Option<ProgressiveLoad> = None;
+            let decoded = decode_audio_with(&source, track, |params, pcm, done| {
+                c.load_progress.store(done.to_bits(), Ordering::Relaxed);
+                if cancel.load(Ordering::Relaxed) { return false; }
+                if let Some(p) = &progressive { p.follow(pcm, c.cursor.load(Ordering::Relaxed)); }
+                let (Some(rate), Some(layout), Some(frames)) = (params.sample_rate, params.channels, stated_frames(params)) else { return true };
+                let channels = layout.count();
+                let drawn = progressive.as_ref().map_or(0, |p| p.drawn);
+                if pcm.len() < drawn + PROGRESSIVE_STEP_SECS * rate as usize * channels { return true; }
+
+                let mut s = s_ptr.lock().unwrap();
+                if cancel.load(Ordering::SeqCst) { return false; }
+                let p = progressive.get_or_insert_with(|| {
+                    // the first few seconds are in: open the player on them
+                    let p = ProgressiveLoad::new(frames as usize * channels, channels, rate);
+                    p.follow(pcm, 0);
+                    c.cursor.store(0, Ordering::SeqCst);
+                    c.loop_start.store(0, Ordering::SeqCst);
+                    c.loop_end.store(p.buffer.total, Ordering::SeqCst);
+                    c.source_rate.store(rate, Ordering::SeqCst);
+                    c.source_channels.store(channels, Ordering::SeqCst);
+                    set_source(&c, Arc::new(Vec::new()), Some(p.buffer.clone()));
+                    swap_stem_mix(&c, Arc::new(StemMix::default()));
+                    s.total_samples = p.buffer.total;
+                    s.sample_rate = rate;
+                    s.channels = channels;
+                    PcmAnalysis::empty().store(&mut s);
+                    c.is_loading.store(false, Ordering::SeqCst);
+                    p
+                });
+                p.draw(pcm);
+                s.waveform = p.waveform.clone();
+                s.waveform_gen = WAVEFORM_GEN.fetch_add(1, Ordering::Relaxed) + 1;
+                true
+            });
+            let DecodedAudio { pcm, sample_rate, channels } = match decoded {
+                Ok(decoded) => decoded,
+                Err(e) => { fail(e); return; }
+            };
+            let total_samples = pcm.len();
+            let pcm = Arc::new(pcm);
+
+            // the engine moves to the whole file straight away, the analysis follows
+            {
+                let mut s = s_ptr.lock().unwrap();
+                if cancel.load(Ordering::SeqCst) { return; }
+                match &progressive {
+                    // already playing: keep the position and loop, unless they were set against the stated length
+                    Some(p) => {
+                        if c.loop_end.load(Ordering::SeqCst) == p.buffer.total { c.loop_end.store(total_samples, Ordering::SeqCst); }
+                        if c.loop_start.load(Ordering::SeqCst) >= total_samples { c.loop_start.store(0, Ordering::SeqCst); }
+                        if c.cursor.load(Ordering::SeqCst) >= total_samples { c.cursor.store(0, Ordering::SeqCst); }
+                    }
+                    None => {
+                        c.cursor.store(0, Ordering::SeqCst);
+                        c.loop_start.store(0, Ordering::SeqCst);
+                        c.loop_end.store(total_samples, Ordering::SeqCst);
+                    }
+                }
+                c.source_rate.store(sample_rate, Ordering::SeqCst);
+                c.source_channels.store(channels, Ordering::SeqCst);
+                set_source(&c, pcm.clone(), None);
+                swap_stem_mix(&c, Arc::new(StemMix::default()));
+                s.total_samples = total_samples;
+                s.sample_rate = sample_rate;
+                s.channels = channels;
+            }
+
+            let analysis = PcmAnalysis::new(&pcm, channels, sample_rate);
+            let mut s = s_ptr.lock().unwrap();
+            if cancel.load(Ordering::SeqCst) { return; }
+            analysis.store(&mut s);
+            
+            c.is_loading.store(false, Ordering::SeqCst);
+        });
+    }
+
+    // reloads the file on another of its audio tracks; the session brings back the place and loop
+    fn switch_audio_track(&mut self, id: u32) {
+        self.track_choice = Some(id);
+        let path = PathBuf::from(&self.state.lock().unwrap().file_path);
+        self.load_audio_file(path);
+    }
+
+    // stops the load in progress and leaves the player empty rather than half on the old file
+    fn cancel_load(&mut self) {
+        self.load_cancel.store(true, Ordering::SeqCst);
+        unload_audio(&self.controls, &mut self.state.lock().unwrap());
+        self.controls.is_loading.store(false, Ordering::SeqCst);
+    }
+
+    fn start_stem_separation(&mut self) {
+        let s_ptr = self.state.clone();
+        let backend = self.stem_backend;
+        let (path, total, sample_rate, channels) = {
+            let mut s = s_ptr.lock().unwrap();
+            s.stem_status = "Separating stems...".into();
+            (PathBuf::from(&s.file_path), s.total_samples, s.sample_rate, s.channels)
+        };
+        let pcm = Arc::clone(&*self.controls.pcm_data.lock().unwrap());
+        self.stem_channels.clear();
+
+        thread::spawn(move || {
+            let result = if backend == StemBackend::Hpss {
+                let (harm, perc) = hpss(&pcm, channels);
+                Ok(vec![("harmonic".to_string(), Arc::new(harm)), ("percussive".to_string(), Arc::new(perc))])
+            } else {
+                separate_stems(&path, backend).and_then(|files| {
+                    files.into_iter().map(|(name, file)| {
+                        let decoded = decode_audio(&file).map_err(|e| format!("could not decode {name} stem: {e}"))?;
+                        if decoded.sample_rate != sample_rate {
+                            return Err(format!("{name} stem is {} Hz, the file is {} Hz", decoded.sample_rate, sample_rate));
+                        }
+                        // the engine reads stems with the file's interleave, so a mono or surround stem is remapped to it
+                        let mut pcm = if decoded.channels == channels { decoded.pcm } else {
+                            let from = decoded.channels.max(1);
+                            decoded.pcm.chunks_exact(from)
+                                .flat_map(|frame| (0..channels).map(move |out| map_channel(|k| frame[k], from, channels, out)))
+                                .collect()
+                        };
+                        pcm.resize(total, 0.0);
+                        Ok((name, Arc::new(pcm)))
+                    }).collect::<Result<Vec<_>, String>>()
+                })
+            };
+            let mut s = s_ptr.lock().unwrap();
+            // the user may have opened another file in the meantime
+            if s.file_path != path.to_string_lossy() { return; }
+            match result {
+                Ok(stems) => {
+                    s.stems = stems;
+                    s.stem_status = "Stems ready".into();
+                }
+                Err(e) => s.stem_status = format!("Stem separation failed: {e}"),
+            }
+        });
+    }
+
+    fn start_transcription(&mut self) {
+        let Some(model) = self.whisper_model.clone() else { return };
+        let s_ptr = self.state.clone();
+        let pcm = Arc::clone(&*self.controls.pcm_data.lock().unwrap());
+        if pcm.is_empty() {
+            s_ptr.lock().unwrap().transcript_status = "Transcription needs the file in memory (long files are streamed)".into();
+            return;
+        }
+        let (path, sample_rate, channels) = {
+            let mut s = s_ptr.lock().unwrap();
+            s.transcript_status = "Transcribing...".into();
+            (PathBuf::from(&s.file_path), s.sample_rate, s.channels)
+        };
+        thread::spawn(move || {
+            let result = transcribe(&path, &pcm, channels, sample_rate, &model);
+            let mut s = s_ptr.lock().unwrap();
+            if s.file_path != path.to_string_lossy() { return; }
+            match result {
+                Ok(cues) => {
+                    s.transcript_status = format!("{} segments", cues.len());
+                    s.subtitles = cues;
+                }
+                Err(e) => s.transcript_status = format!("Transcription failed: {e}"),
+            }
+        });
+    }
+
+    // hands the engine the current stem buffers and their effective gains (mute/solo applied)
+    fn update_stem_mix(&mut self) {
+        let stems = self.state.lock().unwrap().stems.clone();
+        if self.stem_channels.len() != stems.len() {
+            self.stem_channels = stems.iter().map(|_| StemChannel { volume: 1.0, mute: false, solo: false }).collect();
+        }
+        let any_solo = self.stem_channels.iter().any(|ch| ch.solo);
+        let mix = if self.use_stems && !stems.is_empty() {
+            StemMix {
+                stems: stems.into_iter().map(|s| s.1).collect(),
+                gains: self.stem_channels.iter().map(|ch| {
+                    let audible = if any_solo { ch.solo } else { !ch.mute };
+                    if audible { ch.volume } else { 0.0 }
+                }).collect(),
+            }
+        } else {
+            StemMix::default()
+        };
+        swap_stem_mix(&self.controls, Arc::new(mix));
+    }
+
+    fn start_watch(&mut self, dir: PathBuf) {
+        let (tx, rx) = unbounded();
+        let stop = Arc::new(AtomicBool::new(false));
+        let auto_load = self.watch.as_ref().is_some_and(|w| w.auto_load);
+        let thread_dir = dir.clone();
+        let thread_stop = stop.clone();
+        thread::spawn(move || watch_folder(thread_dir, tx, thread_stop));
+        self.watch = Some(FolderWatch { dir, rx, stop, auto_load });
+    }
+
+    // remote commands wait while a file loads, so "open" followed by "loop" lands on the new file
+    fn poll_remote(&mut self, ctx: &egui::Context) {
+        self.remote_queue.extend(self.remote_rx.try_iter());
+        while !self.controls.is_loading.load(Ordering::SeqCst) && let Some(cmd) = self.remote_queue.pop_front() {
+            let (rate, channels, total) = { let s = self.state.lock().unwrap(); (s.sample_rate, s.channels, s.total_samples) };
+            let to_samples = |t: f64| ((t.max(0.0) * rate as f64) as usize * channels).min(total);
+            match cmd {
+                RemoteCommand::Open(path) => self.load_audio_file(path),
+                RemoteCommand::Seek(t) => self.seek_history.jump(&self.controls, to_samples(t)),
+                RemoteCommand::Loop(a, b) => {
+                    self.controls.loop_start.store(to_samples(a.min(b)), Ordering::Relaxed);
+                    self.controls.loop_end.store(to_samples(a.max(b)), Ordering::Relaxed);
+                    self.seek_history.jump(&self.controls, to_samples(a.min(b)));
+                }
+                RemoteCommand::Action(action) => self.perform(ctx, action),
+            }
+        }
+    }
+
+    fn set_sync_mode(&mut self, mode: SyncMode) {
+        let sync = &mut self.lan_sync;
+        sync.mode = mode;
+        sync.socket = None;
+        sync.last_heard = None;
+        sync.status.clear();
+        let bind = match mode {
+            SyncMode::Off => return,
+            SyncMode::Lead => std::net::UdpSocket::bind(("0.0.0.0", 0)).and_then(|s| s.set_broadcast(true).map(|_| s)),
+            SyncMode::Follow => std::net::UdpSocket::bind(("0.0.0.0", SYNC_PORT)),
+        };
+        match bind.and_then(|s| s.set_nonblocking(true).map(|_| s)) {
+            Ok(socket) => sync.socket = Some(socket),
+            Err(e) => {
+                sync.mode = SyncMode::Off;
+                sync.status = format!("Can't open the network: {e}");
+            }
+        }
+    }
+
+    fn poll_sync(&mut self) {
+        let Some(socket) = &self.lan_sync.socket else { return };
+        let (file_path, rate, channels, total) = { let s = self.state.lock().unwrap(); (s.file_path.clone(), s.sample_rate, s.channels, s.total_samples) };
+        let file_name = Path::new(&file_path).file_name().map(|n| n.to_string_lossy().into_owned()).unwrap_or_default();
+        let per_sec = (rate as f64 * channels as f64).max(1.0);
+        let c = self.controls.clone();
+        match self.lan_sync.mode {
+            SyncMode::Off => {}
+            SyncMode::Lead => {
+                if self.lan_sync.last_sent.elapsed().as_millis() < 100 { return; }
+                self.lan_sync.last_sent = std::time::Instant::now();
+                let (a, b) = (c.loop_start.load(Ordering::Relaxed), c.loop_end.load(Ordering::Relaxed));
+                let st = SyncState {
+                    playing: c.is_playing.load(Ordering::Relaxed),
+                    position: c.cursor.load(Ordering::Relaxed) as f64 / per_sec,
+                    loop_range: (a > 0 || b < total).then_some((a as f64 / per_sec, b as f64 / per_sec)),
+                    speed: f32::from_bits(c.speed.load(Ordering::Relaxed)),
+                    pitch: f32::from_bits(c.pitch.load(Ordering::Relaxed)),
+                    file_name,
+                };
+                let target = self.lan_sync.target.trim();
+                let target = if target.is_empty() { format!("255.255.255.255:{SYNC_PORT}") }
+                    else if target.contains(':') { target.to_string() } else { format!("{target}:{SYNC_PORT}") };
+                self.lan_sync.status = match socket.send_to(format_sync(&st).as_bytes(), &target) {
+                    Ok(_) => format!("Sending to {target}"),
+                    Err(e) => format!("Send failed: {e}"),
+                };
+            }
+            SyncMode::Follow => {
+                let mut buf = [0u8; 1024];
+                let mut latest = None;
+                while let Ok((n, from)) = socket.recv_from(&mut buf) {
+                    if let Some(st) = std::str::from_utf8(&buf[..n]).ok().and_then(parse_sync) { latest = Some((st, from)); }
+                }
+                let Some((st, from)) = latest else {
+                    if self.lan_sync.last_heard.is_some_and(|t| t.elapsed().as_secs() > 3) { self.lan_sync.status = "Leader went quiet".into(); }
+                    return;
+                };
+                self.lan_sync.last_heard = Some(std::time::Instant::now());
+                if st.file_name != file_name {
+                    self.lan_sync.status = format!("Leader {} is playing {}, open the same file", from.ip(), st.file_name);
+                    return;
+                }
+                self.lan_sync.status = format!("Following {}", from.ip());
+                if self.controls.is_loading.load(Ordering::Relaxed) { return; }
+                let to_samples = |t: f64| ((t.max(0.0) * rate as f64) as usize * channels).min(total);
+                let (a, b) = st.loop_range.map_or((0, total), |(a, b)| (to_samples(a), to_samples(b)));
+                c.loop_start.store(a, Ordering::Relaxed);
+                c.loop_end.store(b, Ordering::Relaxed);
+                c.is_playing.store(st.playing, Ordering::Relaxed);
+                // small drift is left alone, re-seeking on every packet would stutter
+                let here = c.cursor.load(Ordering::Relaxed) as f64 / per_sec;
+                if (here - st.position).abs() > 0.15 { c.cursor.store(to_samples(st.position), Ordering::Relaxed); }
+                if (f32::from_bits(c.speed.load(Ordering::Relaxed)) - st.speed).abs() > 1e-4 { self.perform_quietly(Action::SetSpeed(st.speed)); }
+                if (f32::from_bits(c.pitch.load(Ordering::Relaxed)) - st.pitch).abs() > 1e-4 { self.perform_quietly(Action::SetPitch(st.pitch)); }
+            }
+        }
+    }
+
+    fn play_track(&mut self, i: usize) {
+        let Some(path) = self.playlist.tracks.get(i).cloned() else { return };
+        self.playlist.current = Some(i);
+        self.load_audio_file(path);
+    }
+
+    fn step_track(&mut self, step: isize) {
+        let Some(i) = self.playlist.current.and_then(|i| i.checked_add_signed(step)) else { return };
+        if i >= self.playlist.tracks.len() { return; }
+        let playing = self.controls.is_playing.load(Ordering::Relaxed);
+        self.play_track(i);
+        self.controls.is_playing.store(playing, Ordering::Relaxed);
+    }
+
+    fn poll_playlist(&mut self) {
+        let p = &self.playlist;
+        let has_next = p.current.is_some_and(|i| i + 1 < p.tracks.len());
+        self.controls.advance_at_end.store(p.auto_advance && has_next, Ordering::Relaxed);
+        if self.controls.track_ended.swap(false, Ordering::Relaxed) && has_next {
+            self.step_track(1);
+            self.controls.is_playing.store(true, Ordering::Relaxed);
+        }
+    }
+
+    // each loop pass the engine reports counts towards the repeat limit and moves the speed trainer on
+    fn poll_loop_wraps(&mut self) {
+        let c = self.controls.clone();
+        let range = (c.loop_start.load(Ordering::Relaxed), c.loop_end.load(Ordering::Relaxed));
+        if range != self.loop_counted {
+            self.loop_counted = range;
+            self.loop_count = 0;
+        }
+        let wraps = c.loop_wraps.load(Ordering::Relaxed);
+        let passes = wraps.wrapping_sub(self.seen_wraps);
+        self.seen_wraps = wraps;
+        for _ in 0..passes.min(16) { self.loop_pass(); }
+
+        // the last pass stops at the loop end rather than wrapping, then goes back to the start ready for the next round
+        let total = self.state.lock().unwrap().total_samples;
+        let looping = range.0 > 0 || range.1 < total;
+        if !self.stop_after_enabled || !looping || self.loop_count + 1 < self.stop_after {
+            // turned off, or not on the last pass (any more)
+            c.stop_at.store(usize::MAX, Ordering::Relaxed);
+            return;
+        }
+        if c.is_playing.load(Ordering::Relaxed) {
+            c.stop_at.store(range.1, Ordering::Relaxed);
+        } else if c.cursor.load(Ordering::Relaxed) >= range.1 {
+            c.cursor.store(range.0, Ordering::Relaxed);
+            self.loop_count = 0;
+        }
+    }
+
+    fn loop_pass(&mut self) {
+        self.loop_count += 1;
+        let t = &mut self.trainer;
+        if !t.enabled { return; }
+        t.passes += 1;
+        if t.passes < t.every { return; }
+        t.passes = 0;
+        let speed = f32::from_bits(self.controls.speed.load(Ordering::Relaxed));
+        if speed < t.target {
+            let next = (speed + t.step / 100.0).min(t.target);
+            self.perform_quietly(Action::SetSpeed(next));
+        }
+    }
+
+    fn trainer_panel(&mut self, ui: &mut egui::Ui) {
+        egui::CollapsingHeader::new("Speed trainer").show(ui, |ui| {
+            let t = &mut self.trainer;
+            ui.horizontal(|ui| {
+                if ui.checkbox(&mut t.enabled, "Speed up").changed() { t.passes = 0; }
+                ui.add(egui::DragValue::new(&mut t.step).range(0.5..=25.0).speed(0.1).prefix("+").suffix(" %"));
+                ui.label("every");
+                ui.add(egui::DragValue::new(&mut t.every).range(1..=50).suffix(" passes"));
+                ui.label("up to");
+                let mut target = t.target * 100.0;
+                if ui.add(egui::DragValue::new(&mut target).range(10.0..=200.0).speed(0.5).suffix(" %")).changed() { t.target = target / 100.0; }
+            });
+            // the schedule from here: how many more passes until each step and the target
+            let speed = f32::from_bits(self.controls.speed.load(Ordering::Relaxed));
+            let steps = ((t.target - speed) * 100.0 / t.step).ceil().max(0.0) as u32;
+            ui.label(if steps == 0 {
+                format!("At {:.0}%, the target", speed * 100.0)
+            } else {
+                let next = t.every - t.passes.min(t.every - 1);
+                format!("Now {:.0}%, {:.0}% in {} pass{}, {:.0}% after {} more passes",
+                    speed * 100.0, ((speed + t.step / 100.0).min(t.target)) * 100.0, next, if next == 1 { "" } else { "es" },
+                    t.target * 100.0, next + (steps - 1) * t.every)
+            });
+        });
+    }
+
+    fn poll_watch(&mut self) {
+        let Some(watch) = &self.watch else { return };
+        let new: Vec<PathBuf> = watch.rx.try_iter().collect();
+        let auto_load = watch.auto_load;
+        for path in new {
+            self.playlist.tracks.push(path.clone());
+            if auto_load {
+                self.playlist.current = Some(self.playlist.tracks.len() - 1);
+                self.load_audio_file(path);
+            }
+        }
+    }
+
+    // record one pass of the loop from the default input, starting at the loop start
+    fn start_take(&mut self) {
+        let c = &self.controls;
+        let start = c.loop_start.load(Ordering::Relaxed);
+        let end = c.loop_end.load(Ordering::Relaxed);
+        if end <= start { return; }
+        let buffer = Arc::new(Mutex::new(Vec::new()));
+        let buf = buffer.clone();
+        let result = open_input(move |mono| buf.lock().unwrap().extend_from_slice(mono));
+        match result {
+            Ok((stream, rate)) => {
+                let speed = f32::from_bits(c.speed.load(Ordering::Relaxed));
+                self.seek_history.jump(c, start);
+                c.is_playing.store(true, Ordering::SeqCst);
+                self.recorder = Some(Recorder { _stream: stream, buffer, rate, speed, start, end, last_cursor: start });
+                self.take_status = "Recording...".into();
+            }
+            Err(e) => self.take_status = format!("Recording failed: {e}"),
+        }
+    }
+
+    // stop when the loop wraps (or playback stops) and map the capture onto the file timeline
+    fn poll_take(&mut self) {
+        let Some(rec) = &mut self.recorder else { return };
+        let cursor = self.controls.cursor.load(Ordering::Relaxed);
+        let wrapped = cursor < rec.last_cursor || cursor >= rec.end;
+        rec.last_cursor = cursor;
+        if !wrapped && self.controls.is_playing.load(Ordering::Relaxed) { return; }
+        let Some(rec) = self.recorder.take() else { return };
+
+        let (sample_rate, channels) = { let s = self.state.lock().unwrap(); (s.sample_rate, s.channels) };
+        let captured = std::mem::take(&mut *rec.buffer.lock().unwrap());
+        let frames = (rec.end - rec.start) / channels;
+        let step = rec.rate as f64 / (sample_rate as f64 * rec.speed as f64);
+        // the input hears the loop a round trip after it was sent, so that much of the capture comes before the loop start
+        let late = (self.take_latency_ms as f64 * rec.rate as f64 / 1000.0) as usize;
+        let pcm: Vec<f32> = (0..frames).map(|j| captured.get(late + (j as f64 * step) as usize).copied().unwrap_or(0.0)).collect();
+
+        let original: Vec<f32> = {
+            let data = self.controls.pcm_data.lock().unwrap();
+            data.get(rec.start..rec.end).map(|d| d.to_vec()).unwrap_or_default()
+        };
+        let peaks = |data: &[f32]| -> Vec<f32> {
+            let chunk = (data.len() / 400).max(1);
+            data.chunks(chunk).map(|c| c.iter().fold(0.0f32, |a, &b| a.max(b.abs()))).collect()
+        };
+        let span = (rec.end - rec.start) as f32;
+        let original_onsets = detect_onsets(&original, channels, sample_rate).iter().map(|&i| i as f32 / span).collect();
+        let take_onsets = detect_onsets(&pcm, 1, sample_rate).iter().map(|&i| (i * channels) as f32 / span).collect();
+        self.take_overlay = Some(TakeOverlay { original: peaks(&original), take: peaks(&pcm), original_onsets, take_onsets });
+        swap_take(&self.controls, Arc::new(Take { start: rec.start, channels, pcm }));
+        if f32::from_bits(self.controls.take_blend.load(Ordering::Relaxed)) == 0.0 {
+            self.controls.take_blend.store(0.5f32.to_bits(), Ordering::Relaxed);
+        }
+        self.take_status = format!("Take recorded ({:.1} s)", captured.len() as f32 / rec.rate as f32);
+    }
+
+    // mirrors the main mix to another device, e.g. headphones next to the room speakers
+    fn open_second_output(&mut self, name: &str) -> Result<(), String> {
+        self.close_second_output();
+        let device = cpal::default_host().output_devices().map_err(|e| e.to_string())?
+            .find(|d| d.name().is_ok_and(|n| n == name)).ok_or("device not found")?;
+        let config = device.default_output_config().map_err(|e| e.to_string())?.config();
+        let channels = config.channels as usize;
+        // the main engine runs at its own device rate, so step through its frames at the ratio of the two
+        let ratio = self.output_rate as f64 / config.sample_rate.0 as f64;
+        let max_backlog = self.output_rate as usize / 5;
+        let (tx, mut rx) = HeapRb::<(f32, f32)>::new(self.output_rate as usize).split();
+        let c = self.controls.clone();
+        let (mut frac, mut a, mut b) = (0.0f64, (0.0f32, 0.0f32), (0.0f32, 0.0f32));
+        let stream = device.build_output_stream(&config, move |data: &mut [f32], _| {
+            // the two clocks drift; drop the oldest audio rather than let latency build up
+            if rx.len() > max_backlog { rx.skip(rx.len() - max_backlog / 2); }
+            let volume = f32::from_bits(c.second_volume.load(Ordering::Relaxed));
+            for frame in data.chunks_mut(channels) {
+                while frac >= 1.0 {
+                    a = b;
+                    b = rx.pop().unwrap_or((0.0, 0.0));
+                    frac -= 1.0;
+                }
+                let t = frac as f32;
+                let (l, r) = (a.0 + (b.0 - a.0) * t, a.1 + (b.1 - a.1) * t);
+                frame.fill(0.0);
+                if channels == 1 { frame[0] = (l + r) * 0.5 * volume; } else { frame[0] = l * volume; frame[1] = r * volume; }
+                frac += ratio;
+            }
+        }, |e| eprintln!("{}", e), None).map_err(|e| e.to_string())?;
+        stream.play().map_err(|e| e.to_string())?;
+        let _ = self.tx.send(ParamUpdate::SecondOutput(Some(tx)));
+        self.second_output = Some((name.to_string(), stream));
+        Ok(())
+    }
+
+    fn close_second_output(&mut self) {
+        if self.second_output.take().is_some() { let _ = self.tx.send(ParamUpdate::SecondOutput(None)); }
+    }
+
+    // DJ-style pre-listen: plays the loaded file at 1x from cue_cursor on its own device
+    fn open_cue_output(&mut self, name: &str) -> Result<(), String> {
+        self.cue_output = None;
+        let device = cpal::default_host().output_devices().map_err(|e| e.to_string())?
+            .find(|d| d.name().is_ok_and(|n| n == name)).ok_or("device not found")?;
+        let config = device.default_output_config().map_err(|e| e.to_string())?.config();
+        let channels = config.channels as usize;
+        let cue_rate = config.sample_rate.0 as f64;
+        let c = self.controls.clone();
+        let mut frac = 0.0f64;
+        let stream = device.build_output_stream(&config, move |data: &mut [f32], _| {
+            data.fill(0.0);
+            if !c.cue_playing.load(Ordering::Relaxed) { return; }
+            let pcm = Arc::clone(&*c.pcm_data.lock().unwrap());
+            if pcm.len() < 4 { return; }
+            let volume = f32::from_bits(c.cue_volume.load(Ordering::Relaxed));
+            let (start, end) = (c.cue_start.load(Ordering::Relaxed), c.cue_end.load(Ordering::Relaxed));
+            let file_channels = c.source_channels.load(Ordering::Relaxed).max(1);
+            let ratio = c.source_rate.load(Ordering::Relaxed).max(1) as f64 / cue_rate;
+            let align = |p: usize| p - p % file_channels;
+            let mut pos = align(c.cue_cursor.load(Ordering::Relaxed));
+            for frame in data.chunks_mut(channels) {
+                if end > start && pos >= end { pos = align(start); }
+                if pos + 2 * file_channels > pcm.len() { c.cue_playing.store(false, Ordering::Relaxed); break; }
+                let t = frac as f32;
+                for (ch, x) in frame.iter_mut().enumerate() {
+                    let a = map_channel(|k| pcm[pos + k], file_channels, channels, ch);
+                    let b = map_channel(|k| pcm[pos + file_channels + k], file_channels, channels, ch);
+                    *x = (a + (b - a) * t) * volume;
+                }
+                frac += ratio;
+                pos += frac as usize * file_channels;
+                frac = frac.fract();
+            }
+            c.cue_cursor.store(pos, Ordering::Relaxed);
+        }, |e| eprintln!("{}", e), None).map_err(|e| e.to_string())?;
+        stream.play().map_err(|e| e.to_string())?;
+        self.cue_output = Some((name.to_string(), stream));
+        Ok(())
+    }
+
+    fn start_tray(&mut self, ctx: &egui::Context) {
+        self.tray_started = true;
+        let (ctx, controls, state) = (ctx.clone(), self.controls.clone(), self.state.clone());
+        // on linux the indicator lives in a gtk main loop, which we run on its own thread
+        #[cfg(target_os = "linux")]
+        thread::spawn(move || {
+            if gtk::init().is_err() { return; }
+            let c = controls.clone();
+            let Ok(tray) = build_tray(ctx, controls, state) else { return };
+            let mut shown = false;
+            gtk::glib::timeout_add_local(std::time::Duration::from_millis(250), move || {
+                let playing = c.is_playing.load(Ordering::Relaxed);
+                if playing != shown {
+                    shown = playing;
+                    let _ = tray.set_icon(tray_image(playing));
+                }
+                gtk::glib::ControlFlow::Continue
+            });
+            gtk::main();
+        });
+        #[cfg(not(target_os = "linux"))]
+        { self.tray = build_tray(ctx, controls, state).ok().map(|t| (t, false)); }
+    }
+
+    fn update_tray(&mut self, ctx: &egui::Context) {
+        if !self.minimize_to_tray { return; }
+        if !self.tray_started { self.start_tray(ctx); }
+        #[cfg(not(target_os = "linux"))]
+        if let Some((tray, shown)) = &mut self.tray {
+            let playing = self.controls.is_playing.load(Ordering::Relaxed);
+            if playing != *shown {
+                *shown = playing;
+                let _ = tray.set_icon(tray_image(playing));
+            }
+        }
+        if ctx.input(|i| i.viewport().minimized) == Some(true) {
+            ctx.send_viewport_cmd(egui::ViewportCommand::Visible(false));
+        }
+    }
+
+    fn add_setlist_item(&mut self) {
+        let (path, rate, channels) = { let s = self.state.lock().unwrap(); (PathBuf::from(&s.file_path), s.sample_rate, s.channels) };
+        if !path.is_file() { return; }
+        let secs = |i: usize| i as f64 / (rate as f64 * channels as f64);
+        let start = secs(self.controls.loop_start.load(Ordering::Relaxed));
+        let end = secs(self.controls.loop_end.load(Ordering::Relaxed));
+        let name = if self.setlist.new_name.trim().is_empty() {
+            path.file_stem().map(|n| n.to_string_lossy().into_owned()).unwrap_or_default()
+        } else {
+            std::mem::take(&mut self.setlist.new_name)
+        };
+        self.setlist.items.push(SetlistItem { name, path, start, end });
+    }
+
+    fn start_setlist_item(&mut self, index: usize) {
+        let Some(item) = self.setlist.items.get(index) else { self.setlist.running = None; return };
+        let path = item.path.clone();
+        if self.state.lock().unwrap().file_path != path.to_string_lossy() { self.load_audio_file(path); }
+        self.setlist.running = Some((index, SetlistPhase::Loading));
+    }
+
+    // walks the setlist: load, play the region once, wait out the gap, move on
+    fn poll_setlist(&mut self) {
+        let c = self.controls.clone();
+        let Some((index, phase)) = &mut self.setlist.running else { return };
+        let index = *index;
+        match phase {
+            SetlistPhase::Loading => {
+                if c.is_loading.load(Ordering::SeqCst) { return; }
+                let item = &self.setlist.items[index];
+                let (file_path, rate, channels, total) = { let s = self.state.lock().unwrap(); (s.file_path.clone(), s.sample_rate, s.channels, s.total_samples) };
+                if file_path != item.path.to_string_lossy() || total == 0 { self.setlist.running = None; return; }
+                let to_samples = |t: f64| ((t * rate as f64) as usize * channels).min(total);
+                let (start, end) = (to_samples(item.start), to_samples(item.end));
+                c.loop_start.store(start, Ordering::Relaxed);
+                c.loop_end.store(end, Ordering::Relaxed);
+                self.seek_history.jump(&c, start);
+                c.is_playing.store(true, Ordering::Relaxed);
+                *phase = SetlistPhase::Playing { last_cursor: start, end };
+            }
+            SetlistPhase::Playing { last_cursor, end } => {
+                if !c.is_playing.load(Ordering::Relaxed) { return; } // paused by hand, wait
+                let cursor = c.cursor.load(Ordering::Relaxed);
+                if cursor >= *last_cursor && cursor < *end { *last_cursor = cursor; return; }
+                c.is_playing.store(false, Ordering::Relaxed);
+                if index + 1 >= self.setlist.items.len() { self.setlist.running = None; return; }
+                *phase = SetlistPhase::Gap(std::time::Instant::now() + std::time::Duration::from_secs_f32(self.setlist.gap_secs));
+            }
+            SetlistPhase::Gap(until) => {
+                if std::time::Instant::now() >= *until { self.start_setlist_item(index + 1); }
+            }
+        }
+    }
+
+    fn publish_automation(&self) {
+        let active = |lane: &AutomationLane| Arc::new(if lane.enabled { lane.curve.clone() } else { Automation::default() });
+        *self.controls.speed_curve.lock().unwrap() = active(&self.speed_lane);
+        *self.controls.pitch_regions.lock().unwrap() = active(&self.pitch_lane);
+        *self.controls.volume_envelope.lock().unwrap() = active(&self.volume_lane);
+    }
+
+    // restore_transport brings back the loop, position, speed, pitch and volume too; a deck swap
+    // only wants the envelope since it carries the transport across itself
+    fn load_session(&mut self, restore_transport: bool) {
+        let path = PathBuf::from(&self.state.lock().unwrap().file_path);
+        let session = Session::load(&path);
+        self.volume_lane.curve.points = session.volume_envelope;
+        self.publish_automation();
+        self.markers = session.markers;
+        if !restore_transport { return; }
+        if let Some(v) = session.speed { self.perform_quietly(Action::SetSpeed(v)); }
+        if let Some(v) = session.pitch { self.perform_quietly(Action::SetPitch(v)); }
+        self.set_tuning(session.tuning.unwrap_or(0.0));
+        if let Some(v) = session.level { self.controls.volume.store(v.to_bits(), Ordering::Relaxed); }
+        if self.track_choice.is_none() { self.track_choice = session.track; }
+        // positions can only be set once the load thread has reset them for the new file
+        self.pending_restore = Some(PendingRestore { file: path.to_string_lossy().into_owned(), loop_range: session.loop_range, cursor: session.cursor });
+    }
+
+    fn apply_pending_restore(&mut self) {
+        if self.controls.is_loading.load(Ordering::Relaxed) { return; }
+        let Some(PendingRestore { file, loop_range, cursor }) = self.pending_restore.take() else { return };
+        let s = self.state.lock().unwrap();
+        if s.file_path != file || s.total_samples == 0 { return; }
+        let c = &self.controls;
+        if let Some((a, b)) = loop_range.filter(|&(a, b)| a < b && b <= s.total_samples) {
+            c.loop_start.store(a, Ordering::Relaxed);
+            c.loop_end.store(b, Ordering::Relaxed);
+        }
+        if let Some(v) = cursor.filter(|&v| v < s.total_samples) { c.cursor.store(v - v % s.channels.max(1), Ordering::Relaxed); }
+    }
+
+    fn save_session(&self) {
+        let (path, total, track) = {
+            let s = self.state.lock().unwrap();
+            (PathBuf::from(&s.file_path), s.total_samples, s.audio_track.filter(|_| s.audio_tracks.len() > 1))
+        };
+        if !path.is_file() || total == 0 || self.controls.is_loading.load(Ordering::Relaxed) { return; }
+        let c = &self.controls;
+        let (a, b) = (c.loop_start.load(Ordering::Relaxed), c.loop_end.load(Ordering::Relaxed));
+        let session = Session {
+            volume_envelope: self.volume_lane.curve.points.clone(),
+            loop_range: (a > 0 || b < total).then_some((a, b)),
+            cursor: Some(c.cursor.load(Ordering::Relaxed)),
+            speed: Some(f32::from_bits(c.speed.load(Ordering::Relaxed))),
+            pitch: Some(f32::from_bits(c.pitch.load(Ordering::Relaxed))),
+            tuning: Some(self.tuning_cents).filter(|&v| v != 0.0),
+            level: Some(f32::from_bits(c.volume.load(Ordering::Relaxed))),
+            track,
+            markers: self.markers.clone(),
+        };
+        if let Err(e) = session.save(&path) { eprintln!("session: {}", e); }
+    }
+
+    // automation is drawn against one file's timeline, so it goes when the file does
+    fn clear_automation(&mut self) {
+        self.speed_lane.curve.points.clear();
+        self.pitch_lane.curve.points.clear();
+        self.volume_lane.curve.points.clear();
+        self.publish_automation();
+    }
+
+    // non-realtime edits of the loaded buffer over the loop region; the file on disk is untouched
+    fn edit_region(&mut self, edit: RegionEdit) {
+        if self.controls.is_loading.load(Ordering::Relaxed) { return; }
+        let channels = self.state.lock().unwrap().channels.max(1);
+        let old = Arc::clone(&*self.controls.pcm_data.lock().unwrap());
+        let start = self.controls.loop_start.load(Ordering::Relaxed) / channels * channels;
+        let end = (self.controls.loop_end.load(Ordering::Relaxed) / channels * channels).min(old.len());
+        if end <= start || (edit == RegionEdit::Delete && start == 0 && end == old.len()) { return; }
+
+        let region = &old[start..end];
+        let frames = (region.len() / channels) as f32;
+        let mut pcm = Vec::with_capacity(old.len());
+        pcm.extend_from_slice(&old[..start]);
+        match edit {
+            RegionEdit::Delete => {}
+            RegionEdit::Silence => pcm.resize(end, 0.0),
+            RegionEdit::FadeIn | RegionEdit::FadeOut => pcm.extend(region.iter().enumerate().map(|(i, v)| {
+                let t = (i / channels) as f32 / frames;
+                v * if edit == RegionEdit::FadeIn { t } else { 1.0 - t }
+            })),
+        }
+        pcm.extend_from_slice(&old[end..]);
+        let inserted = if edit == RegionEdit::Delete { 0 } else { region.len() };
+        self.edit_undo.push(EditUndo { at: start, removed: region.to_vec(), inserted });
+        if self.edit_undo.len() > 50 { self.edit_undo.remove(0); }
+        self.apply_edit(pcm, (start, start + inserted));
+    }
+
+    fn undo_edit(&mut self) {
+        let Some(u) = self.edit_undo.pop() else { return };
+        let old = Arc::clone(&*self.controls.pcm_data.lock().unwrap());
+        let mut pcm = Vec::with_capacity(old.len() + u.removed.len());
+        pcm.extend_from_slice(&old[..u.at]);
+        pcm.extend_from_slice(&u.removed);
+        pcm.extend_from_slice(&old[u.at + u.inserted..]);
+        self.apply_edit(pcm, (u.at, u.at + u.removed.len()));
+    }
+
+    // swaps in the edited buffer, selects the affected span and re-runs the analysis in the background
+    fn apply_edit(&mut self, pcm: Vec<f32>, span: (usize, usize)) {
+        let old_len = self.controls.pcm_data.lock().unwrap().len();
+        let total = pcm.len();
+        let envelope_dropped = total != old_len && !self.volume_lane.curve.points.is_empty();
+        let pcm = Arc::new(pcm);
+        set_source(&self.controls, Arc::clone(&pcm), None);
+        // stems hold the unedited audio, and anything positioned past a length change no longer lines up
+        swap_stem_mix(&self.controls, Arc::new(StemMix::default()));
+        self.stem_channels.clear();
+        if total != old_len {
+            self.clear_take();
+            self.clear_automation();
+        }
+        // markers, the cue and subtitles past the edit move with the audio; any inside a deleted stretch go to its start
+        let delta = total as isize - old_len as isize;
+        let old_end = (span.1 as isize - delta) as usize;
+        let moved = |pos: usize| if delta == 0 || pos < span.0 { pos } else if pos >= old_end { pos.saturating_add_signed(delta) } else { span.0 };
+        let c = &self.controls;
+        if delta != 0 {
+            self.markers.retain(|m| m.pos < span.0 || m.pos >= old_end);
+            for m in &mut self.markers { m.pos = moved(m.pos); }
+            for a in [&c.cue_cursor, &c.cue_start, &c.cue_end] { a.store(moved(a.load(Ordering::Relaxed)), Ordering::Relaxed); }
+        }
+        if span.1 > span.0 {
+            c.loop_start.store(span.0, Ordering::Relaxed);
+            c.loop_end.store(span.1, Ordering::Relaxed);
+        } else {
+            c.loop_start.store(0, Ordering::Relaxed);
+            c.loop_end.store(total, Ordering::Relaxed);
+        }
+        c.cursor.store(span.0.min(total), Ordering::Relaxed);
+
+        let (channels, sample_rate) = {
+            let mut s = self.state.lock().unwrap();
+            s.total_samples = total;
+            s.stems.clear();
+            if delta != 0 {
+                let per_sec = (s.sample_rate * s.channels as u32) as f64;
+                let shift = |t: f64| moved((t * per_sec) as usize) as f64 / per_sec;
+                for cue in &mut s.subtitles { (cue.start, cue.end) = (shift(cue.start), shift(cue.end)); }
+                s.subtitles.retain(|cue| cue.end > cue.start);
+            }
+            (s.channels, s.sample_rate)
+        };
+        // the sidecar would otherwise bring the cleared envelope back on the next load
+        if envelope_dropped { self.save_session(); }
+        let s_ptr = self.state.clone();
+        let controls = self.controls.clone();
+        thread::spawn(move || {
+            let analysis = PcmAnalysis::new(&pcm, channels, sample_rate);
+            // skip it if another edit or a new file got there first
+            if Arc::ptr_eq(&pcm, &controls.pcm_data.lock().unwrap()) { analysis.store(&mut s_ptr.lock().unwrap()); }
+        });
+    }
+
+    fn start_sing_along(&mut self) {
+        let (mut tx, rx) = HeapRb::<f32>::new(1 << 16).split();
+        match open_input(move |mono| { tx.push_slice(mono); }) {
+            Ok((stream, rate)) => {
+                self.sing_along = Some(SingAlong { _stream: stream, rx, rate, window: std::collections::VecDeque::new(), trace: std::collections::VecDeque::new(), last: std::time::Instant::now() });
+                self.sing_along_status.clear();
+            }
+            Err(e) => self.sing_along_status = format!("No input: {e}"),
+        }
+    }
+
+    fn start_monitor(&mut self) {
+        let probe = Arc::new(LatencyProbe::new());
+        let (mut tx, rx) = HeapRb::<f32>::new(1 << 15).split();
+        let p = Arc::clone(&probe);
+        match open_input(move |mono| {
+            tx.push_slice(mono);
+            if !p.listening.load(Ordering::Relaxed) { return; }
+            // the UI only holds the lock to arm and to search, never while this should be writing
+            let Ok(mut capture) = p.capture.try_lock() else { return };
+            if capture.1.is_empty() {
+                // the buffer ends now, so its first sample came in its length earlier
+                let behind = mono.len() as f64 / p.input_rate.load(Ordering::Relaxed) as f64 * 1e6;
+                capture.0 = (p.epoch.elapsed().as_micros() as f64 - behind).max(0.0) as u64;
+            }
+            let room = capture.1.capacity() - capture.1.len();
+            capture.1.extend_from_slice(&mono[..mono.len().min(room)]);
+        }) {
+            Ok((stream, input_rate)) => {
+                probe.input_rate.store(input_rate, Ordering::Relaxed);
+                let _ = self.tx.send(ParamUpdate::Monitor(Some((rx, input_rate, Arc::clone(&probe)))));
+                self.monitor = Some(InputMonitor { _stream: stream, probe });
+                self.monitor_status.clear();
+            }
+            Err(e) => self.monitor_status = format!("No input: {e}"),
+        }
+    }
+
+    fn stop_monitor(&mut self) {
+        let _ = self.tx.send(ParamUpdate::Monitor(None));
+        self.monitor = None;
+    }
+
+    fn poll_sing_along(&mut self) {
+        const WINDOW: usize = 2048;
+        let shift = self.heard_shift();
+        let Some(sa) = &mut self.sing_along else { return };
+        sa.window.extend(sa.rx.pop_iter());
+        let excess = sa.window.len().saturating_sub(WINDOW);
+        sa.window.drain(..excess);
+        if sa.last.elapsed().as_millis() < 25 || sa.window.len() < WINDOW { return; }
+        sa.last = std::time::Instant::now();
+
+        let mic: Vec<f32> = sa.window.iter().copied().collect();
+        let mic_note = yin_pitch(&mic, sa.rate, 70.0, 1200.0).map(freq_to_midi);
+        // the track as heard: the frames just behind the cursor, moved by the current pitch shift
+        let track_note = if self.controls.is_playing.load(Ordering::Relaxed) {
+            let (rate, channels) = { let s = self.state.lock().unwrap(); (s.sample_rate, s.channels.max(1)) };
+            let pcm = Arc::clone(&*self.controls.pcm_data.lock().unwrap());
+            let end = self.controls.cursor.load(Ordering::Relaxed).min(pcm.len());
+            let start = end.saturating_sub(WINDOW * channels);
+            let mono: Vec<f32> = pcm[start..end].chunks(channels).map(|f| f.iter().sum::<f32>() / channels as f32).collect();
+            yin_pitch(&mono, rate, 70.0, 1200.0).map(|f| freq_to_midi(f) + shift)
+        } else {
+            None
+        };
+        sa.trace.push_back((mic_note, track_note));
+        if sa.trace.len() > 240 { sa.trace.pop_front(); }
+    }
+
+    fn clear_take(&mut self) {
+        self.recorder = None;
+        self.take_overlay = None;
+        swap_take(&self.controls, Arc::new(Take::default()));
+        self.take_status.clear();
+    }
+
+    fn add_plugin(&mut self) {
+        let Some(path) = FileDialog::new().add_filter("CLAP plugin", &["clap"]).pick_file() else { return };
+        let id = self.next_plugin_id;
+        match ClapPlugin::load(&path, id, self.output_rate) {
+            Ok((fx, name)) => {
+                self.next_plugin_id += 1;
+                self.plugin_status = format!("Loaded {name}");
+                let params = fx.params();
+                self.plugin_names.push((id, name, path, params));
+                self.effect_layout.push((EffectKind::Plugin(id), false));
+                let _ = self.tx.send(ParamUpdate::InsertEffect(Box::new(fx)));
+            }
+            Err(e) => self.plugin_status = format!("Plugin failed: {e}"),
+        }
+    }
+
+    fn park_deck(&mut self) -> Deck {
+        Deck {
+            state: std::mem::take(&mut *self.state.lock().unwrap()),
+            pcm: std::mem::take(&mut *self.controls.pcm_data.lock().unwrap()),
+            stream: self.controls.stream.lock().unwrap().take(),
+            stem_mix: std::mem::take(&mut *self.controls.stem_mix.lock().unwrap()),
+            stem_channels: std::mem::take(&mut self.stem_channels),
+        }
+    }
+
+    fn load_deck_b(&mut self, path: PathBuf) {
+        if self.ab.other.is_none() {
+            self.ab.other = Some(self.park_deck());
+            self.ab.on_b = true;
+        } else if !self.ab.on_b {
+            self.swap_decks();
+        }
+        self.apply_deck_gain();
+        self.load_audio_file(path);
+    }
+
+    // the A/B offset plus, with normalizing on, the makeup gain that brings the file to the target
+    // loudness; called every frame too, since the loudness only arrives once analysis is done
+    fn apply_deck_gain(&self) {
+        let db = self.ab.gain_db[self.ab.on_b as usize] + self.makeup_db().unwrap_or(0.0);
+        self.controls.deck_gain.store(10f32.powf(db / 20.0).to_bits(), Ordering::Relaxed);
+    }
+
+    fn makeup_db(&self) -> Option<f32> {
+        if !self.normalize.0 { return None; }
+        let lufs = self.state.lock().unwrap().file_lufs?;
+        Some((self.normalize.1 - lufs).clamp(-MAX_MAKEUP_DB, MAX_MAKEUP_DB))
+    }
+
+    // switch to the other deck at the same musical moment: cursor and loop are carried over by time
+    fn swap_decks(&mut self) {
+        // a load in flight writes into whichever deck is active, so hold still until it lands
+        if self.controls.is_loading.load(Ordering::SeqCst) { return; }
+        let Some(mut other) = self.ab.other.take() else { return };
+        self.save_session();
+        self.clear_take();
+        self.clear_automation();
+        self.edit_undo.clear();
+        self.seek_history = SeekHistory::default();
+        let c = &self.controls;
+        let (rate, channels, total) = { let s = self.state.lock().unwrap(); (s.sample_rate, s.channels, s.total_samples) };
+        let (o_rate, o_channels, o_total) = (other.state.sample_rate, other.state.channels, other.state.total_samples);
+        let shift = if self.ab.on_b { -self.ab.offset_secs } else { self.ab.offset_secs } as f64;
+        let to_other = |i: usize| {
+            let secs = i as f64 / (rate as f64 * channels as f64) + shift;
+            ((secs.max(0.0) * o_rate as f64) as usize * o_channels).min(o_total)
+        };
+        let cursor = to_other(c.cursor.load(Ordering::Relaxed));
+        let loop_start = to_other(c.loop_start.load(Ordering::Relaxed));
+        let loop_end = c.loop_end.load(Ordering::Relaxed);
+        let loop_end = if loop_end >= total { o_total } else { to_other(loop_end) };
+
+        std::mem::swap(&mut *self.state.lock().unwrap(), &mut other.state);
+        std::mem::swap(&mut *c.pcm_data.lock().unwrap(), &mut other.pcm);
+        std::mem::swap(&mut *c.stream.lock().unwrap(), &mut other.stream);
+        std::mem::swap(&mut *c.stem_mix.lock().unwrap(), &mut other.stem_mix);
+        std::mem::swap(&mut self.stem_channels, &mut other.stem_channels);
+        c.cursor.store(cursor, Ordering::Relaxed);
+        c.loop_start.store(loop_start, Ordering::Relaxed);
+        c.loop_end.store(loop_end.max(loop_start), Ordering::Relaxed);
+        c.source_rate.store(o_rate, Ordering::Relaxed);
+        c.source_channels.store(o_channels, Ordering::Relaxed);
+
+        self.ab.other = Some(other);
+        self.ab.on_b = !self.ab.on_b;
+        self.apply_deck_gain();
+        self.load_session(false);
+    }
+
+    fn clear_deck_b(&mut self) {
+        if self.ab.on_b { self.swap_decks(); }
+        self.ab.other = None;
+        self.ab.on_b = false;
+        self.apply_deck_gain();
+    }
+
+    fn open_location(&mut self, location: String) {
+        if !is_url(&location) {
+            self.load_audio_file(PathBuf::from(location));
+            return;
+        }
+        self.state.lock().unwrap().location_status = format!("Downloading {location}");
+        let tx = self.open_tx.clone();
+        let login_tx = self.login_tx.clone();
+        let error_tx = self.load_error_tx.clone();
+        let login = self.logins.get(url_host(&location)).cloned();
+        let s_ptr = self.state.clone();
+        thread::spawn(move || {
+            let result = download_url(&location, login.as_ref());
+            s_ptr.lock().unwrap().location_status.clear();
+            match result {
+                Ok(path) => { let _ = tx.send(path); }
+                // the login prompt says so itself
+                Err(FetchError::NeedsLogin) => { let _ = login_tx.send(location); }
+                Err(FetchError::Failed(e)) => { let _ = error_tx.send(format!("{location}: download failed: {e}")); }
+            }
+        });
+    }
+
+    fn open_with_ytdlp(&mut self, url: String) {
+        self.state.lock().unwrap().location_status = format!("Fetching {url} with yt-dlp");
+        let tx = self.open_tx.clone();
+        let error_tx = self.load_error_tx.clone();
+        let s_ptr = self.state.clone();
+        thread::spawn(move || {
+            let result = fetch_with_ytdlp(&url);
+            s_ptr.lock().unwrap().location_status.clear();
+            match result {
+                Ok(path) => { let _ = tx.send(path); }
+                Err(e) => { let _ = error_tx.send(format!("{url}: yt-dlp failed: {e}")); }
+            }
+        });
+    }
+
+    fn location_window(&mut self, ctx: &egui::Context) {
+        let Some(prompt) = &mut self.location_prompt else { return };
+        let mut open = true;
+        let mut go = false;
+        let mut ytdlp = false;
+        egui::Window::new("Open Location").open(&mut open).collapsible(false).resizable(false).show(ctx, |ui| {
+            ui.add(egui::TextEdit::singleline(&mut prompt.location).hint_text("https://, webdav://, smb://host/share/file.mp3").desired_width(320.0));
+            ui.horizontal(|ui| {
+                ui.add(egui::TextEdit::singleline(&mut prompt.user).hint_text("user (optional)").desired_width(120.0));
+                let pw = ui.add(egui::TextEdit::singleline(&mut prompt.password).password(true).hint_text("password").desired_width(120.0));
+                go = pw.lost_focus() && ui.input(|i| i.key_pressed(egui::Key::Enter));
+                go |= ui.button("Open").clicked();
+                ytdlp = ui.button("via yt-dlp").on_hover_text("YouTube and other video sites, using an installed yt-dlp").clicked();
+            });
+            if !prompt.message.is_empty() { ui.label(&prompt.message); }
+            ui.label(egui::RichText::new("Files are cached locally after the first open. SMB needs smbclient.").weak());
+        });
+        if ytdlp {
+            let url = prompt.location.trim().to_string();
+            self.location_prompt = None;
+            self.open_with_ytdlp(url);
+        } else if go {
+            let location = prompt.location.trim().to_string();
+            if !prompt.user.is_empty() {
+                self.logins.insert(url_host(&location).to_string(), Login { user: prompt.user.clone(), password: prompt.password.clone() });
+            }
+            self.location_prompt = None;
+            if let Some(location) = parse_location(&location) { self.open_location(location); }
+        } else if !open {
+            self.location_prompt = None;
+        }
+    }
+
+    // the texture to draw for samples from..to and the part of it that covers them: one column per pixel
+    // from the mipmap, or the whole-file overview for a streamed file, which has no mipmap
+    fn waveform_texture(&mut self, ctx: &egui::Context, waveform_gen: u64, lane: usize, (from, to): (usize, usize), total: usize, columns: usize) -> Option<(egui::TextureId, egui::Rect)> {
+        let pcm = Arc::clone(&*self.controls.pcm_data.lock().unwrap());
+        let mipmaps = Arc::clone(&self.state.lock().unwrap().wave_mipmaps);
+        let Some(mipmap) = mipmaps.get(lane).filter(|_| pcm.len() >= total) else {
+            if lane > 0 { return None; }
+            let key = (waveform_gen, self.spectral_colors);
+            if self.wave_texture.as_ref().map(|t| t.0) != Some(key) {
+                let s = self.state.lock().unwrap();
+                let image = (!s.waveform.is_empty()).then(|| waveform_image(&s.waveform, if self.spectral_colors { &s.waveform_colors } else { &[] }));
+                drop(s);
+                self.wave_texture = image.map(|image| (key, ctx.load_texture("waveform", image, egui::TextureOptions::LINEAR)));
+            }
+            let uv = egui::Rect::from_x_y_ranges(from as f32 / total as f32..=to as f32 / total as f32, 0.0..=1.0);
+            return self.wave_texture.as_ref().map(|t| (t.1.id(), uv));
+        };
+        let key = (waveform_gen, self.spectral_colors, from, to, columns);
+        if self.zoom_textures.len() <= lane { self.zoom_textures.resize_with(lane + 1, || None); }
+        if self.zoom_textures[lane].as_ref().map(|t| t.0) != Some(key) {
+            let ch = mipmap.channels.max(1);
+            let buckets = mipmap.columns(&pcm, from / ch, to / ch, columns);
+            let s = self.state.lock().unwrap();
+            // keep the tint of the overview bucket each column falls in
+            let colors: Vec<egui::Color32> = if self.spectral_colors && !s.waveform_colors.is_empty() {
+                (0..columns).map(|c| {
+                    let at = from + (c as f64 * (to - from) as f64 / columns as f64) as usize;
+                    s.waveform_colors[(at * s.waveform_colors.len() / total).min(s.waveform_colors.len() - 1)]
+                }).collect()
+            } else { Vec::new() };
+            drop(s);
+            self.zoom_textures[lane] = Some((key, ctx.load_texture("waveform-view", waveform_image(&buckets, &colors), egui::TextureOptions::LINEAR)));
+        }
+        self.zoom_textures[lane].as_ref().map(|t| (t.1.id(), egui::Rect::from_min_max(egui::pos2(0.0, 0.0), egui::pos2(1.0, 1.0))))
+    }
+
+    // the spectrogram for the visible frames, with the part of the texture to draw: the whole file's, computed on
+    // a background thread the first time it's asked for, and once zoomed in past its resolution a sharper one
+    // for the view and a view's width either side, recomputed when the view leaves it or the zoom changes
+    fn spectrogram_texture(&mut self, ctx: &egui::Context, waveform_gen: u64, view: (usize, usize), width: usize) -> Option<(egui::TextureId, egui::Rect)> {
+        if let Some((made, rx)) = &self.spectrogram_job && let Ok(image) = rx.try_recv() {
+            self.spectrogram = Some((*made, ctx.load_texture("spectrogram", image, egui::TextureOptions::LINEAR)));
+            self.spectrogram_job = None;
+        }
+        if let Some((key, rx)) = &self.spectrogram_view_job && let Ok(image) = rx.try_recv() {
+            self.spectrogram_view = Some((*key, ctx.load_texture("spectrogram-view", image, egui::TextureOptions::LINEAR)));
+            self.spectrogram_view_job = None;
+        }
+        let pcm = Arc::clone(&*self.controls.pcm_data.lock().unwrap());
+        let (rate, channels) = { let s = self.state.lock().unwrap(); (s.sample_rate, s.channels.max(1)) };
+        let frames = pcm.len() / channels;
+        let (from, to) = (view.0 / channels, (view.1 / channels).min(frames));
+        if to <= from { return None; }
+        let uv = |range: (usize, usize)| egui::Rect::from_x_y_ranges(
+            (from - range.0) as f32 / (range.1 - range.0) as f32..=(to - range.0) as f32 / (range.1 - range.0) as f32, 0.0..=1.0);
+
+        let whole = match &self.spectrogram {
+            Some((made, texture)) if *made == waveform_gen => Some((texture.id(), texture.size()[0])),
+            _ => {
+                if self.spectrogram_job.as_ref().is_none_or(|(made, _)| *made != waveform_gen) && !pcm.is_empty() {
+                    let (tx, rx) = unbounded();
+                    let pcm = Arc::clone(&pcm);
+                    thread::spawn(move || { let _ = tx.send(spectrogram_image(&pcm, channels, rate, 0..frames, frames / 512)); });
+                    self.spectrogram_job = Some((waveform_gen, rx));
+                }
+                None
+            }
+        };
+        let (whole_id, whole_columns) = whole?;
+        let span = to - from;
+        // the whole-file texture already has a column per pixel or more here
+        if whole_columns as f64 * span as f64 / frames as f64 >= width as f64 { return Some((whole_id, uv((0, frames)))); }
+
+        let frames_per_px = span as f64 / width.max(1) as f64;
+        if let Some(((made, a, b, columns), texture)) = &self.spectrogram_view && *made == waveform_gen && *a <= from && to <= *b {
+            // within a third either way of the resolution the view wants is sharp enough
+            let ratio = (*b - *a) as f64 / *columns as f64 / frames_per_px;
+            if (0.75..1.5).contains(&ratio) { return Some((texture.id(), uv((*a, *b)))); }
+        }
+        if self.spectrogram_view_job.is_none() {
+            let (a, b) = (from.saturating_sub(span), (to + span).min(frames));
+            let columns = (((b - a) as f64 / frames_per_px) as usize).min(SPECTRO_MAX_COLUMNS);
+            let key = (waveform_gen, a, b, columns);
+            let (tx, rx) = unbounded();
+            thread::spawn(move || { let _ = tx.send(spectrogram_image(&pcm, channels, rate, a..b, columns)); });
+            self.spectrogram_view_job = Some((key, rx));
+        }
+        // stretched until the sharper one is in
+        Some((whole_id, uv((0, frames))))
+    }
+
+    // the note curve for the loop region, tracked on a background thread whenever the region or audio
+    // changes; None while it's being worked out or when the region is too long
+    fn pitch_track(&mut self, waveform_gen: u64) -> Option<&[(usize, f32)]> {
+        let (start, end) = (self.controls.loop_start.load(Ordering::Relaxed), self.controls.loop_end.load(Ordering::Relaxed));
+        let key = (waveform_gen, start, end);
+        if let Some((made, rx)) = &self.pitch_job && let Ok(points) = rx.try_recv() {
+            self.pitch_curve = Some((*made, points));
+            self.pitch_job = None;
+        }
+        let have = self.pitch_curve.as_ref().is_some_and(|(made, _)| *made == key);
+        let pending = self.pitch_job.as_ref().is_some_and(|(made, _)| *made == key);
+        // not while a loop marker is being dragged, or every step of the drag would start a job
+        if !have && !pending && self.dragging_marker.is_none() {
+            let pcm = Arc::clone(&*self.controls.pcm_data.lock().unwrap());
+            let (rate, channels) = { let s = self.state.lock().unwrap(); (s.sample_rate, s.channels) };
+            let secs = end.saturating_sub(start) as f32 / (rate as f32 * channels.max(1) as f32).max(1.0);
+            if pcm.is_empty() || secs > PITCH_TRACK_MAX_SECS { return None; }
+            let (tx, rx) = unbounded();
+            thread::spawn(move || { let _ = tx.send(track_pitch(&pcm, channels, rate, start, end)); });
+            self.pitch_job = Some((key, rx));
+        }
+        self.pitch_curve.as_ref().filter(|(made, _)| *made == key).map(|(_, points)| points.as_slice())
+    }
+
+    // zoom the waveform by factor (>1 zooms in) keeping the point at anchor (0..1 across the view) still
+    fn zoom_wave(&mut self, factor: f64, anchor: f64, min_span: f64) {
+        let (a, b) = self.wave_view;
+        let span = ((b - a) / factor).clamp(min_span.min(1.0), 1.0);
+        let pivot = a + (b - a) * anchor;
+        let start = (pivot - span * anchor).clamp(0.0, 1.0 - span);
+        self.wave_view = (start, start + span);
+    }
+
+    // scroll the zoomed waveform sideways by a fraction of the visible span
+    fn pan_wave(&mut self, by: f64) {
+        let (a, b) = self.wave_view;
+        let start = (a + (b - a) * by).clamp(0.0, 1.0 - (b - a));
+        self.wave_view = (start, start + (b - a));
+    }
+
+    fn load_subtitles(&mut self, path: PathBuf) {
+        if let Ok(text) = std::fs::read_to_string(&path) {
+            self.state.lock().unwrap().subtitles = parse_subtitles(&text);
+        }
+    }
+
+    // plays the bit of audio leading into a start marker, or following an end marker
+    fn snap_markers_to_zero(&mut self, start: bool, end: bool) {
+        let pcm = Arc::clone(&*self.controls.pcm_data.lock().unwrap());
+        let (rate, channels) = { let s = self.state.lock().unwrap(); (s.sample_rate, s.channels) };
+        let radius = rate as usize / 50; // 20 ms either side
+        let c = &self.controls;
+        if start { c.loop_start.store(nearest_zero_crossing(&pcm, channels, c.loop_start.load(Ordering::Relaxed), radius), Ordering::Relaxed); }
+        if end { c.loop_end.store(nearest_zero_crossing(&pcm, channels, c.loop_end.load(Ordering::Relaxed), radius), Ordering::Relaxed); }
+    }
+
+    fn audition_edge(&mut self, start_marker: bool, force: bool) {
+        const SNIPPET_SECS: f32 = 0.4;
+        let c = &self.controls;
+        let point = if start_marker { c.loop_start.load(Ordering::Relaxed) } else { c.loop_end.load(Ordering::Relaxed) };
+        // while dragging, let each snippet mostly finish instead of stuttering on every pixel
+        let (at, last_point) = self.last_audition;
+        if point == last_point || (!force && at.elapsed().as_secs_f32() < SNIPPET_SECS * 0.75) { return; }
+        let (rate, channels) = { let s = self.state.lock().unwrap(); (s.sample_rate, s.channels) };
+        let len = (SNIPPET_SECS * rate as f32) as usize * channels;
+        let point = point - point % channels.max(1);
+        let (from, to) = if start_marker { (point.saturating_sub(len), point) } else { (point, point + len) };
+        c.audition_pos.store(usize::MAX, Ordering::SeqCst);
+        c.audition_end.store(to, Ordering::SeqCst);
+        c.audition_pos.store(from, Ordering::SeqCst);
+        self.last_audition = (std::time::Instant::now(), point);
+    }
+
+    fn perform(&mut self, ctx: &egui::Context, action: Action) {
+        if let Some(rec) = &mut self.macros.recording {
+            // a dragged slider only needs its final value
+            match (rec.last_mut(), action) {
+                (Some(Action::SetSpeed(v)), Action::SetSpeed(n)) | (Some(Action::SetPitch(v)), Action::SetPitch(n)) => *v = n,
+                (_, Action::Quit) => {}
+                _ => rec.push(action),
+            }
+        }
+        let c = self.controls.clone();
+        let total = self.state.lock().unwrap().total_samples;
+        match action {
+            Action::TogglePlay => self.toggle_play(),
+            Action::Play => if !c.is_playing.load(Ordering::Relaxed) { self.toggle_play(); },
+            Action::Pause => c.is_playing.store(false, Ordering::Relaxed),
+            Action::Quit => ctx.send_viewport_cmd(egui::ViewportCommand::Close),
+            Action::ResetSpeedPitch => {
+                self.perform_quietly(Action::SetSpeed(1.0));
+                self.perform_quietly(Action::SetPitch(1.0));
+            }
+            Action::ClearLoop => {
+                c.loop_start.store(0, Ordering::Relaxed);
+                c.loop_end.store(total, Ordering::Relaxed);
+            }
+            Action::SwapDecks => self.swap_decks(),
+            Action::NextPhrase => self.jump_phrase(true),
+            Action::PrevPhrase => self.jump_phrase(false),
+            Action::LoopPhrase => self.loop_phrase(),
+            Action::RestartLoop => self.seek_history.jump(&c, c.loop_start.load(Ordering::Relaxed)),
+            Action::BeatJumpBack => self.jump_beats(-(self.beat_jump as i32)),
+            Action::BeatJumpForward => self.jump_beats(self.beat_jump as i32),
+            Action::LoopStartAtCursor => c.loop_start.store(c.cursor.load(Ordering::Relaxed), Ordering::Relaxed),
+            Action::LoopEndAtCursor => c.loop_end.store(c.cursor.load(Ordering::Relaxed), Ordering::Relaxed),
+            Action::ShiftLoopBack => shift_loop(&c, total, false),
+            Action::ShiftLoopForward => shift_loop(&c, total, true),
+            Action::UndoEdit => self.undo_edit(),
+            Action::SeekBack => self.seek_history.step(&c, false),
+            Action::SeekForward => self.seek_history.step(&c, true),
+            Action::NextTrack => self.step_track(1),
+            Action::PrevTrack => self.step_track(-1),
+            Action::ToggleSpectrogram => self.show_spectrogram = !self.show_spectrogram,
+            Action::AddMarker => self.add_marker(),
+            Action::NextMarker => self.jump_marker(true),
+            Action::PrevMarker => self.jump_marker(false),
+            Action::NudgeBack => self.nudge(-self.nudge_secs),
+            Action::NudgeForward => self.nudge(self.nudge_secs),
+            // poll_metronome passes it on to the engine
+            Action::ToggleMetronome => self.metronome.enabled = !self.metronome.enabled,
+            Action::Nudge(v) => self.nudge(v),
+            Action::SetSpeed(_) | Action::SetPitch(_) => self.perform_quietly(action),
+        }
+    }
+
+    // semitones the playback is shifted by as heard: pitch follows speed in varispeed, and fine-tuning goes on top
+    fn heard_shift(&self) -> f32 {
+        let c = &self.controls;
+        let ratio = if self.playback_mode == PlaybackMode::Varispeed { f32::from_bits(c.speed.load(Ordering::Relaxed)) }
+            else { f32::from_bits(c.pitch.load(Ordering::Relaxed)) };
+        12.0 * ratio.log2() + self.tuning_cents / 100.0
+    }
+
+    // the parameter actions, without being recorded
+    fn perform_quietly(&mut self, action: Action) {
+        match action {
+            Action::SetSpeed(v) => {
+                self.controls.speed.store(v.to_bits(), Ordering::Relaxed);
+                let _ = self.tx.send(ParamUpdate::Speed(v));
+            }
+            Action::SetPitch(v) => {
+                self.controls.pitch.store(v.to_bits(), Ordering::Relaxed);
+                let _ = self.tx.send(ParamUpdate::Pitch(v));
+            }
+            _ => {}
+        }
+    }
+
+    fn run_macro(&mut self, ctx: &egui::Context, index: usize) {
+        let actions = self.macros.list[index].actions.clone();
+        for action in actions { self.perform(ctx, action); }
+    }
+
+    // tells the extensions about anything that changed since the last frame
+    fn notify_extensions(&mut self) {
+        if self.extensions.is_empty() || self.controls.is_loading.load(Ordering::Relaxed) { return; }
+        let (file, total, rate, channels, tempo, key, phrases) = {
+            let s = self.state.lock().unwrap();
+            (s.file_path.clone(), s.total_samples, s.sample_rate, s.channels, s.tempo.map(|t| t.bpm), s.key.map(|k| k.name()), s.phrases.len())
+        };
+        if total == 0 { return; }
+        let secs = |i: usize| i as f64 / (rate as f64 * channels as f64).max(1.0);
+        let seen = &mut self.ext_seen;
+        let mut events: Vec<(u32, f64, Option<String>)> = Vec::new();
+        if seen.file != file {
+            *seen = ExtSeen { file: file.clone(), wraps: self.controls.loop_wraps.load(Ordering::Relaxed), ..Default::default() };
+            events.push((EXT_EVENT_FILE_LOADED, secs(total), Some(file)));
+        }
+        let c = &self.controls;
+        let cursor = c.cursor.load(Ordering::Relaxed);
+        let l_start = c.loop_start.load(Ordering::Relaxed);
+        let wraps = c.loop_wraps.load(Ordering::Relaxed);
+        if wraps != seen.wraps {
+            seen.wraps = wraps;
+            events.push((EXT_EVENT_LOOP_WRAP, secs(l_start), None));
+        }
+        if cursor != seen.cursor {
+            seen.cursor = cursor;
+            events.push((EXT_EVENT_POSITION, secs(cursor), None));
+        }
+        if tempo != seen.tempo {
+            seen.tempo = tempo;
+            events.push((EXT_EVENT_TEMPO, tempo.unwrap_or(0.0) as f64, None));
+        }
+        if key != seen.key {
+            seen.key = key.clone();
+            events.push((EXT_EVENT_KEY, 0.0, key));
+        }
+        if phrases != seen.phrases {
+            seen.phrases = phrases;
+            events.push((EXT_EVENT_PHRASES, phrases as f64, None));
+        }
+        for ext in &self.extensions {
+            for (kind, value, text) in &events { ext.send(*kind, *value, text.as_deref()); }
+        }
+    }
+
+    fn extension_panels(&mut self, ui: &mut egui::Ui, ctx: &egui::Context) {
+        let mut actions = Vec::new();
+        for ext in self.extensions.iter().filter(|e| e.has_panel()) {
+            egui::CollapsingHeader::new(&ext.name).show(ui, |ui| ext.draw(ui, &mut actions));
+        }
+        for action in actions { self.perform(ctx, action); }
+    }
+
+    fn profile(&self) -> &'static Profile {
+        &PROFILES[self.profile.min(PROFILES.len() - 1)]
+    }
+
+    fn set_profile(&mut self, index: usize) {
+        self.profile = index;
+        self.keymap = self.profile().keymap();
+        // pull the speed into the new range
+        let (lo, hi) = self.profile().speed_range;
+        let speed = f32::from_bits(self.controls.speed.load(Ordering::Relaxed));
+        if !(lo..=hi).contains(&speed) { self.perform_quietly(Action::SetSpeed(speed.clamp(lo, hi))); }
+        let dir = config_dir();
+        if let Err(e) = std::fs::create_dir_all(&dir).and_then(|_| std::fs::write(dir.join("profile.txt"), self.profile().name)) {
+            eprintln!("profile: {}", e);
+        }
+    }
+
+    fn save_macros(&self) {
+        let dir = config_dir();
+        if let Err(e) = std::fs::create_dir_all(&dir).and_then(|_| std::fs::write(dir.join("macros.txt"), format_macros(&self.macros.list))) {
+            eprintln!("macros: {}", e);
+        }
+    }
+
+    fn toggle_play(&mut self) {
+        let p = self.controls.is_playing.load(Ordering::Relaxed);
+        if !p {
+            self.arm_dictation();
+            self.arm_count_in();
+        } else {
+            self.controls.count_in.store(0, Ordering::Relaxed);
+        }
+        self.controls.is_playing.store(!p, Ordering::Relaxed);
+    }
+
+    // a bar of clicks at the playback tempo when starting from the loop start
+    fn arm_count_in(&mut self) {
+        let c = &self.controls;
+        let (tempo, rate, channels) = { let s = self.state.lock().unwrap(); (s.tempo, s.sample_rate, s.channels) };
+        let Some(grid) = tempo.filter(|_| self.count_in) else { return };
+        let near = rate as usize / 20 * channels; // 50 ms
+        if c.cursor.load(Ordering::Relaxed).abs_diff(c.loop_start.load(Ordering::Relaxed)) > near { return; }
+        let speed = f32::from_bits(c.speed.load(Ordering::Relaxed));
+        let beat = (60.0 / (grid.bpm * speed) * self.output_rate as f32) as usize;
+        c.count_in_beat.store(beat.max(1), Ordering::Relaxed);
+        c.count_in.store(beat * COUNT_IN_BEATS, Ordering::Relaxed);
+    }
+
+    // sets the next point where the audio thread pauses for dictation
+    fn arm_dictation(&mut self) {
+        if !self.dictation.enabled {
+            self.controls.pause_at.store(usize::MAX, Ordering::Relaxed);
+            return;
+        }
+        let (phrases, sample_rate, channels) = {
+            let s = self.state.lock().unwrap();
+            (s.phrases.clone(), s.sample_rate, s.channels)
+        };
+        let cursor = self.controls.cursor.load(Ordering::Relaxed);
+        let stop = if self.dictation.at_phrases {
+            phrases.iter().find(|p| p.1 > cursor).map(|p| p.1).unwrap_or(usize::MAX)
+        } else {
+            cursor + (self.dictation.interval_secs * sample_rate as f32) as usize * channels
+        };
+        self.controls.pause_at.store(stop, Ordering::Relaxed);
+    }
+
+    fn add_marker(&mut self) {
+        if self.state.lock().unwrap().total_samples == 0 { return; }
+        let pos = self.controls.cursor.load(Ordering::Relaxed);
+        let i = self.markers.partition_point(|m| m.pos < pos);
+        self.markers.insert(i, Marker { pos, label: format!("Marker {}", self.markers.len() + 1) });
+        self.show_markers = true;
+        self.focus_marker = Some(i);
+    }
+
+    fn jump_marker(&mut self, forward: bool) {
+        let cursor = self.controls.cursor.load(Ordering::Relaxed);
+        // a little slack backwards, so pressing it while playing past a marker goes to the one before
+        let (rate, channels) = { let s = self.state.lock().unwrap(); (s.sample_rate as usize, s.channels) };
+        let slack = rate / 2 * channels;
+        let target = if forward {
+            self.markers.iter().find(|m| m.pos > cursor).map(|m| m.pos)
+        } else {
+            self.markers.iter().rev().find(|m| m.pos + slack < cursor).map(|m| m.pos).or(self.markers.first().map(|m| m.pos))
+        };
+        if let Some(t) = target { self.seek_history.jump(&self.controls, t); }
+    }
+
+    fn markers_side_panel(&mut self, ctx: &egui::Context) {
+        if !self.show_markers { return; }
+        let (rate, channels) = { let s = self.state.lock().unwrap(); (s.sample_rate, s.channels) };
+        let secs = |i: usize| i as f32 / (rate as f32 * channels as f32).max(1.0);
+        egui::SidePanel::right("markers").default_width(200.0).show(ctx, |ui| {
+            ui.horizontal(|ui| {
+                ui.heading("Markers");
+                if ui.small_button("x").on_hover_text("Hide").clicked() { self.show_markers = false; }
+            });
+            if ui.button("M Add at cursor").clicked() { self.add_marker(); }
+            ui.label("J / K previous / next");
+            ui.separator();
+            let mut remove = None;
+            let mut jump = None;
+            egui::ScrollArea::vertical().show(ui, |ui| {
+                for (i, m) in self.markers.iter_mut().enumerate() {
+                    ui.horizontal(|ui| {
+                        let t = secs(m.pos);
+                        if ui.button(format!("{}:{:05.2}", (t / 60.0) as u32, t % 60.0)).clicked() { jump = Some(m.pos); }
+                        let edit = ui.add(egui::TextEdit::singleline(&mut m.label).desired_width(110.0));
+                        if self.focus_marker == Some(i) {
+                            edit.request_focus();
+                            self.focus_marker = None;
+                        }
+                        if ui.small_button("x").clicked() { remove = Some(i); }
+                    });
+                }
+            });
+            if let Some(i) = remove { self.markers.remove(i); }
+            if let Some(pos) = jump { self.seek_history.jump(&self.controls, pos); }
+        });
+    }
+
+    // moves the cursor by whole frames, so it stays on the first channel; too small a step to
+    // go into the jump history
+    fn nudge(&mut self, secs: f32) {
+        let (sample_rate, channels, total) = {
+            let s = self.state.lock().unwrap();
+            (s.sample_rate, s.channels.max(1), s.total_samples)
+        };
+        if total == 0 { return; }
+        let frames = (secs as f64 * sample_rate as f64).round() as i64;
+        let cursor = (self.controls.cursor.load(Ordering::Relaxed) / channels) as i64;
+        let last = (total / channels) as i64 - 1;
+        let frame = (cursor + frames).clamp(0, last.max(0)) as usize;
+        self.controls.cursor.store(frame * channels, Ordering::Relaxed);
+    }
+
+    // moves the cursor by whole beats without interrupting playback
+    fn jump_beats(&mut self, beats: i32) {
+        let (tempo, sample_rate, channels, total) = {
+            let s = self.state.lock().unwrap();
+            (s.tempo, s.sample_rate, s.channels, s.total_samples)
+        };
+        let Some(grid) = tempo else { return };
+        let cursor = self.controls.cursor.load(Ordering::Relaxed) as f64;
+        let target = (cursor + beats as f64 * grid.beat_samples(sample_rate, channels)).clamp(0.0, total.saturating_sub(1) as f64) as usize;
+        self.seek_history.jump(&self.controls, target - target % channels.max(1));
+    }
+
+    fn jump_phrase(&mut self, forward: bool) {
+        let (phrases, sample_rate, channels) = {
+            let s = self.state.lock().unwrap();
+            (s.phrases.clone(), s.sample_rate, s.channels)
+        };
+        let cursor = self.controls.cursor.load(Ordering::Relaxed);
+        let target = if forward {
+            phrases.iter().find(|p| p.0 > cursor).map(|p| p.0)
+        } else {
+            // like a CD player: restart the current phrase unless we're right at its beginning
+            let grace = sample_rate as usize * channels / 2;
+            phrases.iter().rev().find(|p| p.0 + grace < cursor).map(|p| p.0)
+        };
+        if let Some(t) = target { self.seek_history.jump(&self.controls, t); }
+    }
+
+    fn loop_phrase(&mut self) {
+        let phrases = self.state.lock().unwrap().phrases.clone();
+        let cursor = self.controls.cursor.load(Ordering::Relaxed);
+        // the phrase under the cursor, or the next one when sitting in a pause
+        let phrase = phrases.iter().find(|p| p.0 <= cursor && cursor < p.1)
+            .or_else(|| phrases.iter().find(|p| p.0 > cursor));
+        if let Some(&(start, end)) = phrase {
+            self.controls.loop_start.store(start, Ordering::Relaxed);
+            self.controls.loop_end.store(end, Ordering::Relaxed);
+            self.seek_history.jump(&self.controls, start);
+        }
+    }
+
+    fn start_playback(&mut self) {
+        let rx = self.param_rx.clone();
+        let c = self.controls.clone();
+        let host = cpal::default_host();
+        // the chosen device, or the default one when it isn't plugged in
+        let chosen = self.output_device.as_ref().and_then(|name| host.output_devices().ok()?.find(|d| d.name().is_ok_and(|n| &n == name)));
+        self.output_status = match (&self.output_device, &chosen) {
+            (Some(name), None) => format!("{name} not found, using the default output"),
+            _ => String::new(),
+        };
+        let device = chosen.or_else(|| host.default_output_device()).expect("No output device");
+        let config = device.default_output_config().unwrap().config();
+        
+        let device_channels = config.channels as usize;
+        let device_rate = config.sample_rate.0;
+        self.output_rate = device_rate;
+        self.output_channels = device_channels;
+        let mut active_preset = StretchPreset::Default;
+        let mut stretch_tuning: Option<StretchTuning> = None;
+        let mut tonality_limit: Option<f32> = None;
+        let mut stretchers = active_preset.build(device_channels, device_rate);
+        // building a stretcher allocates, so the callback asks this thread for one and plays on with
+        // the old preset until it comes back as ParamUpdate::Stretchers
+        let (build_tx, build_rx) = bounded::<StretchPreset>(4);
+        let mut requested_preset: Option<StretchPreset> = None;
+        let builder_tx = self.tx.clone();
+        thread::spawn(move || {
+            while let Ok(preset) = build_rx.recv() {
+                let stretchers = preset.build(device_channels, device_rate);
+                if builder_tx.send(ParamUpdate::Stretchers { preset, rate: device_rate, stretchers }).is_err() { break; }
+            }
+        });
+        let (stretch_retire_tx, stretch_retire_rx) = bounded::<Vec<Stretch>>(8);
+        self.retired_stretchers = Some(stretch_retire_rx);
+        
+        let mut input_scratch = vec![0.0f32; SCRATCH_FRAMES];
+        let mut output_scratch = vec![0.0f32; SCRATCH_FRAMES];
+
+        let mut local_speed = 1.0f32;
+        let mut deck_gain = f32::from_bits(c.deck_gain.load(Ordering::Relaxed));
+        let mut nudge = 1.0f32;
+        let mut nudge_target = 1.0f32;
+        let mut local_pitch = 1.0f32;
+        let mut local_tuning = 1.0f32;
+        let mut matrix = ChannelMatrix::Stereo;
+        let mut local_mode = PlaybackMode::Stretch;
+        let mut resample_frac = 0.0f64;
+        let mut src_frac = 0.0f64;
+        let mut audition_frac = 0.0f64;
+        let mut audition_last = usize::MAX; // where the audition left off, to tell a new snippet from the running one
+        // where the last callback left the cursor, to tell a seek from our own progress
+        let mut engine_cursor: Option<usize> = None;
+        let mut seek_fade_in = false;
+        let seek_fade = (SEEK_FADE_MS * 0.001 * device_rate as f32) as usize;
+        let mut tone = ToneGenerator::new();
+        let mut drone = DroneGenerator::new();
+        let mut metronome = Metronome::new();
+        let mut aux: Vec<f32> = Vec::new();
+        let mut second_tx: Option<HeapProducer<(f32, f32)>> = None;
+        let mut monitor: Option<(HeapConsumer<f32>, u32, Arc<LatencyProbe>)> = None;
+        let mut monitor_frac = 0.0f64;
+        let mut monitor_last = [0.0f32; 2];
+        let mut probe_wait: Option<usize> = None; // frames of silence left before the latency click
+        let mut effects = EffectsChain::new(vec![Box::new(SpectralDenoiser::new(device_channels)), Box::new(Leveler::new()), Box::new(BandSolo::new(device_channels)), Box::new(Karaoke::new()), Box::new(Equalizer::new(device_channels))]);
+        let mut loudness_comp = LoudnessCompensation::new(device_channels);
+        let mut limiter = SoftLimiter::new();
+        let mut output_meter = LoudnessMeter::new(device_channels, device_rate);
+        let (mut scope_tx, scope_rx) = HeapRb::<(f32, f32)>::new(8192).split();
+        self.scope_rx = Some(scope_rx);
+        let (retire_tx, retire_rx) = unbounded();
+        self.retired_effects = Some(retire_rx);
+        let mut correlation = 0.0f32;
+        let mut level_power = [0.0f32; 2];
+        let mut meter_countdown = 0usize;
+        let lost = c.clone();
+
+        let stream = device.build_output_stream(&config, move |data: &mut [f32], _| {
+            while let Ok(update) = rx.try_recv() {
+                match update {
+                    ParamUpdate::Speed(s) => local_speed = s,
+                    ParamUpdate::Nudge(n) => nudge_target = n,
+                    ParamUpdate::Pitch(p) => local_pitch = p,
+                    ParamUpdate::Tuning(cents) => local_tuning = 2f32.powf(cents / 1200.0),
+                    ParamUpdate::Channels(m) => matrix = m,
+                    ParamUpdate::Stretcher { tuning, tonality_limit: limit } => {
+                        stretch_tuning = tuning;
+                        tonality_limit = limit.map(|hz| hz / device_rate as f32);
+                    }
+                    // one built for a device this stream has since replaced is sent back too
+                    ParamUpdate::Stretchers { preset, rate, stretchers: built } => {
+                        if rate == device_rate && built.len() == device_channels && requested_preset == Some(preset) {
+                            let _ = stretch_retire_tx.try_send(std::mem::replace(&mut stretchers, built));
+                            active_preset = preset;
+                            requested_preset = None;
+                        } else {
+                            let _ = stretch_retire_tx.try_send(built);
+                        }
+                    }
+                    ParamUpdate::Mode(m) => {
+                        // stale stretcher state would smear into the first block after switching back
+                        if m == PlaybackMode::Stretch && local_mode != m {
+                            stretchers.iter_mut().for_each(|s| s.reset());
+                        }
+                        local_mode = m;
+                        resample_frac = 0.0;
+                        src_frac = 0.0;
+                    }
+                    ParamUpdate::Metronome { enabled, level, accent, beat, downbeat } => {
+                        metronome.enabled = enabled;
+                        metronome.level = level;
+                        metronome.accent = accent;
+                        metronome.beat = beat;
+                        metronome.downbeat = downbeat;
+                    }
+                    ParamUpdate::Tone { enabled, freq, level } => {
+                        tone.enabled = enabled;
+                        tone.freq = freq;
+                        tone.level = level;
+                    }
+                    ParamUpdate::Drone { enabled, root, octave, fifth, level } => {
+                        drone.enabled = enabled;
+                        drone.root = root;
+                        drone.octave = octave;
+                        drone.fifth = fifth;
+                        drone.level = level;
+                    }
+                    ParamUpdate::ResetLoudness => output_meter.reset(),
+                    ParamUpdate::LoudnessCompensation(enabled) => loudness_comp.enabled = enabled,
+                    ParamUpdate::Limiter(enabled) => limiter.enabled = enabled,
+                    ParamUpdate::SecondOutput(tx) => second_tx = tx,
+                    ParamUpdate::Monitor(rx) => monitor = rx,
+                    ParamUpdate::InsertEffect(fx) => effects.effects.push((fx, false)),
+                    ParamUpdate::RemoveEffect(kind) => {
+                        if let Some(mut fx) = effects.remove(kind) {
+                            fx.retire();
+                            let _ = retire_tx.send(fx);
+                        }
+                    }
+                    other => effects.update(&other),
+                }
+            }
+
+            // glide towards the nudge target so pressing or releasing a nudge key doesn't jump
+            nudge += (nudge_target - nudge) * 0.2;
+            if (nudge - nudge_target).abs() < 1e-4 { nudge = nudge_target; }
+
+            let cursor_before = c.cursor.load(Ordering::Relaxed);
+            // a seek while playing: the old position plays on for one more block, fading out, then the
+            // stretcher is flushed so none of it smears into the new position, which fades in. The fade-out
+            // reads from here only; the shared cursor stays at the target, so the UI never sees it step back
+            let playing = c.is_playing.load(Ordering::Relaxed) && !c.is_loading.load(Ordering::Relaxed);
+            let mut fade_from = None;
+            if playing && let Some(own) = engine_cursor && own != cursor_before {
+                fade_from = Some(own);
+            } else if seek_fade_in {
+                stretchers.iter_mut().for_each(|s| s.reset());
+                resample_frac = 0.0;
+                src_frac = 0.0;
+            }
+            'music: {
+                // loop-edge audition: a short raw snippet at 1x, heard even while paused or dragging
+                let audition = c.audition_pos.load(Ordering::Relaxed);
+                let audition_end = c.audition_end.load(Ordering::Relaxed);
+                if audition < audition_end && !c.is_loading.load(Ordering::Relaxed) {
+                    let pcm = Arc::clone(&*c.pcm_data.lock().unwrap());
+                    let file_channels = c.source_channels.load(Ordering::Relaxed).max(1);
+                    // resampled to the device rate like the main path, so it plays at the file's pitch
+                    let rate_ratio = match c.source_rate.load(Ordering::Relaxed) { 0 => 1.0, r => r as f64 / device_rate as f64 };
+                    if audition != audition_last { audition_frac = 0.0; }
+                    let end = audition_end.min(pcm.len());
+                    let sample = |i: usize| if i < end { pcm[i] } else { 0.0 };
+                    for (i, out) in data.chunks_mut(device_channels).enumerate() {
+                        let pos = audition_frac + i as f64 * rate_ratio;
+                        for (ch, x) in out.iter_mut().enumerate() {
+                            let frame = |k: usize| map_channel(|j| sample(audition + k * file_channels + j), file_channels, device_channels, ch);
+                            *x = resample_at(frame, pos, rate_ratio);
+                        }
+                    }
+                    let advanced = audition_frac + (data.len() / device_channels) as f64 * rate_ratio;
+                    let consumed = advanced as usize;
+                    audition_frac = advanced - consumed as f64;
+                    audition_last = audition + consumed * file_channels;
+                    c.audition_pos.store(audition_last, Ordering::Relaxed);
+                    break 'music;
+                }
+
+                // Mute while loading, or if paused
+                if !playing {
+                    data.fill(0.0);
+                    break 'music;
+                }
+
+                // a bar of clicks before the music, the cursor waits at the loop start meanwhile
+                let count_in = c.count_in.load(Ordering::Relaxed);
+                if count_in > 0 {
+                    let beat = c.count_in_beat.load(Ordering::Relaxed).max(1);
+                    for (k, frame) in data.chunks_mut(device_channels).enumerate() {
+                        let left = count_in.saturating_sub(k);
+                        let t = (beat * COUNT_IN_BEATS).saturating_sub(left);
+                        let v = if left > 0 { click(t % beat, t < beat, device_rate) } else { 0.0 };
+                        frame.fill(v);
+                    }
+                    c.count_in.store(count_in.saturating_sub(data.len() / device_channels), Ordering::Relaxed);
+                    break 'music;
+                }
+
+                let pcm = Arc::clone(&*c.pcm_data.lock().unwrap());
+                let stream = c.stream.lock().unwrap().clone();
+                let pcm_len = stream.as_ref().map_or(pcm.len(), |s| s.total);
+                if pcm_len == 0 {
+                    data.fill(0.0);
+                    break 'music;
+                }
+                let stem_mix = Arc::clone(&*c.stem_mix.lock().unwrap());
+                let take = Arc::clone(&*c.take.lock().unwrap());
+                let blend = f32::from_bits(c.take_blend.load(Ordering::Relaxed));
+                let envelope = Arc::clone(&*c.volume_envelope.lock().unwrap());
+                let source = |i: usize| envelope.gain_at(i) * match &stream {
+                    Some(s) => s.get(i),
+                    None if stem_mix.stems.is_empty() => pcm[i],
+                    None => stem_mix.sample(i),
+                };
+                let src = |i: usize| match take.sample(i) {
+                    Some(t) if blend > 0.0 => source(i) * (1.0 - blend) + t * blend,
+                    _ => source(i),
+                };
+
+                let cursor = fade_from.unwrap_or(cursor_before);
+                // the fade-out block moves nothing shared: no cursor, no loop wraps, no end of track
+                let advance = |pos: usize| if fade_from.is_none() { c.cursor.store(pos, Ordering::Relaxed); };
+                let count_wrap = |wrapped: bool| if wrapped && fade_from.is_none() { c.loop_wraps.fetch_add(1, Ordering::Relaxed); };
+                let l_start = c.loop_start.load(Ordering::Relaxed);
+                let l_end = c.loop_end.load(Ordering::Relaxed);
+                let channels = c.source_channels.load(Ordering::Relaxed).max(1);
+
+                let speed_curve = Arc::clone(&*c.speed_curve.lock().unwrap());
+                let speed = local_speed * nudge * speed_curve.value_at(cursor).unwrap_or(1.0);
+                let pitch_regions = Arc::clone(&*c.pitch_regions.lock().unwrap());
+                let pitch = local_pitch * local_tuning * 2f32.powf(pitch_regions.value_at(cursor).unwrap_or(0.0) / 12.0);
+                let preset = StretchPreset::resolve(speed, stretch_tuning);
+                if preset != active_preset && requested_preset != Some(preset) && build_tx.try_send(preset).is_ok() {
+                    requested_preset = Some(preset);
+                }
+
+                // file frames per device frame at 1x; a 48 kHz file on a 44.1 kHz device reads ~1.09 per output frame
+                let rate_ratio = match c.source_rate.load(Ordering::Relaxed) { 0 => 1.0, r => r as f64 / device_rate as f64 };
+                // varispeed can only retune by running a touch faster or slower, like a tape deck's pitch control
+                let step = speed as f64 * rate_ratio * if local_mode == PlaybackMode::Varispeed { local_tuning as f64 } else { 1.0 };
+                let output_frames = data.len() / device_channels;
+                let input_frames_needed = (output_frames as f64 * step) as usize;
+                // a new deck or makeup gain (normalizing lands mid-playback) glides in over ~50 ms
+                let gain_from = deck_gain;
+                let gain_target = f32::from_bits(c.deck_gain.load(Ordering::Relaxed));
+                deck_gain += (gain_target - deck_gain) * (1.0 - (-(output_frames as f32) / (0.05 * device_rate as f32)).exp());
+                if (deck_gain - gain_target).abs() < 1e-4 { deck_gain = gain_target; }
+                let gain = |frame: usize| gain_from + (deck_gain - gain_from) * frame as f32 / output_frames.max(1) as f32;
+
+                // past the loop end, carry on from the loop start; an overshoot from the last block keeps
+                // its offset so the wrap lands sample-accurately, anything bigger (markers moved) restarts it
+                let loop_len = l_end.saturating_sub(l_start);
+                let wrap = |pos: usize| {
+                    if pos < l_end || loop_len == 0 { return pos; }
+                    let over = pos - l_end;
+                    l_start + if over < loop_len.min((input_frames_needed + 2) * channels * 2) { over } else { 0 }
+                };
+                // equal-power crossfade from the loop tail into the audio just before the loop start,
+                // which then runs straight on into the start when the cursor wraps
+                let fade_frames = ((f32::from_bits(c.loop_xfade_ms.load(Ordering::Relaxed)) as f64 * 0.001 * device_rate as f64 * rate_ratio) as usize)
+                    .min(l_start / channels).min(loop_len / channels / 2);
+                let looped = |i: usize| {
+                    if loop_len == 0 { return src(i); }
+                    if i >= l_end { return src(l_start + (i - l_end) % loop_len); }
+                    let left = (l_end - i).div_ceil(channels);
+                    if left > fade_frames { return src(i); }
+                    let t = (1.0 - left as f32 / fade_frames as f32) * std::f32::consts::FRAC_PI_2;
+                    src(i) * t.cos() + src(i - loop_len) * t.sin()
+                };
+                // one device channel of the file frame starting at sample index base
+                let mapped = |base: usize, out: usize| matrix.channel(|k| looped(base + k), channels, device_channels, out);
+
+                // a streamed file that hasn't decoded this far yet: wait in silence, the worker
+                // follows the cursor, which is moved to the loop start first if it's about to wrap
+                if let Some(s) = &stream {
+                    let from = wrap(cursor);
+                    if !s.covers(from, (input_frames_needed + 3) * channels) {
+                        count_wrap(from != cursor);
+                        advance(from);
+                        data.fill(0.0);
+                        break 'music;
+                    }
+                }
+
+                if local_mode == PlaybackMode::Varispeed && cursor + (input_frames_needed + 1) * channels < pcm_len {
+                    let active_cursor = wrap(cursor);
+                    count_wrap(active_cursor != cursor);
+
+                    // linear interpolation between neighbouring frames, no stretcher involved
+                    let last_frame = (pcm_len - active_cursor) / channels - 1;
+                    for i in 0..output_frames {
+                        let pos = resample_frac + i as f64 * step;
+                        let idx = (pos as usize).min(last_frame);
+                        let next = (idx + 1).min(last_frame);
+                        let t = (pos - pos.floor()) as f32;
+                        for ch in 0..device_channels {
+                            let a = mapped(active_cursor + idx * channels, ch);
+                            let b = mapped(active_cursor + next * channels, ch);
+                            data[i * device_channels + ch] = (a + (b - a) * t) * gain(i);
+                        }
+                    }
+                    let advanced = resample_frac + output_frames as f64 * step;
+                    let consumed = advanced as usize;
+                    resample_frac = advanced - consumed as f64;
+                    advance(active_cursor + consumed * channels);
+                } else if local_mode == PlaybackMode::Stretch && cursor + (input_frames_needed * channels) < pcm_len {
+                    let mut active_cursor = wrap(cursor);
+                    count_wrap(active_cursor != cursor);
+
+                    // keep each chunk's input within the scratch buffer, even at 8x
+                    let chunk_frames = ((SCRATCH_FRAMES as f32 / speed.max(1.0)) as usize).min(SCRATCH_FRAMES);
+                    let mut out_pos = 0;
+                    while out_pos < output_frames {
+                        let out_n = (output_frames - out_pos).min(chunk_frames);
+                        let in_n = ((out_n as f32 * speed) as usize).min(SCRATCH_FRAMES);
+                        // the stretcher runs at the device rate, so a file at another rate is resampled on the way in
+                        let last_frame = ((pcm_len - active_cursor) / channels).saturating_sub(1);
+                        for ch in 0..device_channels {
+                            stretchers[ch].set_transpose_factor(pitch, tonality_limit);
+                            for (i, x) in input_scratch[..in_n].iter_mut().enumerate() { 
+                                *x = if rate_ratio == 1.0 {
+                                    mapped(active_cursor + (i * channels), ch)
+                                } else {
+                                    let frame = |k: usize| mapped(active_cursor + k.min(last_frame) * channels, ch);
+                                    resample_at(frame, src_frac + i as f64 * rate_ratio, rate_ratio)
+                                };
+                            }
+                            let mut output_view = &mut output_scratch[..out_n];
+                            stretchers[ch].process(&input_scratch[..in_n], &mut output_view);
+                            for i in 0..out_n { 
+                                data[(out_pos + i) * device_channels + ch] = output_scratch[i] * gain(out_pos + i); 
+                            }
+                        }
+                        let advanced = if rate_ratio == 1.0 { in_n as f64 } else { src_frac + in_n as f64 * rate_ratio };
+                        let consumed = advanced as usize;
+                        src_frac = advanced - consumed as f64;
+                        active_cursor += consumed * channels;
+                        out_pos += out_n;
+                    }
+                    advance(active_cursor);
+                } else {
+                    data.fill(0.0);
+                    if fade_from.is_none() && c.advance_at_end.load(Ordering::Relaxed) {
+                        c.is_playing.store(false, Ordering::Relaxed);
+                        c.track_ended.store(true, Ordering::Relaxed);
+                    }
+                }
+
+                let now = c.cursor.load(Ordering::Relaxed);
+                for stop in [&c.pause_at, &c.stop_at] {
+                    if fade_from.is_none() && now >= stop.load(Ordering::Relaxed) {
+                        c.is_playing.store(false, Ordering::Relaxed);
+                        stop.store(usize::MAX, Ordering::Relaxed);
+                    }
+                }
+            }
+            if fade_from.is_some() {
+                seek_ramp(data, device_channels, seek_fade, false);
+                seek_fade_in = true;
+            } else if seek_fade_in {
+                seek_ramp(data, device_channels, seek_fade, true);
+                seek_fade_in = false;
+            }
+            engine_cursor = Some(c.cursor.load(Ordering::Relaxed));
+
+            effects.process(data, device_channels, device_rate);
+            // tone and drone go to a side buffer so the second output can get its own level of the whole mix
+            aux.clear();
+            aux.resize(data.len(), 0.0);
+            tone.mix(&mut aux, device_channels, device_rate);
+            drone.mix(&mut aux, device_channels, device_rate);
+            {
+                // spread the block's cursor movement over its frames, across the loop wrap if there was one
+                let fch = c.source_channels.load(Ordering::Relaxed).max(1) as f64;
+                let (l0, l1) = (c.loop_start.load(Ordering::Relaxed) as f64 / fch, c.loop_end.load(Ordering::Relaxed) as f64 / fch);
+                let (a, b) = (cursor_before as f64 / fch, c.cursor.load(Ordering::Relaxed) as f64 / fch);
+                let n = (aux.len() / device_channels).max(1) as f64;
+                let span = if b >= a { b - a } else { (l1 - a).max(0.0) + (b - l0).max(0.0) };
+                let ratio = c.source_rate.load(Ordering::Relaxed).max(1) as f64 / device_rate as f64;
+                let expected = (local_speed * nudge) as f64 * ratio * n;
+                // anything much further than the speed allows is a seek, not playback
+                let moving = c.is_playing.load(Ordering::Relaxed) && span > 0.0 && span < expected * 4.0 + 1024.0;
+                // the stretcher holds audio back, so what's heard trails the cursor
+                let lag = if local_mode == PlaybackMode::Stretch {
+                    (stretchers[0].input_latency() + stretchers[0].output_latency()) as f64 * local_speed as f64 * ratio
+                } else { 0.0 };
+                metronome.mix(&mut aux, device_channels, device_rate, |k| {
+                    if !moving { return None; }
+                    let f = a + span * k as f64 / n;
+                    Some(if b < a && f >= l1 { f - l1 + l0 } else { f } - lag)
+                });
+            }
+            if let Some((rx, rate, probe)) = &mut monitor {
+                let step = *rate as f64 / device_rate as f64;
+                // never let more than a couple of callbacks queue up, or the monitor drifts late
+                let keep = (data.len() / device_channels) * 2 + 256;
+                if rx.len() > keep { rx.skip(rx.len() - keep); }
+                let gain = f32::from_bits(c.monitor_gain.load(Ordering::Relaxed));
+                for frame in aux.chunks_exact_mut(device_channels) {
+                    monitor_frac += step;
+                    while monitor_frac >= 1.0 {
+                        if let Some(x) = rx.pop() { monitor_last = [monitor_last[1], x]; }
+                        monitor_frac -= 1.0;
+                    }
+                    let v = (monitor_last[0] + (monitor_last[1] - monitor_last[0]) * monitor_frac as f32) * gain;
+                    for s in frame { *s += v; }
+                }
+                if probe.armed.swap(false, Ordering::Relaxed) { probe_wait = Some(device_rate as usize * PROBE_QUIET_MS / 1000); }
+                // the deck, the generators and the monitored input stay quiet from arming until the
+                // result is in, so only the click comes back
+                if probe_wait.is_some() || probe.sent_us.load(Ordering::Relaxed) != 0 {
+                    data.fill(0.0);
+                    aux.fill(0.0);
+                }
+                if let Some(wait) = probe_wait {
+                    let frames = aux.len() / device_channels;
+                    if wait > frames {
+                        probe_wait = Some(wait - frames);
+                    } else {
+                        for (v, frame) in probe_click(device_rate).zip(aux.chunks_exact_mut(device_channels)) { frame.fill(v); }
+                        probe.sent_us.store(probe.epoch.elapsed().as_micros().max(1) as u64, Ordering::Relaxed);
+                        probe_wait = None;
+                    }
+                }
+            }
+            if let Some(tx) = &mut second_tx {
+                let r = 1.min(device_channels - 1);
+                for (frame, extra) in data.chunks_exact(device_channels).zip(aux.chunks_exact(device_channels)) {
+                    let _ = tx.push((frame[0] + extra[0], frame[r] + extra[r]));
+                }
+            }
+            // compensation follows the main volume, so it goes after the second output's tap
+            let volume = f32::from_bits(c.volume.load(Ordering::Relaxed));
+            loudness_comp.process(data, device_rate, volume);
+            for (s, a) in data.iter_mut().zip(&aux) { *s = *s * volume + a; }
+            let reduction = limiter.process(data, device_channels, device_rate);
+            // negative floats order like their bits too, just the other way round, so max is the deepest
+            c.limiter_reduction.fetch_max(reduction.to_bits(), Ordering::Relaxed);
+            c.levels.process(data, device_channels, device_rate, &mut level_power);
+
+            // only meter what's actually playing, so pauses don't drag the integrated value down
+            if c.is_playing.load(Ordering::Relaxed) {
+                output_meter.process(data);
+                // the gated integration is O(blocks), so publish it a few times per second rather than every callback
+                meter_countdown = meter_countdown.saturating_sub(data.len() / device_channels);
+                if meter_countdown == 0 {
+                    c.lufs_short.store(output_meter.short_term().to_bits(), Ordering::Relaxed);
+                    c.lufs_integrated.store(output_meter.integrated().to_bits(), Ordering::Relaxed);
+                    meter_countdown = device_rate as usize / 4;
+                }
+            }
+
+            if device_channels >= 2 {
+                let (mut lr, mut ll, mut rr) = (0.0f32, 0.0f32, 0.0f32);
+                for frame in data.chunks_exact(device_channels) {
+                    lr += frame[0] * frame[1];
+                    ll += frame[0] * frame[0];
+                    rr += frame[1] * frame[1];
+                    // a full ring just means the UI isn't drawing the scope right now
+                    let _ = scope_tx.push((frame[0], frame[1]));
+                }
+                let block = if ll * rr > 1e-12 { lr / (ll * rr).sqrt() } else { 0.0 };
+                correlation += (block - correlation) * 0.1;
+                c.correlation.store(correlation.to_bits(), Ordering::Relaxed);
+            }
+        }, move |e| {
+            if matches!(e, cpal::StreamError::DeviceNotAvailable) { lost.device_lost.store(true, Ordering::Relaxed); }
+            eprintln!("{}", e);
+        }, None).unwrap();
+
+        stream.play().unwrap();
+        self._stream = Some(stream);
+    }
+
+    // a new output stream starts from scratch, so replay every engine setting into it
+    fn rebuild_output(&mut self) {
+        self._stream = None;
+        let second = self.second_output.as_ref().map(|o| o.0.clone());
+        self.close_second_output();
+        let monitoring = self.monitor.is_some();
+        self.stop_monitor();
+        while self.param_rx.try_recv().is_ok() {}
+
+        self.start_playback();
+        let c = &self.controls;
+        let send = |u: ParamUpdate| { let _ = self.tx.send(u); };
+        send(ParamUpdate::Speed(f32::from_bits(c.speed.load(Ordering::Relaxed))));
+        send(ParamUpdate::Pitch(f32::from_bits(c.pitch.load(Ordering::Relaxed))));
+        send(ParamUpdate::Tuning(self.tuning_cents));
+        send(ParamUpdate::Mode(self.playback_mode));
+        send(ParamUpdate::Channels(self.channel_matrix));
+        let adv = &self.stretch_advanced;
+        send(ParamUpdate::Stretcher { tuning: adv.tuning_for(self.output_channels, self.output_rate), tonality_limit: adv.tonality.0.then_some(adv.tonality.1) });
+        let (t, d) = (&self.tone, &self.drone);
+        send(ParamUpdate::Tone { enabled: t.enabled, freq: t.freq, level: t.level });
+        send(ParamUpdate::Drone { enabled: d.enabled, root: midi_to_freq(d.root), octave: d.octave, fifth: d.fifth, level: d.level });
+        if !self.denoise.profile.is_empty() { send(ParamUpdate::NoiseProfile(Arc::clone(&self.denoise.profile))); }
+        send(ParamUpdate::Denoise { enabled: self.denoise.enabled, strength: self.denoise.strength });
+        let l = &self.leveler;
+        send(ParamUpdate::Leveler { enabled: l.enabled, target_db: l.target_db, attack_ms: l.attack_ms, release_ms: l.release_ms, max_gain_db: l.max_gain_db });
+        let b = &self.band_solo;
+        send(ParamUpdate::BandSolo { enabled: b.enabled, low: b.low, high: b.high });
+        let k = &self.karaoke;
+        send(ParamUpdate::Karaoke { enabled: k.enabled, amount: k.amount, keep_bass: k.keep_bass });
+        send(ParamUpdate::Eq(self.eq));
+        send(ParamUpdate::LoudnessCompensation(self.loudness_comp));
+        send(ParamUpdate::Limiter(self.limiter));
+        self.metronome.sent = None;
+        let mut failed = Vec::new();
+        for (id, _, path, params) in &self.plugin_names {
+            match ClapPlugin::load(path, *id, self.output_rate) {
+                Ok((fx, _)) => {
+                    send(ParamUpdate::InsertEffect(Box::new(fx)));
+                    // the reloaded instance starts from its defaults
+                    for p in params.iter().filter(|p| p.value != p.default) {
+                        send(ParamUpdate::PluginParam { id: *id, param: p.id, value: p.value });
+                    }
+                }
+                Err(_) => failed.push(*id),
+            }
+        }
+        self.plugin_names.retain(|p| !failed.contains(&p.0));
+        self.effect_layout.retain(|(k, _)| !matches!(k, EffectKind::Plugin(id) if failed.contains(id)));
+        let _ = self.tx.send(ParamUpdate::ChainLayout(self.effect_layout.clone()));
+
+        if let Some(name) = second && let Err(e) = self.open_second_output(&name) { eprintln!("second output: {}", e); }
+        if monitoring { self.start_monitor(); }
+    }
+
+    fn set_output_device(&mut self, name: Option<String>) {
+        let _ = std::fs::create_dir_all(config_dir());
+        let _ = std::fs::write(config_dir().join("output_device.txt"), name.as_deref().unwrap_or(""));
+        self.output_device = name;
+        self.rebuild_output();
+    }
+}
+
+impl PlayerApp {
+    fn suggestion_panel(&mut self, ui: &mut egui::Ui, sample_rate: u32, channels: usize) {
+        let suggestions = self.state.lock().unwrap().loop_suggestions.clone();
+        if suggestions.is_empty() { return; }
+        let secs = |s: usize| s as f32 / (sample_rate as f32 * channels as f32).max(1.0);
+        let mmss = |t: f32| format!("{}:{:02}", (t / 60.0) as u32, (t % 60.0) as u32);
+        ui.horizontal_wrapped(|ui| {
+            ui.label("Suggested loops");
+            for sug in &suggestions {
+                let label = format!("{}-{} x{}", mmss(secs(sug.start)), mmss(secs(sug.end)), sug.repeats);
+                if ui.button(label).on_hover_text("Repeated section, click to loop it").clicked() {
+                    self.controls.loop_start.store(sug.start, Ordering::Relaxed);
+                    self.controls.loop_end.store(sug.end, Ordering::Relaxed);
+                    self.seek_history.jump(&self.controls, sug.start);
+                }
+            }
+        });
+    }
+
+    fn tempo_panel(&mut self, ui: &mut egui::Ui) {
+        ui.horizontal(|ui| {
+            ui.label("Tempo");
+            let (mut tempo, detected) = { let s = self.state.lock().unwrap(); (s.tempo, s.detected_tempo) };
+            // tapped along with playback, so take the speed back out to get the file's tempo
+            if ui.button("Tap").on_hover_text("Tap along with the beat to set the tempo").clicked() {
+                let now = std::time::Instant::now();
+                if self.taps.last().is_some_and(|t| now.duration_since(*t).as_secs_f32() > 2.0) { self.taps.clear(); }
+                self.taps.push(now);
+                if self.taps.len() >= 3 {
+                    let secs = self.taps.last().unwrap().duration_since(self.taps[0]).as_secs_f32() / (self.taps.len() - 1) as f32;
+                    let speed = f32::from_bits(self.controls.speed.load(Ordering::Relaxed));
+                    let bpm = ((60.0 / secs / speed) * 10.0).round() / 10.0;
+                    let downbeat = tempo.map_or(self.controls.cursor.load(Ordering::Relaxed), |g| g.downbeat);
+                    tempo = Some(TempoGrid { bpm: bpm.clamp(20.0, 300.0), downbeat });
+                }
+            }
+            match &mut tempo {
+                None => {
+                    if ui.button("Set tempo grid").clicked() {
+                        tempo = Some(TempoGrid { bpm: 120.0, downbeat: 0 });
+                    }
+                }
+                Some(grid) => {
+                    ui.add(egui::DragValue::new(&mut grid.bpm).range(20.0..=300.0).speed(0.1).suffix(" bpm"));
+                    if ui.small_button("x2").on_hover_text("Double time").clicked() { grid.bpm = (grid.bpm * 2.0).min(300.0); }
+                    if ui.small_button("/2").on_hover_text("Half time").clicked() { grid.bpm = (grid.bpm / 2.0).max(20.0); }
+                    if ui.button("Downbeat here").clicked() {
+                        grid.downbeat = self.controls.cursor.load(Ordering::Relaxed);
+                    }
+                    if ui.small_button("x").on_hover_text("Remove tempo grid").clicked() { tempo = None; }
+
+                    ui.separator();
+                    ui.label("Beat jump");
+                    egui::ComboBox::from_id_source("beat_jump").width(40.0).selected_text(self.beat_jump.to_string()).show_ui(ui, |ui| {
+                        for n in [1, 4, 8, 16] { ui.selectable_value(&mut self.beat_jump, n, n.to_string()); }
+                    });
+                }
+            }
+            if let Some(d) = detected && tempo.is_none_or(|t| t.bpm != d.bpm)
+                && ui.button(format!("Detected {:.1}", d.bpm)).on_hover_text("Go back to the detected tempo and downbeat").clicked() {
+                tempo = Some(d);
+            }
+            let has_grid = tempo.is_some();
+            self.state.lock().unwrap().tempo = tempo;
+            if has_grid {
+                if ui.button(", Back").clicked() { self.jump_beats(-(self.beat_jump as i32)); }
+                if ui.button(". Fwd").clicked() { self.jump_beats(self.beat_jump as i32); }
+            }
+        });
+        ui.horizontal(|ui| {
+            ui.spacing_mut().slider_width = 100.0;
+            let has_grid = self.state.lock().unwrap().tempo.is_some();
+            // through the action, so macros record it and it can have a key
+            let mut enabled = self.metronome.enabled;
+            if ui.add_enabled(has_grid, egui::Checkbox::new(&mut enabled, "Metronome"))
+                .on_hover_text("Clicks on the beat grid along with the music (T)")
+                .on_disabled_hover_text("Needs a tempo grid")
+                .changed() {
+                self.perform(&ui.ctx().clone(), Action::ToggleMetronome);
+            }
+            let m = &mut self.metronome;
+            ui.add(egui::Slider::new(&mut m.level, 0.0..=1.0).text("level"));
+            ui.checkbox(&mut m.accent, "Accent beat one");
+        });
+    }
+
+    // peak (with a hold line) and rms bars for the output, on the fader's dB scale; the clip
+    // light stays on until clicked
+    fn level_meters(&mut self, ui: &mut egui::Ui, width: f32) {
+        let now = std::time::Instant::now();
+        let to_x = |rect: egui::Rect, db: f32| rect.left() + ((db - VOLUME_FLOOR_DB) / (6.0 - VOLUME_FLOOR_DB)).clamp(0.0, 1.0) * rect.width();
+        ui.horizontal(|ui| {
+            ui.vertical(|ui| {
+                ui.spacing_mut().item_spacing.y = 2.0;
+                for ch in 0..2 {
+                    let (peak, rms) = self.controls.levels.take(ch);
+                    let shown = &mut self.meter_peaks[ch];
+                    // fall back at about 20 dB a second
+                    *shown = peak.max(*shown * 0.92);
+                    let hold = &mut self.meter_hold[ch];
+                    if peak >= hold.0 || now.duration_since(hold.1).as_secs_f32() > 1.5 { *hold = (peak, now); }
+                    if peak >= 1.0 { self.clipped = true; }
+
+                    let (rect, _) = ui.allocate_exact_size(egui::vec2(width - 40.0, 6.0), egui::Sense::hover());
+                    let painter = ui.painter_at(rect);
+                    painter.rect_filled(rect, 1.0, egui::Color32::from_rgb(10, 10, 10));
+                    let bar = |db: f32| egui::Rect::from_x_y_ranges(rect.left()..=to_x(rect, db), rect.y_range());
+                    painter.rect_filled(bar(gain_to_db(*shown)), 0.0, egui::Color32::from_rgb(0, 90, 50));
+                    painter.rect_filled(bar(gain_to_db(rms)), 0.0, egui::Color32::from_rgb(0, 180, 100));
+                    let x = to_x(rect, gain_to_db(hold.0));
+                    let color = if hold.0 >= 1.0 { egui::Color32::RED } else { egui::Color32::from_gray(200) };
+                    painter.line_segment([egui::pos2(x, rect.top()), egui::pos2(x, rect.bottom())], (1.5, color));
+                    let unity = to_x(rect, 0.0);
+                    painter.line_segment([egui::pos2(unity, rect.top()), egui::pos2(unity, rect.bottom())], (1.0, egui::Color32::from_gray(90)));
+                }
+            });
+            let clip = egui::RichText::new("CLIP").small().color(if self.clipped { egui::Color32::RED } else { egui::Color32::from_gray(60) });
+            if ui.add(egui::Label::new(clip).sense(egui::Sense::click())).on_hover_text("The output went over 0 dBFS; click to reset").clicked() {
+                self.clipped = false;
+            }
+        });
+    }
+
+    fn meter_panel(&mut self, ui: &mut egui::Ui) {
+        // drain the ring every frame, even when collapsed, so it never fills up with stale audio
+        if let Some(rx) = &mut self.scope_rx {
+            self.scope_points.extend(rx.pop_iter());
+            let excess = self.scope_points.len().saturating_sub(1024);
+            self.scope_points.drain(..excess);
+        }
+
+        egui::CollapsingHeader::new("Phase / correlation").show(ui, |ui| {
+            ui.horizontal(|ui| {
+                let size = 120.0;
+                let (rect, _) = ui.allocate_exact_size(egui::vec2(size, size), egui::Sense::hover());
+                let painter = ui.painter_at(rect);
+                painter.rect_filled(rect, 2.0, egui::Color32::from_rgb(10, 10, 10));
+                let c = rect.center();
+                let r = size * 0.5;
+                painter.line_segment([egui::pos2(c.x, rect.top()), egui::pos2(c.x, rect.bottom())], (1.0, egui::Color32::from_gray(40)));
+                painter.line_segment([egui::pos2(rect.left(), c.y), egui::pos2(rect.right(), c.y)], (1.0, egui::Color32::from_gray(40)));
+                // mid on the vertical axis, side on the horizontal one: mono is a vertical line
+                for &(l, rt) in &self.scope_points {
+                    let x = (rt - l) * std::f32::consts::FRAC_1_SQRT_2;
+                    let y = (l + rt) * std::f32::consts::FRAC_1_SQRT_2;
+                    let p = egui::pos2(c.x + x.clamp(-1.0, 1.0) * r, c.y - y.clamp(-1.0, 1.0) * r);
+                    painter.circle_filled(p, 0.8, egui::Color32::from_rgb(0, 180, 100));
+                }
+
+                ui.vertical(|ui| {
+                    let corr = f32::from_bits(self.controls.correlation.load(Ordering::Relaxed));
+                    ui.label(format!("Correlation {:+.2}", corr));
+                    let (bar, _) = ui.allocate_exact_size(egui::vec2(160.0, 14.0), egui::Sense::hover());
+                    ui.painter().rect_filled(bar, 2.0, egui::Color32::from_rgb(10, 10, 10));
+                    let mid = bar.center().x;
+                    let x = mid + corr.clamp(-1.0, 1.0) * bar.width() * 0.5;
+                    let color = if corr < 0.0 { egui::Color32::RED } else { egui::Color32::from_rgb(0, 180, 100) };
+                    ui.painter().rect_filled(egui::Rect::from_x_y_ranges(mid.min(x)..=mid.max(x), bar.y_range()), 0.0, color);
+                    if corr < -0.2 { ui.colored_label(egui::Color32::RED, "Out of phase: mono will cancel"); }
+                });
+            });
+        });
+    }
+
+    fn stem_panel(&mut self, ui: &mut egui::Ui) {
+        let (status, stem_names, has_file) = {
+            let s = self.state.lock().unwrap();
+            (s.stem_status.clone(), s.stems.iter().map(|st| st.0.clone()).collect::<Vec<_>>(), s.total_samples > 0)
+        };
+        ui.horizontal(|ui| {
+            ui.label("Stems");
+            if stem_names.is_empty() {
+                egui::ComboBox::from_id_source("stem_backend").width(130.0)
+                    .selected_text(self.stem_backend.label())
+                    .show_ui(ui, |ui| {
+                        for b in [StemBackend::Demucs, StemBackend::Spleeter, StemBackend::Hpss] {
+                            ui.selectable_value(&mut self.stem_backend, b, b.label());
+                        }
+                    });
+                let busy = status.starts_with("Separating");
+                if ui.add_enabled(has_file && !busy, egui::Button::new("Separate")).clicked() {
+                    self.start_stem_separation();
+                }
+            } else {
+                if ui.checkbox(&mut self.use_stems, "Mixer").changed() {
+                    self.update_stem_mix();
+                }
+                if ui.small_button("x").on_hover_text("Discard stems").clicked() {
+                    {
+                        let mut s = self.state.lock().unwrap();
+                        s.stems.clear();
+                        s.stem_status.clear();
+                    }
+                    self.update_stem_mix();
+                    return;
+                }
+            }
+            ui.label(status);
+        });
+
+        if stem_names.is_empty() || !self.use_stems { return; }
+        // first frame after the stems arrived
+        if self.stem_channels.len() != stem_names.len() { self.update_stem_mix(); }
+        let mut changed = false;
+        for (name, ch) in stem_names.iter().zip(self.stem_channels.iter_mut()) {
+            ui.horizontal(|ui| {
+                ui.spacing_mut().slider_width = 150.0;
+                ui.add_sized([60.0, 18.0], egui::Label::new(name.as_str()));
+                changed |= ui.add(egui::Slider::new(&mut ch.volume, 0.0..=2.0)).changed();
+                changed |= ui.toggle_value(&mut ch.mute, "M").changed();
+                changed |= ui.toggle_value(&mut ch.solo, "S").changed();
+            });
+        }
+        if changed { self.update_stem_mix(); }
+    }
+
+    fn denoise_panel(&mut self, ui: &mut egui::Ui) {
+        ui.horizontal(|ui| {
+            ui.spacing_mut().slider_width = 100.0;
+            let mut changed = ui.add_enabled(self.denoise.has_profile, egui::Checkbox::new(&mut self.denoise.enabled, "Noise reduction")).changed();
+            changed |= ui.add(egui::Slider::new(&mut self.denoise.strength, 0.5..=4.0).text("strength")).changed();
+            if changed {
+                let _ = self.tx.send(ParamUpdate::Denoise { enabled: self.denoise.enabled, strength: self.denoise.strength });
+            }
+
+            let l_start = self.controls.loop_start.load(Ordering::Relaxed);
+            let l_end = self.controls.loop_end.load(Ordering::Relaxed);
+            let (total, channels) = {
+                let s = self.state.lock().unwrap();
+                (s.total_samples, s.channels)
+            };
+            let has_region = l_end > l_start && (l_start > 0 || l_end < total);
+            if ui.add_enabled(has_region, egui::Button::new("Learn noise from loop")).on_hover_text("Select a noise-only stretch (e.g. tape hiss before the music) as the loop first").clicked() {
+                let pcm = Arc::clone(&*self.controls.pcm_data.lock().unwrap());
+                let profile = noise_profile(&pcm, channels, l_start, l_end);
+                self.denoise.has_profile = profile.iter().any(|&p| p > 0.0);
+                // the denoiser lets go of the old profile when the new one arrives; keep it until then
+                // so the audio thread never holds the last reference
+                let old = std::mem::replace(&mut self.denoise.profile, Arc::new(profile));
+                self.controls.retired.lock().unwrap().push((std::time::Instant::now(), Box::new(old)));
+                let _ = self.tx.send(ParamUpdate::NoiseProfile(Arc::clone(&self.denoise.profile)));
+            }
+        });
+    }
+
+    fn macro_panel(&mut self, ui: &mut egui::Ui, ctx: &egui::Context) {
+        egui::CollapsingHeader::new("Macros").show(ui, |ui| {
+            let mut run = None;
+            let mut remove = None;
+            for (i, m) in self.macros.list.iter().enumerate() {
+                ui.horizontal(|ui| {
+                    if ui.button(&m.name).clicked() { run = Some(i); }
+                    if let Some(k) = m.key { ui.label(k.name()); }
+                    let steps: Vec<String> = m.actions.iter().map(|a| a.name()).collect();
+                    ui.weak(steps.join(", "));
+                    if ui.small_button("x").clicked() { remove = Some(i); }
+                });
+            }
+            if let Some(i) = run { self.run_macro(ctx, i); }
+            if let Some(i) = remove {
+                self.macros.list.remove(i);
+                self.save_macros();
+            }
+            ui.horizontal(|ui| {
+                let m = &mut self.macros;
+                ui.add(egui::TextEdit::singleline(&mut m.new_name).hint_text("macro name").desired_width(120.0));
+                egui::ComboBox::from_id_source("macro_key").width(60.0).selected_text(m.new_key.map_or("no key", |k| k.name())).show_ui(ui, |ui| {
+                    ui.selectable_value(&mut m.new_key, None, "no key");
+                    for k in [egui::Key::F1, egui::Key::F2, egui::Key::F3, egui::Key::F4, egui::Key::F5, egui::Key::F6,
+                              egui::Key::F7, egui::Key::F8, egui::Key::F9, egui::Key::F10, egui::Key::F11, egui::Key::F12] {
+                        ui.selectable_value(&mut m.new_key, Some(k), k.name());
+                    }
+                });
+                if m.recording.is_none() {
+                    if ui.button("Record").on_hover_text("Then use the keys and controls as usual; each action is added in order").clicked() {
+                        m.recording = Some(Vec::new());
+                    }
+                } else {
+                    let steps = m.recording.as_ref().map_or(0, |r| r.len());
+                    if ui.add_enabled(steps > 0 && !m.new_name.trim().is_empty(), egui::Button::new("Save")).clicked() {
+                        let actions = m.recording.take().unwrap_or_default();
+                        // a key belongs to one macro at a time
+                        if m.new_key.is_some() { for other in &mut m.list { if other.key == m.new_key { other.key = None; } } }
+                        m.list.push(Macro { name: m.new_name.trim().to_string(), key: m.new_key.take(), actions });
+                        m.new_name.clear();
+                        self.save_macros();
+                    } else if ui.button("Cancel").clicked() {
+                        m.recording = None;
+                    } else {
+                        ui.colored_label(egui::Color32::RED, format!("recording, {steps} steps"));
+                    }
+                }
+            });
+        });
+    }
+
+    fn edit_panel(&mut self, ui: &mut egui::Ui) {
+        egui::CollapsingHeader::new("Edit").show(ui, |ui| {
+            ui.label("Applies to the loop region. The file on disk isn't changed.");
+            ui.horizontal(|ui| {
+                if ui.button("Delete").clicked() { self.edit_region(RegionEdit::Delete); }
+                if ui.button("Silence").clicked() { self.edit_region(RegionEdit::Silence); }
+                if ui.button("Fade in").clicked() { self.edit_region(RegionEdit::FadeIn); }
+                if ui.button("Fade out").clicked() { self.edit_region(RegionEdit::FadeOut); }
+                ui.separator();
+                let label = format!("Undo ({})", self.edit_undo.len());
+                if ui.add_enabled(!self.edit_undo.is_empty(), egui::Button::new(label)).on_hover_text("Ctrl+Z").clicked() { self.undo_edit(); }
+            });
+            self.export_row(ui);
+        });
+    }
+
+    // renders the loop region offline at the current speed, pitch and volume
+    fn export_loop(&mut self) {
+        if self.export.is_some() { return; }
+        let (path, rate, channels) = { let s = self.state.lock().unwrap(); (PathBuf::from(&s.file_path), s.sample_rate, s.channels.max(1)) };
+        let c = &self.controls;
+        let pcm = Arc::clone(&*c.pcm_data.lock().unwrap());
+        if pcm.is_empty() {
+            self.export_status = "Export needs the file in memory (long files are streamed)".into();
+            return;
+        }
+        let start = c.loop_start.load(Ordering::Relaxed) / channels * channels;
+        let end = (c.loop_end.load(Ordering::Relaxed) / channels * channels).min(pcm.len());
+        if end <= start { return; }
+        let speed = f32::from_bits(c.speed.load(Ordering::Relaxed));
+        let semitones = self.heard_shift();
+        let volume = f32::from_bits(c.volume.load(Ordering::Relaxed));
+        let stem = path.file_stem().map(|s| s.to_string_lossy().into_owned()).unwrap_or_else(|| "loop".into());
+        let Some(out) = FileDialog::new().add_filter("WAV", &["wav"]).set_file_name(format!("{stem} ({speed:.2}x {semitones:+.0}st).wav")).save_file() else { return };
+
+        let envelope = Arc::clone(&*c.volume_envelope.lock().unwrap());
+        let progress = Arc::new(AtomicU32::new(0f32.to_bits()));
+        let (tx, rx) = unbounded();
+        let job_progress = progress.clone();
+        thread::spawn(move || {
+            let region: Vec<f32> = (start..end).map(|i| pcm[i] * envelope.gain_at(i) * volume).collect();
+            let rendered = render_stretched(&region, channels, rate, speed, semitones, &|p| job_progress.store(p.to_bits(), Ordering::Relaxed));
+            let _ = tx.send(write_wav(&out, &rendered, rate, channels).map(|_| out).map_err(|e| e.to_string()));
+        });
+        self.export = Some(ExportJob { progress, done: rx });
+        self.export_status.clear();
+    }
+
+    // the loop's melody as a .mid: the pitch curve cut into notes, timed from the bar the loop starts in
+    // (or from the loop start at 120 bpm without a tempo) and optionally snapped to a grid
+    fn export_melody(&mut self) {
+        let (path, rate, channels, tempo, made) = {
+            let s = self.state.lock().unwrap();
+            (PathBuf::from(&s.file_path), s.sample_rate, s.channels.max(1), s.tempo, s.waveform_gen)
+        };
+        let c = &self.controls;
+        let pcm = Arc::clone(&*c.pcm_data.lock().unwrap());
+        if pcm.is_empty() {
+            self.export_status = "Export needs the file in memory (long files are streamed)".into();
+            return;
+        }
+        let (start, end) = (c.loop_start.load(Ordering::Relaxed), c.loop_end.load(Ordering::Relaxed));
+        if end.saturating_sub(start) as f32 / (rate as f32 * channels as f32) > PITCH_TRACK_MAX_SECS {
+            self.export_status = format!("Loop at most {:.0} s to export its melody", PITCH_TRACK_MAX_SECS);
+            return;
+        }
+        let shift = self.heard_shift();
+        let stem = path.file_stem().map(|s| s.to_string_lossy().into_owned()).unwrap_or_else(|| "melody".into());
+        let Some(out) = FileDialog::new().add_filter("MIDI", &["mid"]).set_file_name(format!("{stem} melody.mid")).save_file() else { return };
+
+        let curve = self.pitch_curve.as_ref().filter(|(key, _)| *key == (made, start, end)).map(|(_, points)| points.clone());
+        let bpm = tempo.map_or(120.0, |t| t.bpm);
+        let beat = 60.0 / bpm as f64 * rate as f64 * channels as f64;
+        // time zero on the downbeat of the bar holding the loop start, so the grid lines up with the music
+        let origin = tempo.map_or(start as f64, |t| {
+            let bar = beat * 4.0;
+            t.downbeat as f64 + ((start as f64 - t.downbeat as f64) / bar).floor() * bar
+        });
+        let grid = self.midi_quantize;
+        const PPQ: u16 = 480;
+        let progress = Arc::new(AtomicU32::new(0f32.to_bits()));
+        let (tx, rx) = unbounded();
+        let job_progress = progress.clone();
+        thread::spawn(move || {
+            let curve = curve.unwrap_or_else(|| track_pitch(&pcm, channels, rate, start, end));
+            job_progress.store(0.8f32.to_bits(), Ordering::Relaxed);
+            let hop = (rate as usize / 100) * channels;
+            let notes = curve_to_notes(&curve, shift, hop, hop * 3, rate as usize / 16 * channels);
+            let to_tick = |i: usize| ((i as f64 - origin) / beat * PPQ as f64).max(0.0);
+            let snap = |t: f64| if grid > 0 { let g = PPQ as f64 / grid as f64; (t / g).round() * g } else { t.round() };
+            let mut ticks: Vec<(u32, u32, u8)> = Vec::new();
+            for (a, b, note) in notes {
+                let (a, b) = (snap(to_tick(a)) as u32, snap(to_tick(b)) as u32);
+                let b = b.max(a + (PPQ as u32).checked_div(grid).unwrap_or(1));
+                // quantizing can run a note into the next one; the earlier one gives way
+                if let Some(prev) = ticks.last_mut() && prev.1 > a { prev.1 = a; }
+                ticks.push((a, b, note));
+            }
+            ticks.retain(|n| n.1 > n.0);
+            let result = if ticks.is_empty() { Err("no clear melody in the loop".to_string()) }
+                else { write_midi(&out, &ticks, bpm, PPQ).map(|_| out).map_err(|e| e.to_string()) };
+            let _ = tx.send(result);
+        });
+        self.export = Some(ExportJob { progress, done: rx });
+        self.export_status.clear();
+    }
+
+    fn export_row(&mut self, ui: &mut egui::Ui) {
+        ui.horizontal(|ui| {
+            if let Some(job) = &self.export {
+                ui.add(egui::ProgressBar::new(f32::from_bits(job.progress.load(Ordering::Relaxed))).desired_width(200.0).show_percentage());
+                ui.ctx().request_repaint_after(std::time::Duration::from_millis(100));
+                if let Ok(result) = job.done.try_recv() {
+                    self.export_status = match result {
+                        Ok(path) => format!("Exported {}", path.display()),
+                        Err(e) => format!("Export failed: {e}"),
+                    };
+                    self.export = None;
+                }
+            } else {
+                if ui.button("Export selection...").on_hover_text("Write the loop region as a WAV at the current speed, pitch and volume").clicked() {
+                    self.export_loop();
+                }
+                if ui.button("Melody to MIDI...").on_hover_text("Track the pitch of a single line through the loop and write its notes as a .mid, for notation software").clicked() {
+                    self.export_melody();
+                }
+                let grid_name = |g: u32| match g { 0 => "unquantized".to_string(), g => format!("1/{}", g * 4) };
+                egui::ComboBox::from_id_source("midi_quantize").width(90.0).selected_text(grid_name(self.midi_quantize)).show_ui(ui, |ui| {
+                    for g in [0, 1, 2, 4, 8] { ui.selectable_value(&mut self.midi_quantize, g, grid_name(g)); }
+                }).response.on_hover_text("Snap the MIDI notes to the beat grid");
+            }
+            ui.label(&self.export_status);
+        });
+    }
+
+    fn monitor_panel(&mut self, ui: &mut egui::Ui) {
+        ui.horizontal(|ui| {
+            let mut on = self.monitor.is_some();
+            if ui.checkbox(&mut on, "Input monitoring").on_hover_text("Plays the mic or instrument input along with the track").changed() {
+                if on { self.start_monitor(); } else { self.stop_monitor(); }
+            }
+            let mut gain = f32::from_bits(self.controls.monitor_gain.load(Ordering::Relaxed));
+            if ui.add(egui::Slider::new(&mut gain, 0.0..=4.0).text("gain")).changed() {
+                self.controls.monitor_gain.store(gain.to_bits(), Ordering::Relaxed);
+            }
+            let Some(m) = &self.monitor else {
+                ui.label(&self.monitor_status);
+                return;
+            };
+            let p = &m.probe;
+            let sent = p.sent_us.load(Ordering::Relaxed);
+            if sent != 0 && p.epoch.elapsed().as_micros() as u64 > sent + PROBE_LISTEN_MS * 1000 {
+                p.listening.store(false, Ordering::Relaxed);
+                match p.find_click(sent) {
+                    Some(us) => {
+                        p.result_us.store(us, Ordering::Relaxed);
+                        self.take_latency_ms = us as f32 / 1000.0;
+                        self.take_latency_measured = true;
+                    }
+                    None => self.monitor_status = "No click heard".into(),
+                }
+                p.sent_us.store(0, Ordering::Relaxed);
+            }
+            let busy = sent != 0 || p.listening.load(Ordering::Relaxed);
+            if ui.add_enabled(!busy, egui::Button::new("Measure latency"))
+                .on_hover_text("Mutes the track, plays a click and listens for it; the input has to hear the output (mic near the speakers or a loopback cable)").clicked() {
+                p.arm();
+                self.monitor_status.clear();
+            }
+            let result = p.result_us.load(Ordering::Relaxed);
+            if result != 0 {
+                ui.label(format!("round trip {:.1} ms", result as f32 / 1000.0));
+            } else if busy {
+                ui.label("listening...");
+            } else {
+                ui.label(&self.monitor_status);
+            }
+        });
+    }
+
+    fn sing_along_panel(&mut self, ui: &mut egui::Ui) {
+        egui::CollapsingHeader::new("Sing-along pitch").show(ui, |ui| {
+            ui.horizontal(|ui| {
+                if self.sing_along.is_some() {
+                    if ui.button("Stop mic").clicked() { self.sing_along = None; }
+                } else if ui.button("Start mic").clicked() {
+                    self.start_sing_along();
+                }
+                ui.label(&self.sing_along_status);
+                if let Some(&(Some(mic), track)) = self.sing_along.as_ref().and_then(|sa| sa.trace.back()) {
+                    let nearest = mic.round() as i32;
+                    ui.label(format!("You: {} {:+.0}c", note_name(nearest), (mic - nearest as f32) * 100.0));
+                    if let Some(t) = track { ui.label(format!("  vs track {:+.0}c", (mic - t) * 100.0)); }
+                }
+            });
+            let Some(sa) = &self.sing_along else { return };
+
+            let (rect, _) = ui.allocate_exact_size(egui::vec2(ui.available_width(), 140.0), egui::Sense::hover());
+            let painter = ui.painter_at(rect);
+            painter.rect_filled(rect, 2.0, egui::Color32::from_rgb(10, 10, 10));
+            // a two-octave window centred on what was sung/played recently
+            let recent: Vec<f32> = sa.trace.iter().flat_map(|&(m, t)| [m, t]).flatten().collect();
+            let centre = if recent.is_empty() { 60.0 } else { (recent.iter().sum::<f32>() / recent.len() as f32).round() };
+            let (lo, hi) = (centre - 12.0, centre + 12.0);
+            let to_y = |n: f32| rect.bottom() - (n - lo) / (hi - lo) * rect.height();
+            for n in lo as i32..=hi as i32 {
+                let y = to_y(n as f32);
+                let color = if n.rem_euclid(12) == 0 { egui::Color32::from_gray(70) } else { egui::Color32::from_gray(30) };
+                painter.line_segment([egui::pos2(rect.left(), y), egui::pos2(rect.right(), y)], (1.0, color));
+                if n.rem_euclid(12) == 0 { painter.text(egui::pos2(rect.left() + 2.0, y), egui::Align2::LEFT_BOTTOM, note_name(n), egui::FontId::monospace(10.0), egui::Color32::GRAY); }
+            }
+            let dx = rect.width() / 240.0;
+            let x0 = rect.right() - sa.trace.len() as f32 * dx;
+            for (lane, color) in [(1, egui::Color32::from_rgb(0, 180, 100)), (0, egui::Color32::from_rgb(255, 140, 0))] {
+                let mut run: Vec<egui::Pos2> = Vec::new();
+                for (i, &(mic, track)) in sa.trace.iter().enumerate() {
+                    let note = if lane == 0 { mic } else { track };
+                    match note.filter(|n| (lo..=hi).contains(n)) {
+                        Some(n) => run.push(egui::pos2(x0 + i as f32 * dx, to_y(n))),
+                        None => { if run.len() > 1 { painter.add(egui::Shape::line(std::mem::take(&mut run), (2.0, color))); } run.clear(); }
+                    }
+                }
+                if run.len() > 1 { painter.add(egui::Shape::line(run, (2.0, color))); }
+            }
+            ui.horizontal(|ui| {
+                ui.colored_label(egui::Color32::from_rgb(255, 140, 0), "you");
+                ui.colored_label(egui::Color32::from_rgb(0, 180, 100), "track");
+            });
+        });
+    }
+
+    fn effects_panel(&mut self, ui: &mut egui::Ui) {
+        let mut changed = false;
+        egui::CollapsingHeader::new("Effects chain").show(ui, |ui| {
+            ui.label("Processed top to bottom after time-stretching.");
+            let n = self.effect_layout.len();
+            let mut swap = None;
+            let mut remove = None;
+            for (i, (kind, bypassed)) in self.effect_layout.iter_mut().enumerate() {
+                let label = match kind {
+                    EffectKind::Plugin(id) => self.plugin_names.iter().find(|p| p.0 == *id).map_or("Plugin", |p| p.1.as_str()),
+                    _ => kind.label(),
+                };
+                ui.horizontal(|ui| {
+                    ui.label(format!("{}. {}", i + 1, label));
+                    changed |= ui.checkbox(bypassed, "bypass").changed();
+                    if ui.add_enabled(i > 0, egui::Button::new("^").small()).clicked() { swap = Some(i - 1); }
+                    if ui.add_enabled(i + 1 < n, egui::Button::new("v").small()).clicked() { swap = Some(i); }
+                    if matches!(kind, EffectKind::Plugin(_)) && ui.small_button("x").on_hover_text("Remove plugin").clicked() { remove = Some(i); }
+                });
+                if let EffectKind::Plugin(id) = *kind
+                    && let Some((_, _, _, params)) = self.plugin_names.iter_mut().find(|p| p.0 == id)
+                    && !params.is_empty()
+                {
+                    egui::CollapsingHeader::new("Parameters").id_source(("plugin params", id)).show(ui, |ui| {
+                        for p in params.iter_mut() {
+                            let slider = egui::Slider::new(&mut p.value, p.min..=p.max).text(&p.name);
+                            let slider = if p.stepped { slider.step_by(1.0) } else { slider };
+                            if ui.add(slider).changed() {
+                                let _ = self.tx.send(ParamUpdate::PluginParam { id, param: p.id, value: p.value });
+                            }
+                        }
+                    });
+                }
+            }
+            if let Some(i) = swap {
+                self.effect_layout.swap(i, i + 1);
+                changed = true;
+            }
+            if let Some(i) = remove {
+                let (kind, _) = self.effect_layout.remove(i);
+                if let EffectKind::Plugin(id) = kind { self.plugin_names.retain(|p| p.0 != id); }
+                let _ = self.tx.send(ParamUpdate::RemoveEffect(kind));
+            }
+            ui.horizontal(|ui| {
+                if ui.button("Add CLAP plugin...").on_hover_text("Runs the first audio effect in the file; its parameters show under it here (no plugin editor)").clicked() {
+                    self.add_plugin();
+                    changed = true;
+                }
+                ui.label(&self.plugin_status);
+            });
+        });
+        if changed { let _ = self.tx.send(ParamUpdate::ChainLayout(self.effect_layout.clone())); }
+    }
+
+    fn leveler_panel(&mut self, ui: &mut egui::Ui) {
+        ui.horizontal(|ui| {
+            let l = &mut self.leveler;
+            let mut changed = ui.checkbox(&mut l.enabled, "Leveler (AGC)").on_hover_text("Evens out quiet and loud passages within the file").changed();
+            ui.label("target");
+            changed |= ui.add(egui::DragValue::new(&mut l.target_db).range(-30.0..=-6.0).speed(0.2).suffix(" dB")).changed();
+            ui.label("attack");
+            changed |= ui.add(egui::DragValue::new(&mut l.attack_ms).range(10.0..=2000.0).speed(5.0).suffix(" ms")).changed();
+            ui.label("release");
+            changed |= ui.add(egui::DragValue::new(&mut l.release_ms).range(100.0..=10000.0).speed(20.0).suffix(" ms")).changed();
+            ui.label("max boost");
+            changed |= ui.add(egui::DragValue::new(&mut l.max_gain_db).range(0.0..=30.0).speed(0.2).suffix(" dB")).changed();
+            if changed {
+                let _ = self.tx.send(ParamUpdate::Leveler { enabled: l.enabled, target_db: l.target_db, attack_ms: l.attack_ms, release_ms: l.release_ms, max_gain_db: l.max_gain_db });
+            }
+        });
+    }
+
+    fn band_solo_panel(&mut self, ui: &mut egui::Ui) {
+        const BANDS: [(&str, f32, f32); 4] = [("Bass", 40.0, 250.0), ("Vocal", 300.0, 3400.0), ("Guitar/keys", 250.0, 5000.0), ("Hi-hat", 6000.0, 16000.0)];
+        ui.horizontal(|ui| {
+            ui.spacing_mut().slider_width = 100.0;
+            let b = &mut self.band_solo;
+            let mut changed = ui.checkbox(&mut b.enabled, "Band solo").on_hover_text("Hear only the frequencies between the two edges").changed();
+            changed |= ui.add(egui::Slider::new(&mut b.low, 20.0..=20000.0).logarithmic(true).integer().suffix(" Hz")).changed();
+            ui.label("to");
+            changed |= ui.add(egui::Slider::new(&mut b.high, 20.0..=20000.0).logarithmic(true).integer().suffix(" Hz")).changed();
+            for (name, low, high) in BANDS {
+                if ui.small_button(name).clicked() {
+                    (b.low, b.high, b.enabled) = (low, high, true);
+                    changed = true;
+                }
+            }
+            if changed {
+                if b.low > b.high { std::mem::swap(&mut b.low, &mut b.high); }
+                let _ = self.tx.send(ParamUpdate::BandSolo { enabled: b.enabled, low: b.low, high: b.high });
+            }
+        });
+    }
+
+    fn eq_panel(&mut self, ui: &mut egui::Ui) {
+        let before = self.eq;
+        let e = &mut self.eq;
+        let cut = |v: f64, off: f32| if v as f32 == off { "off".to_string() } else { format!("{:.0} Hz", v) };
+        ui.horizontal(|ui| {
+            ui.checkbox(&mut e.enabled, "EQ").on_hover_text("Carve out the bass or the cymbals to hear one instrument better");
+            if ui.small_button("Flat").clicked() { *e = EqSettings { enabled: e.enabled, ..EqSettings::FLAT }; }
+            ui.label("Low cut");
+            ui.add(egui::DragValue::new(&mut e.low_cut).range(EQ_CUT_OFF.0..=2000.0).speed(2.0).custom_formatter(|v, _| cut(v, EQ_CUT_OFF.0)));
+            ui.label("High cut");
+            ui.add(egui::DragValue::new(&mut e.high_cut).range(1000.0..=EQ_CUT_OFF.1).speed(20.0).custom_formatter(|v, _| cut(v, EQ_CUT_OFF.1)));
+        });
+        ui.horizontal(|ui| {
+            ui.spacing_mut().slider_width = 80.0;
+            ui.label("Low");
+            ui.add(egui::Slider::new(&mut e.low_db, -24.0..=12.0).suffix(" dB"));
+            ui.label("Mid");
+            ui.add(egui::Slider::new(&mut e.mid_db, -24.0..=12.0).suffix(" dB"));
+            ui.add(egui::DragValue::new(&mut e.mid_hz).range(200.0..=5000.0).speed(5.0).suffix(" Hz"));
+            ui.label("High");
+            ui.add(egui::Slider::new(&mut e.high_db, -24.0..=12.0).suffix(" dB"));
+        });
+        if self.eq != before { let _ = self.tx.send(ParamUpdate::Eq(self.eq)); }
+    }
+
+    fn karaoke_panel(&mut self, ui: &mut egui::Ui) {
+        ui.horizontal(|ui| {
+            ui.spacing_mut().slider_width = 100.0;
+            let k = &mut self.karaoke;
+            let mut changed = ui.checkbox(&mut k.enabled, "Remove vocals").on_hover_text("Cancels what's in the centre of the mix (L−R), usually the lead vocal").changed();
+            changed |= ui.add(egui::Slider::new(&mut k.amount, 0.0..=1.0).custom_formatter(|v, _| format!("{:.0}%", v * 100.0))).changed();
+            changed |= ui.checkbox(&mut k.keep_bass, "Keep bass").on_hover_text("Leave the centre below 150 Hz alone, where bass and kick usually are").changed();
+            if changed { let _ = self.tx.send(ParamUpdate::Karaoke { enabled: k.enabled, amount: k.amount, keep_bass: k.keep_bass }); }
+        });
+    }
+
+    // the engine clicks on the grid in file frames, so it hears again whenever the settings or the grid change
+    fn poll_metronome(&mut self) {
+        let (tempo, rate, channels) = { let s = self.state.lock().unwrap(); (s.tempo, s.sample_rate, s.channels.max(1)) };
+        let m = &mut self.metronome;
+        let now = (m.enabled, m.level, m.accent, tempo.map_or(0.0, |t| t.bpm), tempo.map_or(0, |t| t.downbeat));
+        if m.sent == Some(now) { return; }
+        m.sent = Some(now);
+        let (beat, downbeat) = tempo.map_or((0.0, 0.0), |t| (t.beat_samples(rate, 1), (t.downbeat / channels) as f64));
+        let _ = self.tx.send(ParamUpdate::Metronome { enabled: m.enabled, level: m.level, accent: m.accent, beat, downbeat });
+    }
+
+    fn tone_panel(&mut self, ui: &mut egui::Ui) {
+        ui.horizontal(|ui| {
+            ui.spacing_mut().slider_width = 100.0;
+            let t = &mut self.tone;
+            let mut changed = ui.checkbox(&mut t.enabled, "Reference tone").changed();
+            let before = t.note;
+            egui::ComboBox::from_id_source("tone_note").width(60.0).selected_text(note_name(t.note)).show_ui(ui, |ui| {
+                for n in 36..=96 { ui.selectable_value(&mut t.note, n, note_name(n)); }
+            });
+            if t.note != before {
+                t.freq = midi_to_freq(t.note);
+                changed = true;
+            }
+            changed |= ui.add(egui::DragValue::new(&mut t.freq).range(20.0..=4000.0).speed(0.1).suffix(" Hz")).changed();
+            changed |= ui.add(egui::Slider::new(&mut t.level, 0.0..=1.0).text("level")).changed();
+            if changed {
+                let _ = self.tx.send(ParamUpdate::Tone { enabled: t.enabled, freq: t.freq, level: t.level });
+            }
+        });
+    }
+
+    fn key_display(&mut self, ui: &mut egui::Ui, pitch_ratio: f32) {
+        let (mut key, detected) = { let s = self.state.lock().unwrap(); (s.key, s.detected_key) };
+        egui::ComboBox::from_id_source("file_key").width(60.0)
+            .selected_text(key.map(|k| k.name()).unwrap_or_else(|| "Key?".into()))
+            .show_ui(ui, |ui| {
+                ui.selectable_value(&mut key, None, "Key?");
+                if let Some(d) = detected { ui.selectable_value(&mut key, Some(d), format!("{} (detected)", d.name())); }
+                for minor in [false, true] {
+                    for tonic in 0..12 {
+                        let k = MusicalKey { tonic, minor };
+                        ui.selectable_value(&mut key, Some(k), k.name());
+                    }
+                }
+            });
+        self.state.lock().unwrap().key = key;
+
+        let semis = 12.0 * pitch_ratio.log2();
+        let rounded = semis.round() as i32;
+        if let Some(k) = key {
+            let approx = if (semis - rounded as f32).abs() > 0.05 { "≈" } else { "" };
+            ui.label(format!("{} → {}{} at {:+} st", k.name(), approx, k.transposed(rounded).name(), rounded));
+        } else {
+            ui.label(format!("{:+.1} st", semis));
+        }
+    }
+
+    fn set_tuning(&mut self, cents: f32) {
+        self.tuning_cents = cents.clamp(-100.0, 100.0);
+        let _ = self.tx.send(ParamUpdate::Tuning(self.tuning_cents));
+    }
+
+    // a recording a little off concert pitch: fine-tune it by ear, or from the A4 it was tuned to
+    fn tuning_row(&mut self, ui: &mut egui::Ui) {
+        ui.horizontal(|ui| {
+            ui.label("Fine-tune");
+            let mut cents = self.tuning_cents;
+            if ui.add(egui::DragValue::new(&mut cents).range(-100.0..=100.0).speed(0.5).suffix(" ct")).changed() { self.set_tuning(cents); }
+            if ui.add_enabled(self.tuning_cents != 0.0, egui::Button::new("0").small()).clicked() { self.set_tuning(0.0); }
+            ui.separator();
+            ui.label("Recording's A4");
+            ui.add(egui::DragValue::new(&mut self.reference_hz).range(400.0..=480.0).speed(0.1).suffix(" Hz"));
+            let correction = 1200.0 * (440.0 / self.reference_hz).log2();
+            if ui.button(format!("Correct to 440 ({:+.1} ct)", correction))
+                .on_hover_text("Sets the fine-tune that brings a recording tuned to this A4 to concert pitch").clicked() {
+                self.set_tuning(correction);
+            }
+        });
+    }
+
+    fn transpose_row(&mut self, ui: &mut egui::Ui, enabled: bool) {
+        let key = self.state.lock().unwrap().key;
+        ui.horizontal(|ui| {
+            ui.label("Transpose to");
+            let Some(key) = key else {
+                ui.label("(set the file's key first)");
+                return;
+            };
+            let target_name = |tonic: i32| MusicalKey { tonic, minor: key.minor }.name();
+            egui::ComboBox::from_id_source("target_key").width(60.0)
+                .selected_text(self.target_key.map(target_name).unwrap_or_else(|| "-".into()))
+                .show_ui(ui, |ui| {
+                    for tonic in 0..12 { ui.selectable_value(&mut self.target_key, Some(tonic), target_name(tonic)); }
+                });
+            let Some(target) = self.target_key else { return };
+            // take the shorter way round, so the shift is never more than a tritone
+            let up = (target - key.tonic).rem_euclid(12);
+            let shift = if up > 6 { up - 12 } else { up };
+            let ratio = 2f32.powf(shift as f32 / 12.0);
+            ui.label(format!("{} − {} = ({} − {}) mod 12 = {} → {:+} st, 2^({:+}/12) = {:.4}x",
+                target_name(target), key.name(), target, key.tonic, up, shift, shift, ratio))
+                .on_hover_text(format!("pitch classes count up from C = 0; {:+} st would also work, an octave away", if shift > 0 { shift - 12 } else { shift + 12 }));
+            // also with no shift, which is how to get back to the original key
+            let current = f32::from_bits(self.controls.pitch.load(Ordering::Relaxed));
+            if ui.add_enabled(enabled && (current - ratio).abs() > 1e-4, egui::Button::new("Apply")).clicked() {
+                self.controls.pitch.store(ratio.to_bits(), Ordering::Relaxed);
+                let _ = self.tx.send(ParamUpdate::Pitch(ratio));
+            }
+        });
+    }
+
+    fn drone_panel(&mut self, ui: &mut egui::Ui) {
+        ui.horizontal(|ui| {
+            ui.spacing_mut().slider_width = 100.0;
+            let d = &mut self.drone;
+            let mut changed = ui.checkbox(&mut d.enabled, "Drone").changed();
+            let before = d.root;
+            egui::ComboBox::from_id_source("drone_root").width(60.0).selected_text(note_name(d.root)).show_ui(ui, |ui| {
+                for n in 24..=72 { ui.selectable_value(&mut d.root, n, note_name(n)); }
+            });
+            changed |= d.root != before;
+            changed |= ui.checkbox(&mut d.octave, "+octave").changed();
+            changed |= ui.checkbox(&mut d.fifth, "+fifth").changed();
+            changed |= ui.add(egui::Slider::new(&mut d.level, 0.0..=1.0).text("level")).changed();
+            if changed {
+                let _ = self.tx.send(ParamUpdate::Drone { enabled: d.enabled, root: midi_to_freq(d.root), octave: d.octave, fifth: d.fifth, level: d.level });
+            }
+        });
+    }
+
+    fn take_panel(&mut self, ui: &mut egui::Ui) {
+        egui::CollapsingHeader::new("Take overlay").show(ui, |ui| {
+            ui.horizontal(|ui| {
+                if self.recorder.is_some() {
+                    if ui.button("Stop").clicked() { self.controls.is_playing.store(false, Ordering::SeqCst); }
+                } else if ui.button("Record take over loop").clicked() {
+                    self.start_take();
+                }
+                if self.take_overlay.is_some() && ui.button("Discard take").clicked() { self.clear_take(); }
+                ui.label(&self.take_status);
+            });
+            ui.horizontal(|ui| {
+                ui.label("Latency");
+                if ui.add(egui::DragValue::new(&mut self.take_latency_ms).range(0.0..=1000.0).speed(0.5).suffix(" ms")).changed() {
+                    self.take_latency_measured = false;
+                }
+                ui.label(if self.take_latency_measured {
+                    "measured"
+                } else if self.take_latency_ms == 0.0 {
+                    "not measured, so takes show up late by the round trip (Measure latency under Input monitoring)"
+                } else {
+                    "set by hand"
+                });
+            });
+            let Some(overlay) = &self.take_overlay else { return };
+
+            ui.horizontal(|ui| {
+                ui.label("Original");
+                let mut blend = f32::from_bits(self.controls.take_blend.load(Ordering::Relaxed));
+                if ui.add(egui::Slider::new(&mut blend, 0.0..=1.0).show_value(false)).changed() {
+                    self.controls.take_blend.store(blend.to_bits(), Ordering::Relaxed);
+                }
+                ui.label("Take");
+            });
+
+            let (rect, _) = ui.allocate_exact_size(egui::vec2(ui.available_width(), 120.0), egui::Sense::hover());
+            let painter = ui.painter_at(rect);
+            painter.rect_filled(rect, 2.0, egui::Color32::from_rgb(10, 10, 10));
+            let lanes = [
+                (&overlay.original, &overlay.original_onsets, egui::Color32::from_rgb(0, 180, 100)),
+                (&overlay.take, &overlay.take_onsets, egui::Color32::from_rgb(80, 140, 255)),
+            ];
+            for (lane, (peaks, onsets, color)) in lanes.into_iter().enumerate() {
+                let top = rect.top() + lane as f32 * rect.height() * 0.5;
+                let mid = top + rect.height() * 0.25;
+                let half = rect.height() * 0.22;
+                let dx = rect.width() / peaks.len().max(1) as f32;
+                for (i, &p) in peaks.iter().enumerate() {
+                    let x = rect.left() + i as f32 * dx;
+                    painter.line_segment([egui::pos2(x, mid - p * half), egui::pos2(x, mid + p * half)], (dx.max(1.0), color));
+                }
+                for &o in onsets.iter() {
+                    let x = rect.left() + o * rect.width();
+                    painter.line_segment([egui::pos2(x, top), egui::pos2(x, top + rect.height() * 0.5)], (1.0, egui::Color32::from_rgb(255, 140, 0)));
+                }
+            }
+            painter.line_segment([egui::pos2(rect.left(), rect.center().y), egui::pos2(rect.right(), rect.center().y)], (1.0, egui::Color32::from_gray(60)));
+
+            let take = self.controls.take.lock().unwrap().clone();
+            let span = (take.pcm.len() * take.channels) as f32;
+            let pos = self.controls.cursor.load(Ordering::Relaxed).saturating_sub(take.start) as f32 / span.max(1.0);
+            if (0.0..=1.0).contains(&pos) {
+                let x = rect.left() + pos * rect.width();
+                painter.line_segment([egui::pos2(x, rect.top()), egui::pos2(x, rect.bottom())], (1.0, egui::Color32::WHITE));
+            }
+        });
+    }
+
+    fn second_output_panel(&mut self, ui: &mut egui::Ui) {
+        egui::CollapsingHeader::new("Second output").show(ui, |ui| {
+            if self.output_devices.is_empty() {
+                self.output_devices = cpal::default_host().output_devices()
+                    .map(|devs| devs.filter_map(|d| d.name().ok()).collect()).unwrap_or_default();
+            }
+            ui.horizontal(|ui| {
+                let current = self.second_output.as_ref().map(|o| o.0.clone());
+                let mut pick = None;
+                egui::ComboBox::from_id_source("second_output").selected_text(current.as_deref().unwrap_or("Off")).show_ui(ui, |ui| {
+                    if ui.selectable_label(current.is_none(), "Off").clicked() { pick = Some(None); }
+                    for name in &self.output_devices {
+                        if ui.selectable_label(current.as_ref() == Some(name), name).clicked() { pick = Some(Some(name.clone())); }
+                    }
+                });
+                if ui.small_button("Refresh").clicked() { self.output_devices.clear(); }
+                match pick {
+                    Some(Some(name)) => if let Err(e) = self.open_second_output(&name) { eprintln!("second output: {}", e); },
+                    Some(None) => self.close_second_output(),
+                    None => {}
+                }
+                ui.label("Volume");
+                let mut vol = f32::from_bits(self.controls.second_volume.load(Ordering::Relaxed));
+                if ui.add(egui::Slider::new(&mut vol, 0.0..=2.0)).changed() {
+                    self.controls.second_volume.store(vol.to_bits(), Ordering::Relaxed);
+                }
+            });
+        });
+    }
+
+    fn sync_panel(&mut self, ui: &mut egui::Ui) {
+        egui::CollapsingHeader::new("Lesson sync (LAN)").show(ui, |ui| {
+            ui.horizontal(|ui| {
+                let mut mode = self.lan_sync.mode;
+                ui.radio_value(&mut mode, SyncMode::Off, "Off");
+                ui.radio_value(&mut mode, SyncMode::Lead, "Lead").on_hover_text("Send play/pause, position, loop, speed and pitch to followers");
+                ui.radio_value(&mut mode, SyncMode::Follow, "Follow").on_hover_text("Copy a leader's transport; both sides need the same file open");
+                if mode != self.lan_sync.mode { self.set_sync_mode(mode); }
+                if self.lan_sync.mode == SyncMode::Lead {
+                    ui.add(egui::TextEdit::singleline(&mut self.lan_sync.target).hint_text("follower IP, blank for the LAN").desired_width(150.0));
+                }
+            });
+            ui.label(&self.lan_sync.status);
+        });
+    }
+
+    fn system_panel(&mut self, ui: &mut egui::Ui) {
+        egui::CollapsingHeader::new("System").show(ui, |ui| {
+            ui.horizontal(|ui| {
+                if ui.button("Register as audio file handler").on_hover_text("Adds Reh to Open with for mp3, wav, flac, ...").clicked() {
+                    self.system_status = register_file_types(true).unwrap_or_else(|e| format!("Failed: {e}"));
+                }
+                if ui.button("Unregister").clicked() {
+                    self.system_status = register_file_types(false).unwrap_or_else(|e| format!("Failed: {e}"));
+                }
+                ui.label(&self.system_status);
+            });
+        });
+    }
+
+    fn stretcher_panel(&mut self, ui: &mut egui::Ui) {
+        let mut changed = false;
+        let mut formats_changed = false;
+        let (channels, rate) = (self.output_channels, self.output_rate);
+        let adv = &mut self.stretch_advanced;
+        egui::CollapsingHeader::new("Advanced stretcher").show(ui, |ui| {
+            ui.label("Shorter blocks keep drum attacks tighter when slowing down a lot; longer ones sound smoother on sustained notes.");
+            ui.label("Without a custom tuning for the output format, the stretcher picks a preset by speed.");
+            let mut remove = None;
+            egui::Grid::new("stretch_formats").show(ui, |ui| {
+                for (i, (ch, r, tuning)) in adv.formats.iter_mut().enumerate() {
+                    let here = *ch == channels && *r == rate;
+                    ui.label(format!("{ch} ch, {r} Hz{}", if here { " (this output)" } else { "" }));
+                    ui.label("block");
+                    formats_changed |= ui.add(egui::DragValue::new(&mut tuning.block_ms).range(10.0..=500.0).speed(1.0).suffix(" ms")).changed();
+                    ui.label("interval");
+                    formats_changed |= ui.add(egui::DragValue::new(&mut tuning.interval_ms).range(2.0..=tuning.block_ms).speed(0.5).suffix(" ms")).changed();
+                    if ui.small_button("Reset").clicked() { *tuning = StretchTuning::NORMAL; formats_changed = true; }
+                    if ui.small_button("x").on_hover_text("Back to the presets by speed for this format").clicked() { remove = Some(i); }
+                    ui.end_row();
+                }
+            });
+            if let Some(i) = remove {
+                adv.formats.remove(i);
+                formats_changed = true;
+            }
+            if adv.tuning_for(channels, rate).is_none() && ui.button(format!("Custom tuning for this output ({channels} ch, {rate} Hz)")).clicked() {
+                adv.formats.push((channels, rate, StretchTuning::NORMAL));
+                formats_changed = true;
+            }
+            ui.horizontal(|ui| {
+                changed |= ui.checkbox(&mut adv.tonality.0, "Tonality limit").on_hover_text("Above this frequency the shift is treated as non-tonal, which keeps shifted voices and cymbals from sounding metallic").changed();
+                changed |= ui.add_enabled(adv.tonality.0, egui::DragValue::new(&mut adv.tonality.1).range(1000.0..=20000.0).speed(50.0).suffix(" Hz")).changed();
+            });
+        });
+        if formats_changed { adv.save_formats(); }
+        if changed || formats_changed {
+            let tonality_limit = adv.tonality.0.then_some(adv.tonality.1);
+            let _ = self.tx.send(ParamUpdate::Stretcher { tuning: adv.tuning_for(channels, rate), tonality_limit });
+        }
+    }
+
+    fn automation_panel(&mut self, ui: &mut egui::Ui, total: usize) {
+        let cursor = self.controls.cursor.load(Ordering::Relaxed);
+        let mut changed = false;
+        egui::CollapsingHeader::new("Automation").show(ui, |ui| {
+            ui.label("Click to add a point, drag to move it, right-click to delete it.");
+            ui.horizontal(|ui| {
+                changed |= ui.checkbox(&mut self.speed_lane.enabled, "Follow speed curve").changed();
+                if let Some(v) = self.speed_lane.curve.value_at(cursor) { ui.label(format!("now {:.0}%", v * 100.0)); }
+                if ui.small_button("Clear").clicked() { self.speed_lane.curve.points.clear(); changed = true; }
+                ui.label("(scales the speed slider)");
+            });
+            changed |= self.speed_lane.ui(ui, total, cursor, 0.25..=1.5, 1.0, |v| format!("{:.0}%", v * 100.0));
+            ui.horizontal(|ui| {
+                changed |= ui.checkbox(&mut self.pitch_lane.enabled, "Pitch regions").changed();
+                if let Some(v) = self.pitch_lane.curve.value_at(cursor) { ui.label(format!("now {:+.0} cents", v * 100.0)); }
+                if ui.small_button("Clear").clicked() { self.pitch_lane.curve.points.clear(); changed = true; }
+                ui.label("(each point shifts pitch from there on, e.g. to follow a recording that drifts sharp)");
+            });
+            changed |= self.pitch_lane.ui(ui, total, cursor, -2.0..=2.0, 0.0, |v| format!("{:+.0} cents", v * 100.0));
+            ui.horizontal(|ui| {
+                if ui.checkbox(&mut self.volume_lane.enabled, "Volume envelope").changed() { self.publish_automation(); }
+                if let Some(v) = self.volume_lane.curve.value_at(cursor) { ui.label(format!("now {:+.1} dB", v)); }
+                if ui.small_button("Clear").clicked() { self.volume_lane.curve.points.clear(); self.volume_dirty = true; }
+                ui.label("(duck or boost parts of the file; saved with the file's session)");
+            });
+            self.volume_dirty |= self.volume_lane.ui(ui, total, cursor, -24.0..=12.0, 0.0, |v| format!("{:+.1} dB", v));
+        });
+        if changed || self.volume_dirty { self.publish_automation(); }
+        // write the sidecar once a drag is over rather than on every frame of it
+        if self.volume_dirty && self.volume_lane.dragging.is_none() {
+            self.volume_dirty = false;
+            self.save_session();
+        }
+    }
+
+    fn cue_panel(&mut self, ui: &mut egui::Ui, sample_rate: u32, channels: usize) {
+        let c = self.controls.clone();
+        egui::CollapsingHeader::new("Cue (pre-listen)").show(ui, |ui| {
+            if self.output_devices.is_empty() {
+                self.output_devices = cpal::default_host().output_devices()
+                    .map(|devs| devs.filter_map(|d| d.name().ok()).collect()).unwrap_or_default();
+            }
+            ui.horizontal(|ui| {
+                let current = self.cue_output.as_ref().map(|o| o.0.clone());
+                let mut pick = None;
+                egui::ComboBox::from_id_source("cue_output").selected_text(current.as_deref().unwrap_or("Off")).show_ui(ui, |ui| {
+                    if ui.selectable_label(current.is_none(), "Off").clicked() { pick = Some(None); }
+                    for name in &self.output_devices {
+                        if ui.selectable_label(current.as_ref() == Some(name), name).clicked() { pick = Some(Some(name.clone())); }
+                    }
+                });
+                match pick {
+                    Some(Some(name)) => if let Err(e) = self.open_cue_output(&name) { eprintln!("cue output: {}", e); },
+                    Some(None) => { self.cue_output = None; c.cue_playing.store(false, Ordering::Relaxed); }
+                    None => {}
+                }
+                ui.label("Volume");
+                let mut vol = f32::from_bits(c.cue_volume.load(Ordering::Relaxed));
+                if ui.add(egui::Slider::new(&mut vol, 0.0..=2.0)).changed() { c.cue_volume.store(vol.to_bits(), Ordering::Relaxed); }
+            });
+            if self.cue_output.is_none() { return; }
+
+            let secs = |i: usize| i as f32 / (sample_rate as f32 * channels as f32).max(1.0);
+            let (start, end) = (c.cue_start.load(Ordering::Relaxed), c.cue_end.load(Ordering::Relaxed));
+            ui.horizontal(|ui| {
+                let playing = c.cue_playing.load(Ordering::Relaxed);
+                if ui.button(if playing { "Stop cue" } else { "Play cue" }).clicked() { c.cue_playing.store(!playing, Ordering::Relaxed); }
+                if ui.button("Cue current loop").clicked() {
+                    let (l_start, l_end) = (c.loop_start.load(Ordering::Relaxed), c.loop_end.load(Ordering::Relaxed));
+                    c.cue_start.store(l_start, Ordering::Relaxed);
+                    c.cue_end.store(l_end, Ordering::Relaxed);
+                    c.cue_cursor.store(l_start, Ordering::Relaxed);
+                }
+                if end > start && ui.button("Clear cue region").clicked() { c.cue_end.store(start, Ordering::Relaxed); }
+                if ui.button("Send to main").on_hover_text("Move the main playback to the cued position and region").clicked() {
+                    if end > start {
+                        c.loop_start.store(start, Ordering::Relaxed);
+                        c.loop_end.store(end, Ordering::Relaxed);
+                    }
+                    self.seek_history.jump(&c, c.cue_cursor.load(Ordering::Relaxed));
+                    c.cue_playing.store(false, Ordering::Relaxed);
+                }
+                let region = if end > start { format!("  loop {:.2}-{:.2}s", secs(start), secs(end)) } else { String::new() };
+                ui.label(format!("at {:.2}s{}  (right-click the waveform to cue)", secs(c.cue_cursor.load(Ordering::Relaxed)), region));
+            });
+        });
+    }
 
-            let mut waveform = Vec::new();
-            let chunk_size = (pcm.len() / 1000).max(1);
-            for chunk in pcm.chunks(chunk_size) { 
-                waveform.push(chunk.iter().fold(0.0f32, |a, &b| a.max(b.abs()))); 
+    fn ab_panel(&mut self, ui: &mut egui::Ui) {
+        ui.horizontal(|ui| {
+            ui.label("A/B");
+            if ui.button("Load B...").clicked()
+                && let Some(path) = FileDialog::new().pick_file() {
+                self.load_deck_b(path);
             }
-
-            let total_samples = pcm.len();
-            let sample_rate = params.sample_rate.unwrap_or(44100);
-            let channels = params.channels.map(|c| c.count()).unwrap_or(2);
-
-            c.cursor.store(0, Ordering::SeqCst);
-            c.loop_start.store(0, Ordering::SeqCst);
-            c.loop_end.store(total_samples, Ordering::SeqCst);
-            *c.pcm_data.lock().unwrap() = Arc::new(pcm);
-
-            let mut s = s_ptr.lock().unwrap();
-            s.total_samples = total_samples;
-            s.sample_rate = sample_rate;
-            s.channels = channels;
-            s.waveform = waveform;
-            
-            c.is_loading.store(false, Ordering::SeqCst);
+            if self.ab.other.is_none() { return; }
+            let on_b = self.ab.on_b;
+            if ui.selectable_label(!on_b, "A").clicked() && on_b { self.swap_decks(); }
+            if ui.selectable_label(on_b, "B").clicked() && !on_b { self.swap_decks(); }
+            ui.label("(B key toggles)");
+            let mut changed = false;
+            for (i, name) in ["A gain", "B gain"].iter().enumerate() {
+                ui.label(*name);
+                changed |= ui.add(egui::DragValue::new(&mut self.ab.gain_db[i]).range(-24.0..=24.0).speed(0.1).suffix(" dB")).changed();
+            }
+            if changed { self.apply_deck_gain(); }
+            ui.label("B offset");
+            ui.add(egui::DragValue::new(&mut self.ab.offset_secs).range(-600.0..=600.0).speed(0.01).suffix(" s"))
+                .on_hover_text("How far into B the moment at 0:00 in A lands");
+            if ui.small_button("x").on_hover_text("Unload B").clicked() { self.clear_deck_b(); }
         });
     }
 
-    fn start_playback(&mut self, rx: Receiver<ParamUpdate>) {
-        let c = self.controls.clone();
-        let host = cpal::default_host();
-        let device = host.default_output_device().expect("No output device");
-        let config = device.default_output_config().unwrap().config();
-        
-        let mut stretchers: Vec<Stretch> = (0..config.channels as usize)
-            .map(|_| Stretch::preset_default(1, config.sample_rate.0)).collect();
-        
-        let mut input_scratch = vec![0.0f32; 8192];
-        let mut output_scratch = vec![0.0f32; 8192];
-
-        let mut local_speed = 1.0f32;
-        let mut local_pitch = 1.0f32;
-
-        let stream = device.build_output_stream(&config, move |data: &mut [f32], _| {
-            while let Ok(update) = rx.try_recv() {
-                match update {
-                    ParamUpdate::Speed(s) => local_speed = s,
-                    ParamUpdate::Pitch(p) => local_pitch = p,
+    fn setlist_panel(&mut self, ui: &mut egui::Ui) {
+        egui::CollapsingHeader::new(format!("Setlist ({})", self.setlist.items.len())).show(ui, |ui| {
+            ui.horizontal(|ui| {
+                ui.add(egui::TextEdit::singleline(&mut self.setlist.new_name).hint_text("name, e.g. Song A bridge").desired_width(180.0));
+                if ui.button("Add current loop").clicked() { self.add_setlist_item(); }
+                ui.label("Pause between");
+                ui.add(egui::DragValue::new(&mut self.setlist.gap_secs).range(0.0..=60.0).speed(0.1).suffix(" s"));
+            });
+            ui.horizontal(|ui| {
+                if self.setlist.running.is_some() {
+                    if ui.button("Stop setlist").clicked() { self.setlist.running = None; }
+                } else if ui.add_enabled(!self.setlist.items.is_empty(), egui::Button::new("Play setlist")).clicked() {
+                    self.start_setlist_item(0);
                 }
-            }
+                let dir = config_dir().join("setlists");
+                if ui.button("Save...").clicked()
+                    && std::fs::create_dir_all(&dir).is_ok()
+                    && let Some(path) = FileDialog::new().add_filter("Setlist", &["setlist"]).set_directory(&dir).save_file() {
+                    let _ = std::fs::write(path, format_setlist(&self.setlist.items));
+                }
+                if ui.button("Load...").clicked()
+                    && let Some(path) = FileDialog::new().add_filter("Setlist", &["setlist"]).set_directory(&dir).pick_file()
+                    && let Ok(text) = std::fs::read_to_string(path) {
+                    self.setlist.items = parse_setlist(&text);
+                    self.setlist.running = None;
+                }
+                if let Some((_, SetlistPhase::Gap(until))) = &self.setlist.running {
+                    ui.label(format!("next in {:.0} s", until.saturating_duration_since(std::time::Instant::now()).as_secs_f32().ceil()));
+                }
+            });
 
-            // Mute during seeking, loading, or if paused
-            if !c.is_playing.load(Ordering::Relaxed) || 
-               c.is_loading.load(Ordering::Relaxed) || 
-               c.is_seeking.load(Ordering::Relaxed) {
-                data.fill(0.0);
-                return;
+            let mmss = |t: f64| format!("{}:{:02}", (t / 60.0) as u32, (t % 60.0) as u32);
+            let current = self.setlist.running.as_ref().map(|r| r.0);
+            let (mut play, mut remove, mut swap) = (None, None, None);
+            for (i, item) in self.setlist.items.iter().enumerate() {
+                ui.horizontal(|ui| {
+                    let label = format!("{}. {}  {}-{}", i + 1, item.name, mmss(item.start), mmss(item.end));
+                    if ui.selectable_label(current == Some(i), label).on_hover_text(item.path.display().to_string()).clicked() { play = Some(i); }
+                    if ui.small_button("^").clicked() && i > 0 { swap = Some(i - 1); }
+                    if ui.small_button("v").clicked() && i + 1 < self.setlist.items.len() { swap = Some(i); }
+                    if ui.small_button("x").clicked() { remove = Some(i); }
+                });
             }
+            if let Some(i) = swap { self.setlist.items.swap(i, i + 1); }
+            if let Some(i) = remove { self.setlist.items.remove(i); self.setlist.running = None; }
+            if let Some(i) = play { self.start_setlist_item(i); }
+        });
+    }
 
-            let pcm = Arc::clone(&*c.pcm_data.lock().unwrap());
-            if pcm.is_empty() {
-                data.fill(0.0);
-                return;
+    fn playlist_panel(&mut self, ui: &mut egui::Ui) {
+        ui.horizontal(|ui| {
+            if ui.button("Watch folder...").clicked()
+                && let Some(dir) = FileDialog::new().pick_folder() {
+                self.start_watch(dir);
+            }
+            let mut stop = false;
+            if let Some(w) = &mut self.watch {
+                ui.label(format!("Watching {}", w.dir.display()));
+                ui.checkbox(&mut w.auto_load, "auto-load new files");
+                stop = ui.small_button("x").on_hover_text("Stop watching").clicked();
             }
+            if stop { self.watch = None; }
+        });
 
-            let cursor = c.cursor.load(Ordering::Relaxed);
-            let l_start = c.loop_start.load(Ordering::Relaxed);
-            let l_end = c.loop_end.load(Ordering::Relaxed);
-            let volume = f32::from_bits(c.volume.load(Ordering::Relaxed));
-            let channels = 2; 
+        let mut load = None;
+        let mut remove = None;
+        let mut step = 0;
+        egui::CollapsingHeader::new(format!("Playlist ({})", self.playlist.tracks.len())).show(ui, |ui| {
+            ui.horizontal(|ui| {
+                if ui.button("Add to queue...").clicked()
+                    && let Some(paths) = FileDialog::new().add_filter("Audio", &AUDIO_EXTENSIONS).pick_files() {
+                    self.playlist.tracks.extend(paths);
+                }
+                let (current, len) = (self.playlist.current, self.playlist.tracks.len());
+                if ui.add_enabled(current.is_some_and(|i| i > 0), egui::Button::new("|< Prev")).on_hover_text("Shift+P").clicked() { step = -1; }
+                if ui.add_enabled(current.map_or(len > 0, |i| i + 1 < len), egui::Button::new("Next >|")).on_hover_text("Shift+N").clicked() { step = 1; }
+                ui.checkbox(&mut self.playlist.auto_advance, "auto-advance").on_hover_text("Play the next track when one ends");
+                if ui.add_enabled(len > 0, egui::Button::new("Clear")).clicked() {
+                    self.playlist.tracks.clear();
+                    self.playlist.current = None;
+                }
+            });
+            egui::ScrollArea::vertical().max_height(120.0).id_source("playlist").show(ui, |ui| {
+                for (i, track) in self.playlist.tracks.iter().enumerate() {
+                    let name = track.file_name().map(|n| n.to_string_lossy().into_owned()).unwrap_or_default();
+                    ui.horizontal(|ui| {
+                        if ui.small_button("x").on_hover_text("Remove from the queue").clicked() { remove = Some(i); }
+                        if ui.selectable_label(self.playlist.current == Some(i), name).clicked() { load = Some(i); }
+                    });
+                }
+            });
+        });
+        if let Some(i) = load { self.play_track(i); }
+        if let Some(i) = remove {
+            let p = &mut self.playlist;
+            p.tracks.remove(i);
+            p.current = match p.current {
+                Some(c) if c == i => None,
+                Some(c) if c > i => Some(c - 1),
+                c => c,
+            };
+        }
+        match (step, self.playlist.current) {
+            (0, _) => {}
+            (_, Some(_)) => self.step_track(step),
+            (_, None) => self.play_track(0),
+        }
+    }
 
-            let stretch_ratio = 1.0 / local_speed; 
-            let output_frames = data.len() / channels;
-            let input_frames_needed = (output_frames as f32 / stretch_ratio) as usize;
+    fn refresh_feed(&self, url: String) {
+        let tx = self.podcasts.tx.clone();
+        thread::spawn(move || { let result = fetch_feed(&url); let _ = tx.send((url, result)); });
+    }
 
-            if cursor + (input_frames_needed * channels) < pcm.len() && input_frames_needed < 8192 {
-                let mut active_cursor = cursor;
-                if active_cursor >= l_end && l_end > l_start { active_cursor = l_start; }
+    fn poll_podcasts(&mut self) {
+        let p = &mut self.podcasts;
+        let mut changed = false;
+        while let Ok((url, result)) = p.rx.try_recv() {
+            let Some(feed) = p.feeds.iter_mut().find(|f| f.url == url) else { continue };
+            match result {
+                Ok((title, episodes)) => {
+                    if !title.is_empty() && title != feed.title { feed.title = title; changed = true; }
+                    feed.episodes = episodes;
+                    p.status.clear();
+                }
+                Err(e) => p.status = format!("{url}: {e}"),
+            }
+        }
+        if changed && let Err(e) = save_subscriptions(&p.feeds) { p.status = format!("Couldn't save subscriptions: {e}"); }
+    }
 
-                for ch in 0..channels {
-                    stretchers[ch].set_transpose_factor(local_pitch, None);
-                    for i in 0..input_frames_needed { 
-                        input_scratch[i] = pcm[active_cursor + (i * channels) + ch]; 
-                    }
-                    let mut output_view = &mut output_scratch[..output_frames];
-                    stretchers[ch].process(&input_scratch[..input_frames_needed], &mut output_view);
-                    for i in 0..output_frames { 
-                        data[i * channels + ch] = output_scratch[i] * volume; 
+    fn podcast_panel(&mut self, ui: &mut egui::Ui) {
+        let mut open = None;
+        let mut remove = None;
+        egui::CollapsingHeader::new(format!("Podcasts ({})", self.podcasts.feeds.len())).show(ui, |ui| {
+            ui.horizontal(|ui| {
+                ui.add(egui::TextEdit::singleline(&mut self.podcasts.new_url).hint_text("RSS feed URL").desired_width(260.0));
+                let url = self.podcasts.new_url.trim().to_string();
+                if ui.add_enabled(is_url(&url), egui::Button::new("Subscribe")).clicked() {
+                    if !self.podcasts.feeds.iter().any(|f| f.url == url) {
+                        self.podcasts.feeds.push(Feed { url: url.clone(), title: String::new(), episodes: Vec::new() });
+                        if let Err(e) = save_subscriptions(&self.podcasts.feeds) { self.podcasts.status = format!("Couldn't save subscriptions: {e}"); }
                     }
+                    self.podcasts.new_url.clear();
+                    self.refresh_feed(url);
                 }
-                c.cursor.store(active_cursor + input_frames_needed * channels, Ordering::Relaxed);
-            } else {
-                data.fill(0.0);
+            });
+            if !self.podcasts.status.is_empty() { ui.label(&self.podcasts.status); }
+            for (i, feed) in self.podcasts.feeds.iter().enumerate() {
+                let name = if feed.title.is_empty() { &feed.url } else { &feed.title };
+                let header = egui::CollapsingHeader::new(name).id_source(("feed", &feed.url)).show(ui, |ui| {
+                    ui.horizontal(|ui| {
+                        if ui.small_button("Refresh").clicked() { self.refresh_feed(feed.url.clone()); }
+                        if ui.small_button("Unsubscribe").clicked() { remove = Some(i); }
+                    });
+                    egui::ScrollArea::vertical().max_height(160.0).id_source(("episodes", &feed.url)).show(ui, |ui| {
+                        for ep in &feed.episodes {
+                            if ui.selectable_label(false, format!("{}  {}", ep.published, ep.title)).on_hover_text("Download and play").clicked() {
+                                open = Some(ep.url.clone());
+                            }
+                        }
+                    });
+                });
+                // episodes are fetched the first time a feed is opened
+                if header.header_response.clicked() && feed.episodes.is_empty() { self.refresh_feed(feed.url.clone()); }
             }
-        }, |e| eprintln!("{}", e), None).unwrap();
+        });
+        if let Some(i) = remove {
+            self.podcasts.feeds.remove(i);
+            if let Err(e) = save_subscriptions(&self.podcasts.feeds) { self.podcasts.status = format!("Couldn't save subscriptions: {e}"); }
+        }
+        if let Some(url) = open { self.open_location(url); }
+    }
 
-        stream.play().unwrap();
-        self._stream = Some(stream);
+    fn subtitle_panel(&mut self, ui: &mut egui::Ui, sample_rate: u32, channels: usize) {
+        let to_samples = |t: f64| (t * sample_rate as f64) as usize * channels;
+        let now = self.controls.cursor.load(Ordering::Relaxed) as f64 / (sample_rate as f64 * channels as f64).max(1.0);
+        let mut transcribe = false;
+        let s = self.state.lock().unwrap();
+        if s.total_samples == 0 && s.subtitles.is_empty() { return; }
+
+        egui::CollapsingHeader::new(format!("Subtitles / transcript ({})", s.subtitles.len())).show(ui, |ui| {
+            ui.horizontal(|ui| {
+                let busy = s.transcript_status.ends_with("...");
+                transcribe = ui.add_enabled(self.whisper_model.is_some() && s.total_samples > 0 && !busy, egui::Button::new("Transcribe"))
+                    .on_hover_text("Speech to text with whisper.cpp, one cue per sentence").clicked();
+                let model = self.whisper_model.as_ref().and_then(|p| p.file_name()).map(|n| n.to_string_lossy().into_owned()).unwrap_or_else(|| "none".into());
+                if ui.button(format!("Model: {model}")).on_hover_text("A whisper.cpp ggml .bin model").clicked()
+                    && let Some(path) = FileDialog::new().add_filter("whisper model", &["bin"]).pick_file() {
+                    self.whisper_model = Some(path);
+                }
+                ui.label(&s.transcript_status);
+            });
+            egui::ScrollArea::vertical().max_height(150.0).id_source("subtitles").show(ui, |ui| {
+                for cue in &s.subtitles {
+                    ui.horizontal(|ui| {
+                        let active = cue.start <= now && now < cue.end;
+                        if ui.small_button("Loop").clicked() {
+                            self.controls.loop_start.store(to_samples(cue.start), Ordering::Relaxed);
+                            self.controls.loop_end.store(to_samples(cue.end), Ordering::Relaxed);
+                            self.seek_history.jump(&self.controls, to_samples(cue.start));
+                        }
+                        let line = format!("{:>7.2}s  {}", cue.start, cue.text.replace('\n', " "));
+                        if ui.selectable_label(active, line).clicked() {
+                            self.seek_history.jump(&self.controls, to_samples(cue.start));
+                        }
+                    });
+                }
+            });
+        });
+        drop(s);
+        if transcribe { self.start_transcription(); }
     }
 }
 
 impl eframe::App for PlayerApp {
+    fn on_exit(&mut self, _gl: Option<&eframe::glow::Context>) {
+        self.save_session();
+    }
+
     fn update(&mut self, ctx: &egui::Context, _frame: &mut eframe::Frame) {
-        let (file_path, total_samples, sample_rate, channels, waveform) = {
+        let (file_path, total_samples, sample_rate, channels, waveform_gen, phrases, onsets, clipped, file_lufs) = {
             let s = self.state.lock().unwrap();
-            (s.file_path.clone(), s.total_samples, s.sample_rate, s.channels, s.waveform.clone())
+            (s.file_path.clone(), s.total_samples, s.sample_rate, s.channels, s.waveform_gen, s.phrases.clone(), s.onsets.clone(), s.clipped.clone(), s.file_lufs)
         };
 
-        // Keyboard Shortcuts
-        if ctx.input(|i| i.key_pressed(egui::Key::Space)) {
-            let p = self.controls.is_playing.load(Ordering::Relaxed);
-            self.controls.is_playing.store(!p, Ordering::Relaxed);
+        // before anything that positions the new file itself (setlists, remote commands) gets a say
+        self.apply_pending_restore();
+        if self.controls.device_lost.swap(false, Ordering::Relaxed) { self.rebuild_output(); }
+        self.poll_watch();
+        self.poll_remote(ctx);
+        self.poll_sync();
+        self.poll_take();
+        if let Some(rx) = &self.retired_effects { while rx.try_recv().is_ok() {} }
+        if let Some(rx) = &self.retired_stretchers { while rx.try_recv().is_ok() {} }
+        self.controls.retired.lock().unwrap().retain(|(at, _)| at.elapsed() < std::time::Duration::from_millis(RETIRE_AFTER_MS));
+        self.poll_sing_along();
+        self.poll_setlist();
+        self.poll_playlist();
+        self.poll_loop_wraps();
+        self.poll_metronome();
+        self.apply_deck_gain();
+        self.poll_podcasts();
+        self.notify_extensions();
+        self.update_tray(ctx);
+        self.sleep_inhibitor.set(self.keep_awake && self.controls.is_playing.load(Ordering::Relaxed) && total_samples > 0);
+        while let Ok(path) = self.open_rx.try_recv() { self.load_audio_file(path); }
+        while let Ok(error) = self.load_error_rx.try_recv() { self.load_error = Some(error); }
+        while let Ok(location) = self.login_rx.try_recv() {
+            let user = self.logins.get(url_host(&location)).map(|l| l.user.clone()).unwrap_or_default();
+            let message = format!("{} asked for a user name and password", url_host(&location));
+            self.location_prompt = Some(LocationPrompt { location, user, password: String::new(), message });
         }
+        self.location_window(ctx);
 
-        // quit keys
-        if ctx.input(|i| i.key_pressed(egui::Key::Q) || i.key_pressed(egui::Key::Escape)) {
-            ctx.send_viewport_cmd(egui::ViewportCommand::Close);
+        // paste a file path or URL (Ctrl+V / Cmd+V) to open it
+        let pasted = ctx.input(|i| i.events.iter().find_map(|e| match e { egui::Event::Paste(t) => parse_location(t), _ => None }));
+        if let Some(location) = pasted && ctx.memory(|m| m.focused().is_none()) {
+            self.open_location(location);
         }
 
-        // reset key
-        if ctx.input(|i| i.key_pressed(egui::Key::R)) {
-            self.controls.speed.store(1.0f32.to_bits(), Ordering::Relaxed);
-            self.controls.pitch.store(1.0f32.to_bits(), Ordering::Relaxed);
-            let _ = self.tx.send(ParamUpdate::Speed(1.0));
-            let _ = self.tx.send(ParamUpdate::Pitch(1.0));
+        // Keyboard Shortcuts, skipped while typing into a text field
+        let mut nudge = 1.0;
+        if ctx.memory(|m| m.focused().is_none()) {
+            // hold-to-nudge keys; with a modifier they're the UI zoom instead
+            nudge = ctx.input(|i| {
+                let amount = self.nudge_percent / 100.0;
+                if !i.modifiers.is_none() { 1.0 }
+                else if i.key_down(egui::Key::Equals) || i.key_down(egui::Key::Plus) { 1.0 + amount }
+                else if i.key_down(egui::Key::Minus) { 1.0 - amount }
+                else { 1.0 }
+            });
+            let macro_key = ctx.input_mut(|i| self.macros.list.iter().position(|m| m.key.is_some_and(|k| i.consume_key(egui::Modifiers::NONE, k))));
+            if let Some(m) = macro_key { self.run_macro(ctx, m); }
+            let pressed: Vec<Action> = ctx.input_mut(|i| self.keymap.iter().filter(|(m, k, _)| i.consume_key(*m, *k)).map(|b| b.2).collect());
+            for action in pressed { self.perform(ctx, action); }
         }
+        // mouse back/forward buttons step through the seek history
+        let (back, forward) = ctx.input(|i| (i.pointer.button_pressed(egui::PointerButton::Extra1), i.pointer.button_pressed(egui::PointerButton::Extra2)));
+        if back { self.perform(ctx, Action::SeekBack); }
+        if forward { self.perform(ctx, Action::SeekForward); }
 
-        // loop clear key
-        if ctx.input(|i| i.key_pressed(egui::Key::C)) {
-            self.controls.loop_start.store(0, Ordering::Relaxed);
-            self.controls.loop_end.store(total_samples, Ordering::Relaxed);
+        if nudge != self.nudge_active {
+            self.nudge_active = nudge;
+            let _ = self.tx.send(ParamUpdate::Nudge(nudge));
         }
 
-        // loop keys
-        if ctx.input(|i| i.key_pressed(egui::Key::OpenBracket)) {
-            self.controls.loop_start.store(self.controls.cursor.load(Ordering::Relaxed), Ordering::Relaxed);
-        }
-        if ctx.input(|i| i.key_pressed(egui::Key::CloseBracket)) {
-            self.controls.loop_end.store(self.controls.cursor.load(Ordering::Relaxed), Ordering::Relaxed);
-        }
+        egui::TopBottomPanel::bottom("status_bar").show(ctx, |ui| {
+            ui.horizontal(|ui| {
+                if total_samples > 0 {
+                    let clip_text = if clipped.is_empty() { "No clipping".to_string() } else { format!("{} clipped regions", clipped.len()) };
+                    ui.colored_label(if clipped.is_empty() { egui::Color32::GRAY } else { egui::Color32::RED }, clip_text);
+                    ui.separator();
+                    if let Some(l) = file_lufs { ui.label(format!("File {:.1} LUFS", l)); }
+                    let (mut on, mut target) = self.normalize;
+                    let mut changed = ui.checkbox(&mut on, "Normalize to").on_hover_text("Gain every file to the same loudness, so songs don't need the volume ridden one by one").changed();
+                    changed |= ui.add(egui::DragValue::new(&mut target).range(-30.0..=-6.0).speed(0.1).suffix(" LUFS")).changed();
+                    if let Some(db) = self.makeup_db() { ui.label(format!("{:+.1} dB", db)); }
+                    if changed {
+                        self.normalize = (on, target);
+                        let _ = std::fs::create_dir_all(config_dir());
+                        let _ = std::fs::write(config_dir().join("normalize.txt"), format!("{}\t{}", on, target));
+                    }
+                    ui.separator();
+                }
+                let fmt = |l: f32| if l.is_finite() { format!("{:.1}", l) } else { "--".to_string() };
+                let short = f32::from_bits(self.controls.lufs_short.load(Ordering::Relaxed));
+                let integrated = f32::from_bits(self.controls.lufs_integrated.load(Ordering::Relaxed));
+                ui.label(format!("Out S {} / I {} LUFS", fmt(short), fmt(integrated)));
+                if ui.small_button("reset").clicked() {
+                    let _ = self.tx.send(ParamUpdate::ResetLoudness);
+                }
+            });
+        });
 
-        // ctl arrow seeking
-        if ctx.input(|i| i.modifiers.command) {
-            let l_start = self.controls.loop_start.load(Ordering::Relaxed);
-            let l_end = self.controls.loop_end.load(Ordering::Relaxed);
-            let width = l_end.saturating_sub(l_start);
-            if ctx.input(|i| i.key_pressed(egui::Key::ArrowLeft)) {
-                let shift = l_start.min(width);
-                self.controls.loop_start.store(l_start - shift, Ordering::Relaxed);
-                self.controls.loop_end.store(l_end - shift, Ordering::Relaxed);
-            }
-            if ctx.input(|i| i.key_pressed(egui::Key::ArrowRight)) {
-                let shift = (total_samples.saturating_sub(l_end)).min(width);
-                self.controls.loop_start.store(l_start + shift, Ordering::Relaxed);
-                self.controls.loop_end.store(l_end + shift, Ordering::Relaxed);
-            }
-        }
+        self.markers_side_panel(ctx);
 
         egui::CentralPanel::default().show(ctx, |ui| {
+            if let Some(error) = self.load_error.clone() {
+                egui::Frame::none().fill(egui::Color32::from_rgb(110, 30, 30)).inner_margin(6.0).rounding(4.0).show(ui, |ui| {
+                    ui.horizontal(|ui| {
+                        ui.colored_label(egui::Color32::WHITE, format!("Could not load {error}"));
+                        ui.with_layout(egui::Layout::right_to_left(egui::Align::Center), |ui| {
+                            if ui.small_button("x").on_hover_text("Dismiss").clicked() { self.load_error = None; }
+                        });
+                    });
+                });
+            }
             if self.controls.is_loading.load(Ordering::Relaxed) {
-                ui.centered_and_justified(|ui| ui.label("Loading..."));
+                let done = f32::from_bits(self.controls.load_progress.load(Ordering::Relaxed));
+                // tracks listed but none playing: the load is waiting for a pick
+                let (tracks, track) = { let s = self.state.lock().unwrap(); (s.audio_tracks.clone(), s.audio_track) };
+                ui.vertical_centered(|ui| {
+                    ui.add_space(ui.available_height() / 3.0);
+                    if track.is_none() && !tracks.is_empty() {
+                        let name = Path::new(&file_path).file_name().map_or(file_path.clone(), |n| n.to_string_lossy().into_owned());
+                        ui.label(format!("{name} has {} audio tracks. Play which?", tracks.len()));
+                        let mut pick = None;
+                        for (id, label) in &tracks {
+                            if ui.button(label).clicked() { pick = Some(*id); }
+                        }
+                        if let Some(id) = pick { self.switch_audio_track(id); }
+                        if ui.button("Cancel").clicked() { self.cancel_load(); }
+                        return;
+                    }
+                    ui.label(if done >= 1.0 { "Analysing..." } else { "Loading..." });
+                    if done >= 0.0 {
+                        ui.add(egui::ProgressBar::new(done).show_percentage().desired_width(300.0));
+                    } else {
+                        ui.spinner();
+                    }
+                    if ui.button("Cancel").clicked() { self.cancel_load(); }
+                });
                 return;
             }
 
-            ui.vertical_centered(|ui| {
+            egui::ScrollArea::vertical().show(ui, |ui| ui.vertical_centered(|ui| {
                 ui.add_space(10.0);
-                if ui.button("Open File").clicked() {
-                    if let Some(path) = FileDialog::new().pick_file() { 
-                        self.load_audio_file(path); 
+                ui.horizontal(|ui| {
+                    if ui.button("Open File").clicked() {
+                        if let Some(path) = FileDialog::new().pick_file() { 
+                            self.load_audio_file(path); 
+                        }
                     }
-                }
+                    let sf_label = self.soundfont.as_ref().and_then(|p| p.file_name()).map(|n| n.to_string_lossy().into_owned()).unwrap_or_else(|| "none".into());
+                    if ui.button(format!("SoundFont: {}", sf_label)).on_hover_text("Used to render .mid files (needs fluidsynth)").clicked()
+                        && let Some(path) = FileDialog::new().add_filter("SoundFont", &["sf2", "sf3"]).pick_file() {
+                        self.soundfont = Some(path);
+                    }
+                    if ui.button("Open Location").on_hover_text("A web, WebDAV or SMB address").clicked() {
+                        self.location_prompt = Some(LocationPrompt { location: String::new(), user: String::new(), password: String::new(), message: String::new() });
+                    }
+                    if ui.button("Markers").on_hover_text("Labelled points on the timeline (M adds one at the cursor)").clicked() { self.show_markers = !self.show_markers; }
+                    if ui.button("Open Subtitles").clicked()
+                        && let Some(path) = FileDialog::new().add_filter("Subtitles", &["srt", "vtt"]).pick_file() {
+                        self.load_subtitles(path);
+                    }
+                    ui.checkbox(&mut self.minimize_to_tray, "Minimize to tray");
+                    let mut profile = self.profile;
+                    egui::ComboBox::from_id_source("profile").selected_text(self.profile().name).show_ui(ui, |ui| {
+                        for (i, p) in PROFILES.iter().enumerate() { ui.selectable_value(&mut profile, i, p.name); }
+                    }).response.on_hover_text("Switches the panels, speed range and extra keys to suit the task");
+                    if profile != self.profile { self.set_profile(profile); }
+                    ui.checkbox(&mut self.keep_awake, "Keep awake while playing").on_hover_text("Stops the screen locking and the system sleeping during playback");
+                    let mut pick = None;
+                    let combo = egui::ComboBox::from_id_source("output_device").selected_text(self.output_device.as_deref().unwrap_or("Default output")).show_ui(ui, |ui| {
+                        if ui.selectable_label(self.output_device.is_none(), "Default output").clicked() { pick = Some(None); }
+                        for name in &self.output_devices {
+                            if ui.selectable_label(self.output_device.as_ref() == Some(name), name).clicked() { pick = Some(Some(name.clone())); }
+                        }
+                    });
+                    // list the devices afresh each time the menu opens, so newly plugged ones show up
+                    if combo.response.clicked() {
+                        self.output_devices = cpal::default_host().output_devices()
+                            .map(|devs| devs.filter_map(|d| d.name().ok()).collect()).unwrap_or_default();
+                    }
+                    if let Some(name) = pick && name != self.output_device { self.set_output_device(name); }
+                    if !self.output_status.is_empty() { ui.label(&self.output_status); }
+                });
 
                 let current_cursor = self.controls.cursor.load(Ordering::Relaxed);
                 let sample_div = (sample_rate as f32 * channels as f32).max(1.0);
                 
                 ui.add_space(10.0);
-                ui.label(&file_path);
-                ui.label(format!("{:.2}s : {:.2}s", current_cursor as f32 / sample_div, total_samples as f32 / sample_div));
+                let (tracks, track) = { let s = self.state.lock().unwrap(); (s.audio_tracks.clone(), s.audio_track) };
+                let location_status = self.state.lock().unwrap().location_status.clone();
+                ui.horizontal(|ui| {
+                    ui.label(&file_path);
+                    if !location_status.is_empty() { ui.spinner(); ui.label(&location_status); }
+                    if tracks.len() < 2 { return; }
+                    let mut pick = None;
+                    let current = tracks.iter().find(|t| Some(t.0) == track).map_or("", |t| t.1.as_str());
+                    egui::ComboBox::from_id_source("audio_track").selected_text(current).show_ui(ui, |ui| {
+                        for (id, label) in &tracks {
+                            if ui.selectable_label(Some(*id) == track, label).clicked() { pick = Some(*id); }
+                        }
+                    }).response.on_hover_text("Audio track");
+                    if let Some(id) = pick && Some(id) != track { self.switch_audio_track(id); }
+                });
+                ui.horizontal(|ui| {
+                    ui.checkbox(&mut self.spectral_colors, "Spectral colors");
+                    ui.checkbox(&mut self.split_channels, "Split channels").on_hover_text("Draw each channel in its own lane, to see where a part is panned");
+                    ui.checkbox(&mut self.show_spectrogram, "Spectrogram (S)").on_hover_text("Frequency over time instead of the waveform, to spot notes and sections");
+                    ui.checkbox(&mut self.show_pitch, "Pitch").on_hover_text(format!("Track the notes of a single line through the loop (up to {:.0} s) and draw them over the waveform", PITCH_TRACK_MAX_SECS));
+                });
+                let (tempo, key) = { let s = self.state.lock().unwrap(); (s.tempo, s.key) };
+                let mut bpm = tempo.map(|t| format!("  ·  {:.1} bpm", t.bpm)).unwrap_or_default();
+                if let Some(k) = key {
+                    // the key as heard, once the pitch (or varispeed) moves it a semitone or more
+                    let shift = self.heard_shift().round() as i32;
+                    bpm.push_str(&format!("  ·  {}", k.full_name()));
+                    if shift != 0 { bpm.push_str(&format!(" → {} ({:+} st)", k.transposed(shift).full_name(), shift)); }
+                }
+                ui.horizontal(|ui| {
+                    ui.label(format!("{:.2}s : {:.2}s{}", current_cursor as f32 / sample_div, total_samples as f32 / sample_div, bpm));
+                    ui.add_space(10.0);
+                    let edit = ui.add(egui::TextEdit::singleline(&mut self.goto_text).desired_width(80.0).hint_text("go to")
+                        .text_color_opt(self.goto_bad.then_some(egui::Color32::LIGHT_RED)))
+                        .on_hover_text("Type a position as mm:ss.mmm or seconds and press Enter");
+                    if edit.changed() { self.goto_bad = false; }
+                    let nudge = ui.add(egui::DragValue::new(&mut self.nudge_secs).range(0.01..=60.0).speed(0.1).prefix("← → ").suffix(" s"))
+                        .on_hover_text("How far the arrow keys seek (Shift: 1 s, Alt: 50 ms)");
+                    if nudge.changed() {
+                        let _ = std::fs::create_dir_all(config_dir());
+                        let _ = std::fs::write(config_dir().join("nudge.txt"), self.nudge_secs.to_string());
+                    }
+                    if edit.lost_focus() && ui.input(|i| i.key_pressed(egui::Key::Enter)) {
+                        match parse_timestamp(&self.goto_text).filter(|t| t.is_finite() && *t >= 0.0) {
+                            Some(t) => {
+                                // whole frames, so the cursor stays on the first channel
+                                let frame = (t * sample_rate as f64).round() as usize;
+                                let pos = (frame * channels.max(1)).min(total_samples.saturating_sub(channels.max(1)));
+                                self.seek_history.jump(&self.controls, pos);
+                                self.goto_text.clear();
+                            }
+                            None => self.goto_bad = true,
+                        }
+                    }
+                });
+                {
+                    let now = current_cursor as f64 / sample_div as f64;
+                    let s = self.state.lock().unwrap();
+                    if let Some(cue) = s.subtitles.iter().find(|c| c.start <= now && now < c.end) {
+                        ui.label(egui::RichText::new(&cue.text).size(16.0).strong());
+                    }
+                }
 
                 let full_width = ui.available_width();
-                let (rect, response) = ui.allocate_at_least(egui::vec2(full_width, 100.0), egui::Sense::click_and_drag());
+                let (rect, response) = ui.allocate_at_least(egui::vec2(full_width, if self.split_channels { 160.0 } else { 100.0 }), egui::Sense::click_and_drag());
                 
                 let mut l_start = self.controls.loop_start.load(Ordering::Relaxed);
                 let mut l_end = self.controls.loop_end.load(Ordering::Relaxed);
                 let total = total_samples.max(1);
 
-                let start_x = rect.left() + (l_start as f32 / total as f32) * rect.width();
-                let end_x = rect.left() + (l_end as f32 / total as f32) * rect.width();
-
-                if response.drag_started() || response.clicked() {
-                    self.controls.is_seeking.store(true, Ordering::Relaxed);
+                // scroll wheel or pinch zooms around the pointer, sideways scrolling pans
+                if response.hovered() && let Some(pointer) = response.hover_pos() {
+                    let (scroll, pinch) = ui.input(|i| (i.smooth_scroll_delta, i.zoom_delta()));
+                    let factor = (scroll.y as f64 / 200.0).exp() * pinch as f64;
+                    let min_span = (256 * channels.max(1)) as f64 / total as f64;
+                    if factor != 1.0 { self.zoom_wave(factor, ((pointer.x - rect.left()) / rect.width()).clamp(0.0, 1.0) as f64, min_span); }
+                    if scroll.x != 0.0 { self.pan_wave(-scroll.x as f64 / rect.width() as f64); }
+                    // the panel's own scroll area shouldn't move as well
+                    if scroll != egui::Vec2::ZERO { ui.input_mut(|i| i.smooth_scroll_delta = egui::Vec2::ZERO); }
                 }
+                // while playing, turn the page when the cursor runs off the zoomed view
+                let (v0, v1) = self.wave_view;
+                let cursor_frac = current_cursor as f64 / total as f64;
+                if v1 - v0 < 1.0 && self.controls.is_playing.load(Ordering::Relaxed) && !response.dragged() && (cursor_frac < v0 || cursor_frac > v1) {
+                    let span = v1 - v0;
+                    let start = cursor_frac.clamp(0.0, 1.0 - span);
+                    self.wave_view = (start, start + span);
+                }
+                let view_from = (self.wave_view.0 * total as f64) as usize;
+                let view_to = ((self.wave_view.1 * total as f64) as usize).clamp(view_from + 1, total);
+                let view_len = (view_to - view_from) as f32;
+                let to_x = |i: usize| rect.left() + ((i as f32 - view_from as f32) / view_len) * rect.width();
+                let to_sample = |x: f32| view_from + (((x - rect.left()) / rect.width()).clamp(0.0, 1.0) * view_len) as usize;
+                let painter = ui.painter_at(rect);
+
+                let start_x = to_x(l_start);
+                let end_x = to_x(l_end);
+
+                // pulls a pointer position onto the nearest beat or bar of the tempo grid, or else
+                // onto a nearby transient when snapping is on
+                let tempo = self.state.lock().unwrap().tempo;
+                let beat = tempo.map(|g| (g.downbeat as f64, g.beat_samples(sample_rate, channels)));
+                let snap_px = 8.0 * view_len / rect.width();
+                let snap = |val: usize| -> usize {
+                    if let Some((downbeat, beat)) = beat && self.snap_grid > 0 {
+                        let step = beat * self.snap_grid as f64;
+                        let at = downbeat + ((val as f64 - downbeat) / step).round() * step;
+                        return (at.max(0.0) as usize).min(total.saturating_sub(1));
+                    }
+                    if !self.snap_transients { return val; }
+                    let i = onsets.partition_point(|&o| o < val);
+                    [i.checked_sub(1), Some(i)].into_iter().flatten()
+                        .filter_map(|k| onsets.get(k).copied())
+                        .min_by_key(|&o| o.abs_diff(val))
+                        .filter(|&o| (o.abs_diff(val) as f32) < snap_px)
+                        .unwrap_or(val)
+                };
 
                 if let Some(pointer) = response.interact_pointer_pos() {
                     let is_near_start = (pointer.x - start_x).abs() < 12.0;
@@ -325,15 +7838,15 @@ impl eframe::App for PlayerApp {
                         else if is_near_end { self.dragging_marker = Some(false); }
                         else {
                             self.dragging_marker = None;
-                            let val = (((pointer.x - rect.left()) / rect.width()).clamp(0.0, 1.0) * total as f32) as usize;
-                            self.controls.cursor.store(val - (val % channels.max(1)), Ordering::Relaxed);
+                            let val = snap(to_sample(pointer.x));
+                            self.seek_history.jump(&self.controls, val - (val % channels.max(1)));
                         }
                     }
                 }
 
                 if response.dragged() {
                     if let Some(pointer) = response.interact_pointer_pos() {
-                        let val = (((pointer.x - rect.left()) / rect.width()).clamp(0.0, 1.0) * total as f32) as usize;
+                        let val = snap(to_sample(pointer.x));
                         let val = val - (val % channels.max(1));
                         
                         // ctl-drag loop markers
@@ -356,88 +7869,714 @@ impl eframe::App for PlayerApp {
                     }
                 }
 
-                if response.drag_stopped() || response.clicked() {
-                    self.controls.is_seeking.store(false, Ordering::Relaxed);
+                if response.secondary_clicked() && self.cue_output.is_some()
+                    && let Some(pointer) = response.interact_pointer_pos() {
+                    let val = snap(to_sample(pointer.x));
+                    let val = val - (val % channels.max(1));
+                    self.controls.cue_cursor.store(val, Ordering::Relaxed);
+                    // a cue point outside the cue region drops the region
+                    if val < self.controls.cue_start.load(Ordering::Relaxed) || val >= self.controls.cue_end.load(Ordering::Relaxed) {
+                        self.controls.cue_start.store(val, Ordering::Relaxed);
+                        self.controls.cue_end.store(val, Ordering::Relaxed);
+                    }
+                }
+
+                if self.snap_zero && response.drag_stopped() && let Some(start_marker) = self.dragging_marker {
+                    // a ctrl-drag moved the whole region, so both ends need it
+                    let both = ctx.input(|i| i.modifiers.command);
+                    self.snap_markers_to_zero(start_marker || both, !start_marker || both);
+                }
+
+                if self.audition_edges && (response.dragged() || response.drag_stopped()) && let Some(start_marker) = self.dragging_marker {
+                    self.audition_edge(start_marker, response.drag_stopped());
+                }
+
+                if (response.drag_stopped() || response.clicked()) && self.dragging_marker.is_none() && self.controls.is_playing.load(Ordering::Relaxed) {
+                    self.arm_dictation();
                 }
 
-                ui.painter().rect_filled(rect, 2.0, egui::Color32::from_rgb(10, 10, 10));
+                painter.rect_filled(rect, 2.0, egui::Color32::from_rgb(10, 10, 10));
                 if l_start > 0 || l_end < total_samples {
                     let loop_rect = egui::Rect::from_x_y_ranges(start_x..=end_x, rect.top()..=rect.bottom());
-                    ui.painter().rect_filled(loop_rect, 0.0, egui::Color32::from_rgba_unmultiplied(0, 255, 0, 30));
+                    painter.rect_filled(loop_rect, 0.0, egui::Color32::from_rgba_unmultiplied(0, 255, 0, 30));
+                }
+                for &(c_start, c_end) in &clipped {
+                    let x0 = to_x(c_start);
+                    let x1 = to_x(c_end).max(x0 + 1.0);
+                    let clip_rect = egui::Rect::from_x_y_ranges(x0..=x1, rect.top()..=rect.bottom());
+                    painter.rect_filled(clip_rect, 0.0, egui::Color32::from_rgba_unmultiplied(255, 0, 0, 90));
+                }
+
+                // stacked lanes per channel when split, once the file is in memory and has more than one
+                let lanes = self.state.lock().unwrap().wave_mipmaps.len();
+                let split = self.split_channels && lanes > 2 && self.controls.pcm_data.lock().unwrap().len() >= total_samples;
+                let lane_ids: Vec<usize> = if self.show_spectrogram { Vec::new() } else if split { (1..lanes).collect() } else { vec![0] };
+                if self.show_spectrogram {
+                    match self.spectrogram_texture(ui.ctx(), waveform_gen, (view_from, view_to), rect.width() as usize) {
+                        Some((texture, uv)) => { painter.image(texture, rect, uv, egui::Color32::WHITE); }
+                        None => {
+                            let note = if self.controls.pcm_data.lock().unwrap().is_empty() { "The spectrogram needs the file in memory" } else { "Computing spectrogram..." };
+                            painter.text(rect.center(), egui::Align2::CENTER_CENTER, note, egui::FontId::proportional(13.0), egui::Color32::GRAY);
+                        }
+                    }
+                }
+                let lane_h = rect.height() / lane_ids.len() as f32;
+                for (k, &lane) in lane_ids.iter().enumerate() {
+                    let lane_rect = egui::Rect::from_min_size(rect.min + egui::vec2(0.0, k as f32 * lane_h), egui::vec2(rect.width(), lane_h));
+                    if let Some((texture, uv)) = self.waveform_texture(ui.ctx(), waveform_gen, lane, (view_from, view_to), total, rect.width() as usize) {
+                        painter.image(texture, lane_rect, uv, egui::Color32::WHITE);
+                    }
+                    if split {
+                        let name = if lanes == 3 { ["L", "R"][k].to_string() } else { format!("{}", k + 1) };
+                        painter.text(lane_rect.left_top() + egui::vec2(4.0, 2.0), egui::Align2::LEFT_TOP, name, egui::FontId::proportional(11.0), egui::Color32::GRAY);
+                        if k > 0 { painter.hline(rect.x_range(), lane_rect.top(), (1.0, egui::Color32::from_gray(60))); }
+                    }
+                }
+
+                for &onset in &onsets {
+                    let x = to_x(onset);
+                    painter.line_segment([egui::pos2(x, rect.bottom() - 6.0), egui::pos2(x, rect.bottom())], (1.0, egui::Color32::from_rgb(255, 160, 60)));
+                }
+
+                // faint beat lines with brighter bar lines, just the bars when beats would crowd together
+                if let Some((downbeat, beat)) = beat && beat > 0.0 {
+                    let bars_only = beat / view_len as f64 * (rect.width() as f64) < 6.0;
+                    let step = if bars_only { beat * 4.0 } else { beat };
+                    let first = ((view_from as f64 - downbeat) / step).ceil() as i64;
+                    let last = ((view_to as f64 - downbeat) / step).floor() as i64;
+                    for k in first..=last.min(first + 2000) {
+                        let x = to_x((downbeat + k as f64 * step).max(0.0) as usize);
+                        let bar = bars_only || k.rem_euclid(4) == 0;
+                        let color = egui::Color32::from_white_alpha(if bar { 60 } else { 22 });
+                        painter.line_segment([egui::pos2(x, rect.top()), egui::pos2(x, rect.bottom())], (1.0, color));
+                    }
                 }
 
-                if !waveform.is_empty() {
-                    let wave_color = egui::Color32::from_rgb(0, 180, 100);
-                    let bar_width = (rect.width() / waveform.len() as f32).max(1.0);
-                    for (i, &peak) in waveform.iter().enumerate() {
-                        let x = rect.left() + (i as f32 / waveform.len() as f32) * rect.width();
-                        let h = (peak * rect.height() * 0.45).max(1.0);
-                        ui.painter().line_segment([egui::pos2(x, rect.center().y - h), egui::pos2(x, rect.center().y + h)], egui::Stroke::new(bar_width, wave_color));
+                if self.show_pitch {
+                    // as heard: with the pitch (or varispeed) and fine-tune applied
+                    let shift = self.heard_shift();
+                    let hover = response.hover_pos();
+                    match self.pitch_track(waveform_gen) {
+                        Some(points) if !points.is_empty() => {
+                            let lo = points.iter().map(|p| p.1).fold(f32::MAX, f32::min).floor() - 1.0;
+                            let hi = points.iter().map(|p| p.1).fold(f32::MIN, f32::max).ceil() + 1.0;
+                            let to_y = |note: f32| rect.bottom() - (note - lo) / (hi - lo).max(1.0) * rect.height();
+                            // a faint line per semitone, named when there's room
+                            let row_h = rect.height() / (hi - lo).max(1.0);
+                            for n in lo as i32..=hi as i32 {
+                                let y = to_y(n as f32);
+                                painter.hline(rect.x_range(), y, (1.0, egui::Color32::from_white_alpha(if n.rem_euclid(12) == 0 { 40 } else { 12 })));
+                                if row_h >= 9.0 {
+                                    let name = note_name((n as f32 + shift).round() as i32);
+                                    painter.text(egui::pos2(rect.right() - 2.0, y), egui::Align2::RIGHT_CENTER, name, egui::FontId::proportional(9.0), egui::Color32::from_gray(120));
+                                }
+                            }
+                            let color = egui::Color32::from_rgb(255, 220, 60);
+                            let gap = (sample_rate as usize / 30) * channels.max(1); // voiced frames further apart than this don't join up
+                            for pair in points.windows(2) {
+                                let ((a, na), (b, nb)) = (pair[0], pair[1]);
+                                if b - a > gap || (nb - na).abs() > 3.0 || b < view_from || a > view_to { continue; }
+                                painter.line_segment([egui::pos2(to_x(a), to_y(na)), egui::pos2(to_x(b), to_y(nb))], (2.0, color));
+                            }
+                            if let Some(pointer) = hover {
+                                let at = to_sample(pointer.x);
+                                let i = points.partition_point(|p| p.0 < at);
+                                let near = [i.checked_sub(1), Some(i)].into_iter().flatten().filter_map(|k| points.get(k)).min_by_key(|p| p.0.abs_diff(at));
+                                if let Some(&(pos, note)) = near && pos.abs_diff(at) < gap {
+                                    let heard = note + shift;
+                                    let cents = ((heard - heard.round()) * 100.0).round();
+                                    let label = format!("{} {:+.0} ct", note_name(heard.round() as i32), cents);
+                                    painter.text(egui::pos2(pointer.x + 8.0, to_y(note) - 8.0), egui::Align2::LEFT_BOTTOM, label, egui::FontId::proportional(12.0), color);
+                                }
+                            }
+                        }
+                        found => {
+                            let note = if found.is_some() { "No clear pitch in the loop" }
+                                else if self.pitch_job.is_some() || self.dragging_marker.is_some() { "Tracking pitch..." }
+                                else if self.controls.pcm_data.lock().unwrap().is_empty() { "Pitch tracking needs the file in memory" }
+                                else { "Loop a shorter passage to track its pitch" };
+                            painter.text(rect.center_bottom() - egui::vec2(0.0, 12.0), egui::Align2::CENTER_CENTER, note, egui::FontId::proportional(12.0), egui::Color32::GRAY);
+                        }
                     }
                 }
 
-                let cur_x = rect.left() + (current_cursor as f32 / total as f32) * rect.width();
-                ui.painter().line_segment([egui::pos2(cur_x, rect.top()), egui::pos2(cur_x, rect.bottom())], (1.5, egui::Color32::WHITE));
-                ui.painter().line_segment([egui::pos2(start_x, rect.top()), egui::pos2(start_x, rect.bottom())], (2.0, egui::Color32::YELLOW));
-                ui.painter().line_segment([egui::pos2(end_x, rect.top()), egui::pos2(end_x, rect.bottom())], (2.0, egui::Color32::from_rgb(50, 80, 255)));
+                for m in &self.markers {
+                    let x = to_x(m.pos);
+                    let color = egui::Color32::from_rgb(200, 120, 255);
+                    painter.line_segment([egui::pos2(x, rect.top()), egui::pos2(x, rect.bottom())], (1.0, color));
+                    painter.text(egui::pos2(x + 3.0, rect.top() + 10.0), egui::Align2::LEFT_TOP, &m.label, egui::FontId::proportional(11.0), color);
+                }
+
+                for &(p_start, _) in &phrases {
+                    let x = to_x(p_start);
+                    painter.line_segment([egui::pos2(x, rect.top()), egui::pos2(x, rect.top() + 8.0)], (1.0, egui::Color32::GRAY));
+                }
+
+                if self.cue_output.is_some() {
+                    let cue_x = to_x(self.controls.cue_cursor.load(Ordering::Relaxed));
+                    painter.line_segment([egui::pos2(cue_x, rect.top()), egui::pos2(cue_x, rect.bottom())], (1.5, egui::Color32::from_rgb(0, 200, 255)));
+                }
+
+                let cur_x = to_x(current_cursor);
+                painter.line_segment([egui::pos2(cur_x, rect.top()), egui::pos2(cur_x, rect.bottom())], (1.5, egui::Color32::WHITE));
+                painter.line_segment([egui::pos2(start_x, rect.top()), egui::pos2(start_x, rect.bottom())], (2.0, egui::Color32::YELLOW));
+                painter.line_segment([egui::pos2(end_x, rect.top()), egui::pos2(end_x, rect.bottom())], (2.0, egui::Color32::from_rgb(50, 80, 255)));
+
+                ui.horizontal(|ui| {
+                    let min_span = (256 * channels.max(1)) as f64 / total as f64;
+                    ui.label("Zoom").on_hover_text("Scroll or pinch over the waveform to zoom, scroll sideways (Shift+wheel) to pan");
+                    if ui.button("-").clicked() { self.zoom_wave(0.5, 0.5, min_span); }
+                    if ui.button("+").clicked() { self.zoom_wave(2.0, 0.5, min_span); }
+                    if ui.button("Fit").clicked() { self.wave_view = (0.0, 1.0); }
+                    if ui.add_enabled(l_end > l_start && (l_start > 0 || l_end < total_samples), egui::Button::new("Loop")).clicked() {
+                        // the loop with a little room either side
+                        let (a, b) = (l_start as f64 / total as f64, l_end as f64 / total as f64);
+                        let pad = ((b - a) * 0.1).max(min_span / 2.0);
+                        let (a, b) = ((a - pad).max(0.0), (b + pad).min(1.0));
+                        self.wave_view = (a, b.max(a + min_span).min(1.0));
+                    }
+                    let span = self.wave_view.1 - self.wave_view.0;
+                    if span < 1.0 {
+                        let mut start = self.wave_view.0;
+                        ui.spacing_mut().slider_width = (full_width - 260.0).max(60.0);
+                        if ui.add(egui::Slider::new(&mut start, 0.0..=1.0 - span).show_value(false)).changed() {
+                            self.wave_view = (start, start + span);
+                        }
+                        ui.label(format!("{:.0}x", 1.0 / span));
+                    }
+                });
 
                 ui.add_space(15.0);
                 ui.spacing_mut().slider_width = full_width - 60.0;
 
-                ui.label("Speed");
+                ui.horizontal(|ui| {
+                    ui.label("Speed");
+                    ui.separator();
+                    let mode = self.playback_mode;
+                    ui.selectable_value(&mut self.playback_mode, PlaybackMode::Stretch, "Stretch").on_hover_text("Change the speed and keep the pitch");
+                    ui.selectable_value(&mut self.playback_mode, PlaybackMode::Varispeed, "Varispeed")
+                        .on_hover_text("Like slowing a record down: the pitch drops with the speed. Light on the CPU too");
+                    if self.playback_mode != mode { let _ = self.tx.send(ParamUpdate::Mode(self.playback_mode)); }
+                    ui.separator();
+                    ui.label("Nudge (hold - / =)");
+                    ui.add(egui::DragValue::new(&mut self.nudge_percent).range(0.5..=20.0).speed(0.1).suffix(" %"));
+                });
                 let mut speed = f32::from_bits(self.controls.speed.load(Ordering::Relaxed));
-                if ui.add(egui::Slider::new(&mut speed, 0.25..=4.0).logarithmic(true).suffix("x")).changed() {
-                    self.controls.speed.store(speed.to_bits(), Ordering::Relaxed);
-                    let _ = self.tx.send(ParamUpdate::Speed(speed));
+                let (lo, hi) = self.profile().speed_range;
+                if ui.add(egui::Slider::new(&mut speed, lo..=hi).logarithmic(hi / lo > 2.0).suffix("x")).changed() {
+                    self.perform(ctx, Action::SetSpeed(speed));
                 }
+                ui.horizontal(|ui| {
+                    for (k, preset) in SPEED_PRESETS.into_iter().enumerate() {
+                        let label = ui.add_enabled((lo..=hi).contains(&preset), egui::SelectableLabel::new((speed - preset).abs() < 1e-3, format!("{:.0}%", preset * 100.0)))
+                            .on_hover_text(format!("Ctrl+{}", k + 1));
+                        if label.clicked() { self.perform(ctx, Action::SetSpeed(preset)); }
+                    }
+                });
+
+                let resample_only = self.playback_mode == PlaybackMode::Varispeed;
 
-                ui.label("Pitch");
                 let mut pitch = f32::from_bits(self.controls.pitch.load(Ordering::Relaxed));
-                if ui.add(egui::Slider::new(&mut pitch, 0.5..=2.0).logarithmic(true).suffix("x")).changed() {
-                    self.controls.pitch.store(pitch.to_bits(), Ordering::Relaxed);
-                    let _ = self.tx.send(ParamUpdate::Pitch(pitch));
+                ui.horizontal(|ui| {
+                    ui.label("Pitch");
+                    ui.separator();
+                    // in varispeed the pitch moves with the speed
+                    let ratio = if resample_only { speed } else { pitch };
+                    self.key_display(ui, ratio);
+                    ui.separator();
+                    ui.selectable_value(&mut self.pitch_semitones, true, "st");
+                    ui.selectable_value(&mut self.pitch_semitones, false, "ratio");
+                });
+                if self.pitch_semitones {
+                    // whole semitones on the slider, the remainder as cents
+                    let st = 12.0 * pitch.log2();
+                    let (mut whole, mut cents) = (st.round() as i32, ((st - st.round()) * 100.0).round());
+                    let changed = ui.horizontal(|ui| {
+                        ui.spacing_mut().slider_width = full_width - 200.0;
+                        let slider = ui.add_enabled(!resample_only, egui::Slider::new(&mut whole, -12..=12).suffix(" st")).changed();
+                        ui.label("fine");
+                        slider | ui.add_enabled(!resample_only, egui::DragValue::new(&mut cents).range(-50.0..=50.0).speed(0.5).suffix(" ct")).changed()
+                    }).inner;
+                    if changed { self.perform(ctx, Action::SetPitch(2f32.powf((whole as f32 + cents / 100.0) / 12.0))); }
+                } else if ui.add_enabled(!resample_only, egui::Slider::new(&mut pitch, 0.5..=2.0).logarithmic(true).suffix("x")).changed() {
+                    self.perform(ctx, Action::SetPitch(pitch));
                 }
+                self.transpose_row(ui, !resample_only);
+                self.tuning_row(ui);
 
-                ui.label("Volume");
-                let mut vol = f32::from_bits(self.controls.volume.load(Ordering::Relaxed));
-                if ui.add(egui::Slider::new(&mut vol, 0.0..=2.0)).changed() {
+                ui.horizontal(|ui| {
+                    ui.label("Volume");
+                    let before = self.channel_matrix;
+                    for (m, label, hint) in [(ChannelMatrix::Stereo, "LR", "Both sides as recorded"), (ChannelMatrix::Left, "L", "Only the left channel, in both ears"),
+                                             (ChannelMatrix::Right, "R", "Only the right channel, in both ears"), (ChannelMatrix::Mono, "Mono", "Both sides summed")] {
+                        ui.selectable_value(&mut self.channel_matrix, m, label).on_hover_text(hint);
+                    }
+                    if self.channel_matrix != before { let _ = self.tx.send(ParamUpdate::Channels(self.channel_matrix)); }
+                    ui.separator();
+                    if ui.checkbox(&mut self.loudness_comp, "Loudness compensation").on_hover_text("Boosts bass and treble as the volume goes down, so quiet listening keeps the low end").changed() {
+                        let _ = self.tx.send(ParamUpdate::LoudnessCompensation(self.loudness_comp));
+                    }
+                    if ui.checkbox(&mut self.limiter, "Limiter").on_hover_text("Bends peaks over -1 dBFS under full scale instead of letting them clip, when the volume is boosted or a pitch shift adds peaks").changed() {
+                        let _ = self.tx.send(ParamUpdate::Limiter(self.limiter));
+                    }
+                    let reduction = f32::from_bits(self.controls.limiter_reduction.swap(0, Ordering::Relaxed));
+                    self.limiter_shown = reduction.min(self.limiter_shown * 0.95);
+                    if self.limiter && self.limiter_shown < -0.1 { ui.label(format!("{:.1} dB", self.limiter_shown)); }
+                });
+                // a fader in dB, the bottom of it is silence
+                let vol = f32::from_bits(self.controls.volume.load(Ordering::Relaxed));
+                let mut db = if vol > 0.0 { gain_to_db(vol).max(VOLUME_FLOOR_DB) } else { VOLUME_FLOOR_DB };
+                let fader = egui::Slider::new(&mut db, VOLUME_FLOOR_DB..=6.0).step_by(0.1)
+                    .custom_formatter(|v, _| if v <= VOLUME_FLOOR_DB as f64 { "-inf dB".into() } else { format!("{:+.1} dB", v) });
+                let fader = ui.add(fader);
+                if fader.double_clicked() { db = 0.0; }
+                if fader.changed() || fader.double_clicked() {
+                    let vol = if db <= VOLUME_FLOOR_DB { 0.0 } else { 10f32.powf(db / 20.0) };
                     self.controls.volume.store(vol.to_bits(), Ordering::Relaxed);
                 }
+                self.level_meters(ui, full_width - 60.0);
 
                 ui.add_space(10.0);
 
                 ui.horizontal(|ui| {
                     let is_p = self.controls.is_playing.load(Ordering::Relaxed);
-                    if ui.button(if is_p { "Pause" } else { "Play" }).clicked() { self.controls.is_playing.store(!is_p, Ordering::Relaxed); }
-                    if ui.button("Reset").clicked() {
-                        self.controls.speed.store(1.0f32.to_bits(), Ordering::Relaxed);
-                        self.controls.pitch.store(1.0f32.to_bits(), Ordering::Relaxed);
-                        let _ = self.tx.send(ParamUpdate::Speed(1.0));
-                        let _ = self.tx.send(ParamUpdate::Pitch(1.0));
-                    }
+                    if ui.button(if is_p { "Pause" } else { "Play" }).clicked() { self.perform(ctx, Action::TogglePlay); }
+                    let h = &self.seek_history;
+                    let (can_back, can_forward) = (!h.back.is_empty(), !h.forward.is_empty());
+                    if ui.add_enabled(can_back, egui::Button::new("<")).on_hover_text("Back to where you jumped from (mouse back button, Alt+Shift+Left)").clicked() { self.perform(ctx, Action::SeekBack); }
+                    if ui.add_enabled(can_forward, egui::Button::new(">")).on_hover_text("Forward again (mouse forward button, Alt+Shift+Right)").clicked() { self.perform(ctx, Action::SeekForward); }
+                    if ui.button("Reset").clicked() { self.perform(ctx, Action::ResetSpeedPitch); }
+                    let has_tempo = self.state.lock().unwrap().tempo.is_some();
+                    ui.add_enabled(has_tempo, egui::Checkbox::new(&mut self.count_in, "Count-in"))
+                        .on_hover_text("A bar of clicks at the tempo before playing from the loop start")
+                        .on_disabled_hover_text("Needs a tempo (Tempo panel)");
                     ui.separator();
-                    if ui.button("[ Set Start").clicked() { self.controls.loop_start.store(current_cursor, Ordering::Relaxed); }
-                    if ui.button("] Set End").clicked() { self.controls.loop_end.store(current_cursor, Ordering::Relaxed); }
-                    if ui.button("Clear Loop").clicked() { 
-                        self.controls.loop_start.store(0, Ordering::Relaxed); 
-                        self.controls.loop_end.store(total_samples, Ordering::Relaxed); 
-                    }
+                    if ui.button("[ Set Start").clicked() { self.perform(ctx, Action::LoopStartAtCursor); }
+                    if ui.button("] Set End").clicked() { self.perform(ctx, Action::LoopEndAtCursor); }
+                    if ui.button("Clear Loop").clicked() { self.perform(ctx, Action::ClearLoop); }
                     ui.separator();
                     ui.label(format!("Loop: {:.2}s - {:.2}s", l_start as f32 / sample_div, l_end as f32 / sample_div));
+                    ui.label(format!("x{}", self.loop_count)).on_hover_text("Times the loop has repeated");
+                    ui.checkbox(&mut self.stop_after_enabled, "stop after");
+                    ui.add_enabled(self.stop_after_enabled, egui::DragValue::new(&mut self.stop_after).range(1..=99));
+                    let mut xfade = f32::from_bits(self.controls.loop_xfade_ms.load(Ordering::Relaxed));
+                    if ui.add(egui::DragValue::new(&mut xfade).range(0.0..=50.0).speed(0.2).prefix("xfade ").suffix(" ms"))
+                        .on_hover_text("Crossfade where the loop wraps, so tight loops don't click").changed() {
+                        self.controls.loop_xfade_ms.store(xfade.to_bits(), Ordering::Relaxed);
+                    }
+                    ui.checkbox(&mut self.snap_transients, "Snap to transients");
+                    ui.checkbox(&mut self.snap_zero, "Zero crossings").on_hover_text("On release, move loop markers to the nearest zero crossing so the loop doesn't click");
+                    egui::ComboBox::from_id_source("snap_grid").width(70.0)
+                        .selected_text(match self.snap_grid { 1 => "beats", 4 => "bars", _ => "no grid" })
+                        .show_ui(ui, |ui| {
+                            ui.selectable_value(&mut self.snap_grid, 0, "no grid");
+                            ui.selectable_value(&mut self.snap_grid, 1, "beats");
+                            ui.selectable_value(&mut self.snap_grid, 4, "bars");
+                        }).response.on_hover_text("Snap seeking and loop markers to the tempo grid (set in the Tempo panel)");
+                    ui.checkbox(&mut self.audition_edges, "Audition edges").on_hover_text("Play the audio leading into the start marker / following the end marker while dragging it");
                 });
-            });
+
+                for &panel in self.profile().panels {
+                    match panel {
+                        Panel::Phrases => {
+                            ui.horizontal(|ui| {
+                                ui.label("Phrase");
+                                if ui.button("P Prev").clicked() { self.perform(ctx, Action::PrevPhrase); }
+                                if ui.button("N Next").clicked() { self.perform(ctx, Action::NextPhrase); }
+                                if ui.button("L Loop Phrase").clicked() { self.perform(ctx, Action::LoopPhrase); }
+                            });
+                        }
+                        Panel::Dictation => {
+                            ui.horizontal(|ui| {
+                                let mut changed = ui.checkbox(&mut self.dictation.enabled, "Dictation: pause").changed();
+                                changed |= ui.radio_value(&mut self.dictation.at_phrases, true, "at each phrase").changed();
+                                changed |= ui.radio_value(&mut self.dictation.at_phrases, false, "every").changed();
+                                changed |= ui.add(egui::DragValue::new(&mut self.dictation.interval_secs).range(1.0..=60.0).suffix(" s")).changed();
+                                if changed && self.controls.is_playing.load(Ordering::Relaxed) { self.arm_dictation(); }
+                                ui.label("(Space resumes)");
+                            });
+                        }
+                        Panel::Stretcher => self.stretcher_panel(ui),
+                        Panel::Automation => self.automation_panel(ui, total_samples),
+                        Panel::Ab => self.ab_panel(ui),
+                        Panel::Macros => self.macro_panel(ui, ctx),
+                        Panel::Edit => self.edit_panel(ui),
+                        Panel::Take => self.take_panel(ui),
+                        Panel::Monitor => self.monitor_panel(ui),
+                        Panel::SingAlong => self.sing_along_panel(ui),
+                        Panel::Suggestions => self.suggestion_panel(ui, sample_rate, channels),
+                        Panel::Tempo => self.tempo_panel(ui),
+                        Panel::Meter => self.meter_panel(ui),
+                        Panel::SecondOutput => self.second_output_panel(ui),
+                        Panel::Cue => self.cue_panel(ui, sample_rate, channels),
+                        Panel::Stems => self.stem_panel(ui),
+                        Panel::Denoise => self.denoise_panel(ui),
+                        Panel::Leveler => self.leveler_panel(ui),
+                        Panel::BandSolo => self.band_solo_panel(ui),
+                        Panel::Karaoke => self.karaoke_panel(ui),
+                        Panel::Eq => self.eq_panel(ui),
+                        Panel::Effects => self.effects_panel(ui),
+                        Panel::Tone => self.tone_panel(ui),
+                        Panel::Drone => self.drone_panel(ui),
+                        Panel::Setlist => self.setlist_panel(ui),
+                        Panel::Playlist => self.playlist_panel(ui),
+                        Panel::Podcasts => self.podcast_panel(ui),
+                        Panel::Subtitles => self.subtitle_panel(ui, sample_rate, channels),
+                        Panel::System => self.system_panel(ui),
+                        Panel::Sync => self.sync_panel(ui),
+                        Panel::Trainer => self.trainer_panel(ui),
+                        Panel::Extensions => self.extension_panels(ui, ctx),
+                    }
+                }
+            }));
         });
         ctx.request_repaint();
     }
 }
 
+// offline version of the playback path: each channel through its own stretcher, aligned to the file start; progress gets 0..1 as it goes
+fn render_stretched(pcm: &[f32], channels: usize, sample_rate: u32, speed: f32, semitones: f32, progress: &dyn Fn(f32)) -> Vec<f32> {
+    const CHUNK: usize = 1 << 16;
+    let frames = pcm.len() / channels;
+    let out_frames = (frames as f64 / speed as f64).round() as usize;
+    let mut out = vec![0.0f32; out_frames * channels];
+    let mut mono = vec![0.0f32; out_frames];
+    for (ch, st) in StretchPreset::for_speed(speed).build(channels, sample_rate).iter_mut().enumerate() {
+        st.set_transpose_factor_semitones(semitones, None);
+        let input: Vec<f32> = pcm.iter().skip(ch).step_by(channels).copied().collect();
+        // pre-roll the input latency, and leave room at the end for the output latency to flush
+        let lat_in = st.input_latency().min(input.len());
+        st.seek(&input[..lat_in], speed as f64);
+        let body = out_frames.saturating_sub(st.output_latency());
+        let rest = &input[lat_in..];
+        let mut out_pos = 0;
+        for (k, chunk) in rest.chunks(CHUNK).enumerate() {
+            let in_end = k * CHUNK + chunk.len();
+            let out_end = (in_end as f64 / rest.len() as f64 * body as f64).round() as usize;
+            st.process(chunk, &mut mono[out_pos..out_end]);
+            out_pos = out_end;
+            progress((ch as f32 + in_end as f32 / rest.len() as f32) / channels as f32);
+        }
+        st.flush(&mut mono[body..]);
+        for (i, v) in mono.iter().enumerate() { out[i * channels + ch] = *v; }
+    }
+    out
+}
+
+// 16-bit PCM, the most widely readable choice for handing files to students
+fn write_wav(path: &Path, pcm: &[f32], sample_rate: u32, channels: usize) -> std::io::Result<()> {
+    let data_len = (pcm.len() * 2) as u32;
+    let mut bytes = Vec::with_capacity(44 + pcm.len() * 2);
+    bytes.extend_from_slice(b"RIFF");
+    bytes.extend_from_slice(&(36 + data_len).to_le_bytes());
+    bytes.extend_from_slice(b"WAVEfmt ");
+    bytes.extend_from_slice(&16u32.to_le_bytes());
+    bytes.extend_from_slice(&1u16.to_le_bytes());
+    bytes.extend_from_slice(&(channels as u16).to_le_bytes());
+    bytes.extend_from_slice(&sample_rate.to_le_bytes());
+    bytes.extend_from_slice(&(sample_rate * channels as u32 * 2).to_le_bytes());
+    bytes.extend_from_slice(&(channels as u16 * 2).to_le_bytes());
+    bytes.extend_from_slice(&16u16.to_le_bytes());
+    bytes.extend_from_slice(b"data");
+    bytes.extend_from_slice(&data_len.to_le_bytes());
+    for v in pcm { bytes.extend_from_slice(&((v.clamp(-1.0, 1.0) * 32767.0).round() as i16).to_le_bytes()); }
+    std::fs::write(path, bytes)
+}
+
+// runs of a pitch curve on one semitone as (start, end, midi note), in interleaved samples; a run
+// stays on its note until the curve is most of a semitone away, so vibrato doesn't chop it up, and
+// breaks where the curve goes unvoiced for longer than `gap`
+fn curve_to_notes(curve: &[(usize, f32)], shift: f32, hop: usize, gap: usize, min_len: usize) -> Vec<(usize, usize, u8)> {
+    let mut notes = Vec::new();
+    let mut run: Option<(usize, usize, f32)> = None; // start, last point, note
+    for &(pos, midi) in curve {
+        let midi = midi + shift;
+        if let Some((start, last, note)) = run {
+            if pos - last <= gap && (midi - note).abs() < 0.7 {
+                run = Some((start, pos, note));
+                continue;
+            }
+            notes.push((start, last + hop, note));
+        }
+        run = Some((pos, pos, midi.round()));
+    }
+    if let Some((start, last, note)) = run { notes.push((start, last + hop, note)); }
+    notes.into_iter()
+        .filter(|&(a, b, note)| b - a >= min_len && (0.0..128.0).contains(&note))
+        .map(|(a, b, note)| (a, b, note as u8))
+        .collect()
+}
+
+// a format 0 standard MIDI file: one track with the tempo and the notes, given as
+// (start tick, end tick, note) at `ppq` ticks per quarter note
+fn write_midi(path: &Path, notes: &[(u32, u32, u8)], bpm: f32, ppq: u16) -> std::io::Result<()> {
+    let vlq = |out: &mut Vec<u8>, mut v: u32| {
+        let mut bytes = vec![(v & 0x7f) as u8];
+        v >>= 7;
+        while v > 0 {
+            bytes.push((v & 0x7f) as u8 | 0x80);
+            v >>= 7;
+        }
+        out.extend(bytes.iter().rev());
+    };
+    // note-offs sort ahead of note-ons at the same tick, so repeated notes retrigger
+    let mut events: Vec<(u32, u8, u8)> = notes.iter().flat_map(|&(a, b, n)| [(a, 0x90, n), (b, 0x80, n)]).collect();
+    events.sort_by_key(|&(t, kind, _)| (t, kind == 0x90));
+
+    let mut track = Vec::new();
+    let tempo = (60_000_000.0 / bpm.max(1.0)) as u32;
+    track.extend_from_slice(&[0x00, 0xff, 0x51, 0x03]);
+    track.extend_from_slice(&tempo.to_be_bytes()[1..]);
+    let mut now = 0;
+    for (t, kind, note) in events {
+        vlq(&mut track, t - now);
+        now = t;
+        track.extend_from_slice(&[kind, note, if kind == 0x90 { 100 } else { 0 }]);
+    }
+    track.extend_from_slice(&[0x00, 0xff, 0x2f, 0x00]);
+
+    let mut bytes = Vec::with_capacity(22 + track.len());
+    bytes.extend_from_slice(b"MThd");
+    bytes.extend_from_slice(&6u32.to_be_bytes());
+    bytes.extend_from_slice(&0u16.to_be_bytes());
+    bytes.extend_from_slice(&1u16.to_be_bytes());
+    bytes.extend_from_slice(&ppq.to_be_bytes());
+    bytes.extend_from_slice(b"MTrk");
+    bytes.extend_from_slice(&(track.len() as u32).to_be_bytes());
+    bytes.extend_from_slice(&track);
+    std::fs::write(path, bytes)
+}
+
+// teacher/student sync: the leader broadcasts its transport over UDP and followers copy it
+const SYNC_PORT: u16 = 47474;
+
+#[derive(Clone, Copy, PartialEq)]
+enum SyncMode {
+    Off,
+    Lead,
+    Follow,
+}
+
+struct SyncState {
+    playing: bool,
+    position: f64, // seconds
+    loop_range: Option<(f64, f64)>,
+    speed: f32,
+    pitch: f32,
+    file_name: String,
+}
+
+// "reh1 playing position loop_start loop_end speed pitch file name", loop_end -1 when there's no loop
+fn format_sync(st: &SyncState) -> String {
+    let (a, b) = st.loop_range.unwrap_or((0.0, -1.0));
+    format!("reh1 {} {:.3} {:.3} {:.3} {} {} {}", st.playing as u8, st.position, a, b, st.speed, st.pitch, st.file_name)
+}
+
+fn parse_sync(text: &str) -> Option<SyncState> {
+    let mut f = text.splitn(8, ' ');
+    if f.next()? != "reh1" { return None; }
+    let playing = f.next()? == "1";
+    let position = f.next()?.parse().ok()?;
+    let (a, b): (f64, f64) = (f.next()?.parse().ok()?, f.next()?.parse().ok()?);
+    let speed = f.next()?.parse().ok()?;
+    let pitch = f.next()?.parse().ok()?;
+    let file_name = f.next().unwrap_or("").to_string();
+    Some(SyncState { playing, position, loop_range: (b >= 0.0).then_some((a, b)), speed, pitch, file_name })
+}
+
+struct LanSync {
+    mode: SyncMode,
+    socket: Option<std::net::UdpSocket>,
+    target: String, // leader only: where to send, blank for the whole LAN
+    last_sent: std::time::Instant,
+    last_heard: Option<std::time::Instant>,
+    status: String,
+}
+
+// commands a running instance takes over its control socket, one per line
+enum RemoteCommand {
+    Open(PathBuf),
+    Seek(f64), // seconds
+    Loop(f64, f64),
+    Action(Action),
+}
+
+fn parse_remote_command(line: &str) -> Result<RemoteCommand, String> {
+    let line = line.trim();
+    let (verb, rest) = line.split_once(' ').unwrap_or((line, ""));
+    let num = |t: &str| t.trim().trim_end_matches('s').parse::<f64>().map_err(|_| format!("expected seconds, got '{}'", t.trim()));
+    match verb {
+        "open" if !rest.trim().is_empty() => Ok(RemoteCommand::Open(PathBuf::from(rest.trim()))),
+        "seek" => Ok(RemoteCommand::Seek(num(rest)?)),
+        "loop" => {
+            let (a, b) = rest.trim().split_once(' ').ok_or("usage: loop <start> <end>")?;
+            Ok(RemoteCommand::Loop(num(a)?, num(b)?))
+        }
+        _ => Action::parse(line).map(RemoteCommand::Action).ok_or(format!("unknown command '{line}'")),
+    }
+}
+
+#[cfg(unix)]
+fn control_socket_path() -> PathBuf {
+    cache_dir().join("reh.sock")
+}
+
+// lets scripts drive the app: `reh remote "open song.mp3" "loop 30 45" "speed 70%" play`. There's no
+// scripting dictionary (sdef) or App Intents, so AppleScript and Shortcuts only reach it through a shell
+// step (do shell script, Run Shell Script).
+#[cfg(unix)]
+fn start_control_server(tx: Sender<RemoteCommand>) {
+    let path = control_socket_path();
+    let _ = std::fs::create_dir_all(cache_dir());
+    // a socket left by a crashed instance would block the bind; a live one answers, so leave that alone
+    if std::os::unix::net::UnixStream::connect(&path).is_ok() { return; }
+    let _ = std::fs::remove_file(&path);
+    let Ok(listener) = std::os::unix::net::UnixListener::bind(&path) else { return };
+    thread::spawn(move || {
+        for stream in listener.incoming().flatten() {
+            // a thread per client, so one that connects and goes quiet can't hold up the others
+            let tx = tx.clone();
+            thread::spawn(move || serve_remote_client(stream, &tx));
+        }
+    });
+}
+
+// a client that sends nothing for this long is dropped
+#[cfg(unix)]
+const REMOTE_IDLE_SECS: u64 = 30;
+
+#[cfg(unix)]
+fn serve_remote_client(stream: std::os::unix::net::UnixStream, tx: &Sender<RemoteCommand>) {
+    use std::io::{BufRead, Write};
+    if stream.set_read_timeout(Some(std::time::Duration::from_secs(REMOTE_IDLE_SECS))).is_err() { return; }
+    let Ok(reader) = stream.try_clone() else { return };
+    let mut stream = stream;
+    // a timed-out read ends the lines like a hang-up does
+    for line in std::io::BufReader::new(reader).lines().map_while(Result::ok) {
+        let reply = match parse_remote_command(&line) {
+            Ok(cmd) => { let _ = tx.send(cmd); "ok".to_string() }
+            Err(e) => format!("error: {e}"),
+        };
+        if writeln!(stream, "{reply}").is_err() { break; }
+    }
+}
+
+#[cfg(unix)]
+fn run_remote(commands: &[String]) -> Result<(), String> {
+    use std::io::{BufRead, Write};
+    if commands.is_empty() { return Err("usage: reh remote <command>... (open <file>, play, pause, speed 70%, seek <s>, loop <start> <end>)".into()); }
+    let stream = std::os::unix::net::UnixStream::connect(control_socket_path()).map_err(|_| "Reh isn't running")?;
+    let mut reader = std::io::BufReader::new(stream.try_clone().map_err(|e| e.to_string())?);
+    let mut stream = stream;
+    let mut failed = false;
+    for cmd in commands {
+        // the app has its own working directory, so send paths absolute
+        let cmd = match cmd.strip_prefix("open ") {
+            Some(p) => format!("open {}", std::path::absolute(p.trim()).map_err(|e| e.to_string())?.display()),
+            None => cmd.clone(),
+        };
+        writeln!(stream, "{cmd}").map_err(|e| e.to_string())?;
+        let mut reply = String::new();
+        reader.read_line(&mut reply).map_err(|e| e.to_string())?;
+        if reply.trim() != "ok" { eprintln!("{cmd}: {}", reply.trim()); failed = true; }
+    }
+    if failed { Err("some commands failed".into()) } else { Ok(()) }
+}
+
+#[cfg(not(unix))]
+fn run_remote(_commands: &[String]) -> Result<(), String> {
+    Err("reh remote isn't available on this platform yet".into())
+}
+
+// reh batch --speed 0.8 --pitch -2 --out dir/ <files or folders>
+fn run_batch(args: &[String]) -> Result<(), String> {
+    let mut speed = 1.0f32;
+    let mut semitones = 0.0f32;
+    let mut out_dir: Option<PathBuf> = None;
+    let mut inputs = Vec::new();
+    let mut it = args.iter();
+    while let Some(arg) = it.next() {
+        let mut value = |name: &str| it.next().ok_or(format!("{name} needs a value"));
+        match arg.as_str() {
+            "--speed" => speed = value("--speed")?.parse().map_err(|_| "--speed expects a number like 0.8")?,
+            "--pitch" => semitones = value("--pitch")?.parse().map_err(|_| "--pitch expects semitones like -2")?,
+            "--out" => out_dir = Some(PathBuf::from(value("--out")?)),
+            "--portable" => {} // already applied in main
+            a if a.starts_with("--") => return Err(format!("unknown option {a}")),
+            a => {
+                let path = PathBuf::from(a);
+                if path.is_dir() {
+                    let mut files: Vec<PathBuf> = std::fs::read_dir(&path).map_err(|e| e.to_string())?
+                        .flatten().map(|e| e.path()).filter(|p| is_audio_file(p)).collect();
+                    files.sort();
+                    inputs.extend(files);
+                } else {
+                    inputs.push(path);
+                }
+            }
+        }
+    }
+    if !(0.1..=8.0).contains(&speed) { return Err("--speed must be between 0.1 and 8".into()); }
+    if inputs.is_empty() { return Err("usage: reh batch [--speed 0.8] [--pitch -2] [--out dir] <files or folders>".into()); }
+    if let Some(dir) = &out_dir { std::fs::create_dir_all(dir).map_err(|e| e.to_string())?; }
+
+    let suffix = if semitones != 0.0 { format!("{speed}x {semitones:+}st") } else { format!("{speed}x") };
+    let soundfont = default_soundfont();
+    let mut failed = 0;
+    for (n, path) in inputs.iter().enumerate() {
+        let stem = path.file_stem().map(|s| s.to_string_lossy().into_owned()).unwrap_or_default();
+        let dir = out_dir.clone().or_else(|| path.parent().map(Path::to_path_buf)).unwrap_or_default();
+        let out = dir.join(format!("{stem} ({suffix}).wav"));
+        print!("[{}/{}] {} ... ", n + 1, inputs.len(), path.display());
+        let _ = std::io::Write::flush(&mut std::io::stdout());
+        let source = if is_midi(path) {
+            soundfont.clone().ok_or_else(|| "no SoundFont (set REH_SOUNDFONT)".to_string()).and_then(|sf| render_midi(path, &sf))
+        } else {
+            Ok(path.clone())
+        };
+        let result = source.and_then(|src| decode_audio(&src)).and_then(|mut a| {
+            // a volume envelope drawn for the file in the app comes along
+            let envelope = Automation { points: Session::load(path).volume_envelope, stepped: false };
+            if !envelope.points.is_empty() {
+                for (i, v) in a.pcm.iter_mut().enumerate() { *v *= envelope.gain_at(i); }
+            }
+            let pcm = render_stretched(&a.pcm, a.channels, a.sample_rate, speed, semitones, &|_| {});
+            write_wav(&out, &pcm, a.sample_rate, a.channels).map_err(|e| e.to_string())
+        });
+        match result {
+            Ok(()) => println!("{}", out.display()),
+            Err(e) => { println!("failed: {e}"); failed += 1; }
+        }
+    }
+    if failed > 0 { Err(format!("{failed} of {} files failed", inputs.len())) } else { Ok(()) }
+}
+
 fn main() -> eframe::Result<()> {
     let args: Vec<String> = std::env::args().collect();
-    let initial_path = args.get(1).map(PathBuf::from);
+    // installer hooks: reh --register / reh --unregister
+    if let Some(flag) = args.get(1).filter(|a| *a == "--register" || *a == "--unregister") {
+        match register_file_types(flag == "--register") {
+            Ok(msg) => println!("{}", msg),
+            Err(e) => { eprintln!("{}", e); std::process::exit(1); }
+        }
+        return Ok(());
+    }
+    let exe_dir = std::env::current_exe().ok().and_then(|e| e.parent().map(Path::to_path_buf));
+    let portable = args.iter().any(|a| a == "--portable") || exe_dir.as_ref().is_some_and(|d| d.join("portable.toml").exists());
+    let _ = PORTABLE_ROOT.set(exe_dir.filter(|_| portable));
+    if args.get(1).is_some_and(|a| a == "remote") {
+        if let Err(e) = run_remote(&args[2..]) { eprintln!("{}", e); std::process::exit(1); }
+        return Ok(());
+    }
+    if args.get(1).is_some_and(|a| a == "batch") {
+        if let Err(e) = run_batch(&args[2..]) { eprintln!("{}", e); std::process::exit(1); }
+        return Ok(());
+    }
+    let initial_paths: Vec<PathBuf> = args.iter().skip(1).filter(|a| !a.starts_with("--")).map(PathBuf::from).collect();
     eframe::run_native("Reh", eframe::NativeOptions {
         viewport: egui::ViewportBuilder::default()
             .with_inner_size([550.0, 350.0])
             .with_min_inner_size([300.0, 200.0]),
         ..Default::default()
-    }, Box::new(|cc| Ok(Box::new(PlayerApp::new(cc, initial_path)))))
+    }, Box::new(|cc| Ok(Box::new(PlayerApp::new(cc, initial_paths)))))
 }